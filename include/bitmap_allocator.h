@@ -0,0 +1,58 @@
+/* C API of the bitmap-allocator crate, built with the `ffi` feature. */
+
+#ifndef BITMAP_ALLOCATOR_H
+#define BITMAP_ALLOCATOR_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Returned by allocations that find no room. */
+#define BITALLOC_NONE SIZE_MAX
+
+/* An allocator, living in memory supplied by the caller. */
+typedef struct BitAllocHandle BitAllocHandle;
+
+/* The bytes an allocator of `cap` bits takes, or 0 if `cap` is over 256M. */
+size_t bitalloc_size(size_t cap);
+
+/* The alignment of the memory of an allocator. */
+size_t bitalloc_align(void);
+
+/* Build an allocator of `cap` bits, all allocated, in `len` bytes at `mem`.
+ * Returns NULL if the memory is too small or misaligned. */
+BitAllocHandle *bitalloc_new_in(void *mem, size_t len, size_t cap);
+
+size_t bitalloc_capacity(const BitAllocHandle *handle);
+
+/* Allocate a free bit, or return BITALLOC_NONE. */
+size_t bitalloc_alloc(BitAllocHandle *handle);
+
+/* Free an allocated bit. False if it is out of range or already free. */
+bool bitalloc_free(BitAllocHandle *handle, size_t key);
+
+/* Allocate `size` contiguous bits aligned to `1 << align_log2`. */
+size_t bitalloc_alloc_contiguous(BitAllocHandle *handle, size_t size, size_t align_log2);
+
+/* Free `size` contiguous bits at `base`. False, changing nothing, if some
+ * are out of range or already free. */
+bool bitalloc_free_contiguous(BitAllocHandle *handle, size_t base, size_t size);
+
+/* Mark the bits in [start, end) free, or allocated. False if out of range. */
+bool bitalloc_insert(BitAllocHandle *handle, size_t start, size_t end);
+bool bitalloc_remove(BitAllocHandle *handle, size_t start, size_t end);
+
+/* Whether a bit is free. */
+bool bitalloc_test(BitAllocHandle *handle, size_t key);
+
+size_t bitalloc_free_count(BitAllocHandle *handle);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
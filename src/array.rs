@@ -0,0 +1,452 @@
+use crate::iter::SetBits;
+use crate::simd::{find_word, rfind_word};
+use crate::{align_size, debug_summary, to_range, AllocPolicy, BitAlloc, HighestFirst, WORD_BITS};
+use bit_field::BitField;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::ops::{Range, RangeBounds};
+
+/// A flat bitmap of exactly `BITS` bits, stored in `WORDS` 64-bit words.
+///
+/// The cascade types only come in powers of 16. This one is for sizes that
+/// are not, e.g. `BitAllocArray<1000, 16>`. `WORDS` must be `BITS.div_ceil(64)`,
+/// which is checked at compile time.
+///
+/// Operations scan the word array linearly, so this is meant for small maps.
+/// An upper bound on the longest free run is kept though, lowered by every
+/// failed `alloc_contiguous` and raised by frees, so that retrying a block
+/// too large for the fragmented map fails without a scan.
+#[derive(Clone)]
+pub struct BitAllocArray<const BITS: usize, const WORDS: usize> {
+    words: [u64; WORDS], // for each bit, 1 indicates available, 0 indicates inavailable
+    free: usize,         // number of available bits, as a summary
+    longest: usize,      // upper bound on the length of the longest run of available bits
+}
+
+// the bound depends on the history, not only on the bits
+impl<const BITS: usize, const WORDS: usize> PartialEq for BitAllocArray<BITS, WORDS> {
+    fn eq(&self, other: &Self) -> bool {
+        self.words == other.words
+    }
+}
+
+impl<const BITS: usize, const WORDS: usize> Eq for BitAllocArray<BITS, WORDS> {}
+
+impl<const BITS: usize, const WORDS: usize> Hash for BitAllocArray<BITS, WORDS> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.words.hash(state)
+    }
+}
+
+impl<const BITS: usize, const WORDS: usize> Default for BitAllocArray<BITS, WORDS> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<const BITS: usize, const WORDS: usize> fmt::Debug for BitAllocArray<BITS, WORDS> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        debug_summary(self, "BitAllocArray", f)
+    }
+}
+
+impl<const BITS: usize, const WORDS: usize> fmt::Binary for BitAllocArray<BITS, WORDS> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Binary::fmt(&self.fmt_range(..), f)
+    }
+}
+
+impl<const BITS: usize, const WORDS: usize> fmt::LowerHex for BitAllocArray<BITS, WORDS> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.fmt_range(..), f)
+    }
+}
+
+impl<const BITS: usize, const WORDS: usize> BitAlloc for BitAllocArray<BITS, WORDS> {
+    const CAP: usize = BITS;
+
+    const DEFAULT: Self = {
+        assert!(
+            WORDS == BITS.div_ceil(64),
+            "WORDS must be BITS / 64 rounded up"
+        );
+        BitAllocArray {
+            words: [0; WORDS],
+            free: 0,
+            longest: 0,
+        }
+    };
+
+    fn alloc(&mut self) -> Option<usize> {
+        self.alloc_with::<HighestFirst>()
+    }
+    fn alloc_with<P: AllocPolicy>(&mut self) -> Option<usize> {
+        let i = self.peek_with::<P>()?;
+        self.words[i / 64].set_bit(i % 64, false);
+        self.free -= 1;
+        Some(i)
+    }
+    fn peek_alloc(&self) -> Option<usize> {
+        self.peek_with::<HighestFirst>()
+    }
+    fn peek_with<P: AllocPolicy>(&self) -> Option<usize> {
+        if self.any() {
+            Some(Self::pick::<P>(0..WORDS, &|w| self.words[w]))
+        } else {
+            None
+        }
+    }
+    fn peek_full<P: AllocPolicy>() -> usize {
+        Self::pick::<P>(0..WORDS, &|w| u64::MAX >> (64 - (BITS - w * 64).min(64)))
+    }
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        if size > self.longest {
+            return None;
+        }
+        if let Some(base) = self.find_contiguous(size, align_log2) {
+            self.remove(base..base + size);
+            Some(base)
+        } else {
+            // an aligned block may fail where a longer unaligned run exists
+            if align_log2 == 0 && size > 0 {
+                self.longest = size - 1;
+            }
+            None
+        }
+    }
+    fn alloc_aligned(&mut self, align_log2: usize) -> Option<usize> {
+        if align_size(align_log2).is_none_or(|align| align > BITS) {
+            return None;
+        }
+        // inside a word, bits at aligned positions; across words, only every
+        // `step`-th word starts at an aligned position
+        let (mask, step) = if align_log2 < 6 {
+            let mask = (0..64)
+                .step_by(1 << align_log2)
+                .fold(0u64, |mask, i| mask | 1 << i);
+            (mask, 1)
+        } else {
+            let step = align_size(align_log2 - 6).unwrap_or(usize::MAX);
+            (1, step)
+        };
+        let (w, word) = (0..WORDS)
+            .step_by(step)
+            .map(|w| (w, self.words[w] & mask))
+            .find(|&(_, word)| word != 0)?;
+        let i = word.trailing_zeros() as usize;
+        self.words[w].set_bit(i, false);
+        self.free -= 1;
+        Some(w * 64 + i)
+    }
+    fn dealloc(&mut self, key: usize) {
+        assert!(key < Self::CAP);
+        assert!(!self.test(key));
+        self.words[key / 64].set_bit(key % 64, true);
+        self.free += 1;
+        self.raise_longest(key..key + 1);
+    }
+    fn insert(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, Self::CAP);
+        self.for_range(range.clone(), |word, mask| *word |= mask);
+        self.raise_longest(range);
+    }
+    fn clear(&mut self) {
+        self.words = [0; WORDS];
+        self.free = 0;
+        self.longest = 0;
+    }
+    fn fill(&mut self) {
+        self.words = [u64::MAX; WORDS];
+        self.rebuild();
+    }
+    fn remove(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, Self::CAP);
+        self.for_range(range, |word, mask| *word &= !mask);
+    }
+    fn any(&self) -> bool {
+        self.free != 0
+    }
+    fn test(&self, key: usize) -> bool {
+        key < Self::CAP && self.words[key / 64].get_bit(key % 64)
+    }
+    fn free_count(&self) -> usize {
+        self.free
+    }
+    fn count_free_in(&self, range: impl RangeBounds<usize>) -> usize {
+        let range = to_range(range, Self::CAP);
+        Self::masks(range)
+            .map(|(w, mask)| (self.words[w] & mask).count_ones() as usize)
+            .sum()
+    }
+    fn next(&self, key: usize) -> Option<usize> {
+        self.scan(key, 0)
+    }
+    fn prev(&self, key: usize) -> Option<usize> {
+        self.scan_back(key, 0)
+    }
+    fn next_allocated(&self, key: usize) -> Option<usize> {
+        self.scan(key, u64::MAX)
+    }
+    fn prev_allocated(&self, key: usize) -> Option<usize> {
+        self.scan_back(key, u64::MAX)
+    }
+    fn raw_word(&self, w: usize) -> usize {
+        // a raw word is a whole u64 or a part of one
+        let bit = w * WORD_BITS;
+        (self.words[bit / 64] >> (bit % 64)) as usize
+    }
+    fn write_raw_word(&mut self, w: usize, word: usize) {
+        let bit = w * WORD_BITS;
+        let mask = (usize::MAX as u64) << (bit % 64);
+        let word = (word as u64) << (bit % 64);
+        self.words[bit / 64] = self.words[bit / 64] & !mask | word & mask;
+    }
+    fn rebuild(&mut self) {
+        if !BITS.is_multiple_of(64) {
+            self.words[WORDS - 1] &= (1 << (BITS % 64)) - 1;
+        }
+        self.free = self.words.iter().map(|w| w.count_ones() as usize).sum();
+        self.longest = BITS;
+    }
+    fn iter_free(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.words
+            .iter()
+            .enumerate()
+            .flat_map(|(w, &word)| SetBits::new(word, w * 64))
+    }
+}
+
+impl<const BITS: usize, const WORDS: usize> BitAllocArray<BITS, WORDS> {
+    /// Create an allocator with all bits allocated, e.g. for a `static`.
+    pub const fn new() -> Self {
+        Self::DEFAULT
+    }
+
+    /// Raise the bound on the longest run to that of the run around `range`,
+    /// which has just been freed.
+    fn raise_longest(&mut self, range: Range<usize>) {
+        if range.start == range.end || self.longest == BITS {
+            return;
+        }
+        let start = self.prev_allocated(range.start).map_or(0, |key| key + 1);
+        let end = self.next_allocated(range.end).unwrap_or(BITS);
+        self.longest = self.longest.max(end - start);
+    }
+
+    /// Pick a free bit among the non-empty words in `words` as `P` says,
+    /// where `word(w)` is the `w`-th word.
+    ///
+    /// There is no real tree here, so the words are split into at most 64
+    /// groups on the fly and `P` picks a non-empty one, recursively.
+    fn pick<P: AllocPolicy>(words: Range<usize>, word: &impl Fn(usize) -> u64) -> usize {
+        if words.len() == 1 {
+            return words.start * 64 + P::pick(word(words.start));
+        }
+        let group = words.len().div_ceil(64);
+        let mask = (0..words.len().div_ceil(group))
+            .filter(|&g| {
+                let begin = words.start + g * group;
+                let end = (begin + group).min(words.end);
+                (begin..end).any(|w| word(w) != 0)
+            })
+            .fold(0u64, |mask, g| mask | 1 << g);
+        let begin = words.start + P::pick(mask) * group;
+        Self::pick::<P>(begin..(begin + group).min(words.end), word)
+    }
+
+    /// Find the first bit not less than `key` and below `BITS` that differs
+    /// from those of `empty`, which is 0 or `u64::MAX`.
+    fn scan(&self, key: usize, empty: u64) -> Option<usize> {
+        if key >= BITS {
+            return None;
+        }
+        let first = (self.words[key / 64] ^ empty) & !((1u64 << (key % 64)) - 1);
+        let (w, word) = if first != 0 {
+            (key / 64, first)
+        } else {
+            let rest = &self.words[key / 64 + 1..];
+            let w = key / 64 + 1 + find_word(rest, empty)?;
+            (w, self.words[w] ^ empty)
+        };
+        let res = w * 64 + word.trailing_zeros() as usize;
+        if res < BITS {
+            Some(res)
+        } else {
+            None
+        }
+    }
+
+    /// Find the last bit not greater than `key`, clamped below `BITS`, that
+    /// differs from those of `empty`, which is 0 or `u64::MAX`.
+    fn scan_back(&self, key: usize, empty: u64) -> Option<usize> {
+        let key = key.min(BITS - 1);
+        // mask off bits above key
+        let first = (self.words[key / 64] ^ empty) & (u64::MAX >> (63 - key % 64));
+        let (w, word) = if first != 0 {
+            (key / 64, first)
+        } else {
+            let w = rfind_word(&self.words[..key / 64], empty)?;
+            (w, self.words[w] ^ empty)
+        };
+        Some(w * 64 + 63 - word.leading_zeros() as usize)
+    }
+
+    fn for_range(&mut self, range: Range<usize>, f: impl Fn(&mut u64, u64)) {
+        for (w, mask) in Self::masks(range) {
+            self.free -= self.words[w].count_ones() as usize;
+            f(&mut self.words[w], mask);
+            self.free += self.words[w].count_ones() as usize;
+        }
+    }
+
+    /// The words covered by `range`, with a mask of the bits of each one
+    /// inside the range.
+    fn masks(range: Range<usize>) -> impl Iterator<Item = (usize, u64)> {
+        let Range { start, end } = range;
+        let words = if start == end {
+            0..0
+        } else {
+            assert!(start < end);
+            assert!(end <= Self::CAP);
+            start / 64..(end - 1) / 64 + 1
+        };
+        words.map(move |w| {
+            let begin = if start / 64 == w { start % 64 } else { 0 };
+            let end = if end / 64 == w { end % 64 } else { 64 };
+            (w, u64::MAX >> (64 - (end - begin)) << begin)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type BitAlloc1000 = BitAllocArray<1000, 16>;
+
+    #[test]
+    fn bitalloc_array() {
+        let mut ba = BitAlloc1000::default();
+        assert_eq!(BitAlloc1000::CAP, 1000);
+        assert!(!ba.any());
+        assert_eq!(ba.alloc(), None);
+        ba.insert(0..1000);
+        assert_eq!(ba.iter_free().count(), 1000);
+        assert_eq!(ba.free_count(), 1000);
+        assert_eq!(ba.next_allocated(0), None);
+        assert_eq!(ba.next(999), Some(999));
+        assert_eq!(ba.next(1000), None);
+        assert!(!ba.test(1000));
+        assert_eq!(ba.alloc(), Some(999));
+        assert_eq!(ba.next_allocated(0), Some(999));
+        assert_eq!(ba.next(998), Some(998));
+        assert_eq!(ba.next(999), None);
+        assert_eq!(ba.prev(999), Some(998));
+        assert_eq!(ba.last(), Some(998));
+        assert_eq!(ba.prev(64), Some(64));
+        assert_eq!(ba.prev(0), Some(0));
+        ba.dealloc(999);
+        assert_eq!(ba.peek_alloc(), Some(999));
+
+        ba.remove(10..990);
+        assert_eq!(ba.count_free_in(0..1000), 20);
+        assert_eq!(ba.count_free_in(5..995), 10);
+        assert!(ba.iter_free().eq((0..10).chain(990..1000)));
+        assert!(ba.iter_free().rev().eq((0..10).chain(990..1000).rev()));
+        assert!(ba.allocated_ranges().rev().eq(core::iter::once(10..990)));
+        assert_eq!(ba.next(10), Some(990));
+        assert_eq!(ba.next_allocated(0), Some(10));
+        assert_eq!(ba.next_allocated(990), None);
+        for i in (990..1000).rev() {
+            assert_eq!(ba.alloc(), Some(i));
+        }
+        for i in 0..10 {
+            assert_eq!(ba.alloc_low(), Some(i));
+        }
+        assert!(!ba.any());
+        assert_eq!(ba.alloc(), None);
+    }
+
+    #[test]
+    fn bitalloc_array_contiguous() {
+        let mut ba = BitAlloc1000::default();
+        ba.insert(0..1000);
+        ba.remove(3..6);
+        assert_eq!(ba.alloc_contiguous(2, 0), Some(0));
+        assert_eq!(ba.alloc_contiguous(2, 0), Some(6));
+        assert_eq!(ba.alloc_contiguous(64, 6), Some(64));
+        assert_eq!(ba.alloc_aligned(6), Some(128));
+        assert_eq!(ba.alloc_aligned(9), Some(512));
+        assert_eq!(ba.alloc_aligned(10), None);
+        assert_eq!(ba.alloc_contiguous(8, 3), Some(8));
+        ba.remove(0..992);
+        assert_eq!(ba.alloc_contiguous(9, 0), None);
+        assert_eq!(ba.alloc_contiguous(8, 3), Some(992));
+        assert!(!ba.any());
+    }
+
+    #[test]
+    fn bitalloc_array_longest_bound() {
+        let mut ba = BitAlloc1000::default();
+        for i in (0..1000).step_by(10) {
+            ba.insert(i..i + 5);
+        }
+        assert_eq!(ba.alloc_contiguous(6, 0), None);
+        assert_eq!(ba.longest, 5);
+        // a free joining two runs raises the bound to the joined run
+        ba.insert(5..10);
+        assert_eq!(ba.longest, 15);
+        assert_eq!(ba.alloc_contiguous(12, 0), Some(0));
+        ba.dealloc(0);
+        assert_eq!(ba.alloc_contiguous(6, 0), None);
+        // a failed aligned block does not bound the unaligned runs
+        ba.remove(..);
+        ba.insert(1..5);
+        assert_eq!(ba.alloc_contiguous(4, 2), None);
+        assert_eq!(ba.alloc_contiguous(4, 0), Some(1));
+        // the bound is not part of the state compared
+        let mut other = BitAlloc1000::default();
+        other.fill();
+        other.remove(..);
+        assert!(ba == other);
+    }
+
+    #[test]
+    fn bitalloc_array_policy() {
+        let mut ba = BitAllocArray::<10000, 157>::default();
+        ba.insert(70..80);
+        ba.insert(5000..5001);
+        ba.insert(9990..10000);
+        assert_eq!(ba.alloc_low(), Some(70));
+        assert_eq!(ba.alloc(), Some(9999));
+        for i in 71..80 {
+            assert_eq!(ba.alloc_low(), Some(i));
+        }
+        assert_eq!(ba.peek_alloc(), Some(9998));
+        assert_eq!(ba.alloc_low(), Some(5000));
+    }
+
+    #[test]
+    fn bitalloc_array_raw_words() {
+        let mut ba = BitAlloc1000::default();
+        ba.insert(10..20);
+        ba.insert(990..1000);
+        let mut words = [0; 1000usize.div_ceil(WORD_BITS)];
+        ba.as_raw_words(&mut |w, word| words[w] = word);
+        assert_eq!(words[0], 0xffc00);
+        // bits past the end are dropped
+        *words.last_mut().unwrap() |= usize::MAX;
+        let copy = BitAlloc1000::from_raw_words(&words);
+        assert_eq!(copy.free_count(), 10 + 1000 % WORD_BITS);
+        assert_eq!(copy.next(1000), None);
+        ba.insert((1000 - 1000 % WORD_BITS)..);
+        assert!(copy == ba);
+    }
+
+    #[test]
+    #[should_panic]
+    fn bitalloc_array_out_of_range() {
+        let mut ba = BitAlloc1000::default();
+        ba.insert(0..1001);
+    }
+}
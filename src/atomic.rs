@@ -0,0 +1,496 @@
+//! Allocators that several CPUs can share without a lock.
+//!
+//! Bits live in `AtomicUsize` words, and `alloc`, `dealloc` and `insert`
+//! take `&self`, so an allocator can be put in a `static` and used from
+//! every CPU at once. A leaf bit is only ever claimed by a single
+//! compare-and-swap, which is what makes two `alloc`s never return the
+//! same bit.
+//!
+//! Instead of a bitset of non-empty children, every node keeps a count of
+//! the free bits below it. `alloc` takes one from the count first and only
+//! then looks for the bit in the children, while `dealloc` frees the bit
+//! first and counts it afterwards. So a node never promises more bits than
+//! its children hold, and `alloc` only fails when there really is no free
+//! bit, rather than when it races with another CPU.
+//!
+//! With the `portable-atomic` feature, the words are those of the
+//! `portable-atomic` crate, so targets without compare-and-swap, like
+//! `thumbv6m-none-eabi`, can use these types too. Enable `critical-section`
+//! as well to fall back on critical sections there, and provide an
+//! implementation as the `critical-section` crate describes.
+
+use crate::{to_range, WORD_BITS};
+use core::fmt;
+use core::ops::{Range, RangeBounds};
+#[cfg(not(feature = "portable-atomic"))]
+use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "portable-atomic")]
+use portable_atomic::{AtomicUsize, Ordering};
+
+/// Allocator of a bit array whose bits can be allocated and freed through
+/// shared references.
+///
+/// It offers the core of [`BitAlloc`](crate::BitAlloc). Bits are only
+/// removed with exclusive access, as a concurrent `alloc` may already count
+/// on them.
+pub trait AtomicBitAlloc: Sized {
+    /// The bitmap has a total of CAP bits, numbered from 0 to CAP-1 inclusively.
+    const CAP: usize;
+
+    /// The default value. Default means all allocated.
+    const DEFAULT: Self;
+
+    /// Allocate a free bit.
+    fn alloc(&self) -> Option<usize>;
+
+    /// Free an allocated bit.
+    fn dealloc(&self, key: usize);
+
+    /// Mark bits in the range as unallocated (available), and return how
+    /// many of them were allocated before.
+    fn insert_counted(&self, range: impl RangeBounds<usize>) -> usize;
+
+    /// Mark bits in the range as unallocated (available).
+    fn insert(&self, range: impl RangeBounds<usize>) {
+        self.insert_counted(range);
+    }
+
+    /// Reverse of insert.
+    fn remove(&mut self, range: impl RangeBounds<usize>);
+
+    /// Whether a specific bit is free.
+    fn test(&self, key: usize) -> bool;
+
+    /// Free a bit, and return whether it was free already. Unlike
+    /// `dealloc`, a free bit is fine, e.g. to mark a page dirty.
+    fn test_and_set(&self, key: usize) -> bool;
+
+    /// Allocate a specific bit, and return whether it was free, i.e. whether
+    /// this call took it. Of concurrent calls for the same bit, and `alloc`s,
+    /// only one gets it.
+    fn test_and_clear(&self, key: usize) -> bool;
+
+    /// Allocate every free bit in the range, passing each to `f`, e.g. to
+    /// harvest a dirty log. Each bit is taken as by `test_and_clear`, so a
+    /// bit freed meanwhile is either passed to `f` or left free.
+    fn clear_and_collect(&self, range: impl RangeBounds<usize>, f: &mut impl FnMut(usize)) {
+        let range = to_range(range, Self::CAP);
+        check(&range, Self::CAP);
+        for key in range {
+            if self.test(key) && self.test_and_clear(key) {
+                f(key);
+            }
+        }
+    }
+
+    /// The number of free bits. While other CPUs are allocating, this may
+    /// lag behind a little.
+    fn free_count(&self) -> usize;
+}
+
+/// A leaf of 256 bits in atomic words.
+pub struct AtomicBitAlloc256 {
+    words: [AtomicUsize; 256 / WORD_BITS],
+}
+
+/// A node of 16 children, each counted in `free`.
+pub struct AtomicBitAllocCascade16<T: AtomicBitAlloc> {
+    free: AtomicUsize, // number of free bits below, not yet taken by an `alloc`
+    sub: [T; 16],
+}
+
+/// A shared bitmap of 4K bits
+pub type AtomicBitAlloc4K = AtomicBitAllocCascade16<AtomicBitAlloc256>;
+/// A shared bitmap of 64K bits
+pub type AtomicBitAlloc64K = AtomicBitAllocCascade16<AtomicBitAlloc4K>;
+/// A shared bitmap of 1M bits
+pub type AtomicBitAlloc1M = AtomicBitAllocCascade16<AtomicBitAlloc64K>;
+/// A shared bitmap of 16M bits
+pub type AtomicBitAlloc16M = AtomicBitAllocCascade16<AtomicBitAlloc1M>;
+/// A shared bitmap of 256M bits
+pub type AtomicBitAlloc256M = AtomicBitAllocCascade16<AtomicBitAlloc16M>;
+
+/// Split `range` into the ranges it covers in consecutive parts of `part`
+/// bits each, as `(part index, range in the part)`.
+fn split(range: Range<usize>, part: usize) -> impl Iterator<Item = (usize, Range<usize>)> {
+    let Range { start, end } = range;
+    let parts = if start == end {
+        0..0
+    } else {
+        start / part..(end - 1) / part + 1
+    };
+    parts.map(move |i| {
+        let begin = start.max(i * part) - i * part;
+        let end = end.min((i + 1) * part) - i * part;
+        (i, begin..end)
+    })
+}
+
+fn check(range: &Range<usize>, cap: usize) {
+    if range.start != range.end {
+        assert!(range.start < range.end);
+        assert!(range.end <= cap);
+    }
+}
+
+/// The bits of `range` within one word, which must be non-empty.
+fn mask(range: Range<usize>) -> usize {
+    usize::MAX >> (WORD_BITS - range.len()) << range.start
+}
+
+impl AtomicBitAlloc for AtomicBitAlloc256 {
+    const CAP: usize = 256;
+
+    const DEFAULT: Self = AtomicBitAlloc256 {
+        words: [const { AtomicUsize::new(0) }; 256 / WORD_BITS],
+    };
+
+    fn alloc(&self) -> Option<usize> {
+        // the highest free bit first, as `BitAlloc::alloc` does
+        for (w, word) in self.words.iter().enumerate().rev() {
+            let mut cur = word.load(Ordering::Acquire);
+            while cur != 0 {
+                let bit = WORD_BITS - 1 - cur.leading_zeros() as usize;
+                match word.compare_exchange_weak(
+                    cur,
+                    cur & !(1 << bit),
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                ) {
+                    Ok(_) => return Some(w * WORD_BITS + bit),
+                    Err(now) => cur = now,
+                }
+            }
+        }
+        None
+    }
+    fn dealloc(&self, key: usize) {
+        assert!(key < Self::CAP);
+        let bit = 1 << (key % WORD_BITS);
+        let old = self.words[key / WORD_BITS].fetch_or(bit, Ordering::AcqRel);
+        assert_eq!(old & bit, 0, "bit {} is already free", key);
+    }
+    fn insert_counted(&self, range: impl RangeBounds<usize>) -> usize {
+        let range = to_range(range, Self::CAP);
+        check(&range, Self::CAP);
+        split(range, WORD_BITS)
+            .map(|(w, bits)| {
+                let mask = mask(bits);
+                let old = self.words[w].fetch_or(mask, Ordering::AcqRel);
+                (mask & !old).count_ones() as usize
+            })
+            .sum()
+    }
+    fn remove(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, Self::CAP);
+        check(&range, Self::CAP);
+        for (w, bits) in split(range, WORD_BITS) {
+            *self.words[w].get_mut() &= !mask(bits);
+        }
+    }
+    fn test(&self, key: usize) -> bool {
+        key < Self::CAP
+            && self.words[key / WORD_BITS].load(Ordering::Acquire) >> (key % WORD_BITS) & 1 != 0
+    }
+    fn test_and_set(&self, key: usize) -> bool {
+        assert!(key < Self::CAP);
+        let bit = 1 << (key % WORD_BITS);
+        self.words[key / WORD_BITS].fetch_or(bit, Ordering::AcqRel) & bit != 0
+    }
+    fn test_and_clear(&self, key: usize) -> bool {
+        assert!(key < Self::CAP);
+        let bit = 1 << (key % WORD_BITS);
+        self.words[key / WORD_BITS].fetch_and(!bit, Ordering::AcqRel) & bit != 0
+    }
+    /// Clears a word at a time.
+    fn clear_and_collect(&self, range: impl RangeBounds<usize>, f: &mut impl FnMut(usize)) {
+        let range = to_range(range, Self::CAP);
+        check(&range, Self::CAP);
+        for (w, bits) in split(range, WORD_BITS) {
+            let mask = mask(bits);
+            if self.words[w].load(Ordering::Relaxed) & mask == 0 {
+                continue;
+            }
+            let old = self.words[w].fetch_and(!mask, Ordering::AcqRel);
+            let mut taken = old & mask;
+            while taken != 0 {
+                f(w * WORD_BITS + taken.trailing_zeros() as usize);
+                taken &= taken - 1;
+            }
+        }
+    }
+    fn free_count(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.load(Ordering::Acquire).count_ones() as usize)
+            .sum()
+    }
+}
+
+impl<T: AtomicBitAlloc> AtomicBitAlloc for AtomicBitAllocCascade16<T> {
+    const CAP: usize = T::CAP * 16;
+
+    const DEFAULT: Self = AtomicBitAllocCascade16 {
+        free: AtomicUsize::new(0),
+        sub: [T::DEFAULT; 16],
+    };
+
+    fn alloc(&self) -> Option<usize> {
+        // take a bit from the count, which some child is then bound to hold
+        self.free
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |free| {
+                free.checked_sub(1)
+            })
+            .ok()?;
+        loop {
+            // another CPU may take the bit we saw first, but not ours
+            for (i, sub) in self.sub.iter().enumerate().rev() {
+                if sub.free_count() != 0 {
+                    if let Some(res) = sub.alloc() {
+                        return Some(res + i * T::CAP);
+                    }
+                }
+            }
+            core::hint::spin_loop();
+        }
+    }
+    fn dealloc(&self, key: usize) {
+        assert!(key < Self::CAP);
+        self.sub[key / T::CAP].dealloc(key % T::CAP);
+        self.free.fetch_add(1, Ordering::AcqRel);
+    }
+    fn insert_counted(&self, range: impl RangeBounds<usize>) -> usize {
+        let range = to_range(range, Self::CAP);
+        check(&range, Self::CAP);
+        let n = split(range, T::CAP)
+            .map(|(i, range)| self.sub[i].insert_counted(range))
+            .sum();
+        self.free.fetch_add(n, Ordering::AcqRel);
+        n
+    }
+    fn remove(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, Self::CAP);
+        check(&range, Self::CAP);
+        for (i, range) in split(range, T::CAP) {
+            self.sub[i].remove(range);
+        }
+        *self.free.get_mut() = self.sub.iter().map(T::free_count).sum();
+    }
+    fn test(&self, key: usize) -> bool {
+        key < Self::CAP && self.sub[key / T::CAP].test(key % T::CAP)
+    }
+    fn test_and_set(&self, key: usize) -> bool {
+        assert!(key < Self::CAP);
+        let was = self.sub[key / T::CAP].test_and_set(key % T::CAP);
+        if !was {
+            self.free.fetch_add(1, Ordering::AcqRel);
+        }
+        was
+    }
+    /// Never waits: a free bit not counted yet is promised to an `alloc`
+    /// still on its way to it, or not quite freed by a `test_and_set`, so it
+    /// is not ours to take. This keeps it safe to call from an interrupt
+    /// handler that may have cut into either.
+    fn test_and_clear(&self, key: usize) -> bool {
+        // take a bit from the count first, as `alloc` does, and give it back
+        // if the bit was not ours to take
+        if !self.test(key)
+            || self
+                .free
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |free| {
+                    free.checked_sub(1)
+                })
+                .is_err()
+        {
+            return false;
+        }
+        let was = self.sub[key / T::CAP].test_and_clear(key % T::CAP);
+        if !was {
+            self.free.fetch_add(1, Ordering::AcqRel);
+        }
+        was
+    }
+    /// Skips the children without free bits.
+    fn clear_and_collect(&self, range: impl RangeBounds<usize>, f: &mut impl FnMut(usize)) {
+        let range = to_range(range, Self::CAP);
+        check(&range, Self::CAP);
+        for (i, range) in split(range, T::CAP) {
+            if self.sub[i].free_count() == 0 {
+                continue;
+            }
+            for key in range {
+                if self.sub[i].test(key) && self.test_and_clear(i * T::CAP + key) {
+                    f(i * T::CAP + key);
+                }
+            }
+        }
+    }
+    fn free_count(&self) -> usize {
+        self.free.load(Ordering::Acquire)
+    }
+}
+
+impl Default for AtomicBitAlloc256 {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<T: AtomicBitAlloc> Default for AtomicBitAllocCascade16<T> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl fmt::Debug for AtomicBitAlloc256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        debug_summary(self, "AtomicBitAlloc256", f)
+    }
+}
+
+impl<T: AtomicBitAlloc> fmt::Debug for AtomicBitAllocCascade16<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        debug_summary(self, "AtomicBitAllocCascade16", f)
+    }
+}
+
+fn debug_summary<A: AtomicBitAlloc>(ba: &A, name: &str, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct(name)
+        .field("cap", &A::CAP)
+        .field("free", &ba.free_count())
+        .finish_non_exhaustive()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitAlloc, BitAlloc4K, BitAlloc64K};
+
+    #[test]
+    fn atomic_reference() {
+        let mut ba = AtomicBitAlloc4K::default();
+        let mut reference = BitAlloc4K::default();
+        let mut seed = 0xa70_u32;
+        for _ in 0..500 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let start = (seed >> 8) as usize % 4096;
+            let end = (start + (seed >> 20) as usize % 300).min(4096);
+            match seed & 3 {
+                0 => {
+                    ba.remove(start..end);
+                    reference.remove(start..end);
+                }
+                1 => assert_eq!(ba.alloc(), reference.alloc()),
+                2 => {
+                    if !reference.test(start) {
+                        ba.dealloc(start);
+                        reference.dealloc(start);
+                    }
+                }
+                _ => assert_eq!(
+                    ba.insert_counted(start..end),
+                    reference.insert_counted(start..end)
+                ),
+            }
+            assert_eq!(ba.free_count(), reference.free_count());
+            assert_eq!(ba.test(start), reference.test(start));
+        }
+    }
+
+    #[test]
+    fn atomic_test_and_clear() {
+        let ba = AtomicBitAlloc4K::default();
+        assert!(!ba.test_and_set(100));
+        assert!(ba.test_and_set(100));
+        assert!(!ba.test_and_set(3000));
+        assert_eq!(ba.free_count(), 2);
+        assert!(ba.test_and_clear(100));
+        assert!(!ba.test_and_clear(100));
+        assert_eq!(ba.free_count(), 1);
+        for key in [5, 63, 64, 70, 255, 256, 2999] {
+            ba.test_and_set(key);
+        }
+        let mut seen = BitAlloc4K::default();
+        ba.clear_and_collect(60..3000, &mut |key| seen.insert(key..key + 1));
+        assert!(seen.iter_free().eq([63, 64, 70, 255, 256, 2999]));
+        assert_eq!(ba.free_count(), 2);
+        assert!(ba.test(5) && ba.test(3000));
+        assert_eq!(ba.alloc(), Some(3000));
+        // as if cut into an `alloc` that took the count for bit 5
+        ba.free.store(0, Ordering::Release);
+        assert!(!ba.test_and_clear(5));
+        assert!(ba.test(5));
+    }
+
+    #[test]
+    fn atomic_harvest_threads() {
+        extern crate std;
+        use std::vec::Vec;
+
+        static BA: AtomicBitAlloc64K = AtomicBitAlloc64K::DEFAULT;
+        BA.insert(..);
+        let allocs = std::thread::spawn(|| {
+            let mut mine = Vec::new();
+            while let Some(key) = BA.alloc() {
+                mine.push(key);
+            }
+            mine
+        });
+        let mut harvested = Vec::new();
+        while BA.free_count() != 0 {
+            BA.clear_and_collect(.., &mut |key| harvested.push(key));
+        }
+        let mut seen = BitAlloc64K::default();
+        for key in allocs.join().unwrap().into_iter().chain(harvested) {
+            // no bit is taken twice
+            seen.dealloc(key);
+        }
+        assert_eq!(seen.free_count(), 65536);
+    }
+
+    #[test]
+    #[should_panic(expected = "already free")]
+    fn atomic_double_free() {
+        let ba = AtomicBitAlloc4K::default();
+        ba.insert(10..20);
+        ba.dealloc(15);
+    }
+
+    #[test]
+    fn atomic_threads() {
+        extern crate std;
+        use std::vec::Vec;
+
+        static BA: AtomicBitAlloc64K = AtomicBitAlloc64K::DEFAULT;
+        BA.insert(..);
+        let per_thread = BitAlloc4K::CAP * 2;
+        let threads: Vec<_> = (0..8)
+            .map(|t| {
+                std::thread::spawn(move || {
+                    let mut mine = Vec::new();
+                    for i in 0..per_thread {
+                        mine.push(BA.alloc().unwrap());
+                        // give some back now and then, for others to take
+                        if i % 3 == t % 3 {
+                            BA.dealloc(mine.pop().unwrap());
+                        }
+                    }
+                    mine
+                })
+            })
+            .collect();
+        let mut seen = BitAlloc64K::default();
+        let mut count = 0;
+        for thread in threads {
+            for key in thread.join().unwrap() {
+                // no bit is handed out twice
+                seen.dealloc(key);
+                count += 1;
+            }
+        }
+        assert_eq!(BA.free_count(), 65536 - count);
+        assert!((0..65536).all(|key| BA.test(key) != seen.test(key)));
+        while BA.alloc().is_some() {}
+        assert_eq!(BA.free_count(), 0);
+    }
+}
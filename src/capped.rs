@@ -0,0 +1,274 @@
+use crate::{align_size, debug_summary, to_range, AllocPolicy, BitAlloc, WORD_BITS};
+use core::fmt;
+use core::ops::{Range, RangeBounds};
+
+/// An allocator of `cap` bits chosen at runtime, built on a larger
+/// allocator `T`, e.g. a `BitAlloc4K` for 1000 DMA descriptors counted at
+/// boot.
+///
+/// This is [`BitAllocConst`](crate::BitAllocConst) with the size in a
+/// field: the bits of `T` from `cap` on are never free, ranges are checked
+/// against `cap`, and `capacity` returns it, so the provided methods stay
+/// within it too. `DEFAULT` has no cap below `T::CAP`.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct BitAllocCapped<T: BitAlloc> {
+    inner: T,
+    cap: usize,
+}
+
+impl<T: BitAlloc> BitAllocCapped<T> {
+    /// Cap an allocator to its first `cap` bits, allocating the others.
+    pub fn new(mut inner: T, cap: usize) -> Self {
+        assert!(cap <= T::CAP, "cap must not exceed the capacity of T");
+        inner.remove(cap..);
+        BitAllocCapped { inner, cap }
+    }
+
+    /// The underlying allocator.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Unwrap the underlying allocator.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn check(&self, range: &Range<usize>) {
+        if range.start != range.end {
+            assert!(range.start < range.end);
+            assert!(range.end <= self.cap);
+        }
+    }
+}
+
+impl<T: BitAlloc> Default for BitAllocCapped<T> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<T: BitAlloc> fmt::Debug for BitAllocCapped<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        debug_summary(self, "BitAllocCapped", f)
+    }
+}
+
+impl<T: BitAlloc> fmt::Binary for BitAllocCapped<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Binary::fmt(&self.fmt_range(..), f)
+    }
+}
+
+impl<T: BitAlloc> fmt::LowerHex for BitAllocCapped<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.fmt_range(..), f)
+    }
+}
+
+impl<T: BitAlloc> BitAlloc for BitAllocCapped<T> {
+    const CAP: usize = T::CAP;
+
+    const DEFAULT: Self = BitAllocCapped {
+        inner: T::DEFAULT,
+        cap: T::CAP,
+    };
+
+    fn capacity(&self) -> usize {
+        self.cap
+    }
+    fn alloc(&mut self) -> Option<usize> {
+        self.inner.alloc()
+    }
+    fn alloc_with<P: AllocPolicy>(&mut self) -> Option<usize> {
+        self.inner.alloc_with::<P>()
+    }
+    fn alloc_many(&mut self, out: &mut [usize]) -> usize {
+        self.inner.alloc_many(out)
+    }
+    fn peek_alloc(&self) -> Option<usize> {
+        self.inner.peek_alloc()
+    }
+    fn peek_with<P: AllocPolicy>(&self) -> Option<usize> {
+        self.inner.peek_with::<P>()
+    }
+    fn subtree_cap(depth: usize) -> usize {
+        T::subtree_cap(depth)
+    }
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        self.inner.alloc_contiguous(size, align_log2)
+    }
+    fn find_contiguous(&self, size: usize, align_log2: usize) -> Option<usize> {
+        self.inner.find_contiguous(size, align_log2)
+    }
+    fn alloc_aligned(&mut self, align_log2: usize) -> Option<usize> {
+        // the inner allocator may have room for an alignment past the cap
+        if align_size(align_log2).is_none_or(|align| align > self.cap) {
+            return None;
+        }
+        self.inner.alloc_aligned(align_log2)
+    }
+    fn next(&self, key: usize) -> Option<usize> {
+        self.inner.next(key)
+    }
+    fn prev(&self, key: usize) -> Option<usize> {
+        self.inner.prev(key)
+    }
+    fn dealloc(&mut self, key: usize) {
+        assert!(key < self.cap);
+        self.inner.dealloc(key)
+    }
+    fn insert(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, self.cap);
+        self.check(&range);
+        self.inner.insert(range)
+    }
+    fn remove(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, self.cap);
+        self.check(&range);
+        self.inner.remove(range)
+    }
+    fn any(&self) -> bool {
+        self.inner.any()
+    }
+    fn test(&self, key: usize) -> bool {
+        self.inner.test(key)
+    }
+    fn free_count(&self) -> usize {
+        self.inner.free_count()
+    }
+    fn count_free_in(&self, range: impl RangeBounds<usize>) -> usize {
+        self.inner.count_free_in(to_range(range, self.cap))
+    }
+    fn max_contiguous(&self) -> Option<Range<usize>> {
+        self.inner.max_contiguous()
+    }
+    fn next_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.next_allocated(key).filter(|&i| i < self.cap)
+    }
+    fn prev_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.prev_allocated(key.min(self.cap.checked_sub(1)?))
+    }
+    fn raw_word(&self, w: usize) -> usize {
+        self.inner.raw_word(w)
+    }
+    fn next_difference(&self, other: &Self, key: usize) -> Option<usize> {
+        self.inner.next_difference(&other.inner, key)
+    }
+    fn is_subset_of(&self, other: &Self) -> bool {
+        self.inner.is_subset_of(&other.inner)
+    }
+    fn is_disjoint_with(&self, other: &Self) -> bool {
+        self.inner.is_disjoint_with(&other.inner)
+    }
+    /// Bits `other` has free past the cap of `self` stay allocated.
+    fn union_with(&mut self, other: &Self) {
+        self.inner.union_with(&other.inner);
+        self.inner.remove(self.cap..);
+    }
+    fn intersect_with(&mut self, other: &Self) {
+        self.inner.intersect_with(&other.inner)
+    }
+    fn subtract(&mut self, other: &Self) {
+        self.inner.subtract(&other.inner)
+    }
+    /// The returned allocator has the same cap.
+    fn split_off(&mut self, at: usize) -> Self {
+        BitAllocCapped {
+            inner: self.inner.split_off(at),
+            cap: self.cap,
+        }
+    }
+    fn write_raw_word(&mut self, w: usize, word: usize) {
+        let valid = self.cap.saturating_sub(w * WORD_BITS);
+        let mask = if valid >= WORD_BITS {
+            !0
+        } else {
+            (1 << valid) - 1
+        };
+        self.inner.write_raw_word(w, word & mask)
+    }
+    fn rebuild(&mut self) {
+        self.inner.rebuild()
+    }
+    fn iter_free(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.inner.iter_free()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitAlloc16, BitAlloc4K};
+
+    #[test]
+    fn bitalloc_capped() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(..);
+        let mut ba = BitAllocCapped::new(ba, 1000);
+        assert_eq!((ba.capacity(), ba.free_count()), (1000, 1000));
+        assert_eq!(ba.alloc(), Some(999));
+        assert_eq!(ba.last(), Some(998));
+        assert_eq!(ba.next_allocated(0), Some(999));
+        assert_eq!(ba.prev_allocated(4000), Some(999));
+        ba.remove(..);
+        ba.insert(900..);
+        assert_eq!(ba.free_ranges().next(), Some(900..1000));
+        assert!(ba.iter_free().rev().eq((900..1000).rev()));
+        assert_eq!(ba.alloc_contiguous(128, 0), None);
+        assert_eq!(ba.find_contiguous_top(16, 4), Some(976));
+        assert!(ba
+            .occupancy(1)
+            .eq([(0..256, 0), (256..512, 0), (512..768, 0), (768..1000, 100)]));
+        // nothing past the cap comes in, whichever way
+        let mut other = BitAllocCapped::new(BitAlloc4K::default(), 4096);
+        other.insert(..);
+        ba.union_with(&other);
+        assert_eq!(ba.free_count(), 1000);
+        ba.write_raw_word(999 / WORD_BITS, !0);
+        ba.rebuild();
+        assert_eq!(ba.free_count(), 1000);
+    }
+
+    #[test]
+    fn bitalloc_capped_provided() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(..);
+        let mut ba = BitAllocCapped::new(ba, 1000);
+        let mut words = 0;
+        ba.as_raw_words(&mut |_, _| words += 1);
+        assert_eq!(words, 1000usize.div_ceil(WORD_BITS));
+        let mut raw = [0; 4096 / WORD_BITS];
+        ba.as_raw_words(&mut |w, word| raw[w] = word);
+        let mut copy = BitAllocCapped::new(BitAlloc4K::default(), 1000);
+        copy.load_raw_words(&raw[..words]);
+        assert_eq!(copy, ba);
+        let hi = ba.split_off(600);
+        assert_eq!((hi.capacity(), hi.free_count()), (1000, 400));
+        assert_eq!((hi.next(0), hi.last()), (Some(600), Some(999)));
+        assert!(ba.is_disjoint_with(&hi) && !ba.is_subset_of(&hi));
+        assert_eq!(ba.next_difference(&hi, 0), Some(0));
+        assert_eq!(ba.next_difference(&hi, 1000), None);
+        ba.merge(&hi);
+        assert_eq!(ba.free_count(), 1000);
+        ba.subtract(&hi);
+        assert_eq!(ba.next_difference(&hi, 0), Some(0));
+        ba.intersect_with(&hi);
+        assert_eq!(
+            (ba.free_count(), ba.next_difference(&hi, 0)),
+            (0, Some(600))
+        );
+        // no bit at all
+        let mut empty = BitAllocCapped::new(BitAlloc16::default(), 0);
+        assert_eq!(empty.last(), None);
+        assert_eq!(empty.alloc_near(5), None);
+        assert_eq!(empty.next_difference(&empty, 0), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn bitalloc_capped_out_of_range() {
+        let mut ba = BitAllocCapped::new(BitAlloc16::default(), 10);
+        ba.insert(5..11);
+    }
+}
@@ -0,0 +1,289 @@
+use crate::{
+    check_range, to_range, AllocPolicy, BitAlloc, BitAllocError, BudgetExhausted, Inconsistency,
+};
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::marker::PhantomData;
+use core::ops::{Range, RangeBounds};
+
+/// Receives the misuse detected by [`CheckedBitAlloc`].
+pub trait CheckHandler {
+    /// Called with the error and the bits involved. The offending operation
+    /// is not performed. This may be called from a kernel panic path, so it
+    /// should not allocate.
+    fn handle(error: BitAllocError, range: Range<usize>);
+}
+
+/// The default handler: panic with the error.
+pub struct PanicOnError;
+
+impl CheckHandler for PanicOnError {
+    fn handle(error: BitAllocError, range: Range<usize>) {
+        panic!("bit allocator misuse at {:?}: {}", range, error);
+    }
+}
+
+/// A wrapper detecting double frees and double inserts in all builds.
+///
+/// Freeing a free bit, inserting a range that is already partly free, or
+/// passing an index out of range is reported to the handler `H`, and the
+/// operation is skipped so the bitmap stays consistent.
+pub struct CheckedBitAlloc<A: BitAlloc, H: CheckHandler = PanicOnError> {
+    inner: A,
+    _handler: PhantomData<H>,
+}
+
+impl<A: BitAlloc, H: CheckHandler> CheckedBitAlloc<A, H> {
+    /// Wrap an allocator.
+    pub fn new(inner: A) -> Self {
+        CheckedBitAlloc {
+            inner,
+            _handler: PhantomData,
+        }
+    }
+
+    /// The underlying allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Unwrap the underlying allocator.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+}
+
+impl<A: BitAlloc + fmt::Debug, H: CheckHandler> fmt::Debug for CheckedBitAlloc<A, H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CheckedBitAlloc")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<A: BitAlloc + Clone, H: CheckHandler> Clone for CheckedBitAlloc<A, H> {
+    fn clone(&self) -> Self {
+        Self::new(self.inner.clone())
+    }
+}
+
+impl<A: BitAlloc + PartialEq, H: CheckHandler> PartialEq for CheckedBitAlloc<A, H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner
+    }
+}
+
+impl<A: BitAlloc + Eq, H: CheckHandler> Eq for CheckedBitAlloc<A, H> {}
+
+impl<A: BitAlloc + Hash, H: CheckHandler> Hash for CheckedBitAlloc<A, H> {
+    fn hash<S: Hasher>(&self, state: &mut S) {
+        self.inner.hash(state);
+    }
+}
+
+impl<A: BitAlloc, H: CheckHandler> Default for CheckedBitAlloc<A, H> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<A: BitAlloc, H: CheckHandler> BitAlloc for CheckedBitAlloc<A, H> {
+    const CAP: usize = A::CAP;
+
+    const DEFAULT: Self = CheckedBitAlloc {
+        inner: A::DEFAULT,
+        _handler: PhantomData,
+    };
+
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    fn alloc(&mut self) -> Option<usize> {
+        self.inner.alloc()
+    }
+    fn alloc_with<P: AllocPolicy>(&mut self) -> Option<usize> {
+        self.inner.alloc_with::<P>()
+    }
+    fn peek_alloc(&self) -> Option<usize> {
+        self.inner.peek_alloc()
+    }
+    fn peek_with<P: AllocPolicy>(&self) -> Option<usize> {
+        self.inner.peek_with::<P>()
+    }
+    fn peek_full<P: AllocPolicy>() -> usize {
+        A::peek_full::<P>()
+    }
+    fn subtree_cap(depth: usize) -> usize {
+        A::subtree_cap(depth)
+    }
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        self.inner.alloc_contiguous(size, align_log2)
+    }
+    fn find_contiguous(&self, size: usize, align_log2: usize) -> Option<usize> {
+        self.inner.find_contiguous(size, align_log2)
+    }
+    fn alloc_aligned(&mut self, align_log2: usize) -> Option<usize> {
+        self.inner.alloc_aligned(align_log2)
+    }
+    fn next(&self, key: usize) -> Option<usize> {
+        self.inner.next(key)
+    }
+    fn prev(&self, key: usize) -> Option<usize> {
+        self.inner.prev(key)
+    }
+    fn dealloc(&mut self, key: usize) {
+        if let Err(error) = self.inner.try_dealloc(key) {
+            H::handle(error, key..key + 1);
+        }
+    }
+    fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        self.insert(base..base + size);
+    }
+    fn insert(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, Self::CAP);
+        if let Err(error) = self.inner.try_insert(range.clone()) {
+            H::handle(error, range);
+        }
+    }
+    fn remove(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, Self::CAP);
+        if let Err(error) = check_range(&range, Self::CAP) {
+            H::handle(error, range);
+        } else {
+            self.inner.remove(range);
+        }
+    }
+    fn any(&self) -> bool {
+        self.inner.any()
+    }
+    fn test(&self, key: usize) -> bool {
+        self.inner.test(key)
+    }
+    fn free_count(&self) -> usize {
+        self.inner.free_count()
+    }
+    fn count_free_in(&self, range: impl RangeBounds<usize>) -> usize {
+        self.inner.count_free_in(range)
+    }
+    fn free_prefix(&self) -> usize {
+        self.inner.free_prefix()
+    }
+    fn free_suffix(&self) -> usize {
+        self.inner.free_suffix()
+    }
+    fn max_contiguous_len(&self) -> usize {
+        self.inner.max_contiguous_len()
+    }
+    fn max_contiguous(&self) -> Option<Range<usize>> {
+        self.inner.max_contiguous()
+    }
+    fn search_contiguous(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+    ) -> Option<usize> {
+        self.inner
+            .search_contiguous(offset, size, align_log2, run_start)
+    }
+    fn search_contiguous_bounded(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+        budget: &mut usize,
+    ) -> Result<Option<usize>, BudgetExhausted> {
+        self.inner
+            .search_contiguous_bounded(offset, size, align_log2, run_start, budget)
+    }
+    fn visit_free_runs(&self, run: &mut usize, f: &mut impl FnMut(usize)) {
+        self.inner.visit_free_runs(run, f)
+    }
+    fn next_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.next_allocated(key)
+    }
+    fn prev_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.prev_allocated(key)
+    }
+    fn raw_word(&self, w: usize) -> usize {
+        self.inner.raw_word(w)
+    }
+    fn next_difference(&self, other: &Self, key: usize) -> Option<usize> {
+        self.inner.next_difference(&other.inner, key)
+    }
+    fn is_subset_of(&self, other: &Self) -> bool {
+        self.inner.is_subset_of(&other.inner)
+    }
+    fn is_disjoint_with(&self, other: &Self) -> bool {
+        self.inner.is_disjoint_with(&other.inner)
+    }
+    fn union_with(&mut self, other: &Self) {
+        self.inner.union_with(&other.inner)
+    }
+    fn intersect_with(&mut self, other: &Self) {
+        self.inner.intersect_with(&other.inner)
+    }
+    fn subtract(&mut self, other: &Self) {
+        self.inner.subtract(&other.inner)
+    }
+    fn write_raw_word(&mut self, w: usize, word: usize) {
+        self.inner.write_raw_word(w, word)
+    }
+    fn rebuild(&mut self) {
+        self.inner.rebuild()
+    }
+    fn validate(&self) -> Result<(), Inconsistency> {
+        self.inner.validate()
+    }
+    fn iter_free(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.inner.iter_free()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BitAlloc4K;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Count;
+
+    impl CheckHandler for Count {
+        fn handle(error: BitAllocError, _range: Range<usize>) {
+            assert_eq!(error, BitAllocError::AlreadyFree);
+            ERRORS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn checked() {
+        let mut ba = CheckedBitAlloc::<BitAlloc4K, Count>::default();
+        ba.insert(0..100);
+        ba.insert(50..150);
+        assert_eq!(ERRORS.load(Ordering::Relaxed), 1);
+        assert!(!ba.test(120));
+        let i = ba.alloc().unwrap();
+        ba.dealloc(i);
+        ba.dealloc(i);
+        assert_eq!(ERRORS.load(Ordering::Relaxed), 2);
+        ba.remove(0..90);
+        let base = ba.alloc_contiguous(10, 0).unwrap();
+        assert_eq!(base, 90);
+        ba.dealloc_contiguous(base, 10);
+        ba.dealloc_contiguous(base, 10);
+        assert_eq!(ERRORS.load(Ordering::Relaxed), 3);
+        assert_eq!(ba.inner().iter_free().count(), 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "already free")]
+    fn checked_panic() {
+        let mut ba = CheckedBitAlloc::<BitAlloc4K>::default();
+        ba.insert(0..10);
+        ba.dealloc(5);
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{BitAlloc, LowestFirst, WORD_BITS};
+use core::ops::Range;
+
+/// The free space of a [`ClusterAlloc`], as a superblock records it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClusterSummary {
+    /// The number of clusters, reserved ones included.
+    pub clusters: usize,
+    /// The number of free clusters.
+    pub free: usize,
+    /// The lowest free cluster, a hint for the next allocation.
+    pub first_free: Option<usize>,
+    /// The length of the longest run of free clusters.
+    pub longest_free: usize,
+}
+
+/// A wrapper for the cluster (block) bitmap of an on-disk filesystem, e.g.
+/// one like ext2: bit `i` stands for cluster `i`, the first `reserved`
+/// clusters holding the boot block and superblock are never handed out, and
+/// the bitmap goes to and from disk blocks in the usual format, bit `i` of
+/// byte `k` being 1 if cluster `8 * k + i` is in use.
+///
+/// Allocation goes from the lowest cluster up, and `alloc_after` keeps the
+/// clusters of a file together.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ClusterAlloc<A: BitAlloc> {
+    inner: A,
+    clusters: usize,
+    cluster_size: usize,
+    reserved: usize,
+}
+
+impl<A: BitAlloc> ClusterAlloc<A> {
+    /// Create an allocator of `clusters` free clusters of `cluster_size`
+    /// bytes, the first `reserved` of them in use for good.
+    pub fn new(clusters: usize, cluster_size: usize, reserved: usize) -> Self {
+        assert!(clusters <= A::CAP, "more clusters than bits");
+        assert!(reserved <= clusters);
+        let mut inner = A::DEFAULT;
+        inner.insert(reserved..clusters);
+        ClusterAlloc {
+            inner,
+            clusters,
+            cluster_size,
+            reserved,
+        }
+    }
+
+    /// The number of clusters, reserved ones included.
+    pub fn clusters(&self) -> usize {
+        self.clusters
+    }
+
+    /// The size of a cluster in bytes.
+    pub fn cluster_size(&self) -> usize {
+        self.cluster_size
+    }
+
+    /// The clusters that are never handed out, from 0.
+    pub fn reserved(&self) -> Range<usize> {
+        0..self.reserved
+    }
+
+    /// The underlying bit allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Allocate the lowest free cluster.
+    pub fn alloc(&mut self) -> Option<usize> {
+        self.inner.alloc_with::<LowestFirst>()
+    }
+
+    /// Allocate a cluster to extend a file whose last cluster is `prev`:
+    /// the one right after it if free, else the next free one up, else the
+    /// lowest free one.
+    pub fn alloc_after(&mut self, prev: usize) -> Option<usize> {
+        let key = self
+            .inner
+            .next(prev.saturating_add(1))
+            .or_else(|| self.inner.next(0))?;
+        self.inner.remove(key..key + 1);
+        Some(key)
+    }
+
+    /// Allocate the lowest run of `len` free clusters, and return the first.
+    pub fn alloc_extent(&mut self, len: usize) -> Option<usize> {
+        self.inner.alloc_contiguous(len, 0)
+    }
+
+    /// Free a cluster in use.
+    pub fn dealloc(&mut self, cluster: usize) {
+        self.check(cluster..cluster + 1);
+        self.inner.dealloc(cluster)
+    }
+
+    /// Free a run of `len` clusters in use from `base`.
+    pub fn dealloc_extent(&mut self, base: usize, len: usize) {
+        self.check(base..base + len);
+        self.inner.dealloc_contiguous(base, len)
+    }
+
+    /// Whether a cluster is in use, reserved ones included.
+    pub fn in_use(&self, cluster: usize) -> bool {
+        !self.inner.test(cluster)
+    }
+
+    /// The figures for the superblock.
+    pub fn summary(&self) -> ClusterSummary {
+        ClusterSummary {
+            clusters: self.clusters,
+            free: self.inner.free_count(),
+            first_free: self.inner.next(0),
+            longest_free: self.inner.max_contiguous_len(),
+        }
+    }
+
+    /// The number of disk blocks of `block_size` bytes the bitmap takes.
+    pub fn bitmap_blocks(&self, block_size: usize) -> usize {
+        self.clusters.div_ceil(block_size * 8)
+    }
+
+    /// Write block `index` of the on-disk bitmap into `block`, which is as
+    /// long as a disk block. Bits past the last cluster are 1, as in use.
+    pub fn write_bitmap_block(&self, index: usize, block: &mut [u8]) {
+        let first = index * block.len() * 8;
+        for (k, byte) in block.iter_mut().enumerate() {
+            let bit = first + k * 8;
+            // bits past `clusters` are allocated, and read as in use
+            *byte = if bit < A::CAP {
+                !(self.inner.raw_word(bit / WORD_BITS) >> (bit % WORD_BITS)) as u8
+            } else {
+                0xff
+            };
+        }
+    }
+
+    /// Load block `index` of the on-disk bitmap from `block`, as written by
+    /// `write_bitmap_block`. Reserved clusters stay in use whatever it says.
+    pub fn read_bitmap_block(&mut self, index: usize, block: &[u8]) {
+        let first = index * block.len() * 8;
+        for (k, &byte) in block.iter().enumerate() {
+            let bit = first + k * 8;
+            let bits = bit.max(self.reserved)..(bit + 8).min(self.clusters);
+            if bits.start >= bits.end {
+                continue;
+            }
+            match byte {
+                0 => self.inner.insert(bits),
+                0xff => self.inner.remove(bits),
+                _ => {
+                    for key in bits {
+                        if byte >> (key - bit) & 1 == 0 {
+                            self.inner.insert(key..key + 1);
+                        } else {
+                            self.inner.remove(key..key + 1);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn check(&self, clusters: Range<usize>) {
+        assert!(
+            clusters.start >= self.reserved && clusters.end <= self.clusters,
+            "clusters {:?} are reserved or out of range",
+            clusters
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BitAlloc4K;
+
+    #[test]
+    fn cluster_extents() {
+        let mut fs = ClusterAlloc::<BitAlloc4K>::new(1000, 4096, 3);
+        assert_eq!(fs.alloc(), Some(3));
+        assert_eq!(fs.alloc_extent(10), Some(4));
+        let file = fs.alloc().unwrap();
+        assert_eq!(file, 14);
+        fs.alloc_extent(5);
+        // the file goes on past the extent in the way, then wraps around
+        assert_eq!(fs.alloc_after(file), Some(20));
+        fs.dealloc(3);
+        assert_eq!(fs.alloc_after(999), Some(3));
+        assert!(fs.in_use(0) && fs.in_use(3) && !fs.in_use(21));
+        assert_eq!(
+            fs.summary(),
+            ClusterSummary {
+                clusters: 1000,
+                free: 979,
+                first_free: Some(21),
+                longest_free: 979,
+            }
+        );
+    }
+
+    #[test]
+    fn cluster_bitmap_blocks() {
+        let mut fs = ClusterAlloc::<BitAlloc4K>::new(1500, 1024, 1);
+        fs.alloc_extent(20);
+        fs.dealloc_extent(10, 5);
+        assert_eq!(fs.bitmap_blocks(128), 2);
+        let mut blocks = [[0; 128]; 2];
+        for (i, block) in blocks.iter_mut().enumerate() {
+            fs.write_bitmap_block(i, block);
+        }
+        assert_eq!(blocks[0][..4], [0xff, 0x83, 0x1f, 0]);
+        // 1500 clusters end in byte 187, half used
+        assert_eq!(blocks[1][186 - 128..190 - 128], [0, 0xf0, 0xff, 0xff]);
+        assert_eq!(blocks[1][127], 0xff);
+        let mut copy = ClusterAlloc::<BitAlloc4K>::new(1500, 1024, 1);
+        for (i, block) in blocks.iter().enumerate() {
+            copy.read_bitmap_block(i, block);
+        }
+        assert_eq!(copy, fs);
+        // nothing frees the reserved clusters
+        copy.read_bitmap_block(0, &[0; 128]);
+        assert!(copy.in_use(0));
+    }
+}
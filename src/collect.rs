@@ -0,0 +1,128 @@
+//! `FromIterator` and `Extend`, building an allocator from its free bits or
+//! free ranges, e.g. `regions.iter().map(to_range).collect()`.
+//!
+//! Collecting starts from an allocator with all bits allocated; extending
+//! frees the bits given, keeping those already free.
+
+use crate::{AllocPolicy, BitAlloc, BitAlloc128, BitAlloc16, BitAlloc32, BitAlloc64};
+use crate::{BitAllocArray, BitAllocCascade, BitAllocConst};
+use core::iter::FromIterator;
+use core::ops::Range;
+
+macro_rules! collect {
+    ($(impl[$($g:tt)*] $ty:ty;)*) => {$(
+        impl<$($g)*> FromIterator<usize> for $ty {
+            fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+                let mut ba = Self::default();
+                ba.extend(iter);
+                ba
+            }
+        }
+
+        impl<$($g)*> FromIterator<Range<usize>> for $ty {
+            fn from_iter<I: IntoIterator<Item = Range<usize>>>(iter: I) -> Self {
+                let mut ba = Self::default();
+                ba.extend(iter);
+                ba
+            }
+        }
+
+        impl<$($g)*> Extend<usize> for $ty {
+            fn extend<I: IntoIterator<Item = usize>>(&mut self, iter: I) {
+                for key in iter {
+                    self.insert(key..key + 1);
+                }
+            }
+        }
+
+        impl<$($g)*> Extend<Range<usize>> for $ty {
+            fn extend<I: IntoIterator<Item = Range<usize>>>(&mut self, iter: I) {
+                for range in iter {
+                    self.insert(range);
+                }
+            }
+        }
+    )*};
+}
+
+collect! {
+    impl[T: BitAlloc, const N: usize, P: AllocPolicy] BitAllocCascade<T, N, P>;
+    impl[] BitAlloc16;
+    impl[] BitAlloc32;
+    impl[] BitAlloc64;
+    impl[] BitAlloc128;
+    impl[const BITS: usize, const WORDS: usize] BitAllocArray<BITS, WORDS>;
+    impl[const BITS: usize, A: BitAlloc] BitAllocConst<BITS, A>;
+}
+
+#[cfg(feature = "alloc")]
+collect! {
+    impl[T: crate::Zeroable] crate::BitAllocLazy16<T>;
+}
+
+#[cfg(feature = "alloc")]
+mod vec {
+    use crate::BitAllocVec;
+    use core::iter::FromIterator;
+    use core::ops::Range;
+
+    /// Grows the capacity to fit the largest bit given.
+    impl FromIterator<usize> for BitAllocVec {
+        fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+            let mut ba = BitAllocVec::with_capacity(0);
+            ba.extend(iter);
+            ba
+        }
+    }
+
+    /// Grows the capacity to fit the largest range given.
+    impl FromIterator<Range<usize>> for BitAllocVec {
+        fn from_iter<I: IntoIterator<Item = Range<usize>>>(iter: I) -> Self {
+            let mut ba = BitAllocVec::with_capacity(0);
+            ba.extend(iter);
+            ba
+        }
+    }
+
+    /// Grows the capacity to fit the largest bit given.
+    impl Extend<usize> for BitAllocVec {
+        fn extend<I: IntoIterator<Item = usize>>(&mut self, iter: I) {
+            self.extend(iter.into_iter().map(|key| key..key + 1));
+        }
+    }
+
+    /// Grows the capacity to fit the largest range given.
+    impl Extend<Range<usize>> for BitAllocVec {
+        fn extend<I: IntoIterator<Item = Range<usize>>>(&mut self, iter: I) {
+            for range in iter {
+                if range.end > self.capacity() {
+                    self.grow(range.end);
+                }
+                self.insert(range);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BitAlloc, BitAlloc16, BitAlloc4K};
+
+    #[test]
+    fn collect_free_bits() {
+        let ba: BitAlloc4K = [0..16, 1000..1100].iter().cloned().collect();
+        assert!(ba.free_ranges().eq([0..16, 1000..1100]));
+        let mut ba: BitAlloc16 = [3, 5, 5].iter().cloned().collect();
+        assert!(ba.iter_free().eq([3, 5]));
+        ba.extend(4..6);
+        assert!(ba.iter_free().eq([3, 4, 5]));
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn collect_vec() {
+        let ba: crate::BitAllocVec = [10..20, 100..130].iter().cloned().collect();
+        assert_eq!(ba.capacity(), 130);
+        assert_eq!(ba.free_count(), 40);
+    }
+}
@@ -0,0 +1,56 @@
+//! `defmt::Format`, behind the `defmt` feature.
+//!
+//! Only a short summary is logged: the capacity, the free count, and the
+//! first and last free bits.
+
+use crate::{AllocPolicy, BitAlloc, BitAlloc128, BitAlloc16, BitAlloc32, BitAlloc64};
+use crate::{BitAllocArray, BitAllocCascade};
+use defmt::{Format, Formatter};
+
+fn format_summary<A: BitAlloc>(ba: &A, name: &str, f: Formatter) {
+    defmt::write!(
+        f,
+        "{=str} {{ cap: {=usize}, free: {=usize}, first: {}, last: {} }}",
+        name,
+        A::CAP,
+        ba.free_count(),
+        ba.next(0),
+        ba.last()
+    )
+}
+
+impl<T: BitAlloc, const N: usize, P: AllocPolicy> Format for BitAllocCascade<T, N, P> {
+    fn format(&self, f: Formatter) {
+        format_summary(self, Self::NAME, f)
+    }
+}
+
+impl Format for BitAlloc16 {
+    fn format(&self, f: Formatter) {
+        format_summary(self, "BitAlloc16", f)
+    }
+}
+
+impl Format for BitAlloc32 {
+    fn format(&self, f: Formatter) {
+        format_summary(self, "BitAlloc32", f)
+    }
+}
+
+impl Format for BitAlloc64 {
+    fn format(&self, f: Formatter) {
+        format_summary(self, "BitAlloc64", f)
+    }
+}
+
+impl Format for BitAlloc128 {
+    fn format(&self, f: Formatter) {
+        format_summary(self, "BitAlloc128", f)
+    }
+}
+
+impl<const BITS: usize, const WORDS: usize> Format for BitAllocArray<BITS, WORDS> {
+    fn format(&self, f: Formatter) {
+        format_summary(self, "BitAllocArray", f)
+    }
+}
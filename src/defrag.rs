@@ -0,0 +1,199 @@
+use crate::{align_size, BitAlloc};
+use core::ops::Range;
+
+/// A plan to vacate a range of bits by moving its allocated bits, one at a
+/// time, to free bits outside it, e.g. to defragment physical memory.
+///
+/// The plan only picks the range; the moves are read off the allocator as
+/// they are made, so each one costs a couple of `next` lookups. The caller
+/// copies the contents of `src` to `dst` and then confirms the move:
+///
+/// ```
+/// use bitmap_allocator::{BitAlloc, BitAlloc4K, Defrag};
+///
+/// let mut ba = BitAlloc4K::default();
+/// ba.insert(0..1024);
+/// ba.remove(100..101);
+/// ba.remove(500..501);
+/// let plan = Defrag::free_run(&ba, 1000, 0).unwrap();
+/// while let Some((src, dst)) = plan.next_move(&ba) {
+///     // copy frame `src` to frame `dst`
+///     plan.confirm(&mut ba, src, dst);
+/// }
+/// assert!(ba.max_contiguous_len() >= 1000);
+/// ```
+///
+/// Allocations and frees in between are fine, as long as nothing allocates
+/// inside the range.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Defrag {
+    range: Range<usize>,
+}
+
+impl Defrag {
+    /// Plan a free run of `size` bits aligned to `1 << align_log2`, with the
+    /// fewest moves: the aligned range with the most free bits, the lowest
+    /// one on ties. None if there are fewer than `size` free bits.
+    ///
+    /// This counts the free bits of every aligned range, so it costs
+    /// `CAP >> align_log2` calls of `count_free_in`.
+    pub fn free_run<A: BitAlloc>(ba: &A, size: usize, align_log2: usize) -> Option<Self> {
+        let align = align_size(align_log2)?;
+        if size == 0 || size > ba.free_count() {
+            return None;
+        }
+        let mut best: Option<(usize, usize)> = None; // (free bits, base)
+        let mut base = 0;
+        while base + size <= ba.capacity() {
+            let free = ba.count_free_in(base..base + size);
+            if best.is_none_or(|(most, _)| free > most) {
+                best = Some((free, base));
+                if free == size {
+                    break;
+                }
+            }
+            base += align;
+        }
+        let (_, base) = best?;
+        Some(Defrag {
+            range: base..base + size,
+        })
+    }
+
+    /// Plan to move every allocated bit from `limit` on below it, e.g. to
+    /// shrink the usable memory. None if there are too few free bits below.
+    pub fn compact_below<A: BitAlloc>(ba: &A, limit: usize) -> Option<Self> {
+        let range = limit.min(ba.capacity())..ba.capacity();
+        let allocated = range.len() - ba.count_free_in(range.clone());
+        if allocated > ba.count_free_in(..range.start) {
+            return None;
+        }
+        Some(Defrag { range })
+    }
+
+    /// The range being vacated.
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    /// The number of moves left, i.e. of allocated bits in the range.
+    pub fn remaining<A: BitAlloc>(&self, ba: &A) -> usize {
+        self.range.len() - ba.count_free_in(self.range.clone())
+    }
+
+    /// Whether the range is entirely free.
+    pub fn is_done<A: BitAlloc>(&self, ba: &A) -> bool {
+        self.remaining(ba) == 0
+    }
+
+    /// The next move, as `(src, dst)`: the lowest allocated bit in the
+    /// range, and the lowest free bit outside it.
+    pub fn next_move<A: BitAlloc>(&self, ba: &A) -> Option<(usize, usize)> {
+        let src = ba
+            .next_allocated(self.range.start)
+            .filter(|&src| src < self.range.end)?;
+        let dst = self.free_outside(ba, 0)?;
+        Some((src, dst))
+    }
+
+    /// All the moves left, in order, without making them.
+    pub fn moves<'a, A: BitAlloc>(
+        &'a self,
+        ba: &'a A,
+    ) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let srcs = ba
+            .iter_allocated()
+            .skip_while(move |&src| src < self.range.start)
+            .take_while(move |&src| src < self.range.end);
+        let mut key = 0;
+        let dsts = core::iter::from_fn(move || {
+            let dst = self.free_outside(ba, key)?;
+            key = dst + 1;
+            Some(dst)
+        });
+        srcs.zip(dsts)
+    }
+
+    /// Record that the contents of `src` were moved to `dst`: allocate `dst`
+    /// and free `src`.
+    pub fn confirm<A: BitAlloc>(&self, ba: &mut A, src: usize, dst: usize) {
+        assert!(
+            self.range.contains(&src) && !ba.test(src),
+            "bad move source {}",
+            src
+        );
+        assert!(
+            !self.range.contains(&dst) && ba.test(dst),
+            "bad move destination {}",
+            dst
+        );
+        ba.remove(dst..dst + 1);
+        ba.dealloc(src);
+    }
+
+    /// The lowest free bit not less than `key` outside the range.
+    fn free_outside<A: BitAlloc>(&self, ba: &A, key: usize) -> Option<usize> {
+        ba.next(key)
+            .filter(|&dst| !self.range.contains(&dst))
+            .or_else(|| ba.next(key.max(self.range.end)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitAlloc256, BitAlloc4K};
+
+    #[test]
+    fn defrag_free_run() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(..);
+        let mut seed = 3u32;
+        for _ in 0..300 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let key = (seed >> 8) as usize % 4096;
+            ba.remove(key..key + 1);
+        }
+        let free = ba.free_count();
+        assert!(ba.max_contiguous_len() < 256);
+        let plan = Defrag::free_run(&ba, 256, 4).unwrap();
+        // no aligned range needs fewer moves
+        let fewest = (0..4096 - 255)
+            .step_by(16)
+            .map(|base| 256 - ba.count_free_in(base..base + 256))
+            .min();
+        assert_eq!(Some(plan.remaining(&ba)), fewest);
+        let moves = plan.moves(&ba).count();
+        assert_eq!(moves, plan.remaining(&ba));
+        let mut n = 0;
+        while let Some((src, dst)) = plan.next_move(&ba) {
+            plan.confirm(&mut ba, src, dst);
+            n += 1;
+        }
+        assert_eq!(n, moves);
+        assert!(plan.is_done(&ba));
+        assert_eq!(ba.count_free_in(plan.range()), 256);
+        assert_eq!(ba.free_count(), free);
+        assert_eq!(Defrag::free_run(&ba, free + 1, 0), None);
+    }
+
+    #[test]
+    fn defrag_compact_below() {
+        let mut ba = BitAlloc256::default();
+        ba.insert(0..100);
+        ba.remove(10..20);
+        ba.remove(50..51);
+        ba.insert(150..256);
+        assert_eq!(Defrag::compact_below(&ba, 50), None);
+        let plan = Defrag::compact_below(&ba, 100).unwrap();
+        assert!(plan.moves(&ba).take(3).eq([(100, 0), (101, 1), (102, 2)]));
+        assert_eq!(plan.remaining(&ba), 50);
+        while let Some((src, dst)) = plan.next_move(&ba) {
+            plan.confirm(&mut ba, src, dst);
+        }
+        // the lowest free bits were filled first
+        assert_eq!(ba.next(0), Some(61));
+        assert_eq!(ba.prev_allocated(255), Some(60));
+        assert_eq!(ba.free_count(), 195);
+    }
+}
@@ -0,0 +1,234 @@
+use crate::{BitAlloc, BitAllocError};
+use core::ops::Range;
+
+/// The object-safe part of [`BitAlloc`], implemented for every allocator,
+/// so that code can take a `&mut dyn DynBitAlloc` and work with any
+/// capacity without being generic over it.
+///
+/// The capacity is a method instead of `CAP`, and ranges are plain
+/// `Range`s. The methods of the trait are prefixed with `dyn_`, so as not
+/// to clash with those of `BitAlloc` on every allocator; a `dyn
+/// DynBitAlloc` also has them under the names of `BitAlloc`, e.g. `alloc`.
+pub trait DynBitAlloc {
+    /// The number of bits, as `BitAlloc::capacity` says.
+    fn dyn_capacity(&self) -> usize;
+
+    /// Allocate a free bit.
+    fn dyn_alloc(&mut self) -> Option<usize>;
+
+    /// Allocate a free block with a given size, and return the first bit position.
+    fn dyn_alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize>;
+
+    /// Free an allocated bit.
+    fn dyn_dealloc(&mut self, key: usize);
+
+    /// Free a block of `size` allocated bits starting at `base`.
+    fn dyn_dealloc_contiguous(&mut self, base: usize, size: usize);
+
+    /// Free an allocated bit, or report why it can't be freed.
+    fn dyn_try_dealloc(&mut self, key: usize) -> Result<(), BitAllocError>;
+
+    /// Mark bits in the range as unallocated (available).
+    fn dyn_insert(&mut self, range: Range<usize>);
+
+    /// Reverse of insert.
+    fn dyn_remove(&mut self, range: Range<usize>);
+
+    /// Whether there are free bits remaining
+    fn dyn_any(&self) -> bool;
+
+    /// Whether a specific bit is free
+    fn dyn_test(&self, key: usize) -> bool;
+
+    /// The number of free bits.
+    fn dyn_free_count(&self) -> usize;
+
+    /// The number of free bits in the range.
+    fn dyn_count_free_in(&self, range: Range<usize>) -> usize;
+
+    /// The length of the largest run of free bits.
+    fn dyn_max_contiguous_len(&self) -> usize;
+
+    /// Find a index not less than a given key, where the bit is free.
+    fn dyn_next(&self, key: usize) -> Option<usize>;
+
+    /// Find the greatest index not greater than a given key, where the bit is free.
+    fn dyn_prev(&self, key: usize) -> Option<usize>;
+
+    /// Find a index not less than a given key, where the bit is allocated.
+    fn dyn_next_allocated(&self, key: usize) -> Option<usize>;
+
+    /// Find the greatest index not greater than a given key, where the bit
+    /// is allocated.
+    fn dyn_prev_allocated(&self, key: usize) -> Option<usize>;
+}
+
+impl<A: BitAlloc> DynBitAlloc for A {
+    fn dyn_capacity(&self) -> usize {
+        BitAlloc::capacity(self)
+    }
+    fn dyn_alloc(&mut self) -> Option<usize> {
+        BitAlloc::alloc(self)
+    }
+    fn dyn_alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        BitAlloc::alloc_contiguous(self, size, align_log2)
+    }
+    fn dyn_dealloc(&mut self, key: usize) {
+        BitAlloc::dealloc(self, key)
+    }
+    fn dyn_dealloc_contiguous(&mut self, base: usize, size: usize) {
+        BitAlloc::dealloc_contiguous(self, base, size)
+    }
+    fn dyn_try_dealloc(&mut self, key: usize) -> Result<(), BitAllocError> {
+        BitAlloc::try_dealloc(self, key)
+    }
+    fn dyn_insert(&mut self, range: Range<usize>) {
+        BitAlloc::insert(self, range)
+    }
+    fn dyn_remove(&mut self, range: Range<usize>) {
+        BitAlloc::remove(self, range)
+    }
+    fn dyn_any(&self) -> bool {
+        BitAlloc::any(self)
+    }
+    fn dyn_test(&self, key: usize) -> bool {
+        BitAlloc::test(self, key)
+    }
+    fn dyn_free_count(&self) -> usize {
+        BitAlloc::free_count(self)
+    }
+    fn dyn_count_free_in(&self, range: Range<usize>) -> usize {
+        BitAlloc::count_free_in(self, range)
+    }
+    fn dyn_max_contiguous_len(&self) -> usize {
+        BitAlloc::max_contiguous_len(self)
+    }
+    fn dyn_next(&self, key: usize) -> Option<usize> {
+        BitAlloc::next(self, key)
+    }
+    fn dyn_prev(&self, key: usize) -> Option<usize> {
+        BitAlloc::prev(self, key)
+    }
+    fn dyn_next_allocated(&self, key: usize) -> Option<usize> {
+        BitAlloc::next_allocated(self, key)
+    }
+    fn dyn_prev_allocated(&self, key: usize) -> Option<usize> {
+        BitAlloc::prev_allocated(self, key)
+    }
+}
+
+/// The methods of `DynBitAlloc` under their usual names.
+impl dyn DynBitAlloc + '_ {
+    /// The number of bits, as `BitAlloc::capacity` says.
+    pub fn capacity(&self) -> usize {
+        self.dyn_capacity()
+    }
+
+    /// Allocate a free bit.
+    pub fn alloc(&mut self) -> Option<usize> {
+        self.dyn_alloc()
+    }
+
+    /// Allocate a free block with a given size, and return the first bit position.
+    pub fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        self.dyn_alloc_contiguous(size, align_log2)
+    }
+
+    /// Free an allocated bit.
+    pub fn dealloc(&mut self, key: usize) {
+        self.dyn_dealloc(key)
+    }
+
+    /// Free a block of `size` allocated bits starting at `base`.
+    pub fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        self.dyn_dealloc_contiguous(base, size)
+    }
+
+    /// Free an allocated bit, or report why it can't be freed.
+    pub fn try_dealloc(&mut self, key: usize) -> Result<(), BitAllocError> {
+        self.dyn_try_dealloc(key)
+    }
+
+    /// Mark bits in the range as unallocated (available).
+    pub fn insert(&mut self, range: Range<usize>) {
+        self.dyn_insert(range)
+    }
+
+    /// Reverse of insert.
+    pub fn remove(&mut self, range: Range<usize>) {
+        self.dyn_remove(range)
+    }
+
+    /// Whether there are free bits remaining
+    pub fn any(&self) -> bool {
+        self.dyn_any()
+    }
+
+    /// Whether a specific bit is free
+    pub fn test(&self, key: usize) -> bool {
+        self.dyn_test(key)
+    }
+
+    /// The number of free bits.
+    pub fn free_count(&self) -> usize {
+        self.dyn_free_count()
+    }
+
+    /// The number of free bits in the range.
+    pub fn count_free_in(&self, range: Range<usize>) -> usize {
+        self.dyn_count_free_in(range)
+    }
+
+    /// The length of the largest run of free bits.
+    pub fn max_contiguous_len(&self) -> usize {
+        self.dyn_max_contiguous_len()
+    }
+
+    /// Find a index not less than a given key, where the bit is free.
+    pub fn next(&self, key: usize) -> Option<usize> {
+        self.dyn_next(key)
+    }
+
+    /// Find the greatest index not greater than a given key, where the bit is free.
+    pub fn prev(&self, key: usize) -> Option<usize> {
+        self.dyn_prev(key)
+    }
+
+    /// Find a index not less than a given key, where the bit is allocated.
+    pub fn next_allocated(&self, key: usize) -> Option<usize> {
+        self.dyn_next_allocated(key)
+    }
+
+    /// Find the greatest index not greater than a given key, where the bit
+    /// is allocated.
+    pub fn prev_allocated(&self, key: usize) -> Option<usize> {
+        self.dyn_prev_allocated(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DynBitAlloc;
+    use crate::{BitAlloc, BitAlloc256, BitAlloc4K};
+
+    /// Free the upper half, and take a block from it.
+    fn upper_half(ba: &mut dyn DynBitAlloc) -> Option<usize> {
+        let cap = ba.capacity();
+        ba.insert(cap / 2..cap);
+        ba.alloc_contiguous(cap / 4, 0)
+    }
+
+    #[test]
+    fn dyn_capacities() {
+        let mut small = BitAlloc256::default();
+        let mut large = BitAlloc4K::default();
+        assert_eq!(upper_half(&mut small), Some(128));
+        assert_eq!(upper_half(&mut large), Some(2048));
+        let all: [&mut dyn DynBitAlloc; 2] = [&mut small, &mut large];
+        let free: usize = all.iter().map(|ba| ba.free_count()).sum();
+        assert_eq!(free, 64 + 1024);
+        // no clash with the methods of `BitAlloc`
+        assert_eq!(large.next(0), Some(3072));
+        assert_eq!(large.capacity(), BitAlloc4K::CAP);
+    }
+}
@@ -0,0 +1,373 @@
+use crate::{align_size, align_up, to_range};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::{Range, RangeBounds};
+
+/// A bit allocator whose capacity is chosen at runtime, stored on the heap.
+/// Enabled by the `alloc` feature.
+///
+/// The fixed-size types must be sized for the largest machine. This one
+/// takes only `capacity / 8` bytes plus about 1/63 of that for summaries.
+/// The tree has 64-way levels of `u64` words: bit `k` of word `j` on a level
+/// is set if word `64 * j + k` on the level below has any bit set, and the
+/// bottom level is the bitmap itself, 1 meaning free.
+///
+/// It can't implement [`BitAlloc`](crate::BitAlloc), whose capacity is a
+/// constant, but offers the same core operations with the same meaning.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct BitAllocVec {
+    cap: usize,
+    free: usize,
+    levels: Vec<Vec<u64>>, // levels[0] is the bitmap, the last one a single word
+}
+
+impl BitAllocVec {
+    /// Create an allocator of `bits` bits, all allocated.
+    pub fn with_capacity(bits: usize) -> Self {
+        let mut ba = BitAllocVec {
+            cap: bits,
+            free: 0,
+            levels: vec![vec![0; bits.div_ceil(64).max(1)]],
+        };
+        ba.build_summaries();
+        ba
+    }
+
+    /// Extend the capacity to `new_cap` bits, e.g. when memory is hot-plugged.
+    /// Existing bits keep their state; the new ones start allocated, to be
+    /// freed with `insert` once usable.
+    pub fn grow(&mut self, new_cap: usize) {
+        assert!(new_cap >= self.cap);
+        self.cap = new_cap;
+        self.levels[0].resize(new_cap.div_ceil(64).max(1), 0);
+        self.build_summaries();
+    }
+
+    /// Cut the capacity down to `new_cap` bits, e.g. when memory is
+    /// ballooned out. All bits from `new_cap` on must be free; otherwise
+    /// nothing changes and the first allocated one is returned.
+    pub fn shrink_to(&mut self, new_cap: usize) -> Result<(), usize> {
+        assert!(new_cap <= self.cap);
+        if let Some(key) = self.next_allocated(new_cap) {
+            return Err(key);
+        }
+        self.free -= self.cap - new_cap;
+        self.cap = new_cap;
+        let words = &mut self.levels[0];
+        words.truncate(new_cap.div_ceil(64).max(1));
+        if !new_cap.is_multiple_of(64) {
+            words[new_cap / 64] &= u64::MAX >> (64 - new_cap % 64);
+        } else if new_cap == 0 {
+            words[0] = 0;
+        }
+        self.build_summaries();
+        Ok(())
+    }
+
+    /// The number of bits.
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// The number of free bits.
+    pub fn free_count(&self) -> usize {
+        self.free
+    }
+
+    /// Whether there are free bits remaining.
+    pub fn any(&self) -> bool {
+        self.free != 0
+    }
+
+    /// Whether a specific bit is free.
+    pub fn test(&self, key: usize) -> bool {
+        key < self.cap && self.levels[0][key / 64] & 1 << (key % 64) != 0
+    }
+
+    /// Allocate a free bit, the highest one as `BitAlloc::alloc` does.
+    pub fn alloc(&mut self) -> Option<usize> {
+        let pos = self.peek_alloc()?;
+        self.update(pos / 64, |word| *word &= !(1 << (pos % 64)));
+        Some(pos)
+    }
+
+    /// Return the bit that the next `alloc` would allocate, without
+    /// allocating it.
+    pub fn peek_alloc(&self) -> Option<usize> {
+        let mut pos = 0;
+        for level in self.levels.iter().rev() {
+            let word = level[pos];
+            if word == 0 {
+                return None;
+            }
+            pos = pos * 64 + 63 - word.leading_zeros() as usize;
+        }
+        Some(pos)
+    }
+
+    /// Allocate a free block of `size` bits aligned to `1 << align_log2`,
+    /// the lowest one that fits, and return its first bit.
+    pub fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        let base = self.find_contiguous(size, align_log2)?;
+        self.remove(base..base + size);
+        Some(base)
+    }
+
+    /// Return the block `alloc_contiguous` would allocate, without
+    /// allocating it.
+    pub fn find_contiguous(&self, size: usize, align_log2: usize) -> Option<usize> {
+        if size == 0 || align_size(align_log2).is_none_or(|align| align > self.cap) {
+            return None;
+        }
+        let mut key = 0;
+        while let Some(start) = self.next(key) {
+            let end = self.next_allocated(start).unwrap_or(self.cap);
+            let base = align_up(start, align_log2);
+            if base.saturating_add(size) <= end {
+                return Some(base);
+            }
+            key = end;
+        }
+        None
+    }
+
+    /// Free an allocated bit.
+    pub fn dealloc(&mut self, key: usize) {
+        assert!(key < self.cap);
+        assert!(!self.test(key));
+        self.update(key / 64, |word| *word |= 1 << (key % 64));
+    }
+
+    /// Mark bits in the range as free.
+    pub fn insert(&mut self, range: impl RangeBounds<usize>) {
+        self.for_range(range, |word, mask| *word |= mask);
+    }
+
+    /// Mark bits in the range as allocated.
+    pub fn remove(&mut self, range: impl RangeBounds<usize>) {
+        self.for_range(range, |word, mask| *word &= !mask);
+    }
+
+    /// Find the first free bit not less than `key`.
+    pub fn next(&self, key: usize) -> Option<usize> {
+        if key >= self.cap {
+            return None;
+        }
+        self.next_set(0, key)
+    }
+
+    /// Find the first allocated bit not less than `key`.
+    pub fn next_allocated(&self, key: usize) -> Option<usize> {
+        if key >= self.cap {
+            return None;
+        }
+        let words = &self.levels[0];
+        let first = !words[key / 64] & u64::MAX << (key % 64);
+        let (w, word) = core::iter::once((key / 64, first))
+            .chain((key / 64 + 1..words.len()).map(|w| (w, !words[w])))
+            .find(|&(_, word)| word != 0)?;
+        Some(w * 64 + word.trailing_zeros() as usize).filter(|&i| i < self.cap)
+    }
+
+    /// Iterate over all free bits in ascending order.
+    pub fn iter_free(&self) -> impl Iterator<Item = usize> + '_ {
+        let mut key = 0;
+        core::iter::from_fn(move || {
+            let res = self.next(key)?;
+            key = res + 1;
+            Some(res)
+        })
+    }
+
+    /// The first set bit at `pos` or after on `level`, using the levels
+    /// above to skip empty words.
+    fn next_set(&self, level: usize, pos: usize) -> Option<usize> {
+        let words = &self.levels[level];
+        let w = pos / 64;
+        if w >= words.len() {
+            return None;
+        }
+        let word = words[w] & u64::MAX << (pos % 64);
+        if word != 0 {
+            return Some(w * 64 + word.trailing_zeros() as usize);
+        }
+        let w = if level + 1 < self.levels.len() {
+            self.next_set(level + 1, w + 1)?
+        } else {
+            (w + 1..words.len()).find(|&w| words[w] != 0)?
+        };
+        Some(w * 64 + words[w].trailing_zeros() as usize)
+    }
+
+    /// Recompute all levels above the bitmap.
+    fn build_summaries(&mut self) {
+        self.levels.truncate(1);
+        while self.levels.last().unwrap().len() > 1 {
+            let below = self.levels.last().unwrap();
+            let mut level = vec![0; below.len().div_ceil(64)];
+            for (w, _) in below.iter().enumerate().filter(|&(_, &word)| word != 0) {
+                level[w / 64] |= 1 << (w % 64);
+            }
+            self.levels.push(level);
+        }
+    }
+
+    /// Apply `f` to bitmap word `w`, then fix the free count and the levels
+    /// above, stopping as soon as a summary bit does not change.
+    fn update(&mut self, w: usize, f: impl FnOnce(&mut u64)) {
+        let word = &mut self.levels[0][w];
+        let was_any = *word != 0;
+        self.free -= word.count_ones() as usize;
+        f(word);
+        self.free += word.count_ones() as usize;
+        let mut any = *word != 0;
+        if any == was_any {
+            return;
+        }
+        let mut pos = w;
+        for level in self.levels[1..].iter_mut() {
+            let word = &mut level[pos / 64];
+            let was_any = *word != 0;
+            if any {
+                *word |= 1 << (pos % 64);
+            } else {
+                *word &= !(1 << (pos % 64));
+            }
+            any = *word != 0;
+            if any == was_any {
+                return;
+            }
+            pos /= 64;
+        }
+    }
+
+    fn for_range(&mut self, range: impl RangeBounds<usize>, f: impl Fn(&mut u64, u64)) {
+        let Range { start, end } = to_range(range, self.cap);
+        if start == end {
+            return;
+        }
+        assert!(start < end);
+        assert!(end <= self.cap);
+        for w in start / 64..=(end - 1) / 64 {
+            let begin = if start / 64 == w { start % 64 } else { 0 };
+            let end = if end / 64 == w { end % 64 } else { 64 };
+            let mask = u64::MAX >> (64 - (end - begin)) << begin;
+            self.update(w, |word| f(word, mask));
+        }
+    }
+}
+
+impl fmt::Debug for BitAllocVec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BitAllocVec")
+            .field("cap", &self.cap)
+            .field("free", &self.free)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitAlloc, BitAlloc1M};
+
+    #[test]
+    fn bitalloc_vec() {
+        let mut ba = BitAllocVec::with_capacity(100_000);
+        assert_eq!(ba.capacity(), 100_000);
+        assert_eq!(ba.levels.len(), 3);
+        assert_eq!(ba.alloc(), None);
+        ba.insert(..);
+        assert_eq!(ba.free_count(), 100_000);
+        assert_eq!(ba.peek_alloc(), Some(99_999));
+        assert_eq!(ba.alloc(), Some(99_999));
+        assert_eq!(ba.next_allocated(0), Some(99_999));
+        assert_eq!(ba.next(99_999), None);
+        ba.remove(10..99_000);
+        assert_eq!(ba.next(10), Some(99_000));
+        assert_eq!(ba.alloc_contiguous(16, 4), Some(99_008));
+        assert_eq!(ba.alloc_contiguous(10, 0), Some(0));
+        assert_eq!(ba.alloc_contiguous(1000, 0), None);
+        ba.dealloc(5);
+        assert!(ba.test(5));
+        assert!(!ba.test(100_000));
+        assert_eq!(ba.iter_free().count(), ba.free_count());
+    }
+
+    #[test]
+    fn bitalloc_vec_reference() {
+        // behaves just like a cascade of the same size
+        let mut ba = BitAllocVec::with_capacity(BitAlloc1M::CAP);
+        extern crate std;
+        let mut reference = std::boxed::Box::new(BitAlloc1M::default());
+        let mut seed = 0x600d_f00d_u32;
+        for _ in 0..300 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let start = (seed >> 4) as usize % BitAlloc1M::CAP;
+            let end = (start + (seed >> 12) as usize % 20000).min(BitAlloc1M::CAP);
+            match seed & 3 {
+                0 => {
+                    ba.remove(start..end);
+                    reference.remove(start..end);
+                }
+                1 => assert_eq!(ba.alloc(), reference.alloc()),
+                _ => {
+                    ba.insert(start..end);
+                    reference.insert(start..end);
+                }
+            }
+            assert_eq!(ba.free_count(), reference.free_count());
+            assert_eq!(ba.next(start), reference.next(start));
+            assert_eq!(ba.next_allocated(start), reference.next_allocated(start));
+        }
+        for (size, align_log2) in [(1, 0), (100, 3), (5000, 12)] {
+            assert_eq!(
+                ba.alloc_contiguous(size, align_log2),
+                reference.alloc_contiguous(size, align_log2)
+            );
+        }
+    }
+
+    #[test]
+    fn bitalloc_vec_resize() {
+        let mut ba = BitAllocVec::with_capacity(100);
+        ba.insert(..);
+        ba.remove(10..20);
+        ba.grow(10_000);
+        assert_eq!(ba.levels.len(), 3);
+        assert_eq!(ba.free_count(), 90);
+        assert_eq!(ba.next_allocated(20), Some(100));
+        ba.insert(100..10_000);
+        assert_eq!(ba.alloc(), Some(9999));
+        assert_eq!(ba.shrink_to(50), Err(9999));
+        assert_eq!(ba.free_count(), 9989);
+        ba.dealloc(9999);
+        assert_eq!(ba.shrink_to(64), Ok(()));
+        assert_eq!(ba.levels.len(), 1);
+        assert_eq!(ba.free_count(), 54);
+        assert_eq!(ba.alloc(), Some(63));
+        assert_eq!(ba.shrink_to(15), Err(15));
+        ba.remove(..5);
+        ba.insert(5..);
+        assert_eq!(ba.shrink_to(5), Ok(()));
+        assert_eq!(ba.free_count(), 0);
+        ba.insert(..);
+        assert_eq!(ba.iter_free().count(), 5);
+        assert_eq!(ba.shrink_to(0), Ok(()));
+        assert_eq!(ba.free_count(), 0);
+        assert_eq!(ba.alloc(), None);
+    }
+
+    #[test]
+    fn bitalloc_vec_small() {
+        let mut ba = BitAllocVec::with_capacity(0);
+        assert_eq!(ba.alloc(), None);
+        ba.insert(0..0);
+        let mut ba = BitAllocVec::with_capacity(3);
+        ba.insert(..);
+        assert_eq!(ba.free_count(), 3);
+        assert_eq!(ba.next_allocated(0), None);
+        assert_eq!(ba.alloc(), Some(2));
+    }
+}
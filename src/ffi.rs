@@ -0,0 +1,303 @@
+//! A C API, enabled by the `ffi` feature, and declared in
+//! `include/bitmap_allocator.h`.
+//!
+//! The allocator lives in memory supplied by the caller: `bitalloc_size`
+//! tells how many bytes one of a given capacity takes, and
+//! `bitalloc_new_in` builds it there, returning an opaque handle. All bits
+//! start allocated, as in Rust. Bits are numbered from 0 to the capacity,
+//! rounded up internally to the next of 4K, 64K, 1M, 16M or 256M bits,
+//! whose extra bits are never handed out.
+//!
+//! Nothing panics across the boundary: out-of-range arguments and frees
+//! of free bits are refused, reported by a `false` return. Allocations
+//! return `SIZE_MAX` when there is no room.
+//!
+//! # Safety
+//!
+//! Every function taking a handle requires that it was returned by
+//! `bitalloc_new_in`, that its memory is still valid, and that no other
+//! call on the same handle runs at the same time.
+
+use crate::{BitAlloc, BitAlloc16M, BitAlloc1M, BitAlloc256M, BitAlloc4K, BitAlloc64K};
+use core::alloc::Layout;
+use core::ptr;
+
+/// The value allocations return on failure, `SIZE_MAX` in C.
+pub const BITALLOC_NONE: usize = usize::MAX;
+
+/// The opaque handle of an allocator built by `bitalloc_new_in`, at the
+/// start of its memory.
+#[repr(C)]
+pub struct BitAllocHandle {
+    kind: usize,
+    cap: usize,
+}
+
+/// The handle followed by the allocator of its kind.
+#[repr(C)]
+struct Slot<A> {
+    handle: BitAllocHandle,
+    ba: A,
+}
+
+/// The capacities of the allocator kinds, in order.
+const KINDS: [usize; 5] = [
+    BitAlloc4K::CAP,
+    BitAlloc64K::CAP,
+    BitAlloc1M::CAP,
+    BitAlloc16M::CAP,
+    BitAlloc256M::CAP,
+];
+
+/// The layout of the memory of an allocator of `kind`.
+fn layout(kind: usize) -> Layout {
+    match kind {
+        0 => Layout::new::<Slot<BitAlloc4K>>(),
+        1 => Layout::new::<Slot<BitAlloc64K>>(),
+        2 => Layout::new::<Slot<BitAlloc1M>>(),
+        3 => Layout::new::<Slot<BitAlloc16M>>(),
+        _ => Layout::new::<Slot<BitAlloc256M>>(),
+    }
+}
+
+/// Bind `$ba` to the allocator of the handle in `$body`.
+macro_rules! with_alloc {
+    ($handle:expr, $ba:ident => $body:expr) => {{
+        let handle: *mut BitAllocHandle = $handle;
+        match (*handle).kind {
+            0 => {
+                let $ba = &mut (*(handle as *mut Slot<BitAlloc4K>)).ba;
+                $body
+            }
+            1 => {
+                let $ba = &mut (*(handle as *mut Slot<BitAlloc64K>)).ba;
+                $body
+            }
+            2 => {
+                let $ba = &mut (*(handle as *mut Slot<BitAlloc1M>)).ba;
+                $body
+            }
+            3 => {
+                let $ba = &mut (*(handle as *mut Slot<BitAlloc16M>)).ba;
+                $body
+            }
+            _ => {
+                let $ba = &mut (*(handle as *mut Slot<BitAlloc256M>)).ba;
+                $body
+            }
+        }
+    }};
+}
+
+/// Whether `start..end` is a valid range of bits of the handle.
+unsafe fn in_range(handle: *const BitAllocHandle, start: usize, end: usize) -> bool {
+    start <= end && end <= (*handle).cap
+}
+
+/// The number of bytes an allocator of `cap` bits takes, or 0 if `cap` is
+/// more than 256M. Its memory must be aligned to `bitalloc_align()`.
+#[no_mangle]
+pub extern "C" fn bitalloc_size(cap: usize) -> usize {
+    match KINDS.iter().position(|&kind_cap| cap <= kind_cap) {
+        Some(kind) => layout(kind).size(),
+        None => 0,
+    }
+}
+
+/// The alignment the memory of an allocator needs.
+#[no_mangle]
+pub extern "C" fn bitalloc_align() -> usize {
+    (0..KINDS.len())
+        .map(|kind| layout(kind).align())
+        .max()
+        .unwrap()
+}
+
+/// Build an allocator of `cap` bits in the `len` bytes at `mem`, all of
+/// them allocated, and return its handle, or null if the memory is too
+/// small or misaligned, or `cap` is too large.
+///
+/// # Safety
+///
+/// The memory must be valid for reads and writes, and used for nothing
+/// else for as long as the handle is used.
+#[no_mangle]
+pub unsafe extern "C" fn bitalloc_new_in(
+    mem: *mut u8,
+    len: usize,
+    cap: usize,
+) -> *mut BitAllocHandle {
+    let kind = match KINDS.iter().position(|&kind_cap| cap <= kind_cap) {
+        Some(kind) => kind,
+        None => return ptr::null_mut(),
+    };
+    let layout = layout(kind);
+    if mem.is_null() || len < layout.size() || !(mem as usize).is_multiple_of(bitalloc_align()) {
+        return ptr::null_mut();
+    }
+    // zeroed memory is an allocator with every bit allocated
+    ptr::write_bytes(mem, 0, layout.size());
+    let handle = mem as *mut BitAllocHandle;
+    ptr::write(handle, BitAllocHandle { kind, cap });
+    handle
+}
+
+/// The capacity the allocator was built with.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn bitalloc_capacity(handle: *const BitAllocHandle) -> usize {
+    (*handle).cap
+}
+
+/// Allocate a free bit, or return `SIZE_MAX`.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn bitalloc_alloc(handle: *mut BitAllocHandle) -> usize {
+    with_alloc!(handle, ba => ba.alloc().unwrap_or(BITALLOC_NONE))
+}
+
+/// Free an allocated bit. Returns `false`, changing nothing, if it is out
+/// of range or already free.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn bitalloc_free(handle: *mut BitAllocHandle, key: usize) -> bool {
+    in_range(handle, key, key.saturating_add(1))
+        && with_alloc!(handle, ba => ba.try_dealloc(key).is_ok())
+}
+
+/// Allocate `size` contiguous bits aligned to `1 << align_log2`, and return
+/// the first one, or `SIZE_MAX`.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn bitalloc_alloc_contiguous(
+    handle: *mut BitAllocHandle,
+    size: usize,
+    align_log2: usize,
+) -> usize {
+    with_alloc!(handle, ba => ba.alloc_contiguous(size, align_log2).unwrap_or(BITALLOC_NONE))
+}
+
+/// Free `size` contiguous bits starting at `base`. Returns `false`,
+/// changing nothing, if some of them are out of range or already free.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn bitalloc_free_contiguous(
+    handle: *mut BitAllocHandle,
+    base: usize,
+    size: usize,
+) -> bool {
+    let end = base.saturating_add(size);
+    in_range(handle, base, end) && with_alloc!(handle, ba => ba.try_insert(base..end).is_ok())
+}
+
+/// Mark the bits from `start` up to `end`, excluded, as free. Returns
+/// `false`, changing nothing, if the range is out of bounds.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn bitalloc_insert(
+    handle: *mut BitAllocHandle,
+    start: usize,
+    end: usize,
+) -> bool {
+    in_range(handle, start, end)
+        && with_alloc!(handle, ba => {
+            ba.insert(start..end);
+            true
+        })
+}
+
+/// Mark the bits from `start` up to `end`, excluded, as allocated. Returns
+/// `false`, changing nothing, if the range is out of bounds.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn bitalloc_remove(
+    handle: *mut BitAllocHandle,
+    start: usize,
+    end: usize,
+) -> bool {
+    in_range(handle, start, end)
+        && with_alloc!(handle, ba => {
+            ba.remove(start..end);
+            true
+        })
+}
+
+/// Whether a bit is free. Bits out of range are not.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn bitalloc_test(handle: *mut BitAllocHandle, key: usize) -> bool {
+    key < (*handle).cap && with_alloc!(handle, ba => ba.test(key))
+}
+
+/// The number of free bits.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn bitalloc_free_count(handle: *mut BitAllocHandle) -> usize {
+    with_alloc!(handle, ba => ba.free_count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern crate std;
+    use std::vec;
+
+    #[test]
+    fn ffi_roundtrip() {
+        assert_eq!(bitalloc_size(usize::MAX), 0);
+        assert!(bitalloc_size(5000) > bitalloc_size(4096));
+        let mut mem = vec![0u64; bitalloc_size(5000) / 8 + 1];
+        unsafe {
+            let mem = mem.as_mut_ptr() as *mut u8;
+            assert!(bitalloc_new_in(mem, 100, 5000).is_null());
+            let h = bitalloc_new_in(mem, bitalloc_size(5000), 5000);
+            assert!(!h.is_null());
+            assert_eq!(bitalloc_capacity(h), 5000);
+            assert_eq!(bitalloc_alloc(h), BITALLOC_NONE);
+            // the bits past the capacity stay out of reach
+            assert!(!bitalloc_insert(h, 0, 5001));
+            assert!(bitalloc_insert(h, 0, 5000));
+            assert_eq!(bitalloc_free_count(h), 5000);
+            let key = bitalloc_alloc(h);
+            assert!(key < 5000 && !bitalloc_test(h, key));
+            assert!(bitalloc_free(h, key));
+            assert!(!bitalloc_free(h, key));
+            assert!(!bitalloc_free(h, 6000));
+            let base = bitalloc_alloc_contiguous(h, 100, 4);
+            assert_eq!(base % 16, 0);
+            assert!(!bitalloc_free_contiguous(h, base, 101));
+            assert!(bitalloc_free_contiguous(h, base, 100));
+            assert!(bitalloc_remove(h, 10, 20));
+            assert_eq!(bitalloc_free_count(h), 4990);
+            assert!(!bitalloc_test(h, 5001));
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use crate::{debug_summary, to_range, AllocPolicy, BitAlloc, WORD_BITS};
+use core::fmt;
+use core::ops::{Range, RangeBounds};
+
+/// An allocator of exactly `BITS` bits, built on a larger allocator `A`,
+/// e.g. `BitAllocConst<1536, BitAlloc4K>` for 1536 IRQ vectors.
+///
+/// Unlike [`BitAllocArray`](crate::BitAllocArray), this keeps the summaries
+/// of the cascade, so it suits large sizes like 100_000 inodes. The bits of
+/// `A` past `BITS` are never free, and ranges are checked against `BITS`.
+/// `BITS <= A::CAP` is checked at compile time.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct BitAllocConst<const BITS: usize, A: BitAlloc> {
+    inner: A,
+}
+
+impl<const BITS: usize, A: BitAlloc> BitAllocConst<BITS, A> {
+    /// Create an allocator with all bits allocated, e.g. for a `static`.
+    pub const fn new() -> Self {
+        Self::DEFAULT
+    }
+
+    /// The underlying allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    fn check(range: &Range<usize>) {
+        if range.start != range.end {
+            assert!(range.start < range.end);
+            assert!(range.end <= BITS);
+        }
+    }
+}
+
+impl<const BITS: usize, A: BitAlloc> Default for BitAllocConst<BITS, A> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<const BITS: usize, A: BitAlloc> fmt::Debug for BitAllocConst<BITS, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        debug_summary(self, "BitAllocConst", f)
+    }
+}
+
+impl<const BITS: usize, A: BitAlloc> fmt::Binary for BitAllocConst<BITS, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Binary::fmt(&self.fmt_range(..), f)
+    }
+}
+
+impl<const BITS: usize, A: BitAlloc> fmt::LowerHex for BitAllocConst<BITS, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.fmt_range(..), f)
+    }
+}
+
+impl<const BITS: usize, A: BitAlloc> BitAlloc for BitAllocConst<BITS, A> {
+    const CAP: usize = BITS;
+
+    const DEFAULT: Self = {
+        assert!(BITS <= A::CAP, "BITS must not exceed the capacity of A");
+        BitAllocConst { inner: A::DEFAULT }
+    };
+
+    fn alloc(&mut self) -> Option<usize> {
+        self.inner.alloc()
+    }
+    fn alloc_with<P: AllocPolicy>(&mut self) -> Option<usize> {
+        self.inner.alloc_with::<P>()
+    }
+    fn alloc_many(&mut self, out: &mut [usize]) -> usize {
+        self.inner.alloc_many(out)
+    }
+    fn peek_alloc(&self) -> Option<usize> {
+        self.inner.peek_alloc()
+    }
+    fn peek_with<P: AllocPolicy>(&self) -> Option<usize> {
+        self.inner.peek_with::<P>()
+    }
+    fn peek_full<P: AllocPolicy>() -> usize {
+        // only the first `BITS` bits of `A` are free, which `A::peek_full`
+        // does not know about
+        let mut full = Self::DEFAULT;
+        full.insert(..);
+        full.peek_with::<P>().unwrap()
+    }
+    fn subtree_cap(depth: usize) -> usize {
+        A::subtree_cap(depth).min(BITS)
+    }
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        self.inner.alloc_contiguous(size, align_log2)
+    }
+    fn find_contiguous(&self, size: usize, align_log2: usize) -> Option<usize> {
+        self.inner.find_contiguous(size, align_log2)
+    }
+    fn alloc_aligned(&mut self, align_log2: usize) -> Option<usize> {
+        self.inner.alloc_aligned(align_log2)
+    }
+    fn next(&self, key: usize) -> Option<usize> {
+        self.inner.next(key)
+    }
+    fn prev(&self, key: usize) -> Option<usize> {
+        self.inner.prev(key)
+    }
+    fn dealloc(&mut self, key: usize) {
+        assert!(key < BITS);
+        self.inner.dealloc(key)
+    }
+    fn insert(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, BITS);
+        Self::check(&range);
+        self.inner.insert(range)
+    }
+    fn remove(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, BITS);
+        Self::check(&range);
+        self.inner.remove(range)
+    }
+    fn any(&self) -> bool {
+        self.inner.any()
+    }
+    fn test(&self, key: usize) -> bool {
+        self.inner.test(key)
+    }
+    fn free_count(&self) -> usize {
+        self.inner.free_count()
+    }
+    fn count_free_in(&self, range: impl RangeBounds<usize>) -> usize {
+        self.inner.count_free_in(to_range(range, BITS))
+    }
+    fn max_contiguous(&self) -> Option<Range<usize>> {
+        self.inner.max_contiguous()
+    }
+    fn next_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.next_allocated(key).filter(|&i| i < BITS)
+    }
+    fn prev_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.prev_allocated(key.min(BITS.checked_sub(1)?))
+    }
+    fn raw_word(&self, w: usize) -> usize {
+        self.inner.raw_word(w)
+    }
+    fn next_difference(&self, other: &Self, key: usize) -> Option<usize> {
+        self.inner.next_difference(&other.inner, key)
+    }
+    fn is_subset_of(&self, other: &Self) -> bool {
+        self.inner.is_subset_of(&other.inner)
+    }
+    fn is_disjoint_with(&self, other: &Self) -> bool {
+        self.inner.is_disjoint_with(&other.inner)
+    }
+    fn union_with(&mut self, other: &Self) {
+        self.inner.union_with(&other.inner)
+    }
+    fn intersect_with(&mut self, other: &Self) {
+        self.inner.intersect_with(&other.inner)
+    }
+    fn subtract(&mut self, other: &Self) {
+        self.inner.subtract(&other.inner)
+    }
+    fn write_raw_word(&mut self, w: usize, word: usize) {
+        let valid = BITS.saturating_sub(w * WORD_BITS);
+        let mask = if valid >= WORD_BITS {
+            !0
+        } else {
+            (1 << valid) - 1
+        };
+        self.inner.write_raw_word(w, word & mask)
+    }
+    fn rebuild(&mut self) {
+        self.inner.rebuild()
+    }
+    fn iter_free(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.inner.iter_free()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitAlloc16, BitAlloc4K};
+
+    #[test]
+    fn bitalloc_const() {
+        let mut ba = BitAllocConst::<1536, BitAlloc4K>::default();
+        ba.insert(..);
+        assert_eq!(ba.free_count(), 1536);
+        assert_eq!(ba.next_allocated(0), None);
+        assert_eq!(ba.prev_allocated(4000), None);
+        assert_eq!(ba.alloc(), Some(1535));
+        assert_eq!(ba.last(), Some(1534));
+        assert_eq!(ba.prev_allocated(4000), Some(1535));
+        assert!(ba.iter_allocated().eq([1535]));
+        ba.remove(..1500);
+        assert_eq!(ba.free_ranges().next(), Some(1500..1535));
+        assert_eq!(ba.alloc_contiguous(64, 0), None);
+        assert_eq!(ba.max_contiguous_len(), 35);
+        ba.insert(2000..2000);
+    }
+
+    #[test]
+    fn bitalloc_const_raw_words() {
+        // bits past BITS stay unavailable even when written as raw words
+        let ba = BitAllocConst::<10, BitAlloc16>::from_raw_words(&[!0]);
+        assert_eq!(ba.free_count(), 10);
+        assert_eq!(ba.raw_word(0), 0x3ff);
+        assert_eq!(ba.peek_alloc(), Some(9));
+    }
+
+    #[test]
+    #[should_panic]
+    fn bitalloc_const_out_of_range() {
+        let mut ba = BitAllocConst::<10, BitAlloc16>::default();
+        ba.insert(5..11);
+    }
+}
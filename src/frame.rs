@@ -0,0 +1,274 @@
+use crate::{align_size, BitAlloc};
+use core::ops::Range;
+
+/// A thin wrapper that maps bits of a [`BitAlloc`] to physical frames.
+///
+/// Bit `i` stands for the frame at `base_addr + i * frame_size`.
+///
+/// With the `x86_64` feature, it implements `FrameAllocator` and
+/// `FrameDeallocator` of the `x86_64` crate for every page size.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FrameAlloc<A: BitAlloc> {
+    base_addr: usize,
+    frame_size: usize,
+    inner: A,
+}
+
+impl<A: BitAlloc> FrameAlloc<A> {
+    /// Wrap an allocator. `frame_size` must be a power of two.
+    pub const fn new(base_addr: usize, frame_size: usize, inner: A) -> Self {
+        assert!(frame_size.is_power_of_two());
+        FrameAlloc {
+            base_addr,
+            frame_size,
+            inner,
+        }
+    }
+
+    /// Build an allocator of the frames from address 0 from a firmware
+    /// memory map, as `(base, len, usable)` descriptors in bytes like those
+    /// of UEFI, multiboot2 or a device tree.
+    ///
+    /// Whole frames within usable regions are free, and everything else is
+    /// allocated: holes, the partial frames at the edges of usable regions,
+    /// and any frame overlapping a region that is not usable, even where it
+    /// overlaps a usable one too. Memory past the capacity of `A` is left out.
+    pub fn from_memory_map<I>(frame_size: usize, map: I) -> Self
+    where
+        I: IntoIterator<Item = (usize, usize, bool)>,
+        I::IntoIter: Clone,
+    {
+        let map = map.into_iter();
+        let mut fa = FrameAlloc::new(0, frame_size, A::DEFAULT);
+        for (base, len, _) in map.clone().filter(|&(_, _, usable)| usable) {
+            fa.mark_region_usable(base, len);
+        }
+        for (base, len, _) in map.filter(|&(_, _, usable)| !usable) {
+            fa.mark_region_reserved(base, len);
+        }
+        fa
+    }
+
+    /// The address of frame 0.
+    pub fn base_addr(&self) -> usize {
+        self.base_addr
+    }
+
+    /// The size of a frame in bytes.
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    /// The underlying bit allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// The underlying bit allocator, mutably.
+    pub fn inner_mut(&mut self) -> &mut A {
+        &mut self.inner
+    }
+
+    /// Translate a bit index to the address of its frame.
+    pub fn index_to_addr(&self, index: usize) -> usize {
+        self.base_addr + index * self.frame_size
+    }
+
+    /// Translate a frame address back to its bit index.
+    pub fn addr_to_index(&self, addr: usize) -> usize {
+        assert!(addr >= self.base_addr);
+        let offset = addr - self.base_addr;
+        assert_eq!(offset % self.frame_size, 0, "unaligned frame address");
+        offset / self.frame_size
+    }
+
+    /// Mark the frames lying entirely within `len` bytes at `addr` as free,
+    /// e.g. a usable region of the firmware memory map.
+    ///
+    /// The region may start and end anywhere: partial frames at its edges
+    /// stay allocated, as does anything outside the allocator.
+    pub fn mark_region_usable(&mut self, addr: usize, len: usize) {
+        let frames = self.frames_in(addr, len, false);
+        self.inner.insert(frames);
+    }
+
+    /// Mark every frame overlapping `len` bytes at `addr` as allocated, e.g.
+    /// the kernel image. Partial frames at its edges are allocated too.
+    pub fn mark_region_reserved(&mut self, addr: usize, len: usize) {
+        let frames = self.frames_in(addr, len, true);
+        self.inner.remove(frames);
+    }
+
+    /// The frames within the region, or overlapping it if `outward`, clamped
+    /// to those of the allocator.
+    fn frames_in(&self, addr: usize, len: usize, outward: bool) -> Range<usize> {
+        let round = if outward { self.frame_size - 1 } else { 0 };
+        let end = addr.saturating_add(len).saturating_sub(self.base_addr);
+        let start = addr.saturating_sub(self.base_addr);
+        let start = start.saturating_add(self.frame_size - 1 - round) / self.frame_size;
+        let end = (end.saturating_add(round) / self.frame_size).min(A::CAP);
+        start.min(end)..end
+    }
+
+    /// Allocate a free frame and return its address.
+    pub fn alloc_frame(&mut self) -> Option<usize> {
+        self.inner.alloc().map(|i| self.index_to_addr(i))
+    }
+
+    /// Free an allocated frame by its address.
+    pub fn dealloc_frame(&mut self, addr: usize) {
+        let index = self.addr_to_index(addr);
+        self.inner.dealloc(index);
+    }
+
+    /// Allocate `count` contiguous frames whose first address is aligned to
+    /// `align_bytes`, and return that address.
+    ///
+    /// `align_bytes` must be a power of two. It applies to the physical
+    /// address, whatever `base_addr` is, as long as that is a multiple of
+    /// `frame_size`.
+    pub fn alloc_contiguous_frames(&mut self, count: usize, align_bytes: usize) -> Option<usize> {
+        assert!(align_bytes.is_power_of_two());
+        let align_log2 = (align_bytes.max(self.frame_size) / self.frame_size).trailing_zeros();
+        self.alloc_frames_aligned(count, align_log2 as usize)
+    }
+
+    /// Free `count` contiguous frames starting at `addr`, as allocated by
+    /// `alloc_contiguous_frames`.
+    pub fn dealloc_contiguous_frames(&mut self, addr: usize, count: usize) {
+        let index = self.addr_to_index(addr);
+        self.inner.dealloc_contiguous(index, count);
+    }
+
+    /// Allocate a naturally aligned block of `1 << order` frames, e.g. a
+    /// 2 MiB page with 4 KiB frames and `order` 9, or a 1 GiB page with
+    /// `order` 18, and return its address.
+    ///
+    /// The block is aligned to its size in physical memory, however
+    /// `base_addr` is aligned.
+    pub fn alloc_huge(&mut self, order: usize) -> Option<usize> {
+        let count = align_size(order)?;
+        self.alloc_frames_aligned(count, order)
+    }
+
+    /// Free a block allocated by `alloc_huge` with the same `order`.
+    pub fn dealloc_huge(&mut self, addr: usize, order: usize) {
+        self.dealloc_contiguous_frames(addr, 1 << order);
+    }
+
+    /// Allocate `count` frames whose first frame number, counted from
+    /// address 0, is a multiple of `1 << align_log2`.
+    fn alloc_frames_aligned(&mut self, count: usize, align_log2: usize) -> Option<usize> {
+        assert_eq!(
+            self.base_addr % self.frame_size,
+            0,
+            "base address is not aligned to the frame size"
+        );
+        if count == 0 || align_size(align_log2).is_none() {
+            return None;
+        }
+        // align frame numbers rather than bit indices, so the alignment is
+        // that of physical addresses
+        let first = self.base_addr / self.frame_size;
+        let index = self
+            .inner
+            .alloc_contiguous_offset(count, align_log2, first)?;
+        Some(self.index_to_addr(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitAlloc4K, BitAlloc64K, ReserveBitAlloc};
+
+    #[test]
+    fn frame_alloc() {
+        let mut fa = FrameAlloc::new(0x100000, 4096, BitAlloc4K::default());
+        fa.inner_mut().insert(0..16);
+        let addr = fa.alloc_frame().unwrap();
+        assert_eq!(addr % 4096, 0);
+        assert_eq!(addr, 0x100000 + 15 * 4096);
+        assert_eq!(fa.addr_to_index(addr), 15);
+        let addr2 = fa.alloc_frame().unwrap();
+        assert_ne!(addr, addr2);
+        fa.dealloc_frame(addr);
+        assert!(fa.inner().test(15));
+        assert_eq!(fa.alloc_frame(), Some(addr));
+
+        let base = fa.alloc_contiguous_frames(4, 0x4000).unwrap();
+        assert_eq!(base, 0x100000);
+        assert_eq!(base % 0x4000, 0);
+        let base = fa.alloc_contiguous_frames(2, 0x2000).unwrap();
+        assert_eq!(base, 0x104000);
+        assert_eq!(fa.alloc_contiguous_frames(8, 0x8000), None);
+    }
+
+    #[test]
+    fn frame_memory_map() {
+        let map = [
+            (0, 0x9fc00, true),
+            (0x9fc00, 0x400, false),
+            (0x100000, 0x6f00000 + 0x123, true),
+            // reserved ACPI tables inside the usable region
+            (0x200000 + 0x10, 0x2000, false),
+            (0xfec00000, 0x1000, false),
+        ];
+        let fa = FrameAlloc::<BitAlloc64K>::from_memory_map(4096, map.iter().copied());
+        assert_eq!(fa.base_addr(), 0);
+        // 0x9f frames below 640K, and the rest up to 0x7000000
+        assert!(fa
+            .inner()
+            .free_ranges()
+            .eq([0..0x9f, 0x100..0x200, 0x203..0x7000]));
+    }
+
+    #[test]
+    fn frame_huge() {
+        // the base is not 2 MiB aligned, so the first 2 MiB page starts at
+        // frame 256
+        let mut fa = FrameAlloc::new(0x300000, 4096, BitAlloc4K::default());
+        fa.inner_mut().insert(..);
+        assert_eq!(fa.alloc_huge(9), Some(0x400000));
+        assert_eq!(fa.inner().next_allocated(0), Some(256));
+        assert_eq!(fa.alloc_huge(9), Some(0x600000));
+        assert_eq!(fa.alloc_contiguous_frames(3, 0x100000), Some(0x300000));
+        assert_eq!(fa.alloc_huge(18), None);
+        fa.dealloc_huge(0x400000, 9);
+        assert_eq!(fa.inner().free_count(), 4096 - 512 - 3);
+        assert_eq!(fa.alloc_huge(usize::BITS as usize), None);
+    }
+
+    #[test]
+    fn frame_huge_reserve() {
+        // aligned blocks go through the wrapper, which keeps its reserve
+        let inner = ReserveBitAlloc::new(BitAlloc4K::default(), 4096 - 1000);
+        let mut fa = FrameAlloc::new(0x300000, 4096, inner);
+        fa.inner_mut().insert(..);
+        assert_eq!(fa.alloc_huge(9), Some(0x400000));
+        assert_eq!(fa.alloc_huge(9), None);
+        assert_eq!(fa.inner().available(), 1000 - 512);
+    }
+
+    #[test]
+    fn frame_regions() {
+        let mut fa = FrameAlloc::new(0x100000, 4096, BitAlloc4K::default());
+        // partial frames at the edges stay allocated, as does memory below
+        // the base
+        fa.mark_region_usable(0x80000, 0x80000 + 3 * 4096 + 100);
+        assert_eq!(fa.inner().free_ranges().next(), Some(0..3));
+        assert_eq!(fa.inner().free_count(), 3);
+        fa.mark_region_usable(0x100000 + 10 * 4096 - 1, 4 * 4096);
+        assert!(fa.inner().free_ranges().eq([0..3, 10..13]));
+        // memory past the end is ignored
+        fa.mark_region_usable(0x100000 + 4000 * 4096, usize::MAX);
+        assert_eq!(fa.inner().free_count(), 3 + 3 + 96);
+        // reserving rounds outward
+        fa.mark_region_reserved(0x100000 + 4096 + 1, 1);
+        fa.mark_region_reserved(0x100000 + 12 * 4096 - 1, 2);
+        assert!(fa.inner().free_ranges().take(3).eq([0..1, 2..3, 10..11]));
+        fa.mark_region_usable(0x100000 + 5 * 4096, 100);
+        fa.mark_region_reserved(0, 0x100000);
+        assert_eq!(fa.inner().free_count(), 3 + 96);
+    }
+}
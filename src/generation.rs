@@ -0,0 +1,176 @@
+use crate::BitAlloc;
+use core::fmt;
+
+/// A bit handed out by a [`GenerationalBitAlloc`], tagged with the
+/// generation of the bit at the time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Handle {
+    index: usize,
+    generation: u32,
+}
+
+impl Handle {
+    /// The bit position.
+    pub fn index(self) -> usize {
+        self.index
+    }
+
+    /// The generation of the bit when it was allocated.
+    pub fn generation(self) -> u32 {
+        self.generation
+    }
+
+    /// Pack the handle into a `u64`, the generation in the high half, e.g.
+    /// to pass it through an interface taking plain integers.
+    pub fn into_raw(self) -> u64 {
+        (self.generation as u64) << 32 | self.index as u64
+    }
+
+    /// Unpack a handle from `into_raw`. Any `u64` gives a handle, though
+    /// only one from `into_raw` will pass validation.
+    pub fn from_raw(raw: u64) -> Self {
+        Handle {
+            index: raw as u32 as usize,
+            generation: (raw >> 32) as u32,
+        }
+    }
+}
+
+/// Why a [`Handle`] was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandleError {
+    /// The index lies outside `0..CAP`.
+    OutOfRange,
+    /// The bit was freed since the handle was made, and may have been
+    /// allocated again.
+    Stale,
+}
+
+impl fmt::Display for HandleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            HandleError::OutOfRange => "handle index out of range",
+            HandleError::Stale => "stale handle",
+        };
+        f.write_str(msg)
+    }
+}
+
+/// A wrapper pairing every bit with a generation counter, bumped whenever
+/// the bit is freed, so handles to freed bits are caught even after the
+/// bit is allocated again.
+///
+/// `N` is the number of counters and must be `A::CAP`. Counters wrap
+/// around after `2^32` frees of the same bit.
+#[derive(Clone)]
+pub struct GenerationalBitAlloc<A: BitAlloc, const N: usize> {
+    inner: A,
+    generations: [u32; N],
+}
+
+impl<A: BitAlloc, const N: usize> GenerationalBitAlloc<A, N> {
+    /// Wrap an allocator, with all generations 0.
+    pub fn new(inner: A) -> Self {
+        assert_eq!(N, A::CAP, "one generation per bit is needed");
+        assert!(A::CAP as u64 <= 1 << 32, "handles hold 32-bit indices");
+        GenerationalBitAlloc {
+            inner,
+            generations: [0; N],
+        }
+    }
+
+    /// The underlying allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// The underlying allocator, mutably. Bits freed through it keep their
+    /// generation, so handles to them are not caught.
+    pub fn inner_mut(&mut self) -> &mut A {
+        &mut self.inner
+    }
+
+    /// The current generation of bit `index`.
+    pub fn generation(&self, index: usize) -> u32 {
+        self.generations[index]
+    }
+
+    /// Allocate a free bit.
+    pub fn alloc(&mut self) -> Option<Handle> {
+        let index = self.inner.alloc()?;
+        Some(Handle {
+            index,
+            generation: self.generations[index],
+        })
+    }
+
+    /// Return the index of `handle` if its bit is still allocated to it.
+    pub fn validate(&self, handle: Handle) -> Result<usize, HandleError> {
+        let generation = self
+            .generations
+            .get(handle.index)
+            .ok_or(HandleError::OutOfRange)?;
+        if *generation != handle.generation || self.inner.test(handle.index) {
+            return Err(HandleError::Stale);
+        }
+        Ok(handle.index)
+    }
+
+    /// Whether the bit of `handle` is still allocated to it.
+    pub fn is_live(&self, handle: Handle) -> bool {
+        self.validate(handle).is_ok()
+    }
+
+    /// Free the bit of `handle`, making every copy of it stale.
+    pub fn free(&mut self, handle: Handle) -> Result<(), HandleError> {
+        let index = self.validate(handle)?;
+        self.generations[index] = self.generations[index].wrapping_add(1);
+        self.inner.dealloc(index);
+        Ok(())
+    }
+}
+
+impl<A: BitAlloc + fmt::Debug, const N: usize> fmt::Debug for GenerationalBitAlloc<A, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GenerationalBitAlloc")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BitAlloc16;
+
+    fn full() -> GenerationalBitAlloc<BitAlloc16, 16> {
+        let mut ba = BitAlloc16::default();
+        ba.insert(..);
+        GenerationalBitAlloc::new(ba)
+    }
+
+    #[test]
+    fn generation_stale() {
+        let mut ba = full();
+        let h = ba.alloc().unwrap();
+        assert_eq!(ba.validate(h), Ok(15));
+        ba.free(h).unwrap();
+        assert_eq!(ba.free(h), Err(HandleError::Stale));
+        // the bit is handed out again, but the old handle stays stale
+        let h2 = ba.alloc().unwrap();
+        assert_eq!((h2.index(), h2.generation()), (15, 1));
+        assert_eq!(ba.validate(h), Err(HandleError::Stale));
+        assert!(ba.is_live(h2));
+        assert_eq!(Handle::from_raw(h2.into_raw()), h2);
+        assert_eq!(
+            ba.validate(Handle::from_raw(16)),
+            Err(HandleError::OutOfRange)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "one generation per bit")]
+    fn generation_count() {
+        GenerationalBitAlloc::<BitAlloc16, 8>::new(BitAlloc16::default());
+    }
+}
@@ -0,0 +1,156 @@
+use crate::BitAlloc;
+use core::fmt;
+use core::mem::ManuallyDrop;
+use core::ops::Range;
+
+/// A bit allocated by [`BitAlloc::alloc_guarded`], freed when the guard is
+/// dropped, unless it is taken with `leak` or `into_raw` first.
+///
+/// The guard borrows the allocator, so keep it for the length of a scope,
+/// e.g. until an operation that may fail half way is done.
+pub struct BitGuard<'a, A: BitAlloc> {
+    ba: &'a mut A,
+    key: usize,
+}
+
+impl<'a, A: BitAlloc> BitGuard<'a, A> {
+    /// Guard `key` of `ba`, which must be allocated, e.g. as given back by
+    /// `into_raw`.
+    pub fn from_raw(ba: &'a mut A, key: usize) -> Self {
+        assert!(!ba.test(key), "bit {} is not allocated", key);
+        BitGuard { ba, key }
+    }
+
+    /// The bit position.
+    pub fn key(&self) -> usize {
+        self.key
+    }
+
+    /// The allocator, e.g. to allocate more while holding the guard.
+    pub fn allocator(&mut self) -> &mut A {
+        self.ba
+    }
+
+    /// Keep the bit allocated for good, and return it.
+    pub fn leak(self) -> usize {
+        self.into_raw()
+    }
+
+    /// Keep the bit allocated and return it, to be freed by the caller or
+    /// guarded again with `from_raw`.
+    pub fn into_raw(self) -> usize {
+        ManuallyDrop::new(self).key
+    }
+}
+
+impl<A: BitAlloc> Drop for BitGuard<'_, A> {
+    fn drop(&mut self) {
+        self.ba.dealloc(self.key);
+    }
+}
+
+impl<A: BitAlloc> fmt::Debug for BitGuard<'_, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("BitGuard").field(&self.key).finish()
+    }
+}
+
+/// A block allocated by [`BitAlloc::alloc_contiguous_guarded`], freed when
+/// the guard is dropped, unless it is taken with `leak` or `into_raw`
+/// first.
+pub struct RegionGuard<'a, A: BitAlloc> {
+    ba: &'a mut A,
+    range: Range<usize>,
+}
+
+impl<'a, A: BitAlloc> RegionGuard<'a, A> {
+    /// Guard the bits in `range` of `ba`, which must all be allocated, e.g.
+    /// as given back by `into_raw`.
+    pub fn from_raw(ba: &'a mut A, range: Range<usize>) -> Self {
+        assert!(range.end <= A::CAP);
+        assert!(
+            ba.next(range.start).is_none_or(|i| i >= range.end),
+            "block {:?} is not allocated",
+            range
+        );
+        RegionGuard { ba, range }
+    }
+
+    /// The bits of the block.
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    /// The allocator, e.g. to allocate more while holding the guard.
+    pub fn allocator(&mut self) -> &mut A {
+        self.ba
+    }
+
+    /// Keep the block allocated for good, and return it.
+    pub fn leak(self) -> Range<usize> {
+        self.into_raw()
+    }
+
+    /// Keep the block allocated and return it, to be freed by the caller or
+    /// guarded again with `from_raw`.
+    pub fn into_raw(self) -> Range<usize> {
+        let this = ManuallyDrop::new(self);
+        this.range.clone()
+    }
+}
+
+impl<A: BitAlloc> Drop for RegionGuard<'_, A> {
+    fn drop(&mut self) {
+        let size = self.range.end - self.range.start;
+        self.ba.dealloc_contiguous(self.range.start, size);
+    }
+}
+
+impl<A: BitAlloc> fmt::Debug for RegionGuard<'_, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("RegionGuard").field(&self.range).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BitAlloc, BitAlloc16, BitAlloc4K};
+
+    #[test]
+    fn guard_frees_on_drop() {
+        let mut ba = BitAlloc16::default();
+        ba.insert(..);
+        let fail = |ba: &mut BitAlloc16| -> Result<usize, ()> {
+            let mut guard = ba.alloc_guarded().ok_or(())?;
+            guard.allocator().remove(..);
+            // early return with the guard still held
+            guard.allocator().alloc().ok_or(())?;
+            Ok(guard.into_raw())
+        };
+        assert_eq!(fail(&mut ba), Err(()));
+        assert!(ba.test(15));
+        ba.insert(..);
+        let key = ba.alloc_guarded().unwrap().leak();
+        assert_eq!(key, 15);
+        assert!(!ba.test(15));
+        drop(super::BitGuard::from_raw(&mut ba, key));
+        assert_eq!(ba.free_count(), 16);
+    }
+
+    #[test]
+    fn region_guard_frees_on_drop() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(..);
+        {
+            let mut guard = ba.alloc_contiguous_guarded(100, 6).unwrap();
+            assert_eq!(guard.range(), 0..100);
+            assert_eq!(guard.allocator().free_count(), 4096 - 100);
+        }
+        assert_eq!(ba.free_count(), 4096);
+        let block = ba.alloc_contiguous_guarded(64, 6).unwrap().leak();
+        assert_eq!(ba.free_count(), 4096 - 64);
+        drop(super::RegionGuard::from_raw(&mut ba, block));
+        assert_eq!(ba.free_count(), 4096);
+        assert!(ba.alloc_contiguous_guarded(4097, 0).is_none());
+    }
+}
@@ -0,0 +1,178 @@
+use crate::{BitAlloc, FrameAlloc};
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+use spin::Mutex;
+
+/// A heap of fixed-size blocks, one bit each, usable as the
+/// `#[global_allocator]` of small no_std firmware. Enabled by the `spin`
+/// feature.
+///
+/// Requests are rounded up to whole blocks, and those of more than one
+/// block are served by `alloc_contiguous`, aligned in memory as the layout
+/// asks. The heap is empty until `init` hands it a buffer, e.g.
+/// `static HEAP: BlockHeap<BitAlloc4K> = BlockHeap::new(64);`.
+///
+/// As with [`LockedBitAlloc`](crate::LockedBitAlloc), the lock does not
+/// disable interrupts.
+pub struct BlockHeap<A: BitAlloc> {
+    blocks: Mutex<FrameAlloc<A>>,
+    block_size: usize,
+}
+
+impl<A: BitAlloc> BlockHeap<A> {
+    /// An empty heap of blocks of `block_size` bytes, which must be a power
+    /// of two.
+    pub const fn new(block_size: usize) -> Self {
+        BlockHeap {
+            blocks: Mutex::new(FrameAlloc::new(0, block_size, A::DEFAULT)),
+            block_size,
+        }
+    }
+
+    /// Hand the heap `len` bytes at `start`, dropping what it had before.
+    /// The whole blocks within, up to `A::CAP` of them, become free.
+    ///
+    /// # Safety
+    ///
+    /// The memory must be valid for reads and writes, and used for nothing
+    /// else for as long as the heap is used. Nothing allocated from the heap
+    /// before may be in use.
+    pub unsafe fn init(&self, start: *mut u8, len: usize) {
+        // block-aligned, so that layouts are aligned in memory
+        let base = (start as usize).next_multiple_of(self.block_size);
+        let mut blocks = self.blocks.lock();
+        *blocks = FrameAlloc::new(base, self.block_size, A::DEFAULT);
+        blocks.mark_region_usable(start as usize, len);
+    }
+
+    /// The size of a block in bytes.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// The number of free blocks.
+    pub fn free_blocks(&self) -> usize {
+        self.blocks.lock().inner().free_count()
+    }
+
+    /// The number of blocks a layout takes, at least one.
+    fn blocks_for(&self, layout: Layout) -> usize {
+        layout.size().max(1).div_ceil(self.block_size)
+    }
+}
+
+unsafe impl<A: BitAlloc> GlobalAlloc for BlockHeap<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let count = self.blocks_for(layout);
+        match self
+            .blocks
+            .lock()
+            .alloc_contiguous_frames(count, layout.align())
+        {
+            Some(addr) => addr as *mut u8,
+            None => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let count = self.blocks_for(layout);
+        self.blocks
+            .lock()
+            .dealloc_contiguous_frames(ptr as usize, count)
+    }
+
+    /// Keeps the allocation in place if it takes as many blocks as before.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        if self.blocks_for(new_layout) == self.blocks_for(layout) {
+            return ptr;
+        }
+        let new = self.alloc(new_layout);
+        if !new.is_null() {
+            ptr::copy_nonoverlapping(ptr, new, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new
+    }
+}
+
+/// With the `allocator_api` feature, on nightly, the heap is also an
+/// `Allocator`, e.g. for a bounded `Vec::new_in(&HEAP)` per subsystem.
+/// The slice handed out spans whole blocks.
+#[cfg(feature = "allocator_api")]
+unsafe impl<A: BitAlloc> core::alloc::Allocator for BlockHeap<A> {
+    fn allocate(&self, layout: Layout) -> Result<ptr::NonNull<[u8]>, core::alloc::AllocError> {
+        let count = self.blocks_for(layout);
+        let addr = self
+            .blocks
+            .lock()
+            .alloc_contiguous_frames(count, layout.align())
+            .ok_or(core::alloc::AllocError)?;
+        let block = ptr::slice_from_raw_parts_mut(addr as *mut u8, count * self.block_size);
+        Ok(ptr::NonNull::new(block).unwrap())
+    }
+
+    unsafe fn deallocate(&self, ptr: ptr::NonNull<u8>, layout: Layout) {
+        GlobalAlloc::dealloc(self, ptr.as_ptr(), layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BitAlloc256;
+
+    #[test]
+    fn block_heap() {
+        #[repr(align(4096))]
+        struct Buffer([u8; 8192]);
+        let mut buf = Buffer([0; 8192]);
+
+        let heap = BlockHeap::<BitAlloc256>::new(64);
+        // a buffer off by one leaves out the partial blocks at both ends
+        unsafe { heap.init(buf.0.as_mut_ptr().add(1), 8191) };
+        assert_eq!(heap.free_blocks(), 127);
+        unsafe {
+            let small = heap.alloc(Layout::from_size_align(10, 8).unwrap());
+            let large = heap.alloc(Layout::from_size_align(1000, 1024).unwrap());
+            assert!(!small.is_null() && !large.is_null());
+            assert_eq!(large as usize % 1024, 0);
+            assert_eq!(heap.free_blocks(), 127 - 1 - 16);
+            // within the same block, then past it
+            let layout = Layout::from_size_align(10, 8).unwrap();
+            assert_eq!(heap.realloc(small, layout, 64), small);
+            small.write(42);
+            let moved = heap.realloc(small, layout, 100);
+            assert_eq!(moved.read(), 42);
+            assert_eq!(heap.free_blocks(), 127 - 2 - 16);
+            assert!(heap
+                .alloc(Layout::from_size_align(8192, 8).unwrap())
+                .is_null());
+            heap.dealloc(moved, Layout::from_size_align(100, 8).unwrap());
+            heap.dealloc(large, Layout::from_size_align(1000, 1024).unwrap());
+        }
+        assert_eq!(heap.free_blocks(), 127);
+    }
+
+    #[test]
+    #[cfg(feature = "allocator_api")]
+    fn block_heap_allocator() {
+        extern crate std;
+        use std::vec::Vec;
+
+        #[repr(align(64))]
+        struct Buffer([u8; 4096]);
+        let mut buf = Buffer([0; 4096]);
+
+        let heap = BlockHeap::<BitAlloc256>::new(64);
+        unsafe { heap.init(buf.0.as_mut_ptr(), 4096) };
+        let mut v: Vec<u32, _> = Vec::with_capacity_in(10, &heap);
+        assert_eq!(heap.free_blocks(), 63);
+        v.extend(0..100);
+        assert_eq!(heap.free_blocks(), 64 - (v.capacity() * 4).div_ceil(64));
+        assert!(v.iter().copied().eq(0..100));
+        assert!(Vec::<u8, _>::try_with_capacity_in(4096, &heap).is_err());
+        drop(v);
+        assert_eq!(heap.free_blocks(), 64);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::BitAlloc;
+use core::fmt;
+
+/// An allocator of IDs like PIDs, file descriptors or handles, giving out
+/// the lowest free one, but holding back the last `Q` freed ones.
+///
+/// An ID freed by `free_id` is only reused after `Q` more IDs have been
+/// freed, so a stale copy of it is unlikely to name a new object by
+/// accident. Only when no other ID is left is the oldest held-back one
+/// reused early.
+#[derive(Clone)]
+pub struct IdAllocator<B: BitAlloc, const Q: usize> {
+    ids: B, // held-back IDs are still allocated here
+    held: [usize; Q],
+    head: usize, // the oldest held-back ID, if any
+    len: usize,
+}
+
+impl<B: BitAlloc, const Q: usize> IdAllocator<B, Q> {
+    /// Create an allocator with all IDs `0..B::CAP` free.
+    pub fn new() -> Self {
+        let mut ids = B::DEFAULT;
+        ids.insert(..);
+        IdAllocator {
+            ids,
+            held: [0; Q],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// The underlying allocator, where held-back IDs are allocated, e.g. to
+    /// reserve some IDs up front.
+    pub fn inner_mut(&mut self) -> &mut B {
+        &mut self.ids
+    }
+
+    /// Allocate the lowest free ID.
+    pub fn alloc_id(&mut self) -> Option<usize> {
+        self.ids.alloc_low().or_else(|| self.release_oldest())
+    }
+
+    /// Free a live ID. It is held back from reuse for the next `Q` frees.
+    pub fn free_id(&mut self, id: usize) {
+        assert!(self.is_live(id), "ID {} is not live", id);
+        if Q == 0 {
+            self.ids.dealloc(id);
+            return;
+        }
+        if self.len == Q {
+            let oldest = self.release_oldest().unwrap();
+            self.ids.dealloc(oldest);
+        }
+        self.held[(self.head + self.len) % Q] = id;
+        self.len += 1;
+    }
+
+    /// Take the oldest held-back ID out of the queue, still allocated.
+    fn release_oldest(&mut self) -> Option<usize> {
+        if self.len == 0 {
+            return None;
+        }
+        let id = self.held[self.head];
+        self.head = (self.head + 1) % Q;
+        self.len -= 1;
+        Some(id)
+    }
+
+    fn held(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len).map(move |i| self.held[(self.head + i) % Q])
+    }
+
+    /// Whether `id` is allocated and not freed since.
+    pub fn is_live(&self, id: usize) -> bool {
+        id < B::CAP && !self.ids.test(id) && !self.held().any(|held| held == id)
+    }
+
+    /// Iterate over the live IDs in ascending order.
+    pub fn iter_live(&self) -> impl Iterator<Item = usize> + '_ {
+        self.ids
+            .iter_allocated()
+            .filter(move |&id| !self.held().any(|held| held == id))
+    }
+
+    /// The number of live IDs.
+    pub fn live_count(&self) -> usize {
+        B::CAP - self.ids.free_count() - self.len
+    }
+}
+
+impl<B: BitAlloc, const Q: usize> Default for IdAllocator<B, Q> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: BitAlloc, const Q: usize> fmt::Debug for IdAllocator<B, Q> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IdAllocator")
+            .field("cap", &B::CAP)
+            .field("live", &self.live_count())
+            .field("held", &self.len)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitAlloc16, BitAlloc4K};
+
+    #[test]
+    fn id_delayed_reuse() {
+        let mut ids = IdAllocator::<BitAlloc4K, 2>::new();
+        ids.inner_mut().remove(0..1);
+        assert_eq!(ids.alloc_id(), Some(1));
+        assert_eq!(ids.alloc_id(), Some(2));
+        assert_eq!(ids.alloc_id(), Some(3));
+        ids.free_id(1);
+        ids.free_id(2);
+        assert!(!ids.is_live(1) && ids.is_live(3));
+        // 1 and 2 are held back
+        assert_eq!(ids.alloc_id(), Some(4));
+        // freeing 3 lets 1 go
+        ids.free_id(3);
+        assert_eq!(ids.alloc_id(), Some(1));
+        // the reserved ID counts as live
+        assert!(ids.iter_live().eq([0, 1, 4]));
+        assert_eq!(ids.live_count(), 3);
+    }
+
+    #[test]
+    fn id_exhausted() {
+        let mut ids = IdAllocator::<BitAlloc16, 4>::new();
+        for id in 0..16 {
+            assert_eq!(ids.alloc_id(), Some(id));
+        }
+        ids.free_id(7);
+        ids.free_id(3);
+        // nothing else is left, so the oldest held-back ID goes first
+        assert_eq!(ids.alloc_id(), Some(7));
+        assert_eq!(ids.alloc_id(), Some(3));
+        assert_eq!(ids.alloc_id(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "not live")]
+    fn id_double_free() {
+        let mut ids = IdAllocator::<BitAlloc16, 4>::new();
+        let id = ids.alloc_id().unwrap();
+        ids.free_id(id);
+        ids.free_id(id);
+    }
+}
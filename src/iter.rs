@@ -0,0 +1,220 @@
+use crate::BitAlloc;
+use core::ops::Range;
+
+/// The positions of the set bits in a word, offset by `base`.
+pub(crate) struct SetBits {
+    word: u64,
+    base: usize,
+}
+
+impl SetBits {
+    pub(crate) fn new(word: u64, base: usize) -> Self {
+        SetBits { word, base }
+    }
+}
+
+impl Iterator for SetBits {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.word == 0 {
+            return None;
+        }
+        let res = self.word.trailing_zeros() as usize;
+        // clear the lowest set bit
+        self.word &= self.word - 1;
+        Some(self.base + res)
+    }
+}
+
+impl DoubleEndedIterator for SetBits {
+    fn next_back(&mut self) -> Option<usize> {
+        if self.word == 0 {
+            return None;
+        }
+        let res = 63 - self.word.leading_zeros() as usize;
+        self.word &= !(1 << res);
+        Some(self.base + res)
+    }
+}
+
+/// The free (`FREE = true`) or allocated bits of `A` within `front..back`.
+pub(crate) struct Bits<'a, A, const FREE: bool> {
+    ba: &'a A,
+    front: usize,
+    back: usize,
+}
+
+impl<'a, A: BitAlloc, const FREE: bool> Bits<'a, A, FREE> {
+    pub(crate) fn new(ba: &'a A) -> Self {
+        Bits {
+            ba,
+            front: 0,
+            back: ba.capacity(),
+        }
+    }
+}
+
+/// `next` or `next_allocated`, depending on `free`.
+fn forward<A: BitAlloc>(ba: &A, free: bool, key: usize) -> Option<usize> {
+    if free {
+        ba.next(key)
+    } else {
+        ba.next_allocated(key)
+    }
+}
+
+/// `prev` or `prev_allocated`, depending on `free`.
+fn backward<A: BitAlloc>(ba: &A, free: bool, key: usize) -> Option<usize> {
+    if free {
+        ba.prev(key)
+    } else {
+        ba.prev_allocated(key)
+    }
+}
+
+impl<A: BitAlloc, const FREE: bool> Iterator for Bits<'_, A, FREE> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.front >= self.back {
+            return None;
+        }
+        match forward(self.ba, FREE, self.front) {
+            Some(res) if res < self.back => {
+                self.front = res + 1;
+                Some(res)
+            }
+            _ => {
+                self.front = self.back;
+                None
+            }
+        }
+    }
+}
+
+impl<A: BitAlloc, const FREE: bool> DoubleEndedIterator for Bits<'_, A, FREE> {
+    fn next_back(&mut self) -> Option<usize> {
+        if self.front >= self.back {
+            return None;
+        }
+        match backward(self.ba, FREE, self.back - 1) {
+            Some(res) if res >= self.front => {
+                self.back = res;
+                Some(res)
+            }
+            _ => {
+                self.back = self.front;
+                None
+            }
+        }
+    }
+}
+
+/// The maximal runs of free (`FREE = true`) or allocated bits of `A`
+/// within `front..back`.
+pub(crate) struct Runs<'a, A, const FREE: bool> {
+    ba: &'a A,
+    front: usize,
+    back: usize,
+}
+
+impl<'a, A: BitAlloc, const FREE: bool> Runs<'a, A, FREE> {
+    pub(crate) fn new(ba: &'a A) -> Self {
+        Runs {
+            ba,
+            front: 0,
+            back: ba.capacity(),
+        }
+    }
+}
+
+impl<A: BitAlloc, const FREE: bool> Iterator for Runs<'_, A, FREE> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Range<usize>> {
+        if self.front >= self.back {
+            return None;
+        }
+        match forward(self.ba, FREE, self.front) {
+            Some(start) if start < self.back => {
+                let end =
+                    forward(self.ba, !FREE, start).map_or(self.back, |end| end.min(self.back));
+                self.front = end;
+                Some(start..end)
+            }
+            _ => {
+                self.front = self.back;
+                None
+            }
+        }
+    }
+}
+
+impl<A: BitAlloc, const FREE: bool> DoubleEndedIterator for Runs<'_, A, FREE> {
+    fn next_back(&mut self) -> Option<Range<usize>> {
+        if self.front >= self.back {
+            return None;
+        }
+        match backward(self.ba, FREE, self.back - 1) {
+            Some(last) if last >= self.front => {
+                let start = backward(self.ba, !FREE, last)
+                    .map_or(self.front, |start| (start + 1).max(self.front));
+                self.back = start;
+                Some(start..last + 1)
+            }
+            _ => {
+                self.back = self.front;
+                None
+            }
+        }
+    }
+}
+
+/// A run of bits that changed state between two allocators, as yielded by
+/// [`BitAlloc::diff`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Changed {
+    /// Bits free before and allocated now.
+    Allocated(Range<usize>),
+    /// Bits allocated before and free now.
+    Freed(Range<usize>),
+}
+
+/// The runs of bits differing between `old` and `new`.
+pub(crate) struct Diff<'a, A> {
+    old: &'a A,
+    new: &'a A,
+    key: usize,
+}
+
+impl<'a, A: BitAlloc> Diff<'a, A> {
+    pub(crate) fn new(old: &'a A, new: &'a A) -> Self {
+        Diff { old, new, key: 0 }
+    }
+}
+
+impl<A: BitAlloc> Iterator for Diff<'_, A> {
+    type Item = Changed;
+
+    fn next(&mut self) -> Option<Changed> {
+        let start = self.old.next_difference(self.new, self.key)?;
+        // the run goes on while the bit stays free on one side only, the
+        // same side as at the start
+        let freed = self.new.test(start);
+        let end = [
+            forward(self.old, freed, start),
+            forward(self.new, !freed, start),
+        ]
+        .iter()
+        .flatten()
+        .min()
+        .map_or(self.new.capacity(), |&end| end);
+        self.key = end;
+        Some(if freed {
+            Changed::Freed(start..end)
+        } else {
+            Changed::Allocated(start..end)
+        })
+    }
+}
@@ -0,0 +1,465 @@
+//! A journal of the changes to an allocator, see [`JournalBitAlloc`] and
+//! [`BitAlloc::replay_journal`].
+//!
+//! The journal is a sequence of records of 32 bytes, integers little-endian:
+//!
+//! | bytes    | content                                             |
+//! |----------|-----------------------------------------------------|
+//! | 0..8     | sequence number, one more than that of the last     |
+//! | 8        | kind: 1 if the bits were allocated, 2 if freed      |
+//! | 9..16    | reserved, 0                                         |
+//! | 16..24   | first bit of the range, as a `u64`                  |
+//! | 24..32   | end of the range, as a `u64`                        |
+//!
+//! A record of kind 0, e.g. zeroed storage, ends the journal. Each record
+//! sets the bits of its range to a state, so replaying it twice is harmless.
+
+use crate::{to_range, AllocPolicy, BitAlloc, BudgetExhausted, Inconsistency, WORD_BITS};
+use core::fmt;
+use core::ops::{Range, RangeBounds};
+
+const RECORD_LEN: usize = 32;
+const ALLOCATED: u8 = 1;
+const FREED: u8 = 2;
+
+/// Why a journal could not be replayed. The records before the bad one
+/// have been applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalError {
+    /// A record does not follow the one before it.
+    Gap { expected: u64, found: u64 },
+    /// A record has a kind this crate does not know.
+    UnknownKind { seq: u64, kind: u8 },
+    /// The range of a record is reversed or past the end of the allocator.
+    OutOfRange(u64),
+}
+
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JournalError::Gap { expected, found } => {
+                write!(f, "journal record {} follows {}", found, expected - 1)
+            }
+            JournalError::UnknownKind { seq, kind } => {
+                write!(f, "journal record {} has unknown kind {}", seq, kind)
+            }
+            JournalError::OutOfRange(seq) => {
+                write!(f, "journal record {} is out of range", seq)
+            }
+        }
+    }
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(bytes);
+    u64::from_le_bytes(buf)
+}
+
+pub(crate) fn replay<A: BitAlloc>(ba: &mut A, journal: &[u8]) -> Result<Option<u64>, JournalError> {
+    let mut last: Option<u64> = None;
+    for record in journal.chunks_exact(RECORD_LEN) {
+        let kind = record[8];
+        if kind == 0 {
+            break;
+        }
+        let seq = read_u64(&record[0..8]);
+        if let Some(expected) = last.map(|last| last + 1).filter(|&e| e != seq) {
+            return Err(JournalError::Gap {
+                expected,
+                found: seq,
+            });
+        }
+        let (start, end) = (read_u64(&record[16..24]), read_u64(&record[24..32]));
+        if start > end || end > ba.capacity() as u64 {
+            return Err(JournalError::OutOfRange(seq));
+        }
+        let range = start as usize..end as usize;
+        match kind {
+            ALLOCATED => ba.remove(range),
+            FREED => ba.insert(range),
+            _ => return Err(JournalError::UnknownKind { seq, kind }),
+        }
+        last = Some(seq);
+    }
+    Ok(last)
+}
+
+/// A wrapper appending every change to the bitmap to a journal in memory
+/// supplied by the caller, e.g. a persistent region, so that the state can
+/// be recovered from an older snapshot with `replay_journal` after a crash
+/// without writing a snapshot on every change.
+///
+/// Allocations fail once the journal has no room left, and the other
+/// changes panic, so take a snapshot and `checkpoint` before then. Frees
+/// are recorded before they are made, allocations once they are.
+pub struct JournalBitAlloc<'a, A: BitAlloc> {
+    inner: A,
+    // `None` in `DEFAULT`, which cannot borrow an empty slice mutably
+    journal: Option<&'a mut [u8]>,
+    len: usize,
+    seq: u64,
+}
+
+impl<'a, A: BitAlloc> JournalBitAlloc<'a, A> {
+    /// Wrap an allocator, writing records to `journal` from its start and
+    /// numbering them from `seq`, e.g. one past the last number replayed.
+    pub fn new(inner: A, journal: &'a mut [u8], seq: u64) -> Self {
+        JournalBitAlloc {
+            inner,
+            journal: Some(journal),
+            len: 0,
+            seq,
+        }
+    }
+
+    /// The underlying allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Unwrap the underlying allocator.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    /// The records written since the last checkpoint.
+    pub fn journal(&self) -> &[u8] {
+        self.journal
+            .as_deref()
+            .map_or(&[], |journal| &journal[..self.len])
+    }
+
+    /// The number the next record will get.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// The number of records there is room left for.
+    pub fn remaining(&self) -> usize {
+        let len = self.journal.as_deref().map_or(0, <[u8]>::len);
+        (len - self.len) / RECORD_LEN
+    }
+
+    /// Start the journal over, once a snapshot of the current state is safe.
+    /// The records are zeroed, and the numbering goes on.
+    pub fn checkpoint(&mut self) {
+        if let Some(journal) = self.journal.as_deref_mut() {
+            journal[..self.len].fill(0);
+        }
+        self.len = 0;
+    }
+
+    fn log(&mut self, kind: u8, range: Range<usize>) {
+        assert!(self.remaining() > 0, "journal full");
+        let journal = self.journal.as_deref_mut().unwrap();
+        let record = &mut journal[self.len..self.len + RECORD_LEN];
+        record[0..8].copy_from_slice(&self.seq.to_le_bytes());
+        record[8..16].copy_from_slice(&[kind, 0, 0, 0, 0, 0, 0, 0]);
+        record[16..24].copy_from_slice(&(range.start as u64).to_le_bytes());
+        record[24..32].copy_from_slice(&(range.end as u64).to_le_bytes());
+        self.len += RECORD_LEN;
+        self.seq += 1;
+    }
+
+    fn allocated(&mut self, base: Option<usize>, size: usize) -> Option<usize> {
+        if let Some(base) = base {
+            self.log(ALLOCATED, base..base + size);
+        }
+        base
+    }
+
+    /// The runs of equal bits in raw word `w` if it were `word`, as
+    /// `(free, range)`.
+    fn word_runs(w: usize, word: usize) -> impl Iterator<Item = (bool, Range<usize>)> {
+        let start = (w * WORD_BITS).min(Self::CAP);
+        let end = (start + WORD_BITS).min(Self::CAP);
+        let mut key = start;
+        core::iter::from_fn(move || {
+            if key == end {
+                return None;
+            }
+            let free = word >> (key - start) & 1 != 0;
+            let mut run_end = key + 1;
+            while run_end < end && (word >> (run_end - start) & 1 != 0) == free {
+                run_end += 1;
+            }
+            let run = key..run_end;
+            key = run_end;
+            Some((free, run))
+        })
+    }
+}
+
+impl<A: BitAlloc> Default for JournalBitAlloc<'_, A> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<A: BitAlloc + fmt::Debug> fmt::Debug for JournalBitAlloc<'_, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("JournalBitAlloc")
+            .field("inner", &self.inner)
+            .field("records", &(self.len / RECORD_LEN))
+            .field("seq", &self.seq)
+            .finish()
+    }
+}
+
+impl<A: BitAlloc> BitAlloc for JournalBitAlloc<'_, A> {
+    const CAP: usize = A::CAP;
+
+    const DEFAULT: Self = JournalBitAlloc {
+        inner: A::DEFAULT,
+        journal: None,
+        len: 0,
+        seq: 0,
+    };
+
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    fn alloc(&mut self) -> Option<usize> {
+        if self.remaining() == 0 {
+            return None;
+        }
+        let res = self.inner.alloc();
+        self.allocated(res, 1)
+    }
+    fn alloc_with<P: AllocPolicy>(&mut self) -> Option<usize> {
+        if self.remaining() == 0 {
+            return None;
+        }
+        let res = self.inner.alloc_with::<P>();
+        self.allocated(res, 1)
+    }
+    fn alloc_many(&mut self, out: &mut [usize]) -> usize {
+        let room = out.len().min(self.remaining());
+        let n = self.inner.alloc_many(&mut out[..room]);
+        for &key in &out[..n] {
+            self.log(ALLOCATED, key..key + 1);
+        }
+        n
+    }
+    fn alloc_many_with<P: AllocPolicy>(&mut self, out: &mut [usize]) -> usize {
+        let room = out.len().min(self.remaining());
+        let n = self.inner.alloc_many_with::<P>(&mut out[..room]);
+        for &key in &out[..n] {
+            self.log(ALLOCATED, key..key + 1);
+        }
+        n
+    }
+    fn peek_alloc(&self) -> Option<usize> {
+        self.inner.peek_alloc()
+    }
+    fn peek_with<P: AllocPolicy>(&self) -> Option<usize> {
+        self.inner.peek_with::<P>()
+    }
+    fn peek_full<P: AllocPolicy>() -> usize {
+        A::peek_full::<P>()
+    }
+    fn subtree_cap(depth: usize) -> usize {
+        A::subtree_cap(depth)
+    }
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        if self.remaining() == 0 {
+            return None;
+        }
+        let res = self.inner.alloc_contiguous(size, align_log2);
+        self.allocated(res, size)
+    }
+    fn find_contiguous(&self, size: usize, align_log2: usize) -> Option<usize> {
+        self.inner.find_contiguous(size, align_log2)
+    }
+    fn alloc_aligned(&mut self, align_log2: usize) -> Option<usize> {
+        if self.remaining() == 0 {
+            return None;
+        }
+        let res = self.inner.alloc_aligned(align_log2);
+        self.allocated(res, 1)
+    }
+    fn next(&self, key: usize) -> Option<usize> {
+        self.inner.next(key)
+    }
+    fn prev(&self, key: usize) -> Option<usize> {
+        self.inner.prev(key)
+    }
+    fn dealloc(&mut self, key: usize) {
+        self.log(FREED, key..key + 1);
+        self.inner.dealloc(key)
+    }
+    fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        self.log(FREED, base..base + size);
+        self.inner.dealloc_contiguous(base, size)
+    }
+    fn insert(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, Self::CAP);
+        if range.start != range.end {
+            self.log(FREED, range.clone());
+        }
+        self.inner.insert(range)
+    }
+    fn remove(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, Self::CAP);
+        if range.start != range.end {
+            self.log(ALLOCATED, range.clone());
+        }
+        self.inner.remove(range)
+    }
+    fn any(&self) -> bool {
+        self.inner.any()
+    }
+    fn test(&self, key: usize) -> bool {
+        self.inner.test(key)
+    }
+    fn free_count(&self) -> usize {
+        self.inner.free_count()
+    }
+    fn count_free_in(&self, range: impl RangeBounds<usize>) -> usize {
+        self.inner.count_free_in(range)
+    }
+    fn free_prefix(&self) -> usize {
+        self.inner.free_prefix()
+    }
+    fn free_suffix(&self) -> usize {
+        self.inner.free_suffix()
+    }
+    fn max_contiguous_len(&self) -> usize {
+        self.inner.max_contiguous_len()
+    }
+    fn max_contiguous(&self) -> Option<Range<usize>> {
+        self.inner.max_contiguous()
+    }
+    fn search_contiguous(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+    ) -> Option<usize> {
+        self.inner
+            .search_contiguous(offset, size, align_log2, run_start)
+    }
+    fn search_contiguous_bounded(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+        budget: &mut usize,
+    ) -> Result<Option<usize>, BudgetExhausted> {
+        self.inner
+            .search_contiguous_bounded(offset, size, align_log2, run_start, budget)
+    }
+    fn visit_free_runs(&self, run: &mut usize, f: &mut impl FnMut(usize)) {
+        self.inner.visit_free_runs(run, f)
+    }
+    fn next_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.next_allocated(key)
+    }
+    fn prev_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.prev_allocated(key)
+    }
+    fn raw_word(&self, w: usize) -> usize {
+        self.inner.raw_word(w)
+    }
+    fn next_difference(&self, other: &Self, key: usize) -> Option<usize> {
+        self.inner.next_difference(&other.inner, key)
+    }
+    fn is_subset_of(&self, other: &Self) -> bool {
+        self.inner.is_subset_of(&other.inner)
+    }
+    fn is_disjoint_with(&self, other: &Self) -> bool {
+        self.inner.is_disjoint_with(&other.inner)
+    }
+    /// Recorded as the runs of free and allocated bits in the word.
+    fn write_raw_word(&mut self, w: usize, word: usize) {
+        assert!(
+            Self::word_runs(w, word).count() <= self.remaining(),
+            "journal full"
+        );
+        for (free, run) in Self::word_runs(w, word) {
+            self.log(if free { FREED } else { ALLOCATED }, run);
+        }
+        self.inner.write_raw_word(w, word)
+    }
+    fn rebuild(&mut self) {
+        self.inner.rebuild()
+    }
+    fn validate(&self) -> Result<(), Inconsistency> {
+        self.inner.validate()
+    }
+    fn iter_free(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.inner.iter_free()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitAlloc256, BitAlloc4K};
+
+    #[test]
+    fn journal_replay() {
+        let mut base = BitAlloc4K::default();
+        base.insert(..1000);
+        let mut snapshot = [0; 16 + 512];
+        base.serialize_into(&mut snapshot);
+
+        let mut buf = [0; 32 * 16];
+        let mut ba = JournalBitAlloc::new(base, &mut buf, 0);
+        let key = ba.alloc().unwrap();
+        let block = ba.alloc_contiguous(100, 4).unwrap();
+        ba.dealloc(key);
+        ba.remove(500..600);
+        ba.insert(2000..3000);
+        // recorded as its runs of free and allocated bits
+        ba.write_raw_word(3, 0xf0f0_f0f0);
+        ba.rebuild();
+        assert_eq!(ba.seq(), ba.journal().len() as u64 / 32);
+        assert_eq!(&ba.journal()[..9], &[0, 0, 0, 0, 0, 0, 0, 0, ALLOCATED]);
+
+        let mut copy = BitAlloc4K::deserialize_from(&snapshot).unwrap();
+        assert_eq!(copy.replay_journal(ba.journal()), Ok(Some(ba.seq() - 1)));
+        assert!(copy == *ba.inner());
+        assert!(!copy.test(block) && !copy.test(550) && copy.test(2500));
+        // replaying again changes nothing
+        copy.replay_journal(ba.journal()).unwrap();
+        assert!(copy == *ba.inner());
+
+        ba.checkpoint();
+        assert_eq!(ba.journal().len(), 0);
+        assert_eq!(copy.replay_journal(&buf), Ok(None));
+    }
+
+    #[test]
+    fn journal_full() {
+        let mut buf = [0; 32 * 3];
+        let mut ba = JournalBitAlloc::new(BitAlloc256::default(), &mut buf, 7);
+        ba.insert(..);
+        let mut out = [0; 5];
+        assert_eq!(ba.alloc_many(&mut out), 2);
+        assert_eq!(ba.alloc(), None);
+        assert_eq!(ba.free_count(), 254);
+        assert_eq!(ba.seq(), 10);
+
+        let mut copy = BitAlloc256::default();
+        assert_eq!(copy.replay_journal(&buf), Ok(Some(9)));
+        assert_eq!(copy.free_count(), 254);
+        buf[32] = 9;
+        assert_eq!(
+            copy.replay_journal(&buf),
+            Err(JournalError::Gap {
+                expected: 8,
+                found: 9
+            })
+        );
+        buf[32] = 8;
+        buf[64 + 8] = 3;
+        assert_eq!(
+            copy.replay_journal(&buf),
+            Err(JournalError::UnknownKind { seq: 9, kind: 3 })
+        );
+    }
+}
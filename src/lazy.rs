@@ -0,0 +1,345 @@
+use crate::{align_size, WORD_BITS};
+use crate::{debug_summary, to_range, AllocPolicy, BitAlloc, HighestFirst, Zeroable};
+use alloc::boxed::Box;
+use bit_field::BitField;
+use core::fmt;
+use core::ops::{Range, RangeBounds};
+
+/// A 16-way cascade node whose children only exist while they have free
+/// bits. Enabled by the `alloc` feature.
+///
+/// A missing child is entirely allocated. It is created on the heap, with
+/// `T::new_boxed`, when a bit in it is freed, and dropped again as soon as
+/// all its bits are allocated. A `BitAllocLazy16<BitAlloc16M>` of 256M bits
+/// where only a few ranges were ever inserted takes a few megabytes rather
+/// than 32. Nodes can be nested, e.g.
+/// `BitAllocLazy16<BitAllocLazy16<BitAlloc1M>>`, to make the unused parts
+/// cost even less.
+///
+/// Free runs are not summarized as in the eager cascade, so contiguous
+/// searches walk the free runs one by one.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct BitAllocLazy16<T: Zeroable> {
+    bitset: u16, // for each child, 1 if it has a free bit, and so exists
+    free: usize, // number of available bits in the subtree
+    sub: [Option<Box<T>>; 16],
+}
+
+// SAFETY: an all-zero `Option<Box<T>>` is `None`.
+unsafe impl<T: Zeroable> Zeroable for BitAllocLazy16<T> {}
+
+impl<T: Zeroable> BitAllocLazy16<T> {
+    const NONE: Option<Box<T>> = None;
+
+    /// The number of children currently on the heap.
+    pub fn materialized(&self) -> usize {
+        self.sub.iter().filter(|sub| sub.is_some()).count()
+    }
+
+    /// Run `f` on child `i`, creating it first if needed, then update the
+    /// summary and drop the child if it ended up fully allocated.
+    fn with_child<R>(&mut self, i: usize, f: impl FnOnce(&mut T) -> R) -> R {
+        let sub = self.sub[i].get_or_insert_with(T::new_boxed);
+        self.free -= sub.free_count();
+        let res = f(sub);
+        self.free += sub.free_count();
+        let any = sub.any();
+        self.bitset.set_bit(i, any);
+        if !any {
+            self.sub[i] = None;
+        }
+        res
+    }
+
+    /// The child `i`, which must have a free bit.
+    fn child(&self, i: usize) -> &T {
+        self.sub[i].as_deref().unwrap()
+    }
+
+    fn for_range(&mut self, range: Range<usize>, free: bool) {
+        let Range { start, end } = range;
+        if start == end {
+            return;
+        }
+        assert!(start < end);
+        assert!(end <= Self::CAP);
+        for i in start / T::CAP..=(end - 1) / T::CAP {
+            let begin = if start / T::CAP == i {
+                start % T::CAP
+            } else {
+                0
+            };
+            let end = if end / T::CAP == i {
+                end % T::CAP
+            } else {
+                T::CAP
+            };
+            if free {
+                self.with_child(i, |sub| sub.insert(begin..end));
+            } else if self.sub[i].is_some() {
+                self.with_child(i, |sub| sub.remove(begin..end));
+            }
+        }
+    }
+}
+
+impl<T: Zeroable> Default for BitAllocLazy16<T> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<T: Zeroable> fmt::Debug for BitAllocLazy16<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        debug_summary(self, "BitAllocLazy16", f)
+    }
+}
+
+impl<T: Zeroable> BitAlloc for BitAllocLazy16<T> {
+    const CAP: usize = T::CAP * 16;
+
+    const DEFAULT: Self = BitAllocLazy16 {
+        bitset: 0,
+        free: 0,
+        sub: [Self::NONE; 16],
+    };
+
+    fn alloc(&mut self) -> Option<usize> {
+        self.alloc_with::<HighestFirst>()
+    }
+    fn alloc_with<P: AllocPolicy>(&mut self) -> Option<usize> {
+        if !self.any() {
+            return None;
+        }
+        let i = P::pick(self.bitset as u64);
+        let res = self.with_child(i, |sub| sub.alloc_with::<P>().unwrap());
+        Some(res + i * T::CAP)
+    }
+    fn peek_alloc(&self) -> Option<usize> {
+        self.peek_with::<HighestFirst>()
+    }
+    fn peek_with<P: AllocPolicy>(&self) -> Option<usize> {
+        if !self.any() {
+            return None;
+        }
+        let i = P::pick(self.bitset as u64);
+        self.child(i).peek_with::<P>().map(|x| x + i * T::CAP)
+    }
+    fn peek_full<P: AllocPolicy>() -> usize {
+        T::peek_full::<P>() + P::pick(0xffff) * T::CAP
+    }
+    fn subtree_cap(depth: usize) -> usize {
+        match depth {
+            0 => Self::CAP,
+            _ => T::subtree_cap(depth - 1),
+        }
+    }
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        let base = self.find_contiguous(size, align_log2)?;
+        self.remove(base..base + size);
+        Some(base)
+    }
+    fn alloc_aligned(&mut self, align_log2: usize) -> Option<usize> {
+        let align = align_size(align_log2).filter(|&align| align <= Self::CAP)?;
+        if align > T::CAP {
+            // only the first bit of every `align / T::CAP`-th child is aligned
+            let key = (0..Self::CAP).step_by(align).find(|&key| self.test(key))?;
+            self.remove(key..key + 1);
+            return Some(key);
+        }
+        for i in 0..16 {
+            if self.bitset.get_bit(i) {
+                if let Some(res) = self.with_child(i, |sub| sub.alloc_aligned(align_log2)) {
+                    return Some(res + i * T::CAP);
+                }
+            }
+        }
+        None
+    }
+    fn next(&self, key: usize) -> Option<usize> {
+        let ind = key / T::CAP;
+        (ind..16).filter(|&i| self.bitset.get_bit(i)).find_map(|i| {
+            let begin = if i == ind { key % T::CAP } else { 0 };
+            self.child(i).next(begin).map(|x| x + i * T::CAP)
+        })
+    }
+    fn prev(&self, key: usize) -> Option<usize> {
+        let key = key.min(Self::CAP - 1);
+        let ind = key / T::CAP;
+        (0..=ind)
+            .rev()
+            .filter(|&i| self.bitset.get_bit(i))
+            .find_map(|i| {
+                let end = if i == ind { key % T::CAP } else { T::CAP - 1 };
+                self.child(i).prev(end).map(|x| x + i * T::CAP)
+            })
+    }
+    fn dealloc(&mut self, key: usize) {
+        self.with_child(key / T::CAP, |sub| sub.dealloc(key % T::CAP));
+    }
+    fn insert(&mut self, range: impl RangeBounds<usize>) {
+        self.for_range(to_range(range, Self::CAP), true);
+    }
+    fn remove(&mut self, range: impl RangeBounds<usize>) {
+        self.for_range(to_range(range, Self::CAP), false);
+    }
+    fn any(&self) -> bool {
+        self.bitset != 0
+    }
+    fn test(&self, key: usize) -> bool {
+        self.sub[key / T::CAP]
+            .as_ref()
+            .is_some_and(|sub| sub.test(key % T::CAP))
+    }
+    fn free_count(&self) -> usize {
+        self.free
+    }
+    fn next_allocated(&self, key: usize) -> Option<usize> {
+        let ind = key / T::CAP;
+        (ind..16).find_map(|i| {
+            let begin = if i == ind { key % T::CAP } else { 0 };
+            match &self.sub[i] {
+                // a missing child is fully allocated
+                None => Some(begin),
+                Some(sub) => sub.next_allocated(begin),
+            }
+            .map(|x| x + i * T::CAP)
+        })
+    }
+    fn prev_allocated(&self, key: usize) -> Option<usize> {
+        let key = key.min(Self::CAP - 1);
+        let ind = key / T::CAP;
+        (0..=ind).rev().find_map(|i| {
+            let end = if i == ind { key % T::CAP } else { T::CAP - 1 };
+            match &self.sub[i] {
+                None => Some(end),
+                Some(sub) => sub.prev_allocated(end),
+            }
+            .map(|x| x + i * T::CAP)
+        })
+    }
+    fn raw_word(&self, w: usize) -> usize {
+        let raw = |i: usize, w: usize| self.sub[i].as_ref().map_or(0, |sub| sub.raw_word(w));
+        if T::CAP >= WORD_BITS {
+            let per = T::CAP / WORD_BITS;
+            raw(w / per, w % per)
+        } else {
+            // several children share a word
+            let per = WORD_BITS / T::CAP;
+            (0..per).fold(0, |word, j| word | raw(w * per + j, 0) << (j * T::CAP))
+        }
+    }
+    fn next_difference(&self, other: &Self, key: usize) -> Option<usize> {
+        let ind = key / T::CAP;
+        (ind..16).find_map(|i| {
+            let begin = if i == ind { key % T::CAP } else { 0 };
+            match (&self.sub[i], &other.sub[i]) {
+                // missing children are alike, fully allocated
+                (None, None) => None,
+                (Some(sub), None) | (None, Some(sub)) => sub.next(begin),
+                (Some(sub), Some(theirs)) => sub.next_difference(theirs, begin),
+            }
+            .map(|x| x + i * T::CAP)
+        })
+    }
+    fn write_raw_word(&mut self, w: usize, word: usize) {
+        // children are only created for words with free bits; `rebuild`
+        // drops those left fully allocated
+        let mut write = |i: usize, w: usize, word: usize| {
+            if word != 0 || self.sub[i].is_some() {
+                self.sub[i]
+                    .get_or_insert_with(T::new_boxed)
+                    .write_raw_word(w, word);
+            }
+        };
+        if T::CAP >= WORD_BITS {
+            let per = T::CAP / WORD_BITS;
+            write(w / per, w % per, word)
+        } else {
+            let per = WORD_BITS / T::CAP;
+            for j in 0..per {
+                let bits = word >> (j * T::CAP) & ((1 << T::CAP) - 1);
+                write(w * per + j, 0, bits);
+            }
+        }
+    }
+    fn rebuild(&mut self) {
+        self.free = 0;
+        for i in 0..16 {
+            if let Some(sub) = &mut self.sub[i] {
+                sub.rebuild();
+                self.free += sub.free_count();
+                if !sub.any() {
+                    self.sub[i] = None;
+                }
+            }
+            self.bitset.set_bit(i, self.sub[i].is_some());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitAlloc16, BitAlloc16M, BitAlloc256, BitAlloc4K};
+
+    #[test]
+    fn lazy() {
+        let mut ba = BitAllocLazy16::<BitAlloc16M>::default();
+        assert_eq!(BitAllocLazy16::<BitAlloc16M>::CAP, 1 << 28);
+        assert_eq!(ba.next_allocated(12345), Some(12345));
+        ba.insert(100..200);
+        ba.insert(200_000_000..200_000_010);
+        assert_eq!(ba.materialized(), 2);
+        assert_eq!(ba.free_count(), 110);
+        assert_eq!(ba.alloc(), Some(200_000_009));
+        assert_eq!(ba.next(200), Some(200_000_000));
+        assert_eq!(ba.prev(199_999_999), Some(199));
+        assert_eq!(ba.next_allocated(100), Some(200));
+        assert_eq!(ba.alloc_contiguous(50, 4), Some(112));
+        ba.remove(200_000_000..);
+        assert_eq!(ba.materialized(), 1);
+        assert!(ba.free_ranges().eq([100..112, 162..200]));
+    }
+
+    #[test]
+    fn lazy_reference() {
+        let mut ba = BitAllocLazy16::<BitAllocLazy16<BitAlloc16>>::default();
+        let mut eager = BitAlloc4K::default();
+        let mut seed = 0x1a2_u32;
+        for _ in 0..300 {
+            let (before, eager_before) = (ba.snapshot(), eager.snapshot());
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let start = (seed >> 8) as usize % 4096;
+            let end = (start + (seed >> 20) as usize % 300).min(4096);
+            match seed & 3 {
+                0 => {
+                    ba.remove(start..end);
+                    eager.remove(start..end);
+                }
+                1 => assert_eq!(ba.alloc_low(), eager.alloc_low()),
+                _ => {
+                    ba.insert(start..end);
+                    eager.insert(start..end);
+                }
+            }
+            assert_eq!(ba.free_count(), eager.free_count());
+            assert_eq!(ba.next(start), eager.next(start));
+            assert_eq!(ba.prev(start), eager.prev(start));
+            assert_eq!(ba.next_allocated(start), eager.next_allocated(start));
+            assert_eq!(ba.prev_allocated(start), eager.prev_allocated(start));
+            assert_eq!(ba.find_contiguous(40, 3), eager.find_contiguous(40, 3));
+            assert_eq!(
+                ba.raw_word(start / WORD_BITS),
+                eager.raw_word(start / WORD_BITS)
+            );
+            assert!(before.diff(&ba).eq(eager_before.diff(&eager)));
+        }
+        let mut words = [0; 4096 / WORD_BITS];
+        eager.as_raw_words(&mut |w, word| words[w] = word);
+        let copy = BitAllocLazy16::<BitAlloc256>::from_raw_words(&words);
+        assert!(copy.iter_free().eq(eager.iter_free()));
+        let nonempty = (0..16).filter(|&i| eager.any_free(i * 256..(i + 1) * 256));
+        assert_eq!(copy.materialized(), nonempty.count());
+    }
+}
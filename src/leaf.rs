@@ -0,0 +1,288 @@
+//! Leaves of one machine word, to use instead of [`BitAlloc16`] where wide
+//! registers make them as cheap, e.g. `BitAllocCascade64<BitAlloc64>`.
+//!
+//! [`BitAlloc16`]: crate::BitAlloc16
+
+use crate::iter::SetBits;
+use crate::{align_size, debug_summary, to_range, AllocPolicy, BitAlloc, HighestFirst, WORD_BITS};
+use core::fmt;
+use core::ops::{Range, RangeBounds};
+
+fn pick_u32<P: AllocPolicy>(word: u32) -> usize {
+    P::pick(word as u64)
+}
+
+fn pick_u64<P: AllocPolicy>(word: u64) -> usize {
+    P::pick(word)
+}
+
+/// Pick a half as a two-child node would, then a bit in it.
+fn pick_u128<P: AllocPolicy>(word: u128) -> usize {
+    let (low, high) = (word as u64, (word >> 64) as u64);
+    match P::pick((low != 0) as u64 | ((high != 0) as u64) << 1) {
+        0 => P::pick(low),
+        _ => 64 + P::pick(high),
+    }
+}
+
+macro_rules! word_leaf {
+    ($(#[$attr:meta])* $name:ident($word:ty), $pick:ident) => {
+        $(#[$attr])*
+        #[derive(Default, Clone, Copy, PartialEq, Eq, Hash)]
+        pub struct $name($word);
+
+        impl $name {
+            /// Create an allocator with all bits allocated.
+            pub const fn new() -> Self {
+                $name(0)
+            }
+
+            /// The bits of `range`, which must be non-empty.
+            fn mask(range: Range<usize>) -> $word {
+                assert!(range.start < range.end);
+                assert!(range.end <= Self::CAP);
+                <$word>::MAX >> (Self::CAP - range.len()) << range.start
+            }
+        }
+
+        impl BitAlloc for $name {
+            const CAP: usize = <$word>::BITS as usize;
+
+            const DEFAULT: Self = $name(0);
+
+            fn alloc(&mut self) -> Option<usize> {
+                self.alloc_with::<HighestFirst>()
+            }
+            fn alloc_with<P: AllocPolicy>(&mut self) -> Option<usize> {
+                let i = self.peek_with::<P>()?;
+                self.0 &= !(1 << i);
+                Some(i)
+            }
+            fn peek_alloc(&self) -> Option<usize> {
+                self.peek_with::<HighestFirst>()
+            }
+            fn peek_with<P: AllocPolicy>(&self) -> Option<usize> {
+                if self.any() {
+                    Some($pick::<P>(self.0))
+                } else {
+                    None
+                }
+            }
+            fn peek_full<P: AllocPolicy>() -> usize {
+                $pick::<P>(<$word>::MAX)
+            }
+            fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+                let base = self.find_contiguous(size, align_log2)?;
+                self.remove(base..base + size);
+                Some(base)
+            }
+            fn alloc_aligned(&mut self, align_log2: usize) -> Option<usize> {
+                // bits at aligned positions, every `step`-th one
+                let mask = match align_size(align_log2) {
+                    Some(step) if step < Self::CAP => <$word>::MAX / ((1 << step) - 1),
+                    Some(step) if step == Self::CAP => 1,
+                    _ => return None,
+                };
+                let word = self.0 & mask;
+                if word == 0 {
+                    return None;
+                }
+                let i = word.trailing_zeros() as usize;
+                self.0 &= !(1 << i);
+                Some(i)
+            }
+            fn dealloc(&mut self, key: usize) {
+                assert!(!self.test(key));
+                self.0 |= 1 << key;
+            }
+            fn insert(&mut self, range: impl RangeBounds<usize>) {
+                let range = to_range(range, Self::CAP);
+                if range.start != range.end {
+                    self.0 |= Self::mask(range);
+                }
+            }
+            fn remove(&mut self, range: impl RangeBounds<usize>) {
+                let range = to_range(range, Self::CAP);
+                if range.start != range.end {
+                    self.0 &= !Self::mask(range);
+                }
+            }
+            fn any(&self) -> bool {
+                self.0 != 0
+            }
+            fn test(&self, key: usize) -> bool {
+                key < Self::CAP && self.0 >> key & 1 != 0
+            }
+            fn free_count(&self) -> usize {
+                self.0.count_ones() as usize
+            }
+            fn count_free_in(&self, range: impl RangeBounds<usize>) -> usize {
+                let range = to_range(range, Self::CAP);
+                if range.start == range.end {
+                    return 0;
+                }
+                (self.0 & Self::mask(range)).count_ones() as usize
+            }
+            fn free_prefix(&self) -> usize {
+                self.0.trailing_ones() as usize
+            }
+            fn free_suffix(&self) -> usize {
+                self.0.leading_ones() as usize
+            }
+            fn max_contiguous_len(&self) -> usize {
+                // every round shortens each run by one
+                let mut x = self.0;
+                let mut len = 0;
+                while x != 0 {
+                    x &= x >> 1;
+                    len += 1;
+                }
+                len
+            }
+            fn next(&self, key: usize) -> Option<usize> {
+                if key >= Self::CAP {
+                    return None;
+                }
+                let word = self.0 & <$word>::MAX << key;
+                (word != 0).then(|| word.trailing_zeros() as usize)
+            }
+            fn prev(&self, key: usize) -> Option<usize> {
+                let word = self.0 & <$word>::MAX >> (Self::CAP - 1 - key.min(Self::CAP - 1));
+                (word != 0).then(|| Self::CAP - 1 - word.leading_zeros() as usize)
+            }
+            fn next_allocated(&self, key: usize) -> Option<usize> {
+                if key >= Self::CAP {
+                    return None;
+                }
+                let word = !self.0 & <$word>::MAX << key;
+                (word != 0).then(|| word.trailing_zeros() as usize)
+            }
+            fn prev_allocated(&self, key: usize) -> Option<usize> {
+                let word = !self.0 & <$word>::MAX >> (Self::CAP - 1 - key.min(Self::CAP - 1));
+                (word != 0).then(|| Self::CAP - 1 - word.leading_zeros() as usize)
+            }
+            fn raw_word(&self, w: usize) -> usize {
+                assert!(w < Self::CAP.div_ceil(WORD_BITS));
+                (self.0 >> (w * WORD_BITS)) as usize
+            }
+            fn write_raw_word(&mut self, w: usize, word: usize) {
+                assert!(w < Self::CAP.div_ceil(WORD_BITS));
+                let shift = w * WORD_BITS;
+                self.0 = self.0 & !((usize::MAX as $word) << shift) | (word as $word) << shift;
+            }
+            fn rebuild(&mut self) {}
+            fn iter_free(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+                (0..Self::CAP.div_ceil(64))
+                    .flat_map(move |k| SetBits::new((self.0 >> (k * 64)) as u64, k * 64))
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                debug_summary(self, stringify!($name), f)
+            }
+        }
+
+        impl fmt::Binary for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                fmt::Binary::fmt(&self.fmt_range(..), f)
+            }
+        }
+
+        impl fmt::LowerHex for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                fmt::LowerHex::fmt(&self.fmt_range(..), f)
+            }
+        }
+    };
+}
+
+word_leaf! {
+    /// A bitmap of 32 bits in a `u32`.
+    BitAlloc32(u32), pick_u32
+}
+
+word_leaf! {
+    /// A bitmap of 64 bits in a `u64`, the natural leaf of
+    /// [`BitAllocCascade64`](crate::BitAllocCascade64).
+    BitAlloc64(u64), pick_u64
+}
+
+word_leaf! {
+    /// A bitmap of 128 bits in a `u128`.
+    BitAlloc128(u128), pick_u128
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitAllocArray, BitAllocCascade, LowestFirst};
+
+    /// Run the same random operations on `A` and a flat bitmap, whose bits
+    /// past `A::CAP` stay allocated.
+    fn check<A: BitAlloc>(mut seed: u32) {
+        let mut ba = A::default();
+        let mut flat = BitAllocArray::<128, 2>::default();
+        let cap = A::CAP;
+        for _ in 0..300 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let start = (seed >> 8) as usize % cap;
+            let end = (start + (seed >> 20) as usize % 20).min(cap);
+            match seed & 3 {
+                0 => {
+                    ba.remove(start..end);
+                    flat.remove(start..end);
+                }
+                1 => assert_eq!(ba.alloc_low(), flat.alloc_low()),
+                _ => {
+                    ba.insert(start..end);
+                    flat.insert(start..end);
+                }
+            }
+            assert_eq!(ba.free_count(), flat.free_count());
+            assert_eq!(ba.count_free_in(start..end), flat.count_free_in(start..end));
+            assert_eq!(ba.next(start), flat.next(start));
+            assert_eq!(ba.prev(start), flat.prev(start));
+            let next_allocated = flat.next_allocated(start).filter(|&i| i < cap);
+            assert_eq!(ba.next_allocated(start), next_allocated);
+            assert_eq!(ba.prev_allocated(start), flat.prev_allocated(start));
+            assert_eq!(ba.max_contiguous_len(), flat.max_contiguous_len());
+            assert_eq!(ba.free_prefix(), flat.free_prefix().min(cap));
+            assert_eq!(ba.peek_alloc(), flat.peek_alloc());
+            assert_eq!(ba.raw_word(0), flat.raw_word(0));
+            assert!(ba.iter_free().rev().eq(flat.iter_free().rev()));
+        }
+        let align_log2 = (seed >> 4) as usize % 8;
+        assert_eq!(ba.alloc_aligned(align_log2), flat.alloc_aligned(align_log2));
+    }
+
+    #[test]
+    fn word_leaves() {
+        check::<BitAlloc32>(32);
+        check::<BitAlloc64>(64);
+        check::<BitAlloc128>(128);
+    }
+
+    #[test]
+    fn word_leaf_policy() {
+        let mut ba = BitAlloc128::default();
+        ba.insert(3..5);
+        ba.insert(100..101);
+        assert_eq!(ba.alloc(), Some(100));
+        assert_eq!(ba.alloc_with::<LowestFirst>(), Some(3));
+        assert_eq!(ba.alloc_aligned(2), Some(4));
+        assert!(!ba.any());
+        ba.write_raw_word(1, 1);
+        assert_eq!(ba.next(0), Some(WORD_BITS));
+    }
+
+    #[test]
+    fn word_leaf_cascade() {
+        let mut ba = BitAllocCascade::<BitAlloc32, 8>::default();
+        ba.insert(30..200);
+        assert_eq!(ba.alloc_contiguous(40, 5), Some(32));
+        assert_eq!(ba.next_allocated(30), Some(32));
+        assert_eq!(ba.raw_word(0) as u32, 0xc000_0000);
+        assert_eq!(ba.free_count(), 130);
+    }
+}
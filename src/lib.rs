@@ -1,371 +1,4698 @@
 #![no_std]
-#![feature(asm)]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
 
 use bit_field::BitField;
-use core::ops::Range;
+#[cfg(all(feature = "bitvec", feature = "alloc"))]
+use bitvec::{order::Lsb0, vec::BitVec};
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::marker::PhantomData;
+use core::ops::{Bound, Range, RangeBounds, RangeInclusive};
+use iter::{Bits, Diff, Runs, SetBits};
 
-/// Allocator of a bitmap, able to allocate / free bits.
-pub trait BitAlloc: Default {
-    /// The bitmap has a total of CAP bits, numbered from 0 to CAP-1 inclusively.
-    const CAP: usize;
+mod array;
+#[cfg(any(target_has_atomic = "ptr", feature = "portable-atomic"))]
+mod atomic;
+mod capped;
+mod checked;
+mod cluster;
+mod collect;
+#[cfg(feature = "defmt")]
+mod defmt_impl;
+mod defrag;
+mod dyn_alloc;
+#[cfg(feature = "alloc")]
+mod dynamic;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod fixed;
+mod frame;
+mod generation;
+mod guard;
+#[cfg(feature = "spin")]
+mod heap;
+mod id;
+mod iter;
+mod journal;
+#[cfg(feature = "alloc")]
+mod lazy;
+mod leaf;
+#[cfg(feature = "spin")]
+mod locked;
+#[cfg(feature = "log")]
+mod logged;
+#[cfg(feature = "test-util")]
+pub mod model;
+#[cfg(feature = "lock_api")]
+mod mutex;
+mod next_fit;
+mod numa;
+mod observed;
+#[cfg(feature = "owner")]
+mod owner;
+mod pinned;
+mod poison;
+mod pressure;
+mod quota;
+mod raw;
+mod rc;
+#[cfg(feature = "alloc")]
+mod region;
+mod reserve;
+mod scrub;
+#[cfg(any(target_has_atomic = "ptr", feature = "portable-atomic"))]
+mod seqlock;
+#[cfg(feature = "serde")]
+mod serde_impl;
+#[cfg(feature = "spin")]
+mod sharded;
+mod simd;
+mod snapshot;
+mod stats;
+mod tagged;
+#[cfg(feature = "trace")]
+mod traced;
+mod transaction;
+mod validate;
+mod view;
+mod wear;
+mod wide;
+mod window;
+#[cfg(feature = "x86_64")]
+mod x86;
+mod zone;
 
-    /// The default value. Workaround for `const fn new() -> Self`.
-    const DEFAULT: Self;
+pub use array::BitAllocArray;
+#[cfg(any(target_has_atomic = "ptr", feature = "portable-atomic"))]
+pub use atomic::{
+    AtomicBitAlloc, AtomicBitAlloc16M, AtomicBitAlloc1M, AtomicBitAlloc256, AtomicBitAlloc256M,
+    AtomicBitAlloc4K, AtomicBitAlloc64K, AtomicBitAllocCascade16,
+};
+pub use capped::BitAllocCapped;
+pub use checked::{CheckHandler, CheckedBitAlloc, PanicOnError};
+pub use cluster::{ClusterAlloc, ClusterSummary};
+pub use defrag::Defrag;
+pub use dyn_alloc::DynBitAlloc;
+#[cfg(feature = "alloc")]
+pub use dynamic::BitAllocVec;
+pub use fixed::BitAllocConst;
+pub use frame::FrameAlloc;
+pub use generation::{GenerationalBitAlloc, Handle, HandleError};
+pub use guard::{BitGuard, RegionGuard};
+#[cfg(feature = "spin")]
+pub use heap::BlockHeap;
+pub use id::IdAllocator;
+pub use iter::Changed;
+pub use journal::{JournalBitAlloc, JournalError};
+#[cfg(feature = "alloc")]
+pub use lazy::BitAllocLazy16;
+pub use leaf::{BitAlloc128, BitAlloc32, BitAlloc64};
+#[cfg(feature = "spin")]
+pub use locked::LockedBitAlloc;
+#[cfg(feature = "log")]
+pub use logged::LoggedBitAlloc;
+#[cfg(feature = "lock_api")]
+pub use mutex::MutexBitAlloc;
+pub use next_fit::NextFit;
+pub use numa::NumaBitAlloc;
+pub use observed::{AllocObserver, ObservedBitAlloc};
+#[cfg(feature = "owner")]
+pub use owner::OwnerBitAlloc;
+pub use pinned::PinnedBitAlloc;
+pub use poison::PoisonBitAlloc;
+pub use pressure::{PressureBitAlloc, PressureHandler};
+pub use quota::{QuotaBitAlloc, QuotaError};
+pub use raw::{BitAllocRaw, Zeroable};
+pub use rc::RcBitAlloc;
+#[cfg(feature = "alloc")]
+pub use region::RegionPool;
+pub use reserve::ReserveBitAlloc;
+pub use scrub::ScrubBitAlloc;
+#[cfg(any(target_has_atomic = "ptr", feature = "portable-atomic"))]
+pub use seqlock::{PlainBitAlloc, SeqLockBitAlloc};
+#[cfg(feature = "spin")]
+pub use sharded::ShardedBitAlloc;
+pub use snapshot::SnapshotError;
+pub use stats::{BitAllocStats, Outstanding, ScopeMarker, StatsBitAlloc};
+pub use tagged::TaggedBitAlloc;
+#[cfg(feature = "trace")]
+pub use traced::{TraceEvent, TraceOp, TracedBitAlloc};
+pub use transaction::Transaction;
+pub use validate::Inconsistency;
+pub use view::BitAllocView;
+pub use wear::WearLevelBitAlloc;
+pub use wide::BitAllocWide;
+pub use window::BitmapWindow;
+pub use zone::{Zone, ZonedBitAlloc};
 
-    /// Allocate a free bit.
-    fn alloc(&mut self) -> Option<usize>;
+/// Errors reported by the fallible `try_*` methods of [`BitAlloc`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitAllocError {
+    /// A bit or range lies outside `0..CAP`, or the range is reversed.
+    OutOfRange,
+    /// A bit that should be allocated is free.
+    AlreadyFree,
+    /// A bit that should be free is allocated.
+    AlreadyAllocated,
+    /// There is no free bit left.
+    NoSpace,
+}
 
-    /// Allocate a free block with a given size, and return the first bit position.
-    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize>;
+impl fmt::Display for BitAllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            BitAllocError::OutOfRange => "index out of range",
+            BitAllocError::AlreadyFree => "bit is already free",
+            BitAllocError::AlreadyAllocated => "bit is already allocated",
+            BitAllocError::NoSpace => "no free bit left",
+        };
+        f.write_str(msg)
+    }
+}
 
-    /// Find a index not less than a given key, where the bit is free.
-    fn next(&self, key: usize) -> Option<usize>;
+/// Returned by the `*_bounded` methods of [`BitAlloc`] when they give up
+/// after inspecting as many nodes as allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BudgetExhausted;
 
-    /// Free an allocated bit.
-    fn dealloc(&mut self, key: usize);
+impl fmt::Display for BudgetExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("search budget exhausted")
+    }
+}
 
-    /// Mark bits in the range as unallocated (available)
-    fn insert(&mut self, range: Range<usize>);
+/// Allocator of a bitmap, able to allocate / free bits.
+///
+/// Only `test`, `insert` and `remove`, with `CAP` and `DEFAULT`, must be
+/// implemented; the other methods are provided in terms of them. The
+/// provided scans go through `raw_word` a word at a time and descend with
+/// `any_free`, so they cost `O(CAP)`; an implementation with summaries
+/// should override `raw_word`, `next`, `prev` and the like, as the types
+/// of this crate do.
+pub trait BitAlloc: Default {
+    /// The bitmap has a total of CAP bits, numbered from 0 to CAP-1 inclusively.
+    const CAP: usize;
 
-    /// Reverse of insert
-    fn remove(&mut self, range: Range<usize>);
+    /// The empty allocator, with every bit allocated, for generic code in
+    /// const contexts. The allocators of this crate also have a `const fn
+    /// new` giving the same.
+    const DEFAULT: Self;
 
-    /// Whether there are free bits remaining
-    fn any(&self) -> bool;
+    /// The number of bits, `CAP` unless a wrapper caps it lower. The
+    /// provided methods check bounds against this, so generic code can use
+    /// it instead of threading `CAP` around.
+    fn capacity(&self) -> usize {
+        Self::CAP
+    }
 
-    /// Whether a specific bit is free
-    fn test(&self, key: usize) -> bool;
-}
+    /// Allocate a free bit.
+    fn alloc(&mut self) -> Option<usize> {
+        let key = self.peek_alloc()?;
+        self.remove(key..key + 1);
+        Some(key)
+    }
 
-/// A bitmap of 256 bits
-pub type BitAlloc256 = BitAllocCascade16<BitAlloc16>;
-/// A bitmap of 4K bits
-pub type BitAlloc4K = BitAllocCascade16<BitAlloc256>;
-/// A bitmap of 64K bits
-pub type BitAlloc64K = BitAllocCascade16<BitAlloc4K>;
-/// A bitmap of 1M bits
-pub type BitAlloc1M = BitAllocCascade16<BitAlloc64K>;
-/// A bitmap of 16M bits
-pub type BitAlloc16M = BitAllocCascade16<BitAlloc1M>;
-/// A bitmap of 256M bits
-pub type BitAlloc256M = BitAllocCascade16<BitAlloc16M>;
+    /// Allocate a free bit, choosing at every level as the policy `P` says.
+    fn alloc_with<P: AllocPolicy>(&mut self) -> Option<usize> {
+        let key = self.peek_with::<P>()?;
+        self.remove(key..key + 1);
+        Some(key)
+    }
 
-/// Implement the bit allocator by segment tree algorithm.
-#[derive(Default)]
-pub struct BitAllocCascade16<T: BitAlloc> {
-    bitset: u16, // for each bit, 1 indicates available, 0 indicates inavailable
-    sub: [T; 16],
-}
+    /// Allocate the lowest free bit.
+    ///
+    /// `alloc` hands out the highest free bit by default; use this one to grow
+    /// allocations upward from index 0 instead.
+    fn alloc_low(&mut self) -> Option<usize> {
+        self.alloc_with::<LowestFirst>()
+    }
 
-impl<T: BitAlloc> BitAlloc for BitAllocCascade16<T> {
-    const CAP: usize = T::CAP * 16;
+    /// An alias of `alloc_low`, for the class of allocations growing upward
+    /// from index 0.
+    ///
+    /// With `alloc_back` for another class, e.g. pinned frames at the front
+    /// and movable ones at the back, the two grow toward each other and stay
+    /// apart without a split point fixed in advance. `alloc_contiguous` and
+    /// `alloc_contiguous_top` place blocks at the front and back the same way.
+    fn alloc_front(&mut self) -> Option<usize> {
+        self.alloc_low()
+    }
 
-    const DEFAULT: Self = BitAllocCascade16 {
-        bitset: 0,
-        sub: [T::DEFAULT; 16],
-    };
+    /// Allocate the highest free bit, for the class of allocations growing
+    /// downward from `CAP - 1`, see `alloc_front`.
+    fn alloc_back(&mut self) -> Option<usize> {
+        self.alloc_with::<HighestFirst>()
+    }
 
-    fn alloc(&mut self) -> Option<usize> {
-        if self.any() {
-            let i = log2(self.bitset);
-            let res = self.sub[i].alloc().unwrap() + i * T::CAP;
-            self.bitset.set_bit(i, self.sub[i].any());
-            Some(res)
-        } else {
-            None
-        }
+    /// Allocate a free bit, freed again when the returned guard is dropped.
+    fn alloc_guarded(&mut self) -> Option<BitGuard<'_, Self>> {
+        let key = self.alloc()?;
+        Some(BitGuard::from_raw(self, key))
     }
-    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
-        if let Some(base) = find_contiguous(self, Self::CAP, size, align_log2) {
-            self.remove(base..base + size);
-            Some(base)
-        } else {
-            None
+
+    /// Allocate up to `out.len()` bits into `out`, and return how many were
+    /// allocated.
+    ///
+    /// Cascades fill the slice child by child, updating their summaries once
+    /// per child instead of once per bit.
+    fn alloc_many(&mut self, out: &mut [usize]) -> usize {
+        let mut n = 0;
+        while n < out.len() {
+            match self.alloc() {
+                Some(i) => out[n] = i,
+                None => break,
+            }
+            n += 1;
         }
+        n
     }
-    fn dealloc(&mut self, key: usize) {
-        let i = key / T::CAP;
-        self.sub[i].dealloc(key % T::CAP);
-        self.bitset.set_bit(i, true);
-    }
-    fn insert(&mut self, range: Range<usize>) {
-        self.for_range(range, |sub: &mut T, range| sub.insert(range));
+
+    /// Like `alloc_many`, choosing bits as the policy `P` says.
+    fn alloc_many_with<P: AllocPolicy>(&mut self, out: &mut [usize]) -> usize {
+        let mut n = 0;
+        while n < out.len() {
+            match self.alloc_with::<P>() {
+                Some(i) => out[n] = i,
+                None => break,
+            }
+            n += 1;
+        }
+        n
     }
-    fn remove(&mut self, range: Range<usize>) {
-        self.for_range(range, |sub: &mut T, range| sub.remove(range));
+
+    /// Allocate the first free bit at or after `hint`.
+    ///
+    /// This does not wrap around: bits below `hint` are never returned.
+    fn alloc_from(&mut self, hint: usize) -> Option<usize> {
+        let i = self.next(hint)?;
+        self.remove(i..i + 1);
+        Some(i)
     }
-    fn any(&self) -> bool {
-        self.bitset != 0
+
+    /// Allocate the free bit closest to `key` in either direction,
+    /// preferring the lower one on ties.
+    fn alloc_near(&mut self, key: usize) -> Option<usize> {
+        let key = key.min(self.capacity().checked_sub(1)?);
+        let forward = self.next(key);
+        // look backward, but no farther than the free bit found forward
+        let limit = forward.map_or(key, |i| (i - key).min(key));
+        let i = (1..=limit)
+            .map(|d| key - d)
+            .find(|&i| self.test(i))
+            .or(forward)?;
+        self.remove(i..i + 1);
+        Some(i)
     }
-    fn test(&self, key: usize) -> bool {
-        self.sub[key / T::CAP].test(key % T::CAP)
+
+    /// Allocate a free bit chosen uniformly at random, e.g. to randomize
+    /// the placement of frames. `rng` returns uniformly random `u64`s.
+    fn alloc_random(&mut self, rng: &mut impl FnMut() -> u64) -> Option<usize> {
+        let free = self.free_count();
+        if free == 0 {
+            return None;
+        }
+        let i = self.nth_free(random_below(rng, free))?;
+        self.remove(i..i + 1);
+        Some(i)
     }
-    fn next(&self, key: usize) -> Option<usize> {
-        let ind = key / T::CAP;
-        if ind < 16 && self.bitset.get_bit(ind) {
-            if let Some(res) = self.sub[ind].next(key - T::CAP * ind) {
-                return Some(res).map(|x| x + T::CAP * ind);
+
+    /// Allocate a free block like `alloc_contiguous`, chosen uniformly at
+    /// random among all the aligned places it fits in.
+    fn alloc_contiguous_random(
+        &mut self,
+        size: usize,
+        align_log2: usize,
+        rng: &mut impl FnMut() -> u64,
+    ) -> Option<usize> {
+        if size == 0 {
+            return None;
+        }
+        let align = align_size(align_log2)?;
+        // the number of aligned blocks that fit in a free run
+        let fits = |run: &Range<usize>| {
+            let first = align_up(run.start, align_log2);
+            match run.end.checked_sub(size) {
+                Some(last) if last >= first => (last - first) / align + 1,
+                _ => 0,
             }
+        };
+        let total: usize = self.free_ranges().map(|run| fits(&run)).sum();
+        if total == 0 {
+            return None;
         }
-        (ind + 1..16).find_map(|i| {
-            if self.bitset.get_bit(i) {
-                self.sub[i].next(0).map(|x| x + T::CAP * i)
-            } else {
+        let mut n = random_below(rng, total);
+        let base = self.free_ranges().find_map(|run| match fits(&run) {
+            count if n < count => Some(align_up(run.start, align_log2) + n * align),
+            count => {
+                n -= count;
                 None
             }
-        })
+        })?;
+        self.remove(base..base + size);
+        Some(base)
     }
-}
 
-impl<T: BitAlloc> BitAllocCascade16<T> {
-    fn for_range(&mut self, range: Range<usize>, f: impl Fn(&mut T, Range<usize>)) {
-        let Range { start, end } = range;
-        assert!(start <= end);
-        assert!(end <= Self::CAP);
-        for i in start / T::CAP..=(end - 1) / T::CAP {
-            let begin = if start / T::CAP == i {
-                start % T::CAP
-            } else {
-                0
-            };
-            let end = if end / T::CAP == i {
-                end % T::CAP
-            } else {
-                T::CAP
-            };
-            f(&mut self.sub[i], begin..end);
-            self.bitset.set_bit(i, self.sub[i].any());
+    /// The free bit with `n` free bits below it, if there are that many.
+    fn nth_free(&self, n: usize) -> Option<usize> {
+        self.iter_free().nth(n)
+    }
+
+    /// Return the bit that the next `alloc` would allocate, without allocating it.
+    fn peek_alloc(&self) -> Option<usize> {
+        self.peek_with::<HighestFirst>()
+    }
+
+    /// An alias of `peek_alloc`, following what `alloc` would choose, e.g.
+    /// to know the candidate before taking a second lock to allocate it.
+    fn peek(&self) -> Option<usize> {
+        self.peek_alloc()
+    }
+
+    /// Return the bit that `alloc_with::<P>` would allocate, without allocating it.
+    ///
+    /// The provided one splits the bits into 64 parts at every level, as a
+    /// cascade of 64 children would, and lets `P` pick among those with a
+    /// free bit.
+    fn peek_with<P: AllocPolicy>(&self) -> Option<usize> {
+        let mut range = 0..self.capacity();
+        if !self.any_free(range.clone()) {
+            return None;
+        }
+        while range.len() > 1 {
+            let part = range.len().div_ceil(64);
+            let parts = (0..range.len().div_ceil(part))
+                .map(|i| range.start + i * part..(range.start + (i + 1) * part).min(range.end));
+            let bits = parts
+                .clone()
+                .enumerate()
+                .filter(|(_, part)| self.any_free(part.clone()))
+                .fold(0, |bits, (i, _)| bits | 1 << i);
+            range = parts.clone().nth(P::pick(bits)).unwrap();
         }
+        Some(range.start)
     }
-}
 
-/// A bitmap consisting of only 16 bits.
-/// BitAlloc16 acts as the leaf (except the leaf bits of course) nodes
-/// in the segment trees.
-#[derive(Default)]
-pub struct BitAlloc16(u16);
+    /// Return the bit that `peek_with::<P>` returns when every bit is free.
+    ///
+    /// Cascades use it for children they only know to be entirely free.
+    /// Like the other hooks the cascades call on their children, it is
+    /// hidden from the docs and not meant to be called directly.
+    #[doc(hidden)]
+    fn peek_full<P: AllocPolicy>() -> usize {
+        P::pick(u64::MAX >> (64 - Self::CAP.min(64)))
+    }
 
-impl BitAlloc for BitAlloc16 {
-    const CAP: usize = 16;
+    /// The capacity of the nodes `depth` levels below the root, `CAP` at
+    /// depth 0. Leaves have no children, so it stops shrinking below them.
+    #[doc(hidden)]
+    fn subtree_cap(depth: usize) -> usize {
+        let _ = depth;
+        Self::CAP
+    }
 
-    const DEFAULT: Self = BitAlloc16(0);
+    /// Allocate a free block with a given size, and return the first bit position.
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        let base = self.find_contiguous(size, align_log2)?;
+        self.remove(base..base + size);
+        Some(base)
+    }
 
-    fn alloc(&mut self) -> Option<usize> {
-        if self.any() {
-            let i = log2(self.0);
-            self.0.set_bit(i, false);
-            Some(i)
+    /// Return the block that `alloc_contiguous` would allocate, without
+    /// allocating it.
+    fn find_contiguous(&self, size: usize, align_log2: usize) -> Option<usize> {
+        if align_size(align_log2).is_none_or(|align| align > self.capacity())
+            || size == 0
+            || !self.any()
+        {
+            None
         } else {
+            self.search_contiguous(0, size, align_log2, &mut None)
+        }
+    }
+
+    /// Allocate a free block like `alloc_contiguous`, but aligned as if bit 0
+    /// were at index `phase`: the block starts at a bit `base` such that
+    /// `base + phase` is a multiple of `1 << align_log2`. This is what a
+    /// bitmap of frames gives aligned physical addresses with when its first
+    /// frame is not itself aligned, `phase` being its frame number.
+    fn alloc_contiguous_offset(
+        &mut self,
+        size: usize,
+        align_log2: usize,
+        phase: usize,
+    ) -> Option<usize> {
+        let base = self.find_contiguous_offset(size, align_log2, phase)?;
+        self.remove(base..base + size);
+        Some(base)
+    }
+
+    /// Return the block that `alloc_contiguous_offset` would allocate,
+    /// without allocating it.
+    fn find_contiguous_offset(
+        &self,
+        size: usize,
+        align_log2: usize,
+        phase: usize,
+    ) -> Option<usize> {
+        let align = align_size(align_log2)?;
+        // only the phase within an alignment matters
+        let phase = phase & (align - 1);
+        if size == 0 || !self.any() {
             None
+        } else if align > self.capacity() {
+            // at most one bit is aligned
+            let base = (align - phase) & (align - 1);
+            let end = base
+                .checked_add(size)
+                .filter(|&end| end <= self.capacity())?;
+            self.all_free(base..end).then_some(base)
+        } else {
+            self.search_contiguous(phase, size, align_log2, &mut None)
+                .map(|base| base - phase)
         }
     }
-    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
-        if let Some(base) = find_contiguous(self, Self::CAP, size, align_log2) {
+
+    /// Allocate a free block like `alloc_contiguous`, but give up once
+    /// `max_steps` nodes have been inspected, bounding the time taken for
+    /// callers that can't afford a long search, e.g. in interrupt context.
+    ///
+    /// A step is a child of a cascade looked at, or a free run of a leaf.
+    /// Giving up changes nothing.
+    fn alloc_contiguous_bounded(
+        &mut self,
+        size: usize,
+        align_log2: usize,
+        max_steps: usize,
+    ) -> Result<Option<usize>, BudgetExhausted> {
+        let base = self.find_contiguous_bounded(size, align_log2, max_steps)?;
+        if let Some(base) = base {
             self.remove(base..base + size);
-            Some(base)
+        }
+        Ok(base)
+    }
+
+    /// Return the block that `alloc_contiguous_bounded` would allocate,
+    /// without allocating it.
+    fn find_contiguous_bounded(
+        &self,
+        size: usize,
+        align_log2: usize,
+        max_steps: usize,
+    ) -> Result<Option<usize>, BudgetExhausted> {
+        if align_size(align_log2).is_none_or(|align| align > self.capacity())
+            || size == 0
+            || !self.any()
+        {
+            Ok(None)
         } else {
-            None
+            self.search_contiguous_bounded(0, size, align_log2, &mut None, &mut { max_steps })
         }
     }
-    fn dealloc(&mut self, key: usize) {
-        assert!(!self.test(key));
-        self.0.set_bit(key, true);
+
+    /// Find a index not less than a given key, where the bit is free, like
+    /// `next`, but give up once `max_steps` nodes have been inspected.
+    fn next_bounded(&self, key: usize, max_steps: usize) -> Result<Option<usize>, BudgetExhausted> {
+        self.next_budgeted(key, &mut { max_steps })
     }
-    fn insert(&mut self, range: Range<usize>) {
-        self.0.set_bits(range.clone(), 0xffff.get_bits(range));
+
+    /// Allocate the block of `size` bits starting at `base`, if all of them are free.
+    ///
+    /// Returns `false` and changes nothing if any bit in the block is
+    /// allocated or out of range.
+    fn alloc_contiguous_at(&mut self, base: usize, size: usize) -> bool {
+        let end = match base.checked_add(size) {
+            Some(end) if end <= self.capacity() => end,
+            _ => return false,
+        };
+        match self.next_allocated(base) {
+            Some(i) if i < end => false,
+            _ => {
+                self.remove(base..end);
+                true
+            }
+        }
     }
-    fn remove(&mut self, range: Range<usize>) {
-        self.0.set_bits(range, 0);
+
+    /// Allocate a free block like `alloc_contiguous`, but from the smallest
+    /// free run that can hold it, to keep large runs intact.
+    ///
+    /// Ties go to the lowest run. This walks every free run, so it costs more
+    /// than the first-fit search.
+    fn alloc_contiguous_best_fit(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        if size == 0 || size > self.max_contiguous_len() {
+            return None;
+        }
+        let mut best: Option<(usize, usize)> = None; // (run length, base)
+        let mut key = 0;
+        while let Some(start) = self.next(key) {
+            let end = self.next_allocated(start).unwrap_or(self.capacity());
+            let base = align_up(start, align_log2);
+            if base.saturating_add(size) <= end && best.is_none_or(|(len, _)| end - start < len) {
+                best = Some((end - start, base));
+                if end - start == size {
+                    break;
+                }
+            }
+            key = end;
+        }
+        let (_, base) = best?;
+        self.remove(base..base + size);
+        Some(base)
     }
-    fn any(&self) -> bool {
-        self.0 != 0
+
+    /// Allocate a free block of `min..=max` bits aligned to
+    /// `1 << align_log2`, as large as there is room for, and return its
+    /// range.
+    ///
+    /// Ties go to the lowest block. This walks the free runs once, stopping
+    /// at the first that holds `max` bits.
+    fn alloc_contiguous_flex(
+        &mut self,
+        size: RangeInclusive<usize>,
+        align_log2: usize,
+    ) -> Option<Range<usize>> {
+        let range = self.find_contiguous_flex(size, align_log2)?;
+        self.remove(range.clone());
+        Some(range)
     }
-    fn test(&self, key: usize) -> bool {
-        self.0.get_bit(key)
+
+    /// Return the block that `alloc_contiguous_flex` would allocate,
+    /// without allocating it.
+    fn find_contiguous_flex(
+        &self,
+        size: RangeInclusive<usize>,
+        align_log2: usize,
+    ) -> Option<Range<usize>> {
+        let (min, max) = (*size.start().max(&1), *size.end());
+        if min > max || align_size(align_log2).is_none() || min > self.max_contiguous_len() {
+            return None;
+        }
+        let mut best: Option<Range<usize>> = None;
+        let mut key = 0;
+        while let Some(start) = self.next(key) {
+            let end = self.next_allocated(start).unwrap_or(self.capacity());
+            let base = align_up(start, align_log2);
+            let len = end.saturating_sub(base).min(max);
+            if len >= min && best.as_ref().is_none_or(|best| len > best.len()) {
+                best = Some(base..base + len);
+                if len == max {
+                    break;
+                }
+            }
+            key = end;
+        }
+        best
     }
-    fn next(&self, key: usize) -> Option<usize> {
-        for i in key..16 {
-            if self.0.get_bit(i) {
-                return Some(i);
+
+    /// Allocate `total_size` free bits as at most `max_fragments` blocks
+    /// aligned to `1 << align_log2`, e.g. for a device doing scatter-gather
+    /// DMA, and write them to `out` in ascending order. Returns the number
+    /// of blocks, or `None`, allocating nothing, if there is no room.
+    ///
+    /// The blocks are taken from the longest free runs first, so a single
+    /// one is used when it fits. This walks the free runs once, keeping the
+    /// longest in `out`, so no more than `out.len()` blocks are used, and
+    /// the entries of `out` past those returned are left unspecified.
+    fn alloc_scatter(
+        &mut self,
+        total_size: usize,
+        max_fragments: usize,
+        align_log2: usize,
+        out: &mut [Range<usize>],
+    ) -> Option<usize> {
+        let max = max_fragments.min(out.len());
+        if total_size == 0 || max == 0 || align_size(align_log2).is_none() {
+            return None;
+        }
+        if total_size > self.free_count() {
+            return None;
+        }
+        // the longest runs so far, longest first
+        let mut n = 0;
+        let mut key = 0;
+        while let Some(start) = self.next(key) {
+            let end = self.next_allocated(start).unwrap_or(self.capacity());
+            let base = align_up(start, align_log2);
+            if base < end {
+                let i = out[..n]
+                    .iter()
+                    .position(|run| run.len() < end - base)
+                    .unwrap_or(n);
+                if i < max {
+                    n = (n + 1).min(max);
+                    out[i..n].rotate_right(1);
+                    out[i] = base..end;
+                }
             }
+            key = end;
         }
-        None
+        let mut left = total_size;
+        let mut used = 0;
+        while used < n && left > 0 {
+            let len = out[used].len().min(left);
+            out[used].end = out[used].start + len;
+            left -= len;
+            used += 1;
+        }
+        if left > 0 {
+            return None;
+        }
+        let out = &mut out[..used];
+        out.sort_unstable_by_key(|block| block.start);
+        for block in out.iter() {
+            self.remove(block.clone());
+        }
+        Some(used)
+    }
+
+    /// Allocate a free block like `alloc_contiguous`, but the highest one
+    /// that fits, e.g. to keep memory that may be vacated later at the top.
+    ///
+    /// This walks the free runs down from the top, skipping those that are
+    /// too short.
+    fn alloc_contiguous_top(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        let base = self.find_contiguous_top(size, align_log2)?;
+        self.remove(base..base + size);
+        Some(base)
     }
-}
 
-fn find_contiguous<T: BitAlloc>(
-    ba: &T,
-    capacity: usize,
-    size: usize,
-    align_log2: usize,
-) -> Option<usize> {
-    if capacity < (1 << align_log2) || !ba.any() {
+    /// Return the block that `alloc_contiguous_top` would allocate, without
+    /// allocating it.
+    fn find_contiguous_top(&self, size: usize, align_log2: usize) -> Option<usize> {
+        if align_size(align_log2).is_none_or(|align| align > self.capacity())
+            || size == 0
+            || size > self.max_contiguous_len()
+        {
+            return None;
+        }
+        let mut key = self.capacity() - 1;
+        while let Some(last) = self.prev(key) {
+            let start = self.prev_allocated(last).map_or(0, |k| k + 1);
+            if last + 1 - start >= size {
+                let base = (last + 1 - size) >> align_log2 << align_log2;
+                if base >= start {
+                    return Some(base);
+                }
+            }
+            key = start.checked_sub(1)?;
+        }
         None
-    } else {
-        let mut base = 0;
-        let mut offset = base;
-        while offset < capacity {
-            if let Some(next) = ba.next(offset) {
-                if next != offset {
-                    // it can be guarenteed that no bit in (offset..next) is free
-                    // move to next aligned position after next-1
-                    assert!(next > offset);
-                    base = (((next - 1) >> align_log2) + 1) << align_log2;
-                    assert_ne!(offset, next);
-                    offset = base;
-                    continue;
+    }
+
+    /// Allocate a naturally aligned block of `1 << order` bits, as a buddy
+    /// allocator does, and return its base.
+    ///
+    /// The block is split off the smallest free buddy block that holds it,
+    /// i.e. the smallest aligned power-of-two block of free bits whose buddy
+    /// is not entirely free, the lowest one on ties, so that larger blocks
+    /// stay whole for later. Freed blocks merge with their buddies by
+    /// themselves, as free bits do. The block is taken by
+    /// `alloc_contiguous_at`.
+    fn alloc_order(&mut self, order: usize) -> Option<usize> {
+        let size = align_size(order)?;
+        if size > self.max_contiguous_len() {
+            return None;
+        }
+        let mut best: Option<(usize, usize)> = None; // (order, base)
+        for run in self.free_ranges() {
+            // the buddy blocks making up the run
+            let mut key = run.start;
+            while key < run.end {
+                let block = (key.trailing_zeros() as usize).min((run.end - key).ilog2() as usize);
+                if block >= order && best.is_none_or(|(best, _)| block < best) {
+                    best = Some((block, key));
                 }
-            } else {
-                return None;
+                key += 1 << block;
             }
-            offset += 1;
-            if offset - base == size {
-                return Some(base);
+            if best.is_some_and(|(best, _)| best == order) {
+                break;
             }
         }
-        None
+        let (_, base) = best?;
+        self.alloc_contiguous_at(base, size).then_some(base)
     }
-}
 
-#[inline(always)]
-#[cfg(target_arch = "x86_64")]
-fn log2(x: u16) -> usize {
-    assert_ne!(x, 0);
-    let pos: u16;
-    unsafe { asm!("bsrw $1, $0" :"=r"(pos) :"r"(x) : :"volatile") };
-    pos as usize
-}
+    /// Free a block from `alloc_order`.
+    fn dealloc_order(&mut self, base: usize, order: usize) {
+        debug_assert_eq!(base % (1 << order), 0, "block is not aligned to its order");
+        self.dealloc_contiguous(base, 1 << order)
+    }
 
-#[inline(always)]
-#[cfg(not(target_arch = "x86_64"))]
-fn log2(x: u16) -> usize {
-    log2_naive(x)
-}
+    /// Allocate a free bit whose position is aligned to `1 << align_log2`,
+    /// and return the lowest such position.
+    ///
+    /// This is equivalent to `alloc_contiguous(1, align_log2)`.
+    fn alloc_aligned(&mut self, align_log2: usize) -> Option<usize> {
+        self.alloc_contiguous(1, align_log2)
+    }
 
-#[cfg(not(target_arch = "x86_64"))]
-#[inline(always)]
-fn log2_naive(mut x: u16) -> usize {
-    //a naive implement
-    assert_ne!(x, 0);
-    let mut pos = -1;
-    while x != 0 {
-        pos += 1;
-        x >>= 1;
+    /// Move a contiguous allocation of `size` bits at `old_base` to the lowest
+    /// free aligned block below it, and return the new base.
+    ///
+    /// Returns `None` and leaves the allocator untouched if there is no such
+    /// block, i.e. `old_base` is already the lowest fit.
+    fn relocate_contiguous(
+        &mut self,
+        old_base: usize,
+        size: usize,
+        align_log2: usize,
+    ) -> Option<usize> {
+        debug_assert!((old_base..old_base + size).all(|i| !self.test(i)));
+        let base = self.find_contiguous(size, align_log2)?;
+        if base >= old_base {
+            return None;
+        }
+        self.remove(base..base + size);
+        self.insert(old_base..old_base + size);
+        Some(base)
     }
-    pos as usize
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Find a index not less than a given key, where the bit is free.
+    fn next(&self, key: usize) -> Option<usize> {
+        next_set(key, self.capacity(), |w| self.raw_word(w))
+    }
 
-    #[cfg(not(target_arch = "x86_64"))]
-    #[test]
-    fn log2_() {
-        for x in 1..=0xffff {
-            assert_eq!(log2(x), log2_naive(x), "log2 failed: {}", x);
-        }
+    /// Find the greatest index not greater than a given key, where the bit is free.
+    /// Keys beyond the end are treated as the last bit.
+    fn prev(&self, key: usize) -> Option<usize> {
+        prev_set(key, self.capacity(), |w| self.raw_word(w))
     }
 
-    #[test]
-    fn bitalloc16() {
-        let mut ba = BitAlloc16::default();
-        assert_eq!(BitAlloc16::CAP, 16);
-        ba.insert(0..16);
-        for i in 0..16 {
-            assert_eq!(ba.test(i), true);
+    /// The highest free bit.
+    fn last(&self) -> Option<usize> {
+        self.prev(self.capacity().checked_sub(1)?)
+    }
+
+    /// Free an allocated bit.
+    fn dealloc(&mut self, key: usize) {
+        assert!(!self.test(key));
+        self.insert(key..key + 1);
+    }
+
+    /// Free many allocated bits.
+    ///
+    /// Cascades update their summaries once per run of consecutive keys that
+    /// fall into the same child, so sorted keys are the cheapest.
+    fn dealloc_many(&mut self, keys: impl IntoIterator<Item = usize>) {
+        for key in keys {
+            self.dealloc(key);
         }
-        ba.remove(8..14);
-        assert_eq!(ba.alloc(), Some(15));
-        assert_eq!(ba.alloc(), Some(14));
-        assert_eq!(ba.alloc(), Some(7));
-        ba.dealloc(14);
-        ba.dealloc(15);
-        ba.dealloc(7);
+    }
 
-        for _ in 0..10 {
-            assert!(ba.alloc().is_some());
+    /// Free a block of `size` allocated bits starting at `base`,
+    /// the counterpart of `alloc_contiguous`.
+    fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        debug_assert!(
+            self.next(base).is_none_or(|i| i >= base + size),
+            "freeing a block that is not fully allocated"
+        );
+        self.insert(base..base + size);
+    }
+
+    /// Allocate a free block like `alloc_contiguous`, and return the range of
+    /// its bits, so that `dealloc_range` frees exactly it without the size
+    /// kept apart.
+    fn alloc_contiguous_range(&mut self, size: usize, align_log2: usize) -> Option<Range<usize>> {
+        let base = self.alloc_contiguous(size, align_log2)?;
+        Some(base..base + size)
+    }
+
+    /// Free a block from `alloc_contiguous_range`.
+    fn dealloc_range(&mut self, range: Range<usize>) {
+        self.dealloc_contiguous(range.start, range.len())
+    }
+
+    /// Allocate a block like `alloc_contiguous`, freed again when the
+    /// returned guard is dropped.
+    fn alloc_contiguous_guarded(
+        &mut self,
+        size: usize,
+        align_log2: usize,
+    ) -> Option<RegionGuard<'_, Self>> {
+        let base = self.alloc_contiguous(size, align_log2)?;
+        Some(RegionGuard::from_raw(self, base..base + size))
+    }
+
+    /// Allocate a free block like `alloc_contiguous`, with `guard` more free
+    /// bits on either side, and return the base of the block, e.g. for a
+    /// stack between guard pages. The guard bits are allocated along with
+    /// the block, so that no later block can be adjacent to it; free them
+    /// together with `dealloc_contiguous_guarded_gap`.
+    ///
+    /// The first-fit search walks the free runs, as `alloc_contiguous_best_fit`
+    /// does.
+    fn alloc_contiguous_guarded_gap(
+        &mut self,
+        size: usize,
+        align_log2: usize,
+        guard: usize,
+    ) -> Option<usize> {
+        let total = guard.checked_mul(2)?.checked_add(size)?;
+        if size == 0 || align_size(align_log2).is_none() || total > self.max_contiguous_len() {
+            return None;
         }
-        assert!(!ba.any());
-        assert!(ba.alloc().is_none());
+        let mut key = 0;
+        while let Some(start) = self.next(key) {
+            let end = self.next_allocated(start).unwrap_or(self.capacity());
+            let base = align_up(start + guard, align_log2);
+            if base.saturating_add(size + guard) <= end {
+                self.remove(base - guard..base + size + guard);
+                return Some(base);
+            }
+            key = end;
+        }
+        None
+    }
+
+    /// Free a block from `alloc_contiguous_guarded_gap` along with its
+    /// guard bits, given the same `size` and `guard`. The guard bits must
+    /// still be allocated: freeing one of them apart is a bug.
+    fn dealloc_contiguous_guarded_gap(&mut self, base: usize, size: usize, guard: usize) {
+        debug_assert!(
+            !self.any_free(base - guard..base) && !self.any_free(base + size..base + size + guard),
+            "freeing a guarded block whose guard bits are free"
+        );
+        self.dealloc_contiguous(base - guard, size + 2 * guard)
+    }
+
+    /// Mark bits in the range as unallocated (available).
+    /// Any range form works, e.g. `..`, `a..=b` or `a..`.
+    ///
+    /// An empty range is a no-op, even past the end. Otherwise the range must
+    /// lie within `0..CAP`, or this panics; use `try_insert` to get an error
+    /// instead.
+    fn insert(&mut self, range: impl RangeBounds<usize>);
+
+    /// Reverse of insert. Use `try_remove` to get an error instead of a panic
+    /// on out-of-range input.
+    fn remove(&mut self, range: impl RangeBounds<usize>);
+
+    /// Like `insert`, but fail without changing anything if some bit in the
+    /// range is already free, returning the first such run of free bits,
+    /// clipped to the range. This catches regions registered twice.
+    fn insert_strict(&mut self, range: impl RangeBounds<usize>) -> Result<(), Range<usize>> {
+        let range = to_range(range, self.capacity());
+        if let Some(start) = self.next(range.start).filter(|&i| i < range.end) {
+            let end = self
+                .next_allocated(start)
+                .map_or(range.end, |i| i.min(range.end));
+            return Err(start..end);
+        }
+        self.insert(range);
+        Ok(())
+    }
+
+    /// Like `insert`, returning how many bits were allocated before, i.e. how
+    /// many actually became free.
+    fn insert_counted(&mut self, range: impl RangeBounds<usize>) -> usize {
+        let before = self.free_count();
+        self.insert(range);
+        self.free_count() - before
+    }
+
+    /// Mark every bit as allocated, as `remove(..)` does, but resetting the
+    /// whole structure at once where it can.
+    fn clear(&mut self) {
+        self.remove(..)
+    }
+
+    /// Mark every bit as free, as `insert(..)` does, but resetting the
+    /// whole structure at once where it can.
+    fn fill(&mut self) {
+        self.insert(..)
+    }
+
+    /// Like `remove`, returning how many bits were free before, i.e. how many
+    /// actually became allocated.
+    fn remove_counted(&mut self, range: impl RangeBounds<usize>) -> usize {
+        let before = self.free_count();
+        self.remove(range);
+        before - self.free_count()
+    }
+
+    /// Whether there are free bits remaining
+    fn any(&self) -> bool {
+        self.next(0).is_some()
+    }
+
+    /// Whether a specific bit is free
+    fn test(&self, key: usize) -> bool;
+
+    /// Whether every bit in the range is free. True for an empty range.
+    fn all_free(&self, range: impl RangeBounds<usize>) -> bool {
+        let range = to_range(range, self.capacity());
+        self.next_allocated(range.start)
+            .is_none_or(|i| i >= range.end)
+    }
+
+    /// The number of free bits in the range.
+    fn count_free_in(&self, range: impl RangeBounds<usize>) -> usize {
+        let range = to_range(range, self.capacity());
+        let mut count = 0;
+        let mut key = range.start;
+        while let Some(start) = self.next(key).filter(|&i| i < range.end) {
+            let end = self
+                .next_allocated(start)
+                .map_or(range.end, |i| i.min(range.end));
+            count += end - start;
+            key = end;
+        }
+        count
+    }
+
+    /// The free count of each node `depth` levels below the root, with the
+    /// range of bits it covers, in ascending order, e.g. to draw a heatmap.
+    ///
+    /// Depth 0 is the whole allocator, depth 1 the children of a cascade,
+    /// and so on down to the leaves; beyond them, the leaves are repeated.
+    /// Cascades count each node from the free counts of the nodes above it.
+    fn occupancy(&self, depth: usize) -> impl Iterator<Item = (Range<usize>, usize)> + '_ {
+        let (cap, step) = (self.capacity(), Self::subtree_cap(depth));
+        (0..cap.div_ceil(step)).map(move |i| {
+            let range = i * step..((i + 1) * step).min(cap);
+            (range.clone(), self.count_free_in(range))
+        })
+    }
+
+    /// Whether at least one bit in the range is free. False for an empty range.
+    fn any_free(&self, range: impl RangeBounds<usize>) -> bool {
+        let range = to_range(range, self.capacity());
+        self.next(range.start).is_some_and(|i| i < range.end)
+    }
+
+    /// The number of free bits.
+    fn free_count(&self) -> usize {
+        (0..self.capacity().div_ceil(WORD_BITS))
+            .map(|w| self.raw_word(w).count_ones() as usize)
+            .sum()
+    }
+
+    /// The number of allocated bits.
+    fn allocated_count(&self) -> usize {
+        self.capacity() - self.free_count()
+    }
+
+    /// Allocate a free bit, or report `NoSpace`.
+    fn try_alloc(&mut self) -> Result<usize, BitAllocError> {
+        self.alloc().ok_or(BitAllocError::NoSpace)
+    }
+
+    /// Free an allocated bit, or report why it can't be freed.
+    fn try_dealloc(&mut self, key: usize) -> Result<(), BitAllocError> {
+        if key >= self.capacity() {
+            return Err(BitAllocError::OutOfRange);
+        }
+        if self.test(key) {
+            return Err(BitAllocError::AlreadyFree);
+        }
+        self.dealloc(key);
+        Ok(())
+    }
+
+    /// Mark bits in the range as available, requiring that all of them were
+    /// unavailable. Nothing is changed on error.
+    fn try_insert(&mut self, range: impl RangeBounds<usize>) -> Result<(), BitAllocError> {
+        let range = to_range(range, self.capacity());
+        check_range(&range, self.capacity())?;
+        if self.any_free(range.clone()) {
+            return Err(BitAllocError::AlreadyFree);
+        }
+        self.insert(range);
+        Ok(())
+    }
+
+    /// Mark bits in the range as unavailable, requiring that all of them were
+    /// available. Nothing is changed on error.
+    fn try_remove(&mut self, range: impl RangeBounds<usize>) -> Result<(), BitAllocError> {
+        let range = to_range(range, self.capacity());
+        check_range(&range, self.capacity())?;
+        if !self.all_free(range.clone()) {
+            return Err(BitAllocError::AlreadyAllocated);
+        }
+        self.remove(range);
+        Ok(())
+    }
+
+    /// Begin a transaction, whose steps are all undone unless it is
+    /// committed.
+    fn begin(&mut self) -> Transaction<'_, Self> {
+        Transaction::new(self)
+    }
+
+    /// Find a index not less than a given key, where the bit is allocated.
+    fn next_allocated(&self, key: usize) -> Option<usize> {
+        let cap = self.capacity();
+        next_set(key, cap, |w| !self.raw_word(w)).filter(|&i| i < cap)
+    }
+
+    /// Find the greatest index not greater than a given key, where the bit
+    /// is allocated. Keys beyond the end are treated as the last bit.
+    fn prev_allocated(&self, key: usize) -> Option<usize> {
+        prev_set(key, self.capacity(), |w| !self.raw_word(w))
+    }
+
+    /// Raw word `w` of the bitmap: bit `i` is 1 if bit `w * usize::BITS + i`
+    /// is free. Bits past `CAP` are 0.
+    ///
+    /// This is the word-at-a-time view, e.g. for a loop mapping a word of
+    /// frames at once that takes a fast path when all of them are free,
+    /// without `usize::BITS` calls of `test`. Cascades read it straight
+    /// from the leaf, or fill it in from the summaries of uniform children.
+    fn raw_word(&self, w: usize) -> usize {
+        let start = (w * WORD_BITS).min(self.capacity());
+        let end = (start + WORD_BITS).min(self.capacity());
+        (start..end)
+            .filter(|&i| self.test(i))
+            .fold(0, |word, i| word | 1 << (i % WORD_BITS))
+    }
+
+    /// The free bits of the machine word of indices
+    /// `word_index * usize::BITS..`, the same as `raw_word`.
+    fn get_word(&self, word_index: usize) -> usize {
+        self.raw_word(word_index)
+    }
+
+    /// Overwrite raw word `w` of the bitmap, ignoring bits past `CAP`.
+    ///
+    /// Only the leaves are written. The summaries of cascades are left stale,
+    /// and the allocator must not be used until `rebuild` is called.
+    fn write_raw_word(&mut self, w: usize, word: usize) {
+        let start = (w * WORD_BITS).min(self.capacity());
+        let end = (start + WORD_BITS).min(self.capacity());
+        for i in start..end {
+            if word.get_bit(i % WORD_BITS) {
+                self.insert(i..i + 1);
+            } else {
+                self.remove(i..i + 1);
+            }
+        }
+    }
+
+    /// Recompute all summaries from the leaves, after `write_raw_word`.
+    /// Nothing to do for an allocator without summaries.
+    fn rebuild(&mut self) {}
+
+    /// Check that every summary agrees with what it summarizes, e.g. after
+    /// `write_raw_word` or to chase memory corruption, and report the first
+    /// disagreement, from the lowest bit and the deepest level up.
+    fn validate(&self) -> Result<(), Inconsistency> {
+        Ok(())
+    }
+
+    /// Pass every raw word to `f` along with its index, in ascending order.
+    /// There are `capacity().div_ceil(usize::BITS)` of them.
+    fn as_raw_words(&self, f: &mut impl FnMut(usize, usize)) {
+        for w in 0..self.capacity().div_ceil(WORD_BITS) {
+            f(w, self.raw_word(w));
+        }
+    }
+
+    /// Replace the whole bitmap by `words`, in the layout of `raw_word`, and
+    /// rebuild the summaries once. This is much faster than inserting every
+    /// free run of a bitmap built elsewhere. There must be as many words as
+    /// `as_raw_words` passes.
+    fn load_raw_words(&mut self, words: &[usize]) {
+        assert_eq!(words.len(), self.capacity().div_ceil(WORD_BITS));
+        for (w, &word) in words.iter().enumerate() {
+            self.write_raw_word(w, word);
+        }
+        self.rebuild();
+    }
+
+    /// Create an allocator from a `BitSlice` of usable flags, bit `i` being
+    /// free if `usable[i]` is set, whatever the order of the slice. Bits
+    /// past its end are allocated. Enabled by the `bitvec` feature.
+    #[cfg(feature = "bitvec")]
+    fn from_usable<T, O>(usable: &bitvec::slice::BitSlice<T, O>) -> Self
+    where
+        T: bitvec::store::BitStore,
+        O: bitvec::order::BitOrder,
+    {
+        assert!(usable.len() <= Self::CAP, "more flags than bits");
+        let mut ba = Self::default();
+        for (w, chunk) in usable.chunks(WORD_BITS).enumerate() {
+            ba.write_raw_word(w, chunk.iter_ones().fold(0, |word, i| word | 1 << i));
+        }
+        ba.rebuild();
+        ba
+    }
+
+    /// Copy the state of the first `out.len()` bits to `out`, set for the
+    /// free ones, the counterpart of `from_usable`.
+    #[cfg(feature = "bitvec")]
+    fn copy_to_bitslice<T, O>(&self, out: &mut bitvec::slice::BitSlice<T, O>)
+    where
+        T: bitvec::store::BitStore,
+        O: bitvec::order::BitOrder,
+    {
+        assert!(out.len() <= Self::CAP, "more flags than bits");
+        for (w, chunk) in out.chunks_mut(WORD_BITS).enumerate() {
+            let word = self.raw_word(w);
+            for (i, mut flag) in chunk.iter_mut().enumerate() {
+                *flag = word.get_bit(i);
+            }
+        }
+    }
+
+    /// The state of every bit as a `BitVec`, set for the free ones, in
+    /// `Lsb0` order over `u64`, so that its raw words on a 64-bit target
+    /// are those of `raw_word`.
+    #[cfg(all(feature = "bitvec", feature = "alloc"))]
+    fn to_bitvec(&self) -> BitVec<u64, Lsb0> {
+        let mut bits = BitVec::repeat(false, Self::CAP);
+        self.copy_to_bitslice(&mut bits);
+        bits
+    }
+
+    /// Pass the maximal runs of bits to `f` in ascending order, with whether
+    /// they are free, alternating and covering `0..CAP`. A mostly uniform
+    /// bitmap takes far fewer runs than raw words.
+    fn export_rle(&self, f: &mut impl FnMut(Range<usize>, bool)) {
+        let mut end = 0;
+        for run in self.free_ranges() {
+            if run.start > end {
+                f(end..run.start, false);
+            }
+            end = run.end;
+            f(run, true);
+        }
+        if end < self.capacity() {
+            f(end..self.capacity(), false);
+        }
+    }
+
+    /// Replace the whole bitmap by runs from `export_rle`. Bits in no run
+    /// are allocated.
+    fn import_rle(&mut self, runs: impl IntoIterator<Item = (Range<usize>, bool)>) {
+        self.clear();
+        for (run, free) in runs {
+            if free {
+                self.insert(run);
+            }
+        }
+    }
+
+    /// Free every bit that is free in `other`.
+    ///
+    /// This works a word at a time, and cascades take whole children that
+    /// are entirely free or allocated in `other` in one step.
+    fn union_with(&mut self, other: &Self) {
+        for w in 0..self.capacity().div_ceil(WORD_BITS) {
+            self.write_raw_word(w, self.raw_word(w) | other.raw_word(w));
+        }
+        self.rebuild();
+    }
+
+    /// Allocate every bit that is allocated in `other`, keeping free only
+    /// the bits free in both.
+    fn intersect_with(&mut self, other: &Self) {
+        for w in 0..self.capacity().div_ceil(WORD_BITS) {
+            self.write_raw_word(w, self.raw_word(w) & other.raw_word(w));
+        }
+        self.rebuild();
+    }
+
+    /// Allocate every bit that is free in `other`.
+    fn subtract(&mut self, other: &Self) {
+        for w in 0..self.capacity().div_ceil(WORD_BITS) {
+            self.write_raw_word(w, self.raw_word(w) & !other.raw_word(w));
+        }
+        self.rebuild();
+    }
+
+    /// Whether every bit free in `self` is free in `other`.
+    ///
+    /// Cascades skip children that are entirely free or allocated on either
+    /// side, and stop at the first counterexample.
+    fn is_subset_of(&self, other: &Self) -> bool {
+        self.free_count() <= other.free_count()
+            && (0..self.capacity().div_ceil(WORD_BITS))
+                .all(|w| self.raw_word(w) & !other.raw_word(w) == 0)
+    }
+
+    /// Whether no bit is free in both `self` and `other`.
+    fn is_disjoint_with(&self, other: &Self) -> bool {
+        self.free_count() + other.free_count() <= self.capacity()
+            && (0..self.capacity().div_ceil(WORD_BITS))
+                .all(|w| self.raw_word(w) & other.raw_word(w) == 0)
+    }
+
+    /// Split the allocator in two at bit `at`: `self` keeps the bits below
+    /// it, and the returned allocator gets those from `at` on, at the same
+    /// indices. The bits each one does not get are allocated in it.
+    ///
+    /// The returned allocator starts from `Self::default()`, which is only
+    /// right for plain bitmaps. Wrappers with state that must follow the
+    /// bits override this, like the cap of `BitAllocCapped` or the poisoned
+    /// bits of `PoisonBitAlloc`. Other wrapper state starts afresh in the
+    /// returned allocator: `ReserveBitAlloc` has no reserve there, and
+    /// `JournalBitAlloc` no journal.
+    fn split_off(&mut self, at: usize) -> Self {
+        let mut hi = Self::default();
+        hi.union_with(self);
+        hi.remove(..at);
+        self.remove(at..);
+        hi
+    }
+
+    /// Take back the bits of an allocator split off with `split_off`,
+    /// freeing those free in it.
+    fn merge(&mut self, other: &Self) {
+        debug_assert!(
+            self.is_disjoint_with(other),
+            "merging overlapping allocators"
+        );
+        self.union_with(other)
+    }
+
+    /// Create an allocator from `words`, as `load_raw_words` does, with the
+    /// capacity of `Self::default()`.
+    fn from_raw_words(words: &[usize]) -> Self {
+        let mut ba = Self::default();
+        ba.load_raw_words(words);
+        ba
+    }
+
+    /// Copy the state of this allocator into bits `offset..offset + CAP` of
+    /// a larger one, e.g. to move from an early boot allocator to the full
+    /// one. The other bits of `large` are left as they are.
+    ///
+    /// This clears the range and inserts the free runs one by one, so it
+    /// costs what that many `insert` calls do rather than a call per bit.
+    fn copy_into<B: BitAlloc>(&self, large: &mut B, offset: usize) {
+        let end = offset
+            .checked_add(self.capacity())
+            .filter(|&end| end <= large.capacity())
+            .expect("the copy does not fit");
+        large.remove(offset..end);
+        for run in self.free_ranges() {
+            large.insert(run.start + offset..run.end + offset);
+        }
+    }
+
+    /// Create an allocator whose free bits are those of `ranges`, e.g. the
+    /// usable regions of a boot memory map.
+    ///
+    /// Unlike `collect`, ranges may reach past the capacity and are clipped
+    /// to it; overlapping ones are fine too. A range that ends before it
+    /// starts panics. Cascades mark the children a range covers entirely as
+    /// free without touching them, so this is quick even for large maps.
+    fn from_ranges(ranges: impl IntoIterator<Item = Range<usize>>) -> Self {
+        let mut ba = Self::default();
+        let cap = ba.capacity();
+        for range in ranges {
+            assert!(range.start <= range.end, "reversed range {:?}", range);
+            ba.insert(range.start.min(cap)..range.end.min(cap));
+        }
+        ba
+    }
+
+    /// Return the maximal run of free bits containing `index`,
+    /// or `None` if `index` is allocated.
+    fn free_run_containing(&self, index: usize) -> Option<Range<usize>> {
+        if !self.test(index) {
+            return None;
+        }
+        let start = self.prev_allocated(index).map_or(0, |key| key + 1);
+        let end = self.next_allocated(index).unwrap_or(self.capacity());
+        Some(start..end)
+    }
+
+    /// The number of free bits at the start of the bitmap, up to the first
+    /// allocated one.
+    fn free_prefix(&self) -> usize {
+        self.next_allocated(0).unwrap_or(self.capacity())
+    }
+
+    /// The number of free bits at the end of the bitmap, after the last
+    /// allocated one.
+    fn free_suffix(&self) -> usize {
+        (0..self.capacity())
+            .rev()
+            .take_while(|&i| self.test(i))
+            .count()
+    }
+
+    /// The length of the largest run of free bits.
+    fn max_contiguous_len(&self) -> usize {
+        self.max_contiguous().map_or(0, |run| run.len())
+    }
+
+    /// Return the largest run of free bits, the lowest one if there are ties,
+    /// or `None` if no bit is free.
+    fn max_contiguous(&self) -> Option<Range<usize>> {
+        let mut best: Option<Range<usize>> = None;
+        let mut key = 0;
+        while let Some(start) = self.next(key) {
+            let end = self.next_allocated(start).unwrap_or(self.capacity());
+            if best.as_ref().is_none_or(|best| end - start > best.len()) {
+                best = Some(start..end);
+            }
+            key = end;
+        }
+        best
+    }
+
+    /// Continue a first-fit search for `size` free bits aligned to
+    /// `1 << align_log2` over this allocator, whose bit 0 sits at `offset` in
+    /// the index space of the search. Returns the base of the block found.
+    ///
+    /// `run_start` is the start of the free run reaching up to `offset`, if
+    /// any, and is updated to the run reaching up to `offset + CAP` when no
+    /// block is found here, so that the search can go on in the next sibling.
+    ///
+    /// This is the building block of `alloc_contiguous`. Cascades skip
+    /// children that are entirely free or entirely allocated without
+    /// descending into them. An internal hook, hidden from the docs: call
+    /// `find_contiguous` or `find_contiguous_offset` instead.
+    #[doc(hidden)]
+    fn search_contiguous(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+    ) -> Option<usize> {
+        self.search_contiguous_bounded(offset, size, align_log2, run_start, &mut { usize::MAX })
+            .unwrap_or(None)
+    }
+
+    /// `search_contiguous`, taking a step from `budget` for every node
+    /// inspected, and giving up when there are none left.
+    #[doc(hidden)]
+    fn search_contiguous_bounded(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+        budget: &mut usize,
+    ) -> Result<Option<usize>, BudgetExhausted> {
+        let mut key = 0;
+        while key < self.capacity() {
+            spend(budget)?;
+            if run_start.is_none() {
+                key = match self.next(key) {
+                    Some(key) => key,
+                    None => return Ok(None),
+                };
+                *run_start = Some(offset + key);
+            }
+            let end = self.next_allocated(key).unwrap_or(self.capacity());
+            let base = align_up(run_start.unwrap(), align_log2);
+            if base.saturating_add(size) <= offset + end {
+                return Ok(Some(base));
+            }
+            if end == self.capacity() {
+                break;
+            }
+            *run_start = None;
+            key = end;
+        }
+        Ok(None)
+    }
+
+    /// `next`, taking a step from `budget` for every node inspected.
+    #[doc(hidden)]
+    fn next_budgeted(
+        &self,
+        key: usize,
+        budget: &mut usize,
+    ) -> Result<Option<usize>, BudgetExhausted> {
+        spend(budget)?;
+        Ok(self.next(key))
+    }
+
+    /// Count the maximal runs of free bits by length: entry `k` is the number
+    /// of runs of `1 << k` up to `(1 << (k + 1)) - 1` bits, like the orders of
+    /// Linux's buddyinfo.
+    ///
+    /// This is a single walk of the tree, skipping children that are entirely
+    /// free or entirely allocated.
+    fn free_run_histogram(&self) -> [usize; usize::BITS as usize] {
+        let mut hist = [0; usize::BITS as usize];
+        let mut run = 0;
+        let mut count = |len: usize| hist[len.ilog2() as usize] += 1;
+        self.visit_free_runs(&mut run, &mut count);
+        if run > 0 {
+            count(run);
+        }
+        hist
+    }
+
+    /// Pass the length of every maximal run of free bits to `f`, in
+    /// ascending order.
+    ///
+    /// `run` is the length of the free run reaching up to bit 0, if any, and
+    /// is updated to the run reaching up to `CAP`, which is left for the
+    /// caller to pass on, as the next sibling may continue it.
+    ///
+    /// This is the building block of `free_run_histogram`, hidden from the
+    /// docs.
+    #[doc(hidden)]
+    fn visit_free_runs(&self, run: &mut usize, f: &mut impl FnMut(usize)) {
+        let mut end = 0;
+        for range in self.free_ranges() {
+            if range.start > end && *run > 0 {
+                f(*run);
+                *run = 0;
+            }
+            *run += range.len();
+            end = range.end;
+        }
+        if end < self.capacity() && *run > 0 {
+            f(*run);
+            *run = 0;
+        }
+    }
+
+    /// The size in bytes of the snapshot `serialize_into` writes.
+    fn snapshot_len() -> usize {
+        snapshot::len::<Self>()
+    }
+
+    /// Write a snapshot of the bitmap into `buf`, which must hold at least
+    /// `snapshot_len()` bytes, and return its size.
+    ///
+    /// The format is versioned and little-endian, and does not depend on the
+    /// word size or the tree shape, so it can be handed over to another
+    /// kernel built with a later version of this crate.
+    fn serialize_into(&self, buf: &mut [u8]) -> usize {
+        snapshot::write(self, buf)
+    }
+
+    /// Create an allocator from a snapshot written by `serialize_into`.
+    fn deserialize_from(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let mut ba = Self::default();
+        ba.restore_from(bytes)?;
+        Ok(ba)
+    }
+
+    /// Replace the whole bitmap by a snapshot written by `serialize_into`,
+    /// in place. On error the allocator is unchanged.
+    fn restore_from(&mut self, bytes: &[u8]) -> Result<(), SnapshotError> {
+        snapshot::read(self, bytes)
+    }
+
+    /// Apply a journal kept by a [`JournalBitAlloc`] on top of an older
+    /// snapshot, e.g. after a crash, and return the number of the last
+    /// record, or `None` if it is empty. A record cut short at the end is
+    /// ignored, as one torn by the crash.
+    fn replay_journal(&mut self, journal: &[u8]) -> Result<Option<u64>, JournalError> {
+        journal::replay(self, journal)
+    }
+
+    /// A window onto the bits in `range`, printable with `{:b}` or `{:x}`.
+    /// Large bitmaps are best printed a window at a time.
+    fn fmt_range(&self, range: impl RangeBounds<usize>) -> BitmapWindow<'_, Self> {
+        BitmapWindow::new(self, to_range(range, self.capacity()))
+    }
+
+    /// A view of the bits in `range`, renumbered from 0, that can change
+    /// those bits and no others.
+    fn view(&mut self, range: impl RangeBounds<usize>) -> BitAllocView<'_, Self> {
+        let range = to_range(range, self.capacity());
+        BitAllocView::new(self, range)
+    }
+
+    /// Iterate over all free bits in ascending order. The iterator is
+    /// double-ended, so `.rev()` walks from the top down.
+    fn iter_free(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        Bits::<Self, true>::new(self)
+    }
+
+    /// Iterate over all allocated bits in ascending order.
+    fn iter_allocated(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        Bits::<Self, false>::new(self)
+    }
+
+    /// Iterate over the maximal runs of free bits in ascending order.
+    fn free_ranges(&self) -> impl DoubleEndedIterator<Item = Range<usize>> + '_ {
+        Runs::<Self, true>::new(self)
+    }
+
+    /// Iterate over the maximal runs of allocated bits in ascending order.
+    fn allocated_ranges(&self) -> impl DoubleEndedIterator<Item = Range<usize>> + '_ {
+        Runs::<Self, false>::new(self)
+    }
+
+    /// A copy of the bitmap, to `diff` against later.
+    fn snapshot(&self) -> Self
+    where
+        Self: Clone,
+    {
+        self.clone()
+    }
+
+    /// The first bit not less than `key` that is free in one of `self` and
+    /// `other` and allocated in the other.
+    ///
+    /// Cascades skip children that are entirely allocated on both sides, or
+    /// were both freed in bulk, so mostly full bitmaps compare quickly.
+    fn next_difference(&self, other: &Self, key: usize) -> Option<usize> {
+        let mut mask = usize::MAX << (key % WORD_BITS);
+        for w in key / WORD_BITS..self.capacity().div_ceil(WORD_BITS) {
+            let diff = (self.raw_word(w) ^ other.raw_word(w)) & mask;
+            if diff != 0 {
+                let key = w * WORD_BITS + diff.trailing_zeros() as usize;
+                return Some(key).filter(|&key| key < self.capacity());
+            }
+            mask = usize::MAX;
+        }
+        None
+    }
+
+    /// Iterate over the maximal runs of bits whose state changed from
+    /// `self` to `new`, in ascending order, e.g. from a snapshot to the
+    /// allocator as it is now.
+    fn diff<'a>(&'a self, new: &'a Self) -> impl Iterator<Item = Changed> + 'a {
+        Diff::new(self, new)
+    }
+}
+
+/// Decides which free bit `alloc` hands out.
+///
+/// A cascade calls `pick` with the summary bits of its children to choose
+/// which child to descend into, and the leaf calls it with its own bits.
+/// For example, a 4K bitmap allocating from the bottom up is
+/// `BitAllocCascade16<BitAlloc256, LowestFirst>`.
+pub trait AllocPolicy {
+    /// Return the position of one of the set bits in `bits`, which is never 0.
+    fn pick(bits: u64) -> usize;
+}
+
+/// Allocate the highest free bit first. This is the default policy.
+pub struct HighestFirst;
+
+impl AllocPolicy for HighestFirst {
+    fn pick(bits: u64) -> usize {
+        log2(bits)
+    }
+}
+
+/// Allocate the lowest free bit first.
+pub struct LowestFirst;
+
+impl AllocPolicy for LowestFirst {
+    fn pick(bits: u64) -> usize {
+        bits.trailing_zeros() as usize
+    }
+}
+
+/// A cascade of 16 children per node.
+pub type BitAllocCascade16<T, P = HighestFirst> = BitAllocCascade<T, 16, P>;
+
+/// A bitmap of 256 bits
+pub type BitAlloc256 = BitAllocCascade16<BitAlloc16>;
+/// A bitmap of 4K bits
+pub type BitAlloc4K = BitAllocCascade16<BitAlloc256>;
+/// A bitmap of 64K bits
+pub type BitAlloc64K = BitAllocCascade16<BitAlloc4K>;
+/// A bitmap of 1M bits
+pub type BitAlloc1M = BitAllocCascade16<BitAlloc64K>;
+/// A bitmap of 16M bits
+pub type BitAlloc16M = BitAllocCascade16<BitAlloc1M>;
+/// A bitmap of 256M bits
+pub type BitAlloc256M = BitAllocCascade16<BitAlloc16M>;
+/// A bitmap of 4G bits, e.g. 16 TiB of 4 KiB frames. Only on 64-bit targets,
+/// where the indices fit in a `usize`.
+#[cfg(target_pointer_width = "64")]
+pub type BitAlloc4G = BitAllocCascade16<BitAlloc256M>;
+/// A bitmap of 64G bits. Only on 64-bit targets.
+#[cfg(target_pointer_width = "64")]
+pub type BitAlloc64G = BitAllocCascade16<BitAlloc4G>;
+
+/// A cascade of 64 children per node, whose summary is a full `u64`.
+///
+/// On 64-bit targets this makes trees a third shallower than the 16-way
+/// ones: 16M bits take 4 levels instead of 6.
+pub type BitAllocCascade64<T, P = HighestFirst> = BitAllocCascade<T, 64, P>;
+
+/// A bitmap of 4K bits, 64 per level
+pub type BitAlloc4K64 = BitAllocCascade64<BitAlloc64>;
+/// A bitmap of 256K bits, 64 per level
+pub type BitAlloc256K64 = BitAllocCascade64<BitAlloc4K64>;
+/// A bitmap of 16M bits, 64 per level
+pub type BitAlloc16M64 = BitAllocCascade64<BitAlloc256K64>;
+/// A bitmap of 1G bits, 64 per level
+pub type BitAlloc1G64 = BitAllocCascade64<BitAlloc16M64>;
+
+/// Implement the bit allocator by segment tree algorithm, each node having
+/// `N` children of type `T`, up to 64.
+///
+/// A wider node makes a shallower tree for the same capacity, so fewer
+/// levels are touched on each `alloc`, at the cost of scanning more
+/// children per level. [`BitAllocCascade16`] is the usual 16-way node.
+///
+/// Unlike [`BitAlloc16`], this type is intentionally not `Copy`: large
+/// cascades span megabytes and should never be duplicated implicitly.
+/// It is `Clone` though, for taking an explicit checkpoint.
+///
+/// `alloc` picks free bits as the [`AllocPolicy`] `P` says, at every level
+/// below this one as well.
+///
+/// `insert` and `remove` only mark the children a range covers entirely as
+/// all free or all allocated, and leave their contents for the next change
+/// inside them, so `insert(..)` on a 256M map touches a few nodes per level
+/// instead of 32 MiB.
+pub struct BitAllocCascade<T: BitAlloc, const N: usize, P: AllocPolicy = HighestFirst> {
+    bitset: u64,    // for each bit, 1 indicates available, 0 indicates inavailable
+    stale: u64,     // for each child, 1 if it is entirely as `bitset` says, and out of date
+    free: usize,    // number of available bits in the subtree
+    prefix: usize,  // length of the run of available bits at the start
+    suffix: usize,  // length of the run of available bits at the end
+    longest: usize, // length of the longest run of available bits
+    sub: [T; N],
+    _policy: PhantomData<P>,
+}
+
+impl<T: BitAlloc, const N: usize, P: AllocPolicy> Default for BitAllocCascade<T, N, P> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<T: BitAlloc, const N: usize, P: AllocPolicy> BitAlloc for BitAllocCascade<T, N, P> {
+    const CAP: usize = T::CAP * N;
+
+    const DEFAULT: Self = {
+        assert!(N > 0 && N <= 64, "a cascade node has 1 to 64 children");
+        BitAllocCascade {
+            bitset: 0,
+            stale: 0,
+            free: 0,
+            prefix: 0,
+            suffix: 0,
+            longest: 0,
+            sub: [T::DEFAULT; N],
+            _policy: PhantomData,
+        }
+    };
+
+    fn alloc(&mut self) -> Option<usize> {
+        self.alloc_with::<P>()
+    }
+    fn alloc_with<Q: AllocPolicy>(&mut self) -> Option<usize> {
+        if self.any() {
+            let i = Q::pick(self.bitset);
+            let res = self.child_mut(i).alloc_with::<Q>().unwrap() + i * T::CAP;
+            self.bitset.set_bit(i, self.sub[i].any());
+            self.free -= 1;
+            self.update_runs();
+            Some(res)
+        } else {
+            None
+        }
+    }
+    fn alloc_many(&mut self, out: &mut [usize]) -> usize {
+        self.alloc_many_with::<P>(out)
+    }
+    fn alloc_many_with<Q: AllocPolicy>(&mut self, out: &mut [usize]) -> usize {
+        let mut n = 0;
+        while n < out.len() && self.any() {
+            let i = Q::pick(self.bitset);
+            let m = self.child_mut(i).alloc_many_with::<Q>(&mut out[n..]);
+            for x in out[n..n + m].iter_mut() {
+                *x += i * T::CAP;
+            }
+            self.bitset.set_bit(i, self.sub[i].any());
+            self.free -= m;
+            n += m;
+        }
+        self.update_runs();
+        n
+    }
+    fn peek_alloc(&self) -> Option<usize> {
+        self.peek_with::<P>()
+    }
+    fn peek_with<Q: AllocPolicy>(&self) -> Option<usize> {
+        if self.any() {
+            let i = Q::pick(self.bitset);
+            if self.stale.get_bit(i) {
+                Some(T::peek_full::<Q>() + i * T::CAP)
+            } else {
+                self.sub[i].peek_with::<Q>().map(|x| x + i * T::CAP)
+            }
+        } else {
+            None
+        }
+    }
+    fn peek_full<Q: AllocPolicy>() -> usize {
+        let i = Q::pick(u64::MAX >> (64 - N));
+        T::peek_full::<Q>() + i * T::CAP
+    }
+    fn subtree_cap(depth: usize) -> usize {
+        match depth {
+            0 => Self::CAP,
+            _ => T::subtree_cap(depth - 1),
+        }
+    }
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        if let Some(base) = self.find_contiguous(size, align_log2) {
+            self.remove(base..base + size);
+            Some(base)
+        } else {
+            None
+        }
+    }
+    /// Descends the cascade directly instead of running the contiguous
+    /// search.
+    fn alloc_aligned(&mut self, align_log2: usize) -> Option<usize> {
+        let align = align_size(align_log2).filter(|&align| align <= Self::CAP)?;
+        if align > T::CAP {
+            // only the first bit of every `align / T::CAP`-th child is aligned
+            let key = (0..Self::CAP).step_by(align).find(|&key| self.test(key))?;
+            self.remove(key..key + 1);
+            return Some(key);
+        }
+        for i in 0..N {
+            if self.bitset.get_bit(i) {
+                if let Some(res) = self.child_mut(i).alloc_aligned(align_log2) {
+                    self.bitset.set_bit(i, self.sub[i].any());
+                    self.free -= 1;
+                    self.update_runs();
+                    return Some(res + i * T::CAP);
+                }
+            }
+        }
+        None
+    }
+    fn dealloc(&mut self, key: usize) {
+        let i = key / T::CAP;
+        self.child_mut(i).dealloc(key % T::CAP);
+        self.bitset.set_bit(i, true);
+        self.free += 1;
+        self.update_runs();
+    }
+    fn dealloc_many(&mut self, keys: impl IntoIterator<Item = usize>) {
+        let mut keys = keys.into_iter().peekable();
+        while let Some(&key) = keys.peek() {
+            let i = key / T::CAP;
+            let sub_keys = core::iter::from_fn(|| keys.next_if(|&key| key / T::CAP == i));
+            let sub = self.child_mut(i);
+            let before = sub.free_count();
+            sub.dealloc_many(sub_keys.map(|key| key % T::CAP));
+            self.free += self.sub[i].free_count() - before;
+            self.bitset.set_bit(i, true);
+        }
+        self.update_runs();
+    }
+    fn insert(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, Self::CAP);
+        self.for_range(range, true);
+    }
+    fn remove(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, Self::CAP);
+        self.for_range(range, false);
+    }
+    fn clear(&mut self) {
+        self.reset(false)
+    }
+    fn fill(&mut self) {
+        self.reset(true)
+    }
+    fn any(&self) -> bool {
+        self.bitset != 0
+    }
+    fn test(&self, key: usize) -> bool {
+        let i = key / T::CAP;
+        if self.stale.get_bit(i) {
+            self.bitset.get_bit(i)
+        } else {
+            self.sub[i].test(key % T::CAP)
+        }
+    }
+    fn free_count(&self) -> usize {
+        self.free
+    }
+    fn count_free_in(&self, range: impl RangeBounds<usize>) -> usize {
+        let range = to_range(range, Self::CAP);
+        let Range { start, end } = range;
+        if start == end {
+            return 0;
+        }
+        assert!(start < end);
+        assert!(end <= Self::CAP);
+        (start / T::CAP..=(end - 1) / T::CAP)
+            .map(|i| {
+                let begin = if start / T::CAP == i {
+                    start % T::CAP
+                } else {
+                    0
+                };
+                let end = if end / T::CAP == i {
+                    end % T::CAP
+                } else {
+                    T::CAP
+                };
+                if begin == 0 && end == T::CAP {
+                    // the whole child is in the range
+                    self.sub_free(i)
+                } else if !self.bitset.get_bit(i) {
+                    0
+                } else if self.stale.get_bit(i) {
+                    end - begin
+                } else {
+                    self.sub[i].count_free_in(begin..end)
+                }
+            })
+            .sum()
+    }
+    fn free_prefix(&self) -> usize {
+        self.prefix
+    }
+    fn free_suffix(&self) -> usize {
+        self.suffix
+    }
+    fn max_contiguous_len(&self) -> usize {
+        self.longest
+    }
+    fn max_contiguous(&self) -> Option<Range<usize>> {
+        let len = self.longest;
+        let base = self.search_contiguous(0, len, 0, &mut None)?;
+        Some(base..base + len)
+    }
+    fn next(&self, key: usize) -> Option<usize> {
+        let ind = key / T::CAP;
+        if ind < N && self.bitset.get_bit(ind) {
+            if let Some(res) = self.sub_next(ind, key - T::CAP * ind) {
+                return Some(res).map(|x| x + T::CAP * ind);
+            }
+        }
+        (ind + 1..N).find_map(|i| {
+            if self.bitset.get_bit(i) {
+                self.sub_next(i, 0).map(|x| x + T::CAP * i)
+            } else {
+                None
+            }
+        })
+    }
+    fn prev(&self, key: usize) -> Option<usize> {
+        let key = key.min(Self::CAP - 1);
+        let ind = key / T::CAP;
+        if self.bitset.get_bit(ind) {
+            if let Some(res) = self.sub_prev(ind, key % T::CAP) {
+                return Some(res + T::CAP * ind);
+            }
+        }
+        (0..ind).rev().find_map(|i| {
+            if self.bitset.get_bit(i) {
+                self.sub_prev(i, T::CAP - 1).map(|x| x + T::CAP * i)
+            } else {
+                None
+            }
+        })
+    }
+    fn search_contiguous_bounded(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+        budget: &mut usize,
+    ) -> Result<Option<usize>, BudgetExhausted> {
+        if size == 0 {
+            return Ok(None);
+        }
+        if run_start.is_none() && size > self.longest {
+            // no block fits inside, only the free suffix may start one,
+            // which is known without looking at the children
+            *run_start = (self.suffix > 0).then(|| offset + Self::CAP - self.suffix);
+            return Ok(None);
+        }
+        for (i, sub) in self.sub.iter().enumerate() {
+            spend(budget)?;
+            let child = offset + i * T::CAP;
+            if !self.bitset.get_bit(i) {
+                *run_start = None;
+            } else if self.sub_free(i) == T::CAP {
+                // the run goes on through the whole child
+                let base = align_up(*run_start.get_or_insert(child), align_log2);
+                if base.saturating_add(size) <= child + T::CAP {
+                    return Ok(Some(base));
+                }
+            } else {
+                // the run reaching the child may end in its free prefix
+                if let Some(start) = *run_start {
+                    let base = align_up(start, align_log2);
+                    if base.saturating_add(size) <= child + sub.free_prefix() {
+                        return Ok(Some(base));
+                    }
+                }
+                if sub.max_contiguous_len() >= size {
+                    let found =
+                        sub.search_contiguous_bounded(child, size, align_log2, run_start, budget)?;
+                    if found.is_some() {
+                        return Ok(found);
+                    }
+                } else {
+                    // no block fits inside the child, only its free suffix may start one
+                    let suffix = sub.free_suffix();
+                    *run_start = if suffix > 0 {
+                        Some(child + T::CAP - suffix)
+                    } else {
+                        None
+                    };
+                }
+            }
+        }
+        Ok(None)
+    }
+    fn next_budgeted(
+        &self,
+        key: usize,
+        budget: &mut usize,
+    ) -> Result<Option<usize>, BudgetExhausted> {
+        spend(budget)?;
+        let ind = key / T::CAP;
+        if ind < N && self.bitset.get_bit(ind) && !self.stale.get_bit(ind) {
+            if let Some(res) = self.sub[ind].next_budgeted(key - T::CAP * ind, budget)? {
+                return Ok(Some(res + T::CAP * ind));
+            }
+        } else if ind < N && self.bitset.get_bit(ind) {
+            return Ok(Some(key));
+        }
+        match (ind + 1..N).find(|&i| self.bitset.get_bit(i)) {
+            Some(i) if self.stale.get_bit(i) => Ok(Some(T::CAP * i)),
+            Some(i) => Ok(self.sub[i]
+                .next_budgeted(0, budget)?
+                .map(|x| x + T::CAP * i)),
+            None => Ok(None),
+        }
+    }
+    fn nth_free(&self, mut n: usize) -> Option<usize> {
+        // skip whole children by their free counts
+        for i in 0..N {
+            let free = self.sub_free(i);
+            if n < free {
+                return Some(match self.sub_uniform(i) {
+                    Some(_) => T::CAP * i + n,
+                    None => T::CAP * i + self.sub[i].nth_free(n)?,
+                });
+            }
+            n -= free;
+        }
+        None
+    }
+    fn visit_free_runs(&self, run: &mut usize, f: &mut impl FnMut(usize)) {
+        for (i, sub) in self.sub.iter().enumerate() {
+            match self.sub_uniform(i) {
+                Some(false) => {
+                    if *run > 0 {
+                        f(*run);
+                        *run = 0;
+                    }
+                }
+                Some(true) => *run += T::CAP,
+                None if self.sub_free(i) == T::CAP => *run += T::CAP,
+                None => sub.visit_free_runs(run, f),
+            }
+        }
+    }
+    fn raw_word(&self, w: usize) -> usize {
+        if T::CAP >= WORD_BITS {
+            let per = T::CAP / WORD_BITS;
+            self.sub_raw_word(w / per, w % per)
+        } else {
+            // several children share a word
+            let per = WORD_BITS / T::CAP;
+            (0..per.min(N - w * per)).fold(0, |word, j| {
+                word | self.sub_raw_word(w * per + j, 0) << (j * T::CAP)
+            })
+        }
+    }
+    fn next_difference(&self, other: &Self, key: usize) -> Option<usize> {
+        if key >= Self::CAP {
+            return None;
+        }
+        (key / T::CAP..N).find_map(|i| {
+            let from = if i == key / T::CAP { key % T::CAP } else { 0 };
+            let found = match (self.sub_uniform(i), other.sub_uniform(i)) {
+                (Some(mine), Some(theirs)) => (mine != theirs).then_some(from),
+                (Some(true), None) => other.sub[i].next_allocated(from),
+                (Some(false), None) => other.sub[i].next(from),
+                (None, Some(true)) => self.sub[i].next_allocated(from),
+                (None, Some(false)) => self.sub[i].next(from),
+                (None, None) => self.sub[i].next_difference(&other.sub[i], from),
+            };
+            found.map(|j| i * T::CAP + j)
+        })
+    }
+    fn union_with(&mut self, other: &Self) {
+        self.combine(other, |free| free.then_some(true), T::union_with)
+    }
+    fn intersect_with(&mut self, other: &Self) {
+        self.combine(other, |free| (!free).then_some(false), T::intersect_with)
+    }
+    fn subtract(&mut self, other: &Self) {
+        self.combine(other, |free| free.then_some(false), T::subtract)
+    }
+    fn is_subset_of(&self, other: &Self) -> bool {
+        self.free <= other.free
+            && (0..N).all(|i| match (self.sub_uniform(i), other.sub_uniform(i)) {
+                (Some(false), _) | (_, Some(true)) => true,
+                (Some(true), None) => other.sub[i].free_count() == T::CAP,
+                (_, Some(false)) => false,
+                (None, None) => self.sub[i].is_subset_of(&other.sub[i]),
+            })
+    }
+    fn is_disjoint_with(&self, other: &Self) -> bool {
+        self.free + other.free <= Self::CAP
+            && (0..N).all(|i| match (self.sub_uniform(i), other.sub_uniform(i)) {
+                (Some(false), _) | (_, Some(false)) => true,
+                // the other side has a free bit
+                (Some(true), _) | (_, Some(true)) => false,
+                (None, None) => self.sub[i].is_disjoint_with(&other.sub[i]),
+            })
+    }
+    fn write_raw_word(&mut self, w: usize, word: usize) {
+        if T::CAP >= WORD_BITS {
+            let per = T::CAP / WORD_BITS;
+            self.child_mut(w / per).write_raw_word(w % per, word)
+        } else {
+            let per = WORD_BITS / T::CAP;
+            for j in 0..per.min(N - w * per) {
+                let bits = word >> (j * T::CAP) & ((1 << T::CAP) - 1);
+                self.child_mut(w * per + j).write_raw_word(0, bits);
+            }
+        }
+    }
+    fn rebuild(&mut self) {
+        self.free = 0;
+        for i in 0..N {
+            if !self.stale.get_bit(i) {
+                self.sub[i].rebuild();
+                self.bitset.set_bit(i, self.sub[i].any());
+            }
+            self.free += self.sub_free(i);
+        }
+        self.update_runs();
+    }
+    fn validate(&self) -> Result<(), Inconsistency> {
+        let mut free = 0;
+        for i in 0..N {
+            if !self.stale.get_bit(i) {
+                let base = i * T::CAP;
+                self.sub[i].validate().map_err(|e| e.offset(base))?;
+                if self.bitset.get_bit(i) != self.sub[i].any() {
+                    return Err(Inconsistency::Summary { base });
+                }
+            }
+            free += self.sub_free(i);
+        }
+        if free != self.free {
+            return Err(Inconsistency::FreeCount { base: 0 });
+        }
+        if self.runs() != (self.prefix, self.suffix, self.longest) {
+            return Err(Inconsistency::Runs { base: 0 });
+        }
+        Ok(())
+    }
+    fn iter_free(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        (0..N)
+            .filter(move |&i| self.bitset.get_bit(i))
+            .flat_map(move |i| {
+                let (full, sub) = if self.stale.get_bit(i) {
+                    (0..T::CAP, None)
+                } else {
+                    (0..0, Some(self.sub[i].iter_free()))
+                };
+                full.chain(sub.into_iter().flatten())
+                    .map(move |x| x + T::CAP * i)
+            })
+    }
+    fn next_allocated(&self, key: usize) -> Option<usize> {
+        let ind = key / T::CAP;
+        (ind..N).find_map(|i| {
+            let begin = if i == ind { key % T::CAP } else { 0 };
+            if self.sub_free(i) == T::CAP {
+                // every bit in this child is free
+                None
+            } else if self.bitset.get_bit(i) {
+                self.sub[i].next_allocated(begin).map(|x| x + T::CAP * i)
+            } else {
+                // no bit in this child is free
+                Some(begin + T::CAP * i)
+            }
+        })
+    }
+    fn prev_allocated(&self, key: usize) -> Option<usize> {
+        let key = key.min(Self::CAP - 1);
+        let ind = key / T::CAP;
+        (0..=ind).rev().find_map(|i| {
+            let end = if i == ind { key % T::CAP } else { T::CAP - 1 };
+            if self.sub_free(i) == T::CAP {
+                // every bit in this child is free
+                None
+            } else if self.bitset.get_bit(i) {
+                self.sub[i].prev_allocated(end).map(|x| x + T::CAP * i)
+            } else {
+                // no bit in this child is free
+                Some(end + T::CAP * i)
+            }
+        })
+    }
+}
+
+impl<T: BitAlloc, const N: usize, P: AllocPolicy> BitAllocCascade<T, N, P> {
+    /// Create an allocator with all bits allocated, e.g. for a `static`.
+    pub const fn new() -> Self {
+        Self::DEFAULT
+    }
+
+    /// Create an allocator out of its children, child `i` owning bits
+    /// `i * T::CAP..(i + 1) * T::CAP`, e.g. to take over allocators of the
+    /// size of one child without replaying them. Only the summaries of this
+    /// node are computed.
+    pub fn from_parts(parts: [T; N]) -> Self {
+        let mut ba = BitAllocCascade {
+            sub: parts,
+            ..Self::DEFAULT
+        };
+        for i in 0..N {
+            ba.bitset.set_bit(i, ba.sub[i].any());
+            ba.free += ba.sub[i].free_count();
+        }
+        ba.update_runs();
+        ba
+    }
+
+    /// The name printed by `Debug`, that of the alias for common widths.
+    pub(crate) const NAME: &'static str = match N {
+        16 => "BitAllocCascade16",
+        64 => "BitAllocCascade64",
+        _ => "BitAllocCascade",
+    };
+
+    /// Whether child `i` is entirely free, entirely allocated, or neither.
+    fn sub_uniform(&self, i: usize) -> Option<bool> {
+        match (self.stale.get_bit(i), self.bitset.get_bit(i)) {
+            (true, free) => Some(free),
+            (false, false) => Some(false),
+            (false, true) => None,
+        }
+    }
+
+    /// Child `i`, brought up to date first if it is stale, to be changed.
+    ///
+    /// A stale child becomes up to date by a single `insert(..)` or
+    /// `remove(..)`, which only marks its own children stale.
+    fn child_mut(&mut self, i: usize) -> &mut T {
+        if self.stale.get_bit(i) {
+            self.stale.set_bit(i, false);
+            if self.bitset.get_bit(i) {
+                self.sub[i].insert(..);
+            } else {
+                self.sub[i].remove(..);
+            }
+        }
+        &mut self.sub[i]
+    }
+
+    /// The number of free bits in child `i`.
+    fn sub_free(&self, i: usize) -> usize {
+        match (self.stale.get_bit(i), self.bitset.get_bit(i)) {
+            (true, true) => T::CAP,
+            (true, false) => 0,
+            (false, _) => self.sub[i].free_count(),
+        }
+    }
+
+    /// The first free bit of child `i` not less than `key`, if it has any.
+    fn sub_next(&self, i: usize, key: usize) -> Option<usize> {
+        if self.stale.get_bit(i) {
+            self.bitset.get_bit(i).then_some(key)
+        } else {
+            self.sub[i].next(key)
+        }
+    }
+
+    /// The last free bit of child `i` not greater than `key`, if it has any.
+    fn sub_prev(&self, i: usize, key: usize) -> Option<usize> {
+        if self.stale.get_bit(i) {
+            self.bitset.get_bit(i).then_some(key)
+        } else {
+            self.sub[i].prev(key)
+        }
+    }
+
+    fn sub_raw_word(&self, i: usize, w: usize) -> usize {
+        match (self.stale.get_bit(i), self.bitset.get_bit(i)) {
+            (true, true) => usize::MAX >> (WORD_BITS - T::CAP.min(WORD_BITS)),
+            (true, false) => 0,
+            (false, _) => self.sub[i].raw_word(w),
+        }
+    }
+
+    fn for_range(&mut self, range: Range<usize>, free: bool) {
+        let Range { start, end } = range;
+        if start == end {
+            return;
+        }
+        assert!(start < end);
+        assert!(end <= Self::CAP);
+        for i in start / T::CAP..=(end - 1) / T::CAP {
+            debug_assert!(i < N);
+            let begin = if start / T::CAP == i {
+                start % T::CAP
+            } else {
+                0
+            };
+            let end = if end / T::CAP == i {
+                end % T::CAP
+            } else {
+                T::CAP
+            };
+            self.free -= self.sub_free(i);
+            if begin == 0 && end == T::CAP {
+                // the whole child: leave it stale instead of walking it
+                self.stale.set_bit(i, true);
+                self.bitset.set_bit(i, free);
+            } else {
+                let sub = self.child_mut(i);
+                if free {
+                    sub.insert(begin..end);
+                } else {
+                    sub.remove(begin..end);
+                }
+                self.bitset.set_bit(i, self.sub[i].any());
+            }
+            self.free += self.sub_free(i);
+        }
+        self.update_runs();
+    }
+
+    /// Combine with `other` child by child. Where the child of `other` is
+    /// entirely free or allocated, `uniform` says what ours becomes, or
+    /// `None` to keep it; other children are combined by `f`.
+    fn combine(
+        &mut self,
+        other: &Self,
+        uniform: impl Fn(bool) -> Option<bool>,
+        f: impl Fn(&mut T, &T),
+    ) {
+        for i in 0..N {
+            self.free -= self.sub_free(i);
+            match other.sub_uniform(i) {
+                Some(theirs) => {
+                    if let Some(free) = uniform(theirs) {
+                        self.stale.set_bit(i, true);
+                        self.bitset.set_bit(i, free);
+                    }
+                }
+                None => {
+                    f(self.child_mut(i), &other.sub[i]);
+                    self.bitset.set_bit(i, self.sub[i].any());
+                }
+            }
+            self.free += self.sub_free(i);
+        }
+        self.update_runs();
+    }
+
+    /// Mark every child as stale, all free or all allocated, without
+    /// touching them.
+    fn reset(&mut self, free: bool) {
+        let all = u64::MAX >> (64 - N);
+        let len = if free { Self::CAP } else { 0 };
+        self.bitset = if free { all } else { 0 };
+        self.stale = all;
+        self.free = len;
+        self.prefix = len;
+        self.suffix = len;
+        self.longest = len;
+    }
+
+    /// Recompute the free-run lengths of this node from its children.
+    fn update_runs(&mut self) {
+        let (prefix, suffix, longest) = self.runs();
+        self.prefix = prefix;
+        self.suffix = suffix;
+        self.longest = longest;
+    }
+
+    /// The free-run lengths of this node, prefix, suffix and longest, as
+    /// its children say.
+    fn runs(&self) -> (usize, usize, usize) {
+        let mut prefix = None;
+        let mut longest = 0;
+        let mut run = 0;
+        for (i, sub) in self.sub.iter().enumerate() {
+            match self.sub_free(i) {
+                free if free == T::CAP => run += T::CAP,
+                0 => {
+                    prefix.get_or_insert(run);
+                    longest = longest.max(run);
+                    run = 0;
+                }
+                _ => {
+                    prefix.get_or_insert(run + sub.free_prefix());
+                    longest = longest.max(run + sub.free_prefix());
+                    longest = longest.max(sub.max_contiguous_len());
+                    run = sub.free_suffix();
+                }
+            }
+        }
+        (prefix.unwrap_or(run), run, longest.max(run))
+    }
+}
+
+/// A bitmap consisting of only 16 bits.
+/// BitAlloc16 acts as the leaf (except the leaf bits of course) nodes
+/// in the segment trees.
+///
+/// It is just a `u16`, so it is `Copy` and can be used to build arrays of
+/// independent small allocators, e.g. `[BitAlloc16::DEFAULT; N]`.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BitAlloc16(u16);
+
+impl BitAlloc16 {
+    /// Create an allocator with all bits allocated.
+    pub const fn new() -> Self {
+        BitAlloc16(0)
+    }
+}
+
+impl BitAlloc for BitAlloc16 {
+    const CAP: usize = 16;
+
+    const DEFAULT: Self = BitAlloc16(0);
+
+    fn alloc(&mut self) -> Option<usize> {
+        self.alloc_with::<HighestFirst>()
+    }
+    fn alloc_with<P: AllocPolicy>(&mut self) -> Option<usize> {
+        let i = self.peek_with::<P>()?;
+        self.0.set_bit(i, false);
+        Some(i)
+    }
+    fn peek_alloc(&self) -> Option<usize> {
+        self.peek_with::<HighestFirst>()
+    }
+    fn peek_with<P: AllocPolicy>(&self) -> Option<usize> {
+        if self.any() {
+            Some(P::pick(self.0 as u64))
+        } else {
+            None
+        }
+    }
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        if let Some(base) = self.find_contiguous(size, align_log2) {
+            self.remove(base..base + size);
+            Some(base)
+        } else {
+            None
+        }
+    }
+    fn alloc_aligned(&mut self, align_log2: usize) -> Option<usize> {
+        // bits at aligned positions
+        let mask: u16 = match align_log2 {
+            0 => 0xffff,
+            1 => 0x5555,
+            2 => 0x1111,
+            3 => 0x0101,
+            4 => 0x0001,
+            _ => return None,
+        };
+        let word = self.0 & mask;
+        if word == 0 {
+            None
+        } else {
+            let i = word.trailing_zeros() as usize;
+            self.0.set_bit(i, false);
+            Some(i)
+        }
+    }
+    fn dealloc(&mut self, key: usize) {
+        assert!(!self.test(key));
+        self.0.set_bit(key, true);
+    }
+    fn insert(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, Self::CAP);
+        if range.start == range.end {
+            return;
+        }
+        self.0.set_bits(range.clone(), 0xffff.get_bits(range));
+    }
+    fn remove(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, Self::CAP);
+        if range.start == range.end {
+            return;
+        }
+        self.0.set_bits(range, 0);
+    }
+    fn any(&self) -> bool {
+        self.0 != 0
+    }
+    fn test(&self, key: usize) -> bool {
+        self.0.get_bit(key)
+    }
+    fn free_count(&self) -> usize {
+        self.0.count_ones() as usize
+    }
+    fn count_free_in(&self, range: impl RangeBounds<usize>) -> usize {
+        let range = to_range(range, Self::CAP);
+        if range.start == range.end {
+            return 0;
+        }
+        self.0.get_bits(range).count_ones() as usize
+    }
+    fn free_prefix(&self) -> usize {
+        self.0.trailing_ones() as usize
+    }
+    fn free_suffix(&self) -> usize {
+        self.0.leading_ones() as usize
+    }
+    fn max_contiguous_len(&self) -> usize {
+        // every round shortens each run by one
+        let mut x = self.0;
+        let mut len = 0;
+        while x != 0 {
+            x &= x >> 1;
+            len += 1;
+        }
+        len
+    }
+    fn next(&self, key: usize) -> Option<usize> {
+        if key >= Self::CAP {
+            return None;
+        }
+        // mask off bits below key
+        let word = self.0 & !((1u16 << key) - 1);
+        if word == 0 {
+            None
+        } else {
+            Some(word.trailing_zeros() as usize)
+        }
+    }
+    fn prev(&self, key: usize) -> Option<usize> {
+        // mask off bits above key
+        let word = self.0 & (0xffff >> (15 - key.min(15)));
+        if word == 0 {
+            None
+        } else {
+            Some(log2(word as u64))
+        }
+    }
+    fn iter_free(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        SetBits::new(self.0 as u64, 0)
+    }
+    fn next_allocated(&self, key: usize) -> Option<usize> {
+        if key >= Self::CAP {
+            return None;
+        }
+        // invert the word so that allocated bits are set, then mask off bits below key
+        let word = !self.0 & !((1u16 << key) - 1);
+        if word == 0 {
+            None
+        } else {
+            Some(word.trailing_zeros() as usize)
+        }
+    }
+    fn prev_allocated(&self, key: usize) -> Option<usize> {
+        let word = !self.0 & (0xffff >> (15 - key.min(15)));
+        if word == 0 {
+            None
+        } else {
+            Some(log2(word as u64))
+        }
+    }
+    fn raw_word(&self, w: usize) -> usize {
+        assert_eq!(w, 0);
+        self.0 as usize
+    }
+    fn union_with(&mut self, other: &Self) {
+        self.0 |= other.0;
+    }
+    fn intersect_with(&mut self, other: &Self) {
+        self.0 &= other.0;
+    }
+    fn subtract(&mut self, other: &Self) {
+        self.0 &= !other.0;
+    }
+    fn write_raw_word(&mut self, w: usize, word: usize) {
+        assert_eq!(w, 0);
+        self.0 = word as u16;
+    }
+    fn rebuild(&mut self) {}
+}
+
+impl<T: BitAlloc + Clone, const N: usize, P: AllocPolicy> Clone for BitAllocCascade<T, N, P> {
+    fn clone(&self) -> Self {
+        BitAllocCascade {
+            bitset: self.bitset,
+            stale: self.stale,
+            free: self.free,
+            prefix: self.prefix,
+            suffix: self.suffix,
+            longest: self.longest,
+            sub: self.sub.clone(),
+            _policy: PhantomData,
+        }
+    }
+}
+
+// The summaries are derived from the children, so only those are compared.
+// A stale child is only known to be entirely free or allocated, which its
+// free count tells.
+impl<T: BitAlloc + PartialEq, const N: usize, P: AllocPolicy> PartialEq
+    for BitAllocCascade<T, N, P>
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.free == other.free
+            && (0..N).all(|i| {
+                if self.stale.get_bit(i) || other.stale.get_bit(i) {
+                    self.sub_free(i) == other.sub_free(i)
+                } else {
+                    self.sub[i] == other.sub[i]
+                }
+            })
+    }
+}
+
+impl<T: BitAlloc + Eq, const N: usize, P: AllocPolicy> Eq for BitAllocCascade<T, N, P> {}
+
+impl<T: BitAlloc + Hash, const N: usize, P: AllocPolicy> Hash for BitAllocCascade<T, N, P> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for i in 0..N {
+            match self.sub_free(i) {
+                free if free == 0 || free == T::CAP => free.hash(state),
+                _ => self.sub[i].hash(state),
+            }
+        }
+    }
+}
+
+impl<T: BitAlloc, const N: usize, P: AllocPolicy> fmt::Debug for BitAllocCascade<T, N, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        debug_summary(self, Self::NAME, f)
+    }
+}
+
+impl fmt::Debug for BitAlloc16 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        debug_summary(self, "BitAlloc16", f)
+    }
+}
+
+impl<T: BitAlloc, const N: usize, P: AllocPolicy> fmt::Binary for BitAllocCascade<T, N, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Binary::fmt(&self.fmt_range(..), f)
+    }
+}
+
+impl<T: BitAlloc, const N: usize, P: AllocPolicy> fmt::LowerHex for BitAllocCascade<T, N, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.fmt_range(..), f)
+    }
+}
+
+impl fmt::Binary for BitAlloc16 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Binary::fmt(&self.fmt_range(..), f)
+    }
+}
+
+impl fmt::LowerHex for BitAlloc16 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.fmt_range(..), f)
+    }
+}
+
+/// The most free runs `Debug` prints before eliding the rest.
+const DEBUG_RUNS: usize = 8;
+
+/// The first `DEBUG_RUNS` free runs of an allocator, as a `Debug` list.
+struct FreeRuns<'a, A>(&'a A);
+
+impl<A: BitAlloc> fmt::Debug for FreeRuns<'_, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut runs = self.0.free_ranges();
+        let mut list = f.debug_list();
+        list.entries(runs.by_ref().take(DEBUG_RUNS));
+        if runs.next().is_some() {
+            list.finish_non_exhaustive()
+        } else {
+            list.finish()
+        }
+    }
+}
+
+/// Print the capacity, the free count and the first few free runs of an
+/// allocator, everything between the runs being allocated. The output stays
+/// short however large the bitmap is, so it is fine for a panic handler.
+fn debug_summary<A: BitAlloc>(ba: &A, name: &str, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct(name)
+        .field("cap", &A::CAP)
+        .field("free", &ba.free_count())
+        .field("free_runs", &FreeRuns(ba))
+        .finish()
+}
+
+/// Resolve `range` against a bitmap of `capacity` bits, where an unbounded
+/// end stands for `capacity`.
+fn to_range(range: impl RangeBounds<usize>, capacity: usize) -> Range<usize> {
+    let start = match range.start_bound() {
+        Bound::Included(&start) => start,
+        Bound::Excluded(&start) => start.saturating_add(1),
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&end) => end.saturating_add(1),
+        Bound::Excluded(&end) => end,
+        // `start..` is empty rather than reversed when start is past the end
+        Bound::Unbounded => capacity.max(start),
+    };
+    start..end
+}
+
+/// A random number in `0..n`, from the high bits of `rng() * n`.
+fn random_below(rng: &mut impl FnMut() -> u64, n: usize) -> usize {
+    ((rng() as u128 * n as u128) >> 64) as usize
+}
+
+/// Take a step from the budget of a bounded search.
+fn spend(budget: &mut usize) -> Result<(), BudgetExhausted> {
+    *budget = budget.checked_sub(1).ok_or(BudgetExhausted)?;
+    Ok(())
+}
+
+/// Empty ranges are valid wherever they lie.
+fn check_range(range: &Range<usize>, capacity: usize) -> Result<(), BitAllocError> {
+    if range.start == range.end || range.start < range.end && range.end <= capacity {
+        Ok(())
+    } else {
+        Err(BitAllocError::OutOfRange)
+    }
+}
+
+/// The width of a raw word, see [`BitAlloc::raw_word`].
+const WORD_BITS: usize = usize::BITS as usize;
+
+/// `1 << align_log2`, or `None` if that does not fit in a `usize`.
+fn align_size(align_log2: usize) -> Option<usize> {
+    if align_log2 < WORD_BITS {
+        Some(1 << align_log2)
+    } else {
+        None
+    }
+}
+
+/// The lowest set bit not less than `key` in a bitmap of `cap` bits, given
+/// word by word.
+fn next_set(key: usize, cap: usize, word: impl Fn(usize) -> usize) -> Option<usize> {
+    if key >= cap {
+        return None;
+    }
+    let mut w = key / WORD_BITS;
+    let mut bits = word(w) & usize::MAX << (key % WORD_BITS);
+    while bits == 0 {
+        w += 1;
+        if w * WORD_BITS >= cap {
+            return None;
+        }
+        bits = word(w);
+    }
+    Some(w * WORD_BITS + bits.trailing_zeros() as usize)
+}
+
+/// The highest set bit not greater than `key` in a bitmap of `cap` bits,
+/// given word by word. Keys beyond the end are treated as the last bit.
+fn prev_set(key: usize, cap: usize, word: impl Fn(usize) -> usize) -> Option<usize> {
+    let key = key.min(cap.checked_sub(1)?);
+    let mut w = key / WORD_BITS;
+    let mut bits = word(w) & usize::MAX >> (WORD_BITS - 1 - key % WORD_BITS);
+    while bits == 0 {
+        w = w.checked_sub(1)?;
+        bits = word(w);
+    }
+    Some(w * WORD_BITS + WORD_BITS - 1 - bits.leading_zeros() as usize)
+}
+
+/// Round `x` up to a multiple of `1 << align_log2`.
+fn align_up(x: usize, align_log2: usize) -> usize {
+    ((x + (1 << align_log2) - 1) >> align_log2) << align_log2
+}
+
+/// Position of the most significant set bit.
+///
+/// `leading_zeros` lowers to the native bit-scan instruction (`bsr`/`lzcnt`
+/// on x86, `clz` on ARM and RISC-V) where one exists.
+#[inline(always)]
+fn log2(x: u64) -> usize {
+    assert_ne!(x, 0);
+    63 - x.leading_zeros() as usize
+}
+
+#[cfg(test)]
+fn log2_naive(mut x: u64) -> usize {
+    //a naive implement
+    assert_ne!(x, 0);
+    let mut pos = -1;
+    while x != 0 {
+        pos += 1;
+        x >>= 1;
+    }
+    pos as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The original bit-by-bit contiguous search, kept as a reference.
+    fn find_contiguous_naive<T: BitAlloc>(
+        ba: &T,
+        capacity: usize,
+        size: usize,
+        align_log2: usize,
+    ) -> Option<usize> {
+        if capacity < (1 << align_log2) || !ba.any() {
+            None
+        } else {
+            let mut base = 0;
+            let mut offset = base;
+            while offset < capacity {
+                let next = ba.next(offset)?;
+                if next != offset {
+                    // it can be guarenteed that no bit in (offset..next) is free
+                    // move to next aligned position after next-1
+                    assert!(next > offset);
+                    base = (((next - 1) >> align_log2) + 1) << align_log2;
+                    assert_ne!(offset, next);
+                    offset = base;
+                    continue;
+                }
+                offset += 1;
+                if offset - base == size {
+                    return Some(base);
+                }
+            }
+            None
+        }
+    }
+
+    /// A fixed-size `fmt::Write` sink, since there is no `String` here.
+    pub(crate) struct Buf {
+        data: [u8; 1024],
+        len: usize,
+    }
+
+    impl Buf {
+        pub(crate) fn new() -> Self {
+            Buf {
+                data: [0; 1024],
+                len: 0,
+            }
+        }
+
+        pub(crate) fn as_str(&self) -> &str {
+            core::str::from_utf8(&self.data[..self.len]).unwrap()
+        }
+    }
+
+    impl fmt::Write for Buf {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            if end > self.data.len() {
+                return Err(fmt::Error);
+            }
+            self.data[self.len..end].copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    /// Format `args` into a fresh `Buf`.
+    pub(crate) fn format(args: fmt::Arguments) -> Buf {
+        let mut buf = Buf::new();
+        fmt::write(&mut buf, args).unwrap();
+        buf
+    }
+
+    #[test]
+    fn debug() {
+        let mut ba = BitAlloc4K::default();
+        assert_eq!(
+            format(format_args!("{:?}", ba)).as_str(),
+            "BitAllocCascade16 { cap: 4096, free: 0, free_runs: [] }"
+        );
+        ba.insert(10..20);
+        ba.insert(4000..);
+        assert_eq!(
+            format(format_args!("{:?}", ba)).as_str(),
+            "BitAllocCascade16 { cap: 4096, free: 106, free_runs: [10..20, 4000..4096] }"
+        );
+        for i in 0..10 {
+            ba.insert(100 * i + 50..100 * i + 51);
+        }
+        // only the first few runs are printed
+        assert_eq!(
+            format(format_args!("{:?}", ba)).as_str(),
+            "BitAllocCascade16 { cap: 4096, free: 116, free_runs: [10..20, 50..51, 150..151, \
+             250..251, 350..351, 450..451, 550..551, 650..651, ..] }"
+        );
+        let mut ba = BitAlloc16::default();
+        ba.insert(0..4);
+        assert_eq!(
+            format(format_args!("{:?}", ba)).as_str(),
+            "BitAlloc16 { cap: 16, free: 4, free_runs: [0..4] }"
+        );
+    }
+
+    #[test]
+    fn binary_hex() {
+        let mut ba = BitAlloc16::default();
+        ba.insert(0..4);
+        ba.insert(15..16);
+        assert_eq!(
+            format(format_args!("{:b}", ba)).as_str(),
+            "1000000000001111"
+        );
+        assert_eq!(format(format_args!("{:x}", ba)).as_str(), "800f");
+
+        let mut ba = BitAlloc256::default();
+        ba.insert(16..20);
+        ba.insert(255..256);
+        assert_eq!(
+            format(format_args!("{:x}", ba)).as_str(),
+            "0000_000f_0000_0000_0000_0000_0000_0000_\
+             0000_0000_0000_0000_0000_0000_0000_8000"
+        );
+        // windows need not be aligned to a chunk
+        assert_eq!(
+            format(format_args!("{:b}", ba.fmt_range(14..22))).as_str(),
+            "00111100"
+        );
+        assert_eq!(
+            format(format_args!("{:b}", ba.fmt_range(0..20))).as_str(),
+            "0000000000000000_1111"
+        );
+        assert_eq!(
+            format(format_args!("{:x}", ba.fmt_range(250..))).as_str(),
+            "20"
+        );
+        assert_eq!(
+            format(format_args!("{:x}", ba.fmt_range(5..5))).as_str(),
+            ""
+        );
+    }
+
+    /// FNV-1a, to check that equal allocators hash equally.
+    struct Fnv(u64);
+
+    impl Hasher for Fnv {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+        fn write(&mut self, bytes: &[u8]) {
+            for &b in bytes {
+                self.0 = (self.0 ^ b as u64).wrapping_mul(0x100_0000_01b3);
+            }
+        }
+    }
+
+    fn hash_of<T: Hash>(x: &T) -> u64 {
+        let mut h = Fnv(0xcbf2_9ce4_8422_2325);
+        x.hash(&mut h);
+        h.finish()
+    }
+
+    #[test]
+    fn clone_eq() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(100..3000);
+        let checkpoint = ba.clone();
+        assert!(ba == checkpoint);
+        let a = ba.alloc_contiguous(64, 6).unwrap();
+        let b = ba.alloc().unwrap();
+        assert!(ba != checkpoint);
+        ba.dealloc(b);
+        ba.dealloc_contiguous(a, 64);
+        assert!(ba == checkpoint);
+        assert_eq!(hash_of(&ba), hash_of(&checkpoint));
+        // the same state reached by different operations
+        let mut other = BitAlloc4K::default();
+        other.insert(0..3000);
+        other.remove(0..100);
+        assert!(other == ba);
+        assert_eq!(hash_of(&other), hash_of(&ba));
+    }
+
+    #[test]
+    fn from_ranges() {
+        let ba = BitAlloc4K::from_ranges([0..10, 5..20, 30..30, 4000..5000, 6000..7000]);
+        assert!(ba.free_ranges().eq([0..20, 4000..4096]));
+        let ba = BitAllocConst::<100, BitAlloc256>::from_ranges([50..200, 20..20]);
+        assert_eq!(ba.free_count(), 50);
+    }
+
+    #[test]
+    #[should_panic(expected = "reversed range")]
+    fn from_ranges_reversed() {
+        let (start, end) = (20, 10);
+        BitAlloc4K::from_ranges([0..5, start..end]);
+    }
+
+    #[test]
+    fn raw_words() {
+        let mut ba = BitAlloc64K::default();
+        let mut seed = 0x00dd_ba11_u32;
+        for _ in 0..200 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let start = (seed >> 4) as usize % BitAlloc64K::CAP;
+            let end = (start + (seed >> 20) as usize % 2000).min(BitAlloc64K::CAP);
+            if seed & 1 == 0 {
+                ba.insert(start..end);
+            } else {
+                ba.remove(start..end);
+            }
+        }
+        let mut words = [0; BitAlloc64K::CAP / WORD_BITS];
+        ba.as_raw_words(&mut |w, word| words[w] = word);
+        for (w, &word) in words.iter().enumerate() {
+            for i in 0..WORD_BITS {
+                assert_eq!(word >> i & 1 != 0, ba.test(w * WORD_BITS + i));
+            }
+            assert_eq!(ba.get_word(w), word);
+        }
+        let copy = BitAlloc64K::from_raw_words(&words);
+        assert!(copy == ba);
+        // the summaries are rebuilt too
+        assert_eq!(copy.max_contiguous(), ba.max_contiguous());
+        assert_eq!(copy.free_prefix(), ba.free_prefix());
+        assert_eq!(copy.free_suffix(), ba.free_suffix());
+        assert!(copy.iter_free().eq(ba.iter_free()));
+
+        let ba = BitAlloc16::from_raw_words(&[0x1_8001]);
+        assert!(ba.iter_free().eq([0, 15].iter().cloned()));
+        assert_eq!(ba.raw_word(0), 0x8001);
+    }
+
+    #[test]
+    fn log2_() {
+        for x in (1..=0xffff).chain((16..64).map(|i| (1 << i) | 0x1234)) {
+            assert_eq!(log2(x), log2_naive(x), "log2 failed: {}", x);
+        }
+    }
+
+    #[test]
+    fn bitalloc16() {
+        let mut ba = BitAlloc16::default();
+        assert_eq!(BitAlloc16::CAP, 16);
+        ba.insert(0..16);
+        for i in 0..16 {
+            assert!(ba.test(i));
+        }
+        ba.remove(8..14);
+        assert_eq!(ba.alloc(), Some(15));
+        assert_eq!(ba.alloc(), Some(14));
+        assert_eq!(ba.alloc(), Some(7));
+        ba.dealloc(14);
+        ba.dealloc(15);
+        ba.dealloc(7);
+
+        for _ in 0..10 {
+            assert!(ba.alloc().is_some());
+        }
+        assert!(!ba.any());
+        assert!(ba.alloc().is_none());
+    }
+
+    #[test]
+    fn bitalloc16_scan() {
+        for word in [0u16, 1, 0x8000, 0x0ff0, 0xa5a5, 0xffff] {
+            let ba = BitAlloc16(word);
+            for key in 0..20 {
+                let next = (key..16).find(|&i| word.get_bit(i));
+                let next_allocated = (key..16).find(|&i| !word.get_bit(i));
+                let prev = (0..=key.min(15)).rev().find(|&i| word.get_bit(i));
+                assert_eq!(ba.next(key), next);
+                assert_eq!(ba.next_allocated(key), next_allocated);
+                assert_eq!(ba.prev(key), prev);
+            }
+        }
+    }
+
+    #[test]
+    fn bitalloc16_copy() {
+        const ARR: [BitAlloc16; 4] = [BitAlloc16::DEFAULT; 4];
+        let mut arr = ARR;
+        arr[1].insert(0..4);
+        let copy = arr[1];
+        arr[1].alloc();
+        assert!(!arr[0].any() && !arr[2].any() && !arr[3].any());
+        assert_eq!(arr[1].iter_free().count(), 3);
+        assert_eq!(copy.iter_free().count(), 4);
+    }
+
+    #[test]
+    fn const_new() {
+        static FRAMES: BitAlloc4K = BitAlloc4K::new();
+        const SMALL: (BitAlloc16, BitAlloc64, BitAllocArray<300, 5>) =
+            (BitAlloc16::new(), BitAlloc64::new(), BitAllocArray::new());
+        const CAPPED: BitAllocConst<1000, BitAlloc4K> = BitAllocConst::new();
+        assert_eq!(FRAMES, BitAlloc4K::default());
+        assert!(!SMALL.0.any() && !SMALL.1.any() && !SMALL.2.any());
+        let mut ba = CAPPED;
+        ba.insert(..);
+        assert_eq!(ba.free_count(), 1000);
+    }
+
+    #[test]
+    fn rle_roundtrip() {
+        let mut ba = BitAlloc64K::default();
+        let mut runs = 0;
+        ba.export_rle(&mut |run, free| {
+            assert_eq!((run, free), (0..BitAlloc64K::CAP, false));
+            runs += 1;
+        });
+        assert_eq!(runs, 1);
+        ba.insert(..40000);
+        ba.remove(100..200);
+        ba.remove(39999..);
+        ba.dealloc(150);
+        let mut copy = BitAlloc64K::default();
+        let mut end = 0;
+        let mut last = None;
+        let mut collected: [_; 8] = core::array::from_fn(|_| (0..0, false));
+        let mut n = 0;
+        ba.export_rle(&mut |run, free| {
+            assert_eq!(run.start, end);
+            assert_ne!(last, Some(free));
+            end = run.end;
+            last = Some(free);
+            collected[n] = (run, free);
+            n += 1;
+        });
+        assert_eq!((n, end), (6, BitAlloc64K::CAP));
+        assert_eq!(collected[2], (150..151, true));
+        copy.insert(1000..2000);
+        copy.import_rle(collected[..n].iter().cloned());
+        assert!(copy.is_subset_of(&ba) && ba.is_subset_of(&copy));
+        assert_eq!(copy.free_count(), 39999 - 99);
+    }
+
+    #[test]
+    fn contiguous_ranges() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(3..);
+        let block = ba.alloc_contiguous_range(10, 3).unwrap();
+        assert_eq!(block, 8..18);
+        let other = ba.alloc_contiguous_range(4, 0).unwrap();
+        assert_eq!(other, 3..7);
+        assert_eq!(ba.alloc_contiguous_range(5000, 0), None);
+        ba.dealloc_range(block);
+        assert_eq!(ba.free_count(), 4093 - 4);
+        ba.dealloc_range(other);
+        assert_eq!(ba.max_contiguous(), Some(3..4096));
+    }
+
+    #[test]
+    fn clear_fill() {
+        let mut ba = BitAlloc64K::default();
+        ba.insert(100..30000);
+        ba.remove(500..600);
+        ba.clear();
+        assert_eq!((ba.free_count(), ba.any(), ba.next(0)), (0, false, None));
+        assert_eq!(ba.validate(), Ok(()));
+        ba.insert(40000..40010);
+        assert_eq!(ba.iter_free().count(), 10);
+        ba.fill();
+        assert_eq!(ba.free_count(), BitAlloc64K::CAP);
+        assert_eq!(ba.max_contiguous_len(), BitAlloc64K::CAP);
+        assert_eq!(ba.validate(), Ok(()));
+        assert_eq!(ba.alloc_contiguous(BitAlloc64K::CAP, 0), Some(0));
+        let mut array = BitAllocArray::<1000, 16>::default();
+        array.fill();
+        assert_eq!(
+            (array.free_count(), array.next(999), array.next(1000)),
+            (1000, Some(999), None)
+        );
+        array.clear();
+        assert_eq!(array.free_count(), 0);
+    }
+
+    #[test]
+    fn bitalloc4k() {
+        let mut ba = BitAlloc4K::default();
+        assert_eq!(BitAlloc4K::CAP, 4096);
+        ba.insert(0..4096);
+        for i in 0..4096 {
+            assert!(ba.test(i));
+        }
+        ba.remove(8..4094);
+        for i in 0..4096 {
+            assert_eq!(ba.test(i), !(8..4094).contains(&i));
+        }
+        assert_eq!(ba.alloc(), Some(4095));
+        assert_eq!(ba.alloc(), Some(4094));
+        assert_eq!(ba.alloc(), Some(7));
+        ba.dealloc(4095);
+        ba.dealloc(4094);
+        ba.dealloc(7);
+
+        for _ in 0..10 {
+            assert!(ba.alloc().is_some());
+        }
+        assert!(ba.alloc().is_none());
+    }
+
+    #[test]
+    fn empty_range() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(100..3000);
+        ba.insert(0..0);
+        ba.remove(5..5);
+        ba.insert(4096..4096);
+        ba.remove(4096..4096);
+        ba.remove(256..256);
+        assert_eq!(ba.iter_free().count(), 2900);
+        // wherever they lie
+        ba.insert(5000..5000);
+        ba.remove(usize::MAX..);
+        assert_eq!(ba.count_free_in(9000..9000), 0);
+        assert_eq!(ba.try_insert(5000..5000), Ok(()));
+        assert_eq!(ba.try_remove(5000..5000), Ok(()));
+        assert_eq!(ba.try_insert(4000..4097), Err(BitAllocError::OutOfRange));
+        assert_eq!(ba.try_remove(4000..4097), Err(BitAllocError::OutOfRange));
+        // up to exactly CAP is fine
+        assert_eq!(ba.try_insert(4000..4096), Ok(()));
+        assert_eq!(ba.try_remove(4000..4096), Ok(()));
+        assert_eq!(ba.iter_free().count(), 2900);
+
+        let mut ba = BitAlloc16::default();
+        ba.insert(0..0);
+        ba.insert(16..16);
+        assert!(!ba.any());
+        ba.insert(0..16);
+        ba.remove(3..3);
+        ba.remove(20..20);
+        assert_eq!(ba.iter_free().count(), 16);
+    }
+
+    #[test]
+    fn free_count() {
+        let mut ba = BitAlloc4K::default();
+        assert_eq!(ba.free_count(), 0);
+        assert_eq!(ba.allocated_count(), 4096);
+        ba.insert(0..4096);
+        ba.remove(100..300);
+        assert_eq!(ba.free_count(), 3896);
+        ba.insert(200..250);
+        assert_eq!(ba.free_count(), 3946);
+        ba.alloc().unwrap();
+        ba.alloc_low().unwrap();
+        ba.alloc_contiguous(100, 4).unwrap();
+        ba.alloc_aligned(9).unwrap();
+        assert_eq!(ba.free_count(), 3843);
+        ba.dealloc(4095);
+        assert_eq!(ba.free_count(), 3844);
+        assert_eq!(ba.allocated_count(), 252);
+        assert_eq!(ba.free_count(), ba.iter_free().count());
+    }
+
+    #[test]
+    fn max_contiguous() {
+        let mut ba = BitAlloc4K::default();
+        assert_eq!(ba.max_contiguous(), None);
+        ba.insert(10..20);
+        ba.insert(100..300);
+        ba.insert(500..700);
+        ba.insert(4000..4096);
+        assert_eq!(ba.max_contiguous(), Some(100..300));
+        ba.remove(150..151);
+        assert_eq!(ba.max_contiguous(), Some(500..700));
+        ba.insert(3000..4000);
+        assert_eq!(ba.max_contiguous(), Some(3000..4096));
+        ba.insert(0..4096);
+        assert_eq!(ba.max_contiguous(), Some(0..4096));
+    }
+
+    #[test]
+    fn free_runs() {
+        let mut ba = BitAlloc16::default();
+        ba.insert(0..3);
+        ba.insert(5..12);
+        ba.insert(14..16);
+        assert_eq!(ba.free_prefix(), 3);
+        assert_eq!(ba.free_suffix(), 2);
+        assert_eq!(ba.max_contiguous_len(), 7);
+
+        let mut ba = BitAlloc64K::default();
+        assert_eq!(ba.max_contiguous_len(), 0);
+        ba.insert(0..5000);
+        ba.insert(30000..40000);
+        ba.insert(60000..65536);
+        assert_eq!(ba.free_prefix(), 5000);
+        assert_eq!(ba.free_suffix(), 5536);
+        assert_eq!(ba.max_contiguous_len(), 10000);
+        assert_eq!(ba.max_contiguous(), Some(30000..40000));
+        ba.alloc_contiguous(1, 0).unwrap();
+        assert_eq!(ba.free_prefix(), 0);
+        ba.dealloc(0);
+        ba.remove(35000..35001);
+        assert_eq!(ba.max_contiguous(), Some(60000..65536));
+        assert_eq!(ba.alloc_contiguous(5537, 0), None);
+        ba.insert(0..65536);
+        assert_eq!(ba.max_contiguous_len(), 65536);
+        assert_eq!(ba.free_prefix(), 65536);
+    }
+
+    #[test]
+    fn bitalloc_contiguous() {
+        let mut ba0 = BitAlloc16::default();
+        ba0.insert(0..BitAlloc16::CAP);
+        ba0.remove(3..6);
+        assert_eq!(ba0.next(0), Some(0));
+        assert_eq!(ba0.alloc_contiguous(1, 1), Some(0));
+        assert_eq!(ba0.find_contiguous(2, 0), Some(1));
+
+        let mut ba = BitAlloc4K::default();
+        assert_eq!(BitAlloc4K::CAP, 4096);
+        ba.insert(0..BitAlloc4K::CAP);
+        ba.remove(3..6);
+        assert_eq!(ba.next(0), Some(0));
+        assert_eq!(ba.alloc_contiguous(1, 1), Some(0));
+        assert_eq!(ba.next(0), Some(1));
+        assert_eq!(ba.next(1), Some(1));
+        assert_eq!(ba.next(2), Some(2));
+        assert_eq!(ba.find_contiguous(2, 0), Some(1));
+        assert_eq!(ba.alloc_contiguous(2, 0), Some(1));
+        assert_eq!(ba.alloc_contiguous(2, 3), Some(8));
+        ba.remove(0..4096 - 64);
+        assert_eq!(ba.alloc_contiguous(128, 7), None);
+        assert_eq!(ba.alloc_contiguous(7, 3), Some(4096 - 64));
+        ba.insert(321..323);
+        assert_eq!(ba.alloc_contiguous(2, 1), Some(4096 - 64 + 8));
+        assert_eq!(ba.alloc_contiguous(2, 0), Some(321));
+        assert_eq!(ba.alloc_contiguous(64, 6), None);
+        assert_eq!(ba.alloc_contiguous(32, 4), Some(4096 - 48));
+        for i in 0..4096 - 64 + 7 {
+            ba.dealloc(i);
+        }
+        for i in 4096 - 64 + 8..4096 - 64 + 10 {
+            ba.dealloc(i);
+        }
+        for i in 4096 - 48..4096 - 16 {
+            ba.dealloc(i);
+        }
+        // for i in 4096 - 32..4096 {
+        //     ba.dealloc(i);
+        // }
+    }
+
+    #[test]
+    fn iter_free_allocated_partition() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(0..BitAlloc4K::CAP);
+        // a simple LCG gives a reproducible pseudo-random pattern
+        let mut seed = 0x2545_f491_u32;
+        for i in 0..BitAlloc4K::CAP {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            if (seed >> 16) & 3 == 0 {
+                ba.remove(i..i + 1);
+            }
+        }
+        ba.remove(1024..2048);
+        // the cascade descent agrees with chaining next() from the root
+        let mut key = 0;
+        let chained = core::iter::from_fn(|| {
+            let res = ba.next(key)?;
+            key = res + 1;
+            Some(res)
+        });
+        assert!(ba.iter_free().eq(chained));
+        // reverse descent yields the same bits and runs, top down
+        let mut free = [0; BitAlloc4K::CAP];
+        let n = ba
+            .iter_free()
+            .zip(free.iter_mut())
+            .map(|(i, x)| *x = i)
+            .count();
+        assert!(ba.iter_free().rev().eq(free[..n].iter().rev().cloned()));
+        let mut used = [0; BitAlloc4K::CAP];
+        let n = ba
+            .iter_allocated()
+            .zip(used.iter_mut())
+            .map(|(i, x)| *x = i)
+            .count();
+        assert!(ba
+            .iter_allocated()
+            .rev()
+            .eq(used[..n].iter().rev().cloned()));
+        let mut runs = [(0, 0); BitAlloc4K::CAP];
+        let n = ba
+            .free_ranges()
+            .zip(runs.iter_mut())
+            .map(|(r, x)| *x = (r.start, r.end))
+            .count();
+        assert!(ba
+            .free_ranges()
+            .rev()
+            .eq(runs[..n].iter().rev().map(|&(s, e)| s..e)));
+        let n = ba
+            .allocated_ranges()
+            .zip(runs.iter_mut())
+            .map(|(r, x)| *x = (r.start, r.end))
+            .count();
+        assert!(ba
+            .allocated_ranges()
+            .rev()
+            .eq(runs[..n].iter().rev().map(|&(s, e)| s..e)));
+        let mut seen = [false; BitAlloc4K::CAP];
+        let mut last = None;
+        for i in ba.iter_free() {
+            assert!(ba.test(i));
+            assert!(last < Some(i));
+            last = Some(i);
+            seen[i] = true;
+        }
+        let mut last = None;
+        for i in ba.iter_allocated() {
+            assert!(!ba.test(i));
+            assert!(last < Some(i));
+            last = Some(i);
+            assert!(!seen[i], "{} is both free and allocated", i);
+            seen[i] = true;
+        }
+        assert!(seen.iter().all(|&x| x));
+        assert_eq!(
+            ba.iter_allocated()
+                .filter(|i| (1024..2048).contains(i))
+                .count(),
+            1024
+        );
+    }
+
+    #[test]
+    fn alloc_low() {
+        let mut ba = BitAlloc16::default();
+        ba.insert(3..10);
+        assert_eq!(ba.alloc_low(), Some(3));
+        assert_eq!(ba.alloc_low(), Some(4));
+        assert_eq!(ba.alloc(), Some(9));
+
+        let mut ba = BitAlloc4K::default();
+        ba.insert(0..BitAlloc4K::CAP);
+        ba.remove(0..300);
+        for i in 300..4096 {
+            assert_eq!(ba.alloc_low(), Some(i));
+        }
+        assert_eq!(ba.alloc_low(), None);
+        ba.dealloc(1000);
+        ba.dealloc(500);
+        assert_eq!(ba.alloc_low(), Some(500));
+        assert_eq!(ba.alloc_low(), Some(1000));
+    }
+
+    #[test]
+    fn alloc_policy() {
+        struct EvenFirst;
+        impl AllocPolicy for EvenFirst {
+            fn pick(bits: u64) -> usize {
+                let even = bits & 0x5555_5555_5555_5555;
+                LowestFirst::pick(if even != 0 { even } else { bits })
+            }
+        }
+
+        let mut ba = BitAllocCascade16::<BitAlloc256, LowestFirst>::default();
+        ba.insert(100..200);
+        assert_eq!(ba.peek_alloc(), Some(100));
+        assert_eq!(ba.alloc(), Some(100));
+        assert_eq!(ba.alloc(), Some(101));
+        assert_eq!(ba.alloc_with::<HighestFirst>(), Some(199));
+
+        let mut ba = BitAllocCascade16::<BitAlloc256, EvenFirst>::default();
+        ba.insert(17..20);
+        ba.insert(33..34);
+        // child 1 (bits 16..32) is an odd child, child 2 (bits 32..48) is even
+        assert_eq!(ba.alloc(), Some(33));
+        assert_eq!(ba.alloc(), Some(18));
+        assert_eq!(ba.alloc(), Some(17));
+        assert_eq!(ba.alloc(), Some(19));
+        assert_eq!(ba.alloc(), None);
+    }
+
+    #[test]
+    fn alloc_many() {
+        let mut ba = BitAlloc4K::default();
+        let mut expected = BitAlloc4K::default();
+        ba.insert(100..700);
+        expected.insert(100..700);
+        let mut out = [0; 500];
+        assert_eq!(ba.alloc_many(&mut out), 500);
+        for &i in out.iter() {
+            assert_eq!(expected.alloc(), Some(i));
+        }
+        assert_eq!(ba.free_count(), 100);
+        assert_eq!(ba.max_contiguous(), Some(100..200));
+        assert_eq!(ba.alloc_many_with::<LowestFirst>(&mut out[..10]), 10);
+        assert_eq!(
+            &out[..10],
+            &[100, 101, 102, 103, 104, 105, 106, 107, 108, 109]
+        );
+        assert_eq!(ba.alloc_many(&mut out), 90);
+        assert!(!ba.any());
+        assert_eq!(ba.alloc_many(&mut out), 0);
+    }
+
+    #[test]
+    fn dealloc_many() {
+        let mut ba = BitAlloc4K::default();
+        ba.dealloc_many((0..4096).step_by(3));
+        assert_eq!(ba.free_count(), 1366);
+        assert!((0..4096).all(|i| ba.test(i) == (i % 3 == 0)));
+        ba.dealloc_many([4000, 2, 4001, 1, 5].iter().cloned());
+        assert_eq!(ba.free_count(), 1371);
+        assert_eq!(ba.max_contiguous(), Some(0..4));
+        ba.dealloc_many(core::iter::empty());
+        assert_eq!(ba.free_count(), 1371);
+    }
+
+    #[test]
+    #[should_panic]
+    fn dealloc_many_twice() {
+        let mut ba = BitAlloc4K::default();
+        ba.dealloc_many([7, 8, 7].iter().cloned());
+    }
+
+    #[test]
+    fn prev() {
+        let mut ba = BitAlloc16::default();
+        assert_eq!(ba.last(), None);
+        ba.insert(3..5);
+        assert_eq!(ba.prev(2), None);
+        assert_eq!(ba.prev(3), Some(3));
+        assert_eq!(ba.prev(15), Some(4));
+        assert_eq!(ba.last(), Some(4));
+
+        let mut ba = BitAlloc4K::default();
+        assert_eq!(ba.last(), None);
+        assert_eq!(ba.prev(100), None);
+        ba.insert(10..20);
+        ba.insert(1000..1001);
+        assert_eq!(ba.prev(9), None);
+        assert_eq!(ba.prev(10), Some(10));
+        assert_eq!(ba.prev(999), Some(19));
+        assert_eq!(ba.prev(1000), Some(1000));
+        assert_eq!(ba.prev(5000), Some(1000));
+        assert_eq!(ba.last(), Some(1000));
+        ba.insert(4095..4096);
+        assert_eq!(ba.last(), Some(4095));
+        assert_eq!(ba.prev(4094), Some(1000));
+        assert_eq!(ba.prev_allocated(5000), Some(4094));
+        assert_eq!(ba.prev_allocated(1000), Some(999));
+        assert_eq!(ba.prev_allocated(19), Some(9));
+        assert_eq!(ba.prev_allocated(10), Some(9));
+        ba.insert(0..10);
+        assert_eq!(ba.prev_allocated(19), None);
+        assert_eq!(BitAlloc16::default().prev_allocated(100), Some(15));
+    }
+
+    #[test]
+    fn alloc_hint() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(100..110);
+        ba.insert(2000..2001);
+        ba.insert(3000..3001);
+        assert_eq!(ba.alloc_from(105), Some(105));
+        assert_eq!(ba.alloc_from(105), Some(106));
+        assert_eq!(ba.alloc_from(300), Some(2000));
+        assert_eq!(ba.alloc_from(3001), None);
+
+        assert_eq!(ba.alloc_near(2500), Some(3000));
+        assert_eq!(ba.alloc_near(105), Some(104));
+        // equally far: take the lower one
+        assert_eq!(ba.alloc_near(105), Some(103));
+        assert_eq!(ba.alloc_near(105), Some(107));
+        assert_eq!(ba.alloc_near(9999), Some(109));
+        assert_eq!(ba.alloc_near(0), Some(100));
+        for _ in 0..3 {
+            assert!(ba.alloc_near(1000).is_some());
+        }
+        assert_eq!(ba.alloc_near(1000), None);
+    }
+
+    #[test]
+    fn peek_alloc() {
+        let mut ba = BitAlloc4K::default();
+        assert_eq!(ba.peek_alloc(), None);
+        ba.insert(100..200);
+        ba.insert(1000..1001);
+        for _ in 0..102 {
+            let peeked = ba.peek_alloc();
+            assert_eq!(ba.peek_alloc(), peeked);
+            assert_eq!(ba.peek(), peeked);
+            assert_eq!(ba.alloc(), peeked);
+        }
+        assert_eq!(ba.peek_alloc(), None);
+    }
+
+    #[test]
+    fn bitalloc_aligned() {
+        let mut ba0 = BitAlloc16::default();
+        ba0.insert(0..BitAlloc16::CAP);
+        ba0.remove(0..1);
+        assert_eq!(ba0.alloc_aligned(2), Some(4));
+        assert_eq!(ba0.alloc_aligned(4), None);
+        assert_eq!(ba0.alloc_aligned(0), Some(1));
+
+        let mut ba = BitAlloc4K::default();
+        ba.insert(0..BitAlloc4K::CAP);
+        ba.remove(0..300);
+        let mut last = None;
+        while let Some(i) = ba.alloc_aligned(5) {
+            assert_eq!(i % 32, 0);
+            assert!(last < Some(i));
+            assert!(!ba.test(i));
+            last = Some(i);
+        }
+        assert_eq!(last, Some(4096 - 32));
+        assert_eq!(ba.alloc_aligned(12), None);
+        ba.insert(0..1);
+        assert_eq!(ba.alloc_aligned(12), Some(0));
+
+        // same choice as the contiguous search
+        let mut ba1 = BitAlloc4K::default();
+        let mut ba2 = BitAlloc4K::default();
+        ba1.insert(100..4000);
+        ba2.insert(100..4000);
+        for align_log2 in [0, 3, 4, 7, 8, 9, 3, 0] {
+            assert_eq!(
+                ba1.alloc_aligned(align_log2),
+                ba2.alloc_contiguous(1, align_log2)
+            );
+        }
+    }
+
+    #[test]
+    fn alloc_contiguous_at() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(0..BitAlloc4K::CAP);
+        ba.remove(300..301);
+        assert!(ba.alloc_contiguous_at(100, 200));
+        assert!((100..300).all(|i| !ba.test(i)));
+        assert!(ba.test(99));
+        // overlaps an allocated bit: nothing changes
+        assert!(!ba.alloc_contiguous_at(290, 5));
+        assert!(!ba.alloc_contiguous_at(0, 101));
+        assert!(!ba.alloc_contiguous_at(4000, 97));
+        assert!(!ba.alloc_contiguous_at(usize::MAX, 2));
+        assert_eq!(ba.iter_free().count(), 4096 - 201);
+        assert!(ba.alloc_contiguous_at(301, 3795));
+        assert!(ba.alloc_contiguous_at(0, 100));
+        assert!(!ba.any());
+    }
+
+    #[test]
+    fn dealloc_contiguous() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(0..BitAlloc4K::CAP);
+        let base = ba.alloc_contiguous(1000, 8).unwrap();
+        assert_eq!(base, 0);
+        let base2 = ba.alloc_contiguous(300, 4).unwrap();
+        ba.dealloc_contiguous(base, 1000);
+        assert!((0..1000).all(|i| ba.test(i)));
+        assert!((base2..base2 + 300).all(|i| !ba.test(i)));
+        ba.dealloc_contiguous(base2, 300);
+        assert_eq!(ba.iter_free().count(), 4096);
     }
 
     #[test]
-    fn bitalloc4k() {
+    #[cfg(debug_assertions)]
+    #[should_panic]
+    fn dealloc_contiguous_free() {
         let mut ba = BitAlloc4K::default();
-        assert_eq!(BitAlloc4K::CAP, 4096);
-        ba.insert(0..4096);
-        for i in 0..4096 {
-            assert_eq!(ba.test(i), true);
+        ba.insert(0..BitAlloc4K::CAP);
+        ba.alloc_contiguous(64, 0).unwrap();
+        ba.dealloc_contiguous(0, 65);
+    }
+
+    #[test]
+    fn fallible() {
+        let mut ba = BitAlloc4K::default();
+        assert_eq!(ba.try_alloc(), Err(BitAllocError::NoSpace));
+        assert_eq!(ba.try_insert(0..4097), Err(BitAllocError::OutOfRange));
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 10..5;
+        assert_eq!(ba.try_insert(reversed), Err(BitAllocError::OutOfRange));
+        assert_eq!(ba.try_insert(100..200), Ok(()));
+        assert_eq!(ba.try_insert(199..300), Err(BitAllocError::AlreadyFree));
+        assert!(!ba.test(250));
+        assert_eq!(ba.try_alloc(), Ok(199));
+        assert_eq!(ba.try_dealloc(199), Ok(()));
+        assert_eq!(ba.try_dealloc(199), Err(BitAllocError::AlreadyFree));
+        assert_eq!(ba.try_dealloc(4096), Err(BitAllocError::OutOfRange));
+        assert_eq!(
+            ba.try_remove(150..250),
+            Err(BitAllocError::AlreadyAllocated)
+        );
+        assert!(ba.test(150));
+        assert_eq!(ba.try_remove(100..200), Ok(()));
+        assert_eq!(ba.try_remove(0..0), Ok(()));
+        assert!(!ba.any());
+    }
+
+    #[test]
+    fn best_fit() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(0..100);
+        ba.insert(200..240);
+        ba.insert(300..310);
+        ba.insert(1000..1020);
+        assert_eq!(ba.alloc_contiguous_best_fit(10, 0), Some(300));
+        assert_eq!(ba.alloc_contiguous_best_fit(10, 0), Some(1000));
+        assert_eq!(ba.alloc_contiguous_best_fit(10, 0), Some(1010));
+        // the 200..240 run has no room for 16 bits aligned to 64
+        assert_eq!(ba.alloc_contiguous_best_fit(16, 6), Some(0));
+        assert_eq!(ba.alloc_contiguous_best_fit(30, 0), Some(200));
+        assert_eq!(ba.alloc_contiguous_best_fit(101, 0), None);
+        assert_eq!(ba.free_count(), 84 + 10);
+    }
+
+    #[test]
+    fn up_convert() {
+        let mut parts = [BitAlloc4K::DEFAULT; 16];
+        parts[1].insert(100..200);
+        parts[15].insert(..);
+        let ba = BitAlloc64K::from_parts(parts);
+        assert_eq!(ba.validate(), Ok(()));
+        assert!(ba.free_ranges().eq([4196..4296, 61440..65536]));
+        assert_eq!(ba.max_contiguous_len(), 4096);
+
+        let mut small = BitAlloc256::default();
+        small.insert(10..20);
+        small.insert(250..);
+        let mut large = BitAlloc64K::default();
+        large.insert(..);
+        small.copy_into(&mut large, 1000);
+        assert!(large.free_ranges().eq([0..1000, 1010..1020, 1250..65536]));
+        assert_eq!(large.validate(), Ok(()));
+    }
+
+    #[test]
+    fn guarded_gap() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(0..20);
+        ba.insert(100..400);
+        // 0..20 has room for the block, not for the guards as well
+        assert_eq!(ba.alloc_contiguous_guarded_gap(16, 4, 2), Some(112));
+        assert!(ba.free_ranges().eq([0..20, 100..110, 130..400]));
+        assert_eq!(ba.alloc_contiguous_guarded_gap(16, 3, 2), Some(136));
+        // nothing goes right next to a guarded block
+        assert_eq!(ba.alloc_contiguous(3, 0), Some(0));
+        assert_eq!(ba.prev_allocated(153), Some(153));
+        assert_eq!(ba.next(130), Some(130));
+        assert_eq!(ba.next(134), Some(154));
+        assert_eq!(ba.alloc_contiguous_guarded_gap(1, 0, usize::MAX), None);
+        ba.dealloc_contiguous_guarded_gap(112, 16, 2);
+        ba.dealloc_contiguous_guarded_gap(136, 16, 2);
+        ba.dealloc_contiguous(0, 3);
+        assert!(ba.free_ranges().eq([0..20, 100..400]));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "guard bits are free")]
+    fn guarded_gap_freed_guard() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(..);
+        let base = ba.alloc_contiguous_guarded_gap(16, 4, 2).unwrap();
+        ba.dealloc(base + 17);
+        ba.dealloc_contiguous_guarded_gap(base, 16, 2);
+    }
+
+    #[test]
+    fn scatter() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(10..20);
+        ba.insert(100..130);
+        ba.insert(1001..1040);
+        ba.insert(2000..2005);
+        let mut out = [const { 0..0 }; 4];
+        // one block when it fits
+        assert_eq!(ba.alloc_scatter(30, 4, 0, &mut out), Some(1));
+        assert_eq!(out[0], 1001..1031);
+        ba.dealloc_contiguous(1001, 30);
+        // else the longest runs, in ascending order
+        assert_eq!(ba.alloc_scatter(50, 2, 3, &mut out), Some(2));
+        assert_eq!(out[..2], [104..122, 1008..1040]);
+        assert_eq!(ba.free_count(), 34);
+        assert_eq!(ba.alloc_scatter(30, 2, 0, &mut out), None);
+        assert_eq!(ba.free_count(), 34);
+        // no more blocks than `out` holds
+        assert_eq!(ba.alloc_scatter(31, 8, 0, &mut out), None);
+        assert_eq!(ba.alloc_scatter(30, 8, 0, &mut out), Some(4));
+        assert_eq!(ba.free_count(), 4);
+        assert_eq!(ba.alloc_scatter(0, 8, 0, &mut out), None);
+    }
+
+    #[test]
+    fn flex_size() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(0..40);
+        ba.insert(100..130);
+        ba.insert(1000..1100);
+        assert_eq!(ba.find_contiguous_flex(8..=64, 0), Some(1000..1064));
+        assert_eq!(ba.alloc_contiguous_flex(8..=1000, 0), Some(1000..1100));
+        assert_eq!(ba.alloc_contiguous_flex(8..=1000, 4), Some(0..40));
+        // 100..130 only has 18 bits from 16-aligned bit 112 on
+        assert_eq!(ba.alloc_contiguous_flex(20..=1000, 4), None);
+        assert_eq!(ba.alloc_contiguous_flex(0..=20, 0), Some(100..120));
+        assert_eq!(ba.free_count(), 10);
+    }
+
+    #[test]
+    fn top_down() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(0..100);
+        ba.insert(200..240);
+        ba.insert(1000..1020);
+        ba.insert(4090..4096);
+        assert_eq!(ba.alloc_contiguous_top(6, 0), Some(4090));
+        assert_eq!(ba.alloc_contiguous_top(8, 0), Some(1012));
+        // 1000..1012 has no room for 8 bits aligned to 16
+        assert_eq!(ba.find_contiguous_top(8, 4), Some(224));
+        assert_eq!(ba.alloc_contiguous_top(16, 6), Some(64));
+        assert_eq!(ba.alloc_contiguous_top(101, 0), None);
+        assert_eq!(ba.find_contiguous_top(1, 12), Some(0));
+        assert_eq!(ba.find_contiguous_top(1, 13), None);
+
+        // the highest fit, as a brute-force search finds it
+        let mut ba = BitAlloc256::default();
+        let mut seed = 7u32;
+        for _ in 0..500 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let key = (seed >> 8) as usize % 256;
+            if seed & 1 == 0 {
+                ba.insert(key..(key + 3).min(256));
+            } else {
+                ba.remove(key..key + 1);
+            }
+            let (size, align_log2) = (1 + (seed >> 20) as usize % 8, (seed >> 28) as usize % 4);
+            let expected = (0..=256 - size)
+                .rev()
+                .filter(|base| base % (1 << align_log2) == 0)
+                .find(|&base| (base..base + size).all(|i| ba.test(i)));
+            assert_eq!(ba.find_contiguous_top(size, align_log2), expected);
         }
-        ba.remove(8..4094);
-        for i in 0..4096 {
-            assert_eq!(ba.test(i), i < 8 || i >= 4094);
+    }
+
+    #[test]
+    fn occupancy() {
+        let mut ba = BitAlloc1M::default();
+        ba.insert(100..70_000);
+        assert!(ba.occupancy(0).eq([(0..1 << 20, 69_900)]));
+        let mut children = ba.occupancy(1);
+        assert_eq!(children.next(), Some((0..65_536, 65_436)));
+        assert_eq!(children.next(), Some((65_536..131_072, 4464)));
+        assert_eq!(children.map(|(_, free)| free).sum::<usize>(), 0);
+        assert_eq!(ba.occupancy(2).count(), 256);
+        assert_eq!(ba.occupancy(2).next(), Some((0..4096, 3996)));
+        // past the leaves
+        assert_eq!(ba.occupancy(5).count(), 1 << 16);
+        assert_eq!(ba.occupancy(6).nth(6), Some((96..112, 12)));
+
+        let mut ba = BitAllocConst::<100, BitAlloc256>::default();
+        ba.insert(..);
+        assert!(ba
+            .occupancy(1)
+            .map(|(_, free)| free)
+            .eq([16, 16, 16, 16, 16, 16, 4]));
+        assert_eq!(ba.occupancy(1).last(), Some((96..100, 4)));
+    }
+
+    /// An allocator with nothing but the required methods.
+    struct Bools([bool; 300]);
+
+    impl Default for Bools {
+        fn default() -> Self {
+            Self::DEFAULT
         }
-        assert_eq!(ba.alloc(), Some(4095));
-        assert_eq!(ba.alloc(), Some(4094));
-        assert_eq!(ba.alloc(), Some(7));
-        ba.dealloc(4095);
-        ba.dealloc(4094);
-        ba.dealloc(7);
+    }
 
-        for _ in 0..10 {
-            assert!(ba.alloc().is_some());
+    impl BitAlloc for Bools {
+        const CAP: usize = 300;
+        const DEFAULT: Self = Bools([false; 300]);
+
+        fn test(&self, key: usize) -> bool {
+            self.0[key]
+        }
+        fn insert(&mut self, range: impl RangeBounds<usize>) {
+            self.0[to_range(range, Self::CAP)].fill(true);
+        }
+        fn remove(&mut self, range: impl RangeBounds<usize>) {
+            self.0[to_range(range, Self::CAP)].fill(false);
         }
-        assert!(ba.alloc().is_none());
     }
 
     #[test]
-    fn bitalloc_contiguous() {
-        let mut ba0 = BitAlloc16::default();
-        ba0.insert(0..BitAlloc16::CAP);
-        ba0.remove(3..6);
-        assert_eq!(ba0.next(0), Some(0));
-        assert_eq!(ba0.alloc_contiguous(1, 1), Some(0));
-        assert_eq!(find_contiguous(&ba0, BitAlloc4K::CAP, 2, 0), Some(1));
+    fn provided_methods() {
+        let mut ba = Bools::default();
+        let mut reference = BitAllocArray::<300, 5>::default();
+        let mut seed = 42u32;
+        for _ in 0..1000 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let key = (seed >> 8) as usize % 300;
+            let end = (key + (seed >> 16) as usize % 40).min(300);
+            match seed >> 29 {
+                0 | 1 => {
+                    ba.insert(key..end);
+                    reference.insert(key..end);
+                }
+                2 => {
+                    ba.remove(key..end);
+                    reference.remove(key..end);
+                }
+                3 => assert_eq!(ba.alloc(), reference.alloc()),
+                4 => assert_eq!(ba.alloc_low(), reference.alloc_low()),
+                5 => {
+                    let (size, align_log2) = (1 + key % 20, key % 4);
+                    assert_eq!(
+                        ba.alloc_contiguous(size, align_log2),
+                        reference.alloc_contiguous(size, align_log2)
+                    );
+                }
+                _ if !ba.test(key) => {
+                    ba.dealloc(key);
+                    reference.dealloc(key);
+                }
+                _ => {}
+            }
+            assert_eq!(ba.free_count(), reference.free_count());
+            assert_eq!(
+                ba.raw_word(key / WORD_BITS),
+                reference.raw_word(key / WORD_BITS)
+            );
+            assert_eq!(ba.next(key), reference.next(key));
+            assert_eq!(ba.prev(key), reference.prev(key));
+            assert_eq!(ba.next_allocated(key), reference.next_allocated(key));
+            assert_eq!(ba.prev_allocated(key), reference.prev_allocated(key));
+        }
+        ba.write_raw_word(1, 0b1010);
+        assert!(ba.iter_free().skip_while(|&i| i < 64).take(2).eq([65, 67]));
+        ba.fill();
+        assert_eq!((ba.any(), ba.alloc_aligned(7)), (true, Some(0)));
+        assert_eq!(ba.prev_allocated(usize::MAX), Some(0));
+    }
 
+    #[test]
+    fn relocate_contiguous() {
         let mut ba = BitAlloc4K::default();
-        assert_eq!(BitAlloc4K::CAP, 4096);
         ba.insert(0..BitAlloc4K::CAP);
-        ba.remove(3..6);
-        assert_eq!(ba.next(0), Some(0));
-        assert_eq!(ba.alloc_contiguous(1, 1), Some(0));
-        assert_eq!(ba.next(0), Some(1));
-        assert_eq!(ba.next(1), Some(1));
-        assert_eq!(ba.next(2), Some(2));
-        assert_eq!(find_contiguous(&ba, BitAlloc4K::CAP, 2, 0), Some(1));
-        assert_eq!(ba.alloc_contiguous(2, 0), Some(1));
-        assert_eq!(ba.alloc_contiguous(2, 3), Some(8));
-        ba.remove(0..4096 - 64);
-        assert_eq!(ba.alloc_contiguous(128, 7), None);
-        assert_eq!(ba.alloc_contiguous(7, 3), Some(4096 - 64));
-        ba.insert(321..323);
-        assert_eq!(ba.alloc_contiguous(2, 1), Some(4096 - 64 + 8));
-        assert_eq!(ba.alloc_contiguous(2, 0), Some(321));
-        assert_eq!(ba.alloc_contiguous(64, 6), None);
-        assert_eq!(ba.alloc_contiguous(32, 4), Some(4096 - 48));
-        for i in 0..4096 - 64 + 7 {
-            ba.dealloc(i);
+        ba.remove(0..100);
+        assert_eq!(ba.alloc_contiguous(64, 6), Some(128));
+        ba.insert(0..100);
+        assert_eq!(ba.relocate_contiguous(128, 64, 6), Some(0));
+        assert!((0..64).all(|i| !ba.test(i)));
+        assert!((64..4096).all(|i| ba.test(i)));
+
+        // already the lowest fit
+        assert_eq!(ba.relocate_contiguous(0, 64, 6), None);
+        assert_eq!(ba.alloc_contiguous(16, 4), Some(64));
+        ba.remove(80..128);
+        assert_eq!(ba.alloc_contiguous(16, 4), Some(128));
+        assert_eq!(ba.relocate_contiguous(128, 16, 4), None);
+        assert!((128..144).all(|i| !ba.test(i)));
+        assert!(ba.test(144));
+    }
+
+    #[test]
+    fn range_bounds() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(..);
+        assert_eq!(ba.free_count(), 4096);
+        ba.remove(10..=19);
+        assert_eq!(ba.next_allocated(0), Some(10));
+        assert_eq!(ba.next(10), Some(20));
+        ba.remove(4000..);
+        assert_eq!(ba.last(), Some(3999));
+        assert_eq!(ba.count_free_in(..=4095), 4096 - 10 - 96);
+        assert!(ba.all_free(20..4000));
+        assert!(!ba.any_free(4000..));
+        ba.insert(..=4095);
+        assert!(ba.all_free(..));
+        let mut ba = BitAlloc16::default();
+        ba.insert(8..);
+        assert_eq!(ba.free_count(), 8);
+        assert_eq!(ba.try_remove(..=8), Err(BitAllocError::AlreadyAllocated));
+        assert_eq!(ba.try_remove(8..=15), Ok(()));
+        assert!(!ba.any());
+    }
+
+    #[test]
+    fn insert_strict() {
+        let mut ba = BitAlloc4K::default();
+        assert_eq!(ba.insert_strict(100..200), Ok(()));
+        assert_eq!(ba.insert_strict(300..400), Ok(()));
+        assert_eq!(ba.insert_strict(150..250), Err(150..200));
+        assert_eq!(ba.insert_strict(0..1000), Err(100..200));
+        assert_eq!(ba.insert_strict(350..360), Err(350..360));
+        assert_eq!(ba.free_count(), 200);
+        assert_eq!(ba.insert_strict(200..300), Ok(()));
+        assert_eq!(ba.free_run_containing(100), Some(100..400));
+    }
+
+    #[test]
+    fn counted() {
+        let mut ba = BitAlloc4K::default();
+        assert_eq!(ba.insert_counted(0..100), 100);
+        // overlapping regions only count the new bits
+        assert_eq!(ba.insert_counted(50..150), 50);
+        assert_eq!(ba.insert_counted(0..150), 0);
+        assert_eq!(ba.remove_counted(140..200), 10);
+        assert_eq!(ba.remove_counted(..), 140);
+        assert_eq!(ba.remove_counted(..), 0);
+    }
+
+    #[test]
+    fn range_predicates() {
+        let mut ba = BitAlloc64K::default();
+        ba.insert(100..60000);
+        ba.remove(30000..30001);
+        assert!(ba.all_free(100..30000));
+        assert!(!ba.all_free(99..30000));
+        assert!(!ba.all_free(100..30001));
+        assert!(ba.all_free(30001..60000));
+        assert!(ba.all_free(5..5));
+        assert!(ba.any_free(0..101));
+        assert!(!ba.any_free(0..100));
+        assert!(ba.any_free(59999..65536));
+        assert!(!ba.any_free(60000..65536));
+        assert!(!ba.any_free(30000..30001));
+        assert!(!ba.any_free(200..200));
+    }
+
+    #[test]
+    fn count_free_in() {
+        let mut ba = BitAlloc64K::default();
+        ba.insert(100..60000);
+        ba.remove(30000..30010);
+        assert_eq!(ba.count_free_in(0..65536), ba.free_count());
+        assert_eq!(ba.count_free_in(0..100), 0);
+        assert_eq!(ba.count_free_in(0..101), 1);
+        assert_eq!(ba.count_free_in(29990..30020), 20);
+        assert_eq!(ba.count_free_in(4096..8192), 4096);
+        assert_eq!(ba.count_free_in(5..5), 0);
+        let mut seed = 0x1234_5678_u32;
+        for _ in 0..100 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let start = (seed >> 4) as usize % 65536;
+            let end = (start + (seed >> 16) as usize % 10000).min(65536);
+            let naive = (start..end).filter(|&i| ba.test(i)).count();
+            assert_eq!(ba.count_free_in(start..end), naive);
         }
-        for i in 4096 - 64 + 8..4096 - 64 + 10 {
+        let mut ba = BitAlloc16::default();
+        ba.insert(3..9);
+        assert_eq!(ba.count_free_in(0..16), 6);
+        assert_eq!(ba.count_free_in(5..12), 4);
+    }
+
+    #[test]
+    fn free_ranges() {
+        let mut ba = BitAlloc4K::default();
+        assert_eq!(ba.free_ranges().next(), None);
+        ba.insert(0..10);
+        ba.insert(255..257);
+        ba.insert(1000..4096);
+        ba.remove(2000..2001);
+        assert!(ba
+            .free_ranges()
+            .eq([0..10, 255..257, 1000..2000, 2001..4096].iter().cloned()));
+        assert!(ba
+            .allocated_ranges()
+            .eq([10..255, 257..1000, 2000..2001].iter().cloned()));
+        // both ends meet without yielding a run twice
+        let mut runs = ba.free_ranges();
+        assert_eq!(runs.next_back(), Some(2001..4096));
+        assert_eq!(runs.next(), Some(0..10));
+        assert_eq!(runs.next_back(), Some(1000..2000));
+        assert_eq!(runs.next(), Some(255..257));
+        assert_eq!(runs.next_back(), None);
+        assert_eq!(runs.next(), None);
+        let mut ba = BitAlloc16::default();
+        assert!(ba.allocated_ranges().eq(core::iter::once(0..16)));
+        ba.insert(0..16);
+        assert!(ba.free_ranges().eq(core::iter::once(0..16)));
+        assert_eq!(ba.allocated_ranges().next(), None);
+    }
+
+    #[test]
+    fn free_run_containing() {
+        let mut ba = BitAlloc4K::default();
+        for i in 10..20 {
             ba.dealloc(i);
         }
-        for i in 4096 - 48..4096 - 16 {
+        assert_eq!(ba.free_run_containing(15), Some(10..20));
+        assert_eq!(ba.free_run_containing(10), Some(10..20));
+        assert_eq!(ba.free_run_containing(19), Some(10..20));
+        assert_eq!(ba.free_run_containing(5), None);
+        assert_eq!(ba.free_run_containing(20), None);
+
+        // across children, and up to the end
+        for i in 250..600 {
             ba.dealloc(i);
         }
-        // for i in 4096 - 32..4096 {
-        //     ba.dealloc(i);
-        // }
+        ba.insert(4000..4096);
+        assert_eq!(ba.free_run_containing(256), Some(250..600));
+        assert_eq!(ba.free_run_containing(599), Some(250..600));
+        assert_eq!(ba.free_run_containing(4095), Some(4000..4096));
+        ba.insert(..1000);
+        assert_eq!(ba.free_run_containing(999), Some(0..1000));
+    }
+
+    #[test]
+    fn free_runs_reference() {
+        let mut ba = BitAlloc4K::default();
+        let mut seed = 0x0bad_cafe_u32;
+        for _ in 0..300 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let start = (seed >> 8) as usize % BitAlloc4K::CAP;
+            let end = (start + (seed >> 20) as usize % 300).min(BitAlloc4K::CAP);
+            if seed & 1 == 0 {
+                ba.insert(start..end);
+            } else {
+                ba.remove(start..end);
+            }
+            // compare with the run-by-run scan of the trait
+            let mut best = 0;
+            let mut key = 0;
+            while let Some(start) = ba.next(key) {
+                let end = ba.next_allocated(start).unwrap_or(BitAlloc4K::CAP);
+                best = best.max(end - start);
+                key = end;
+            }
+            assert_eq!(ba.max_contiguous_len(), best);
+            assert_eq!(ba.free_prefix(), ba.next_allocated(0).unwrap_or(4096));
+            let suffix = (0..4096).rev().take_while(|&i| ba.test(i)).count();
+            assert_eq!(ba.free_suffix(), suffix);
+        }
+    }
+
+    #[test]
+    fn free_run_histogram() {
+        let mut ba = BitAlloc64K::default();
+        assert_eq!(ba.free_run_histogram(), [0; usize::BITS as usize]);
+        ba.insert(..);
+        assert_eq!(ba.free_run_histogram()[16], 1);
+        // runs across children, and freed in bulk
+        ba.remove(100..101);
+        ba.remove(4000..5000);
+        ba.remove(5003..5004);
+        let hist = ba.free_run_histogram();
+        assert_eq!((hist[6], hist[1], hist[11], hist[15]), (1, 1, 1, 1));
+        assert_eq!(hist.iter().sum::<usize>(), 4);
+
+        let mut seed = 0x5eed_1e55_u32;
+        for _ in 0..200 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let start = (seed >> 8) as usize % BitAlloc64K::CAP;
+            let end = (start + (seed >> 16) as usize % 5000).min(BitAlloc64K::CAP);
+            if seed & 1 == 0 {
+                ba.insert(start..end);
+            } else {
+                ba.remove(start..end);
+            }
+            let mut hist = [0; usize::BITS as usize];
+            for run in ba.free_ranges() {
+                hist[log2(run.len() as u64)] += 1;
+            }
+            assert_eq!(ba.free_run_histogram(), hist);
+        }
+    }
+
+    #[test]
+    fn alloc_contiguous_offset() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(10..);
+        // index 13 is at an aligned position 13 + 3
+        assert_eq!(ba.alloc_contiguous_offset(8, 4, 3), Some(13));
+        assert_eq!(ba.alloc_contiguous_offset(8, 4, 3), Some(29));
+        assert_eq!(ba.alloc_contiguous_offset(8, 4, 16 * 1000 + 3), Some(45));
+        assert_eq!(ba.alloc_contiguous_offset(4, 4, usize::MAX), Some(65));
+        assert_eq!(ba.alloc_contiguous_offset(1, 0, 7), Some(10));
+        // an alignment larger than the allocator
+        assert_eq!(ba.find_contiguous_offset(100, 13, 8000), Some(192));
+        assert_eq!(ba.find_contiguous_offset(100, 13, 4100), None);
+        assert_eq!(ba.find_contiguous_offset(100, 13, 0), None);
+        assert_eq!(ba.find_contiguous_offset(1, 64, 0), None);
+        assert_eq!(ba.find_contiguous_offset(0, 0, 0), None);
+        assert_eq!(ba.find_contiguous_offset(8, 4, 0), ba.find_contiguous(8, 4));
+    }
+
+    #[test]
+    fn bounded_search() {
+        let mut ba = BitAlloc64K::default();
+        for i in (0..20000).step_by(4) {
+            ba.insert(i + 1..i + 4);
+        }
+        ba.insert(30001..30100);
+        // runs of 3 bits, none with an aligned block of 3, until the last
+        assert_eq!(ba.find_contiguous_bounded(3, 1, 1000), Err(BudgetExhausted));
+        assert_eq!(
+            ba.alloc_contiguous_bounded(3, 1, 1000),
+            Err(BudgetExhausted)
+        );
+        assert_eq!(ba.free_count(), 15099);
+        assert_eq!(ba.find_contiguous_bounded(3, 1, 100_000), Ok(Some(30002)));
+        assert_eq!(ba.alloc_contiguous_bounded(3, 1, 100_000), Ok(Some(30002)));
+        assert_eq!(ba.find_contiguous_bounded(500, 0, 100_000), Ok(None));
+        // one node per level
+        let mut ba = BitAlloc64K::default();
+        ba.insert(60000..60001);
+        assert_eq!(ba.next_bounded(0, 4), Ok(Some(60000)));
+        assert_eq!(ba.next_bounded(0, 3), Err(BudgetExhausted));
+        ba.insert(..);
+        assert_eq!(ba.next_bounded(12345, 1), Ok(Some(12345)));
+        assert_eq!(ba.next_bounded(70000, 1), Ok(None));
+    }
+
+    #[test]
+    fn alloc_both_ends() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(100..4000);
+        assert_eq!(ba.alloc_front(), Some(100));
+        assert_eq!(ba.alloc_back(), Some(3999));
+        assert_eq!(ba.alloc_contiguous(10, 0), Some(101));
+        assert_eq!(ba.alloc_contiguous_top(10, 0), Some(3989));
+        // the classes meet in the middle, wherever that falls
+        ba.remove(112..3989);
+        assert_eq!(ba.alloc_back(), Some(111));
+        assert_eq!(ba.alloc_front(), None);
+        assert_eq!(ba.alloc_back(), None);
+        ba.dealloc(3999);
+        assert_eq!(ba.alloc_front(), Some(3999));
+
+        let mut ba = BitAllocArray::<1000, 16>::default();
+        ba.insert(..);
+        assert_eq!(ba.alloc_front(), Some(0));
+        assert_eq!(ba.alloc_back(), Some(999));
+    }
+
+    #[test]
+    fn oversized_fails_fast() {
+        let mut ba = BitAlloc64K::default();
+        for i in (0..65536).step_by(64) {
+            ba.insert(i..i + 32);
+        }
+        // the longest run of the root rules it out, without a step
+        assert_eq!(ba.find_contiguous_bounded(33, 0, 0), Ok(None));
+        assert_eq!(ba.alloc_contiguous(33, 0), None);
+        assert_eq!(ba.find_contiguous_bounded(32, 0, 1), Err(BudgetExhausted));
+        // a run across two children, each too short for the block
+        ba.insert(4000..4200);
+        assert_eq!(ba.alloc_contiguous(150, 0), Some(3968));
+        assert_eq!(ba.alloc_contiguous(83, 0), None);
+        assert_eq!(ba.alloc_contiguous(82, 0), Some(4118));
+    }
+
+    #[test]
+    fn random_placement() {
+        let mut ba = BitAlloc64K::default();
+        let mut seed = 7_u64;
+        let mut rng = move || {
+            seed = seed
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            seed
+        };
+        assert_eq!(ba.alloc_random(&mut rng), None);
+        ba.insert(100..200);
+        ba.insert(4000..12000);
+        ba.remove(5000..5001);
+        for (n, key) in ba.iter_free().enumerate().step_by(97) {
+            assert_eq!(ba.nth_free(n), Some(key));
+        }
+        assert_eq!(ba.nth_free(ba.free_count()), None);
+        let before = ba.clone();
+        let mut low = 0;
+        for _ in 0..1000 {
+            let key = ba.alloc_random(&mut rng).unwrap();
+            assert!(before.test(key) && !ba.test(key));
+            low += (key < 8000) as usize;
+        }
+        // about half of the free bits are below 8000
+        assert!((400..600).contains(&low), "{}", low);
+        for _ in 0..20 {
+            let base = ba.alloc_contiguous_random(4, 2, &mut rng).unwrap();
+            assert_eq!(base % 4, 0);
+            assert!(before.all_free(base..base + 4) && !ba.any_free(base..base + 4));
+        }
+        assert_eq!(ba.alloc_contiguous_random(8000, 0, &mut rng), None);
+        assert_eq!(ba.free_count(), before.free_count() - 1000 - 80);
+    }
+
+    #[test]
+    fn alloc_order_buddy() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(..);
+        ba.remove(1000..1001);
+        // the smallest buddy block is 1001..1002, then 1002..1004
+        assert_eq!(ba.alloc_order(0), Some(1001));
+        assert_eq!(ba.alloc_order(1), Some(1002));
+        assert_eq!(ba.alloc_order(2), Some(1004));
+        assert_eq!(ba.alloc_order(12), None);
+        // single bits go into the 512 block already broken up
+        let mut bits = [0; 64];
+        for key in bits.iter_mut() {
+            *key = ba.alloc_order(0).unwrap();
+        }
+        let blocks: usize = (0..8)
+            .filter(|i| ba.all_free(i * 512..(i + 1) * 512))
+            .count();
+        assert_eq!(blocks, 7);
+        let base = ba.alloc_order(9).unwrap();
+        assert_eq!(base % 512, 0);
+        for &key in &bits {
+            ba.dealloc_order(key, 0);
+        }
+        ba.dealloc_order(base, 9);
+        ba.dealloc_order(1004, 2);
+        ba.dealloc_order(1002, 1);
+        ba.dealloc_order(1001, 0);
+        ba.dealloc(1000);
+        assert_eq!(ba.alloc_order(12), Some(0));
+    }
+
+    #[test]
+    fn split_off_merge() {
+        let mut ba = BitAlloc64K::default();
+        let mut seed = 0x5a1f_0ff5_u32;
+        for _ in 0..100 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let start = (seed >> 8) as usize % BitAlloc64K::CAP;
+            let end = (start + (seed >> 16) as usize % 5000).min(BitAlloc64K::CAP);
+            if seed & 1 == 0 {
+                ba.insert(start..end);
+            } else {
+                ba.remove(start..end);
+            }
+        }
+        let whole = ba.clone();
+        let at = 30001;
+        let mut hi = ba.split_off(at);
+        assert_eq!(ba.count_free_in(at..), 0);
+        assert_eq!(hi.count_free_in(..at), 0);
+        assert_eq!(ba.free_count(), whole.count_free_in(..at));
+        assert!(hi.free_ranges().eq(whole
+            .free_ranges()
+            .map(|run| run.start.max(at)..run.end)
+            .filter(|run| run.start < run.end)));
+        // the halves are independent
+        hi.alloc_low();
+        ba.alloc();
+        ba.merge(&hi);
+        assert_eq!(ba.free_count(), whole.free_count() - 2);
+    }
+
+    #[test]
+    fn contiguous_reference() {
+        let mut ba = BitAlloc64K::default();
+        let mut seed = 0x1234_5678_u32;
+        let mut rand = move || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 8) as usize
+        };
+        for _ in 0..500 {
+            let start = rand() % BitAlloc64K::CAP;
+            let end = (start + rand() % 2000).min(BitAlloc64K::CAP);
+            if rand() % 2 == 0 {
+                ba.insert(start..end);
+            } else {
+                ba.remove(start..end);
+            }
+            let size = rand() % 300 + 1;
+            let align_log2 = rand() % 10;
+            assert_eq!(
+                ba.find_contiguous(size, align_log2),
+                find_contiguous_naive(&ba, BitAlloc64K::CAP, size, align_log2),
+                "size {} align_log2 {}",
+                size,
+                align_log2
+            );
+        }
     }
 
-    // #[test]
-    // fn bitallocContPerformance() {
-    //     let mut ba = Box::new(BitAlloc256M::default());
-    //     assert_eq!(BitAlloc256M::CAP, 1 << 28);
-    //     ba.insert(0..BitAlloc256M::CAP);
-    //     assert_eq!(ba.alloc_contiguous(1 << 20, 20), Some(0));
-    //     assert_eq!(ba.alloc_contiguous(1 << 19, 19), Some(1 << 20));
-    //     assert_eq!(ba.alloc_contiguous(1 << 21, 21), Some(1 << 21));
-    //     assert_eq!(ba.alloc_contiguous(1 << 19, 19), Some(3 << 19));
-    // }
+    #[test]
+    fn contiguous_large() {
+        let mut ba = BitAlloc1M::default();
+        ba.insert(0..BitAlloc1M::CAP);
+        assert_eq!(ba.alloc_contiguous(1 << 18, 18), Some(0));
+        assert_eq!(ba.alloc_contiguous(1 << 17, 17), Some(1 << 18));
+        assert_eq!(ba.alloc_contiguous(1 << 19, 19), Some(1 << 19));
+        assert_eq!(ba.alloc_contiguous(1 << 17, 17), Some(3 << 17));
+        assert_eq!(ba.alloc_contiguous(1, 0), None);
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn capacity_4g() {
+        assert_eq!(BitAlloc4G::CAP, 1 << 32);
+        assert_eq!(BitAlloc64G::CAP, 1 << 36);
+    }
+
+    #[test]
+    fn huge_alignment() {
+        // alignments past the word width are too large, not a shift overflow
+        let mut ba = BitAlloc4K::default();
+        ba.insert(..);
+        for align_log2 in [WORD_BITS, WORD_BITS + 1, usize::MAX] {
+            assert_eq!(ba.find_contiguous(1, align_log2), None);
+            assert_eq!(
+                NextFit::new(ba.clone()).alloc_contiguous(1, align_log2),
+                None
+            );
+        }
+        // an alignment past the capacity fits nowhere, as for
+        // `alloc_contiguous`, though bit 0 is free
+        for align_log2 in [13, WORD_BITS, usize::MAX] {
+            assert_eq!(ba.alloc_aligned(align_log2), None);
+        }
+        assert_eq!(ba.alloc_aligned(12), Some(0));
+        let mut flat = BitAllocArray::<1000, 16>::default();
+        flat.insert(..);
+        assert_eq!(flat.alloc_aligned(10), None);
+        assert_eq!(flat.alloc_aligned(usize::MAX), None);
+        assert_eq!(flat.alloc_aligned(9), Some(0));
+        let mut leaf = BitAlloc64::default();
+        leaf.insert(..);
+        assert_eq!(leaf.alloc_aligned(7), None);
+        assert_eq!(leaf.alloc_aligned(WORD_BITS + 1), None);
+        assert_eq!(leaf.alloc_aligned(6), Some(0));
+        let mut ba0 = BitAlloc16::default();
+        ba0.insert(..);
+        assert_eq!(ba0.alloc_aligned(5), None);
+        assert_eq!(ba0.alloc_aligned(4), Some(0));
+        let mut capped = BitAllocCapped::new(BitAlloc4K::default(), 1000);
+        capped.insert(..);
+        assert_eq!(capped.alloc_aligned(10), None);
+        assert_eq!(capped.alloc_aligned(9), Some(0));
+    }
+
+    #[test]
+    fn next_allocated_large() {
+        let mut ba = BitAlloc1M::default();
+        assert_eq!(ba.next_allocated(12345), Some(12345));
+        ba.insert(0..BitAlloc1M::CAP);
+        assert_eq!(ba.next_allocated(0), None);
+        ba.remove(70000..70001);
+        ba.remove(900000..900010);
+        assert_eq!(ba.next_allocated(0), Some(70000));
+        assert_eq!(ba.next_allocated(70000), Some(70000));
+        assert_eq!(ba.next_allocated(70001), Some(900000));
+        assert_eq!(ba.next_allocated(900009), Some(900009));
+        assert_eq!(ba.next_allocated(900010), None);
+        assert_eq!(ba.next_allocated(BitAlloc1M::CAP), None);
+        assert_eq!(ba.iter_allocated().count(), 11);
+    }
+
+    #[test]
+    fn cascade_fan_out() {
+        // compare with a flat bitmap, whose bits past A::CAP stay allocated
+        fn check<A: BitAlloc>() {
+            let mut ba = A::default();
+            let mut flat = BitAllocArray::<1024, 16>::default();
+            let mut seed = 0xfa11_u32;
+            for _ in 0..200 {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let start = (seed >> 8) as usize % A::CAP;
+                let end = (start + (seed >> 20) as usize % 70).min(A::CAP);
+                if seed & 1 == 0 {
+                    ba.insert(start..end);
+                    flat.insert(start..end);
+                } else {
+                    ba.remove(start..end);
+                    flat.remove(start..end);
+                }
+                let next_allocated = flat.next_allocated(start).filter(|&i| i < A::CAP);
+                assert_eq!(ba.free_count(), flat.free_count());
+                assert_eq!(ba.next(start), flat.next(start));
+                assert_eq!(ba.prev(start), flat.prev(start));
+                assert_eq!(ba.next_allocated(start), next_allocated);
+                assert_eq!(ba.prev_allocated(start), flat.prev_allocated(start));
+                assert_eq!(ba.max_contiguous_len(), flat.max_contiguous_len());
+                assert_eq!(ba.find_contiguous(5, 2), flat.find_contiguous(5, 2));
+                assert_eq!(ba.raw_word(0), flat.raw_word(0));
+            }
+            assert_eq!(ba.peek_with::<HighestFirst>(), flat.peek_alloc());
+            assert_eq!(ba.alloc_low(), flat.alloc_low());
+            assert!(ba.iter_free().eq(flat.iter_free()));
+        }
+        assert_eq!(BitAllocCascade::<BitAlloc16, 64>::CAP, 1024);
+        check::<BitAllocCascade<BitAlloc16, 64>>();
+        check::<BitAllocCascade<BitAlloc16, 3>>();
+    }
+
+    #[test]
+    fn cascade64() {
+        assert_eq!(BitAlloc4K64::CAP, 4096);
+        assert_eq!(BitAlloc16M64::CAP, BitAlloc16M::CAP);
+        let mut ba = BitAlloc256K64::default();
+        ba.insert(100..200_000);
+        assert_eq!(ba.alloc(), Some(199_999));
+        assert_eq!(ba.alloc_low(), Some(100));
+        assert_eq!(ba.alloc_contiguous(5000, 12), Some(4096));
+        assert_eq!(ba.next_allocated(101), Some(4096));
+        assert_eq!(ba.max_contiguous(), Some(9096..199_999));
+        ba.dealloc_contiguous(4096, 5000);
+        assert_eq!(ba.free_count(), 199_898);
+        assert_eq!(ba.count_free_in(..4096), 3995);
+        assert_eq!(
+            format(format_args!("{:?}", BitAlloc4K64::default())).as_str(),
+            "BitAllocCascade64 { cap: 4096, free: 0, free_runs: [] }"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "bitvec")]
+    fn bitslice_roundtrip() {
+        use bitvec::prelude::*;
+        let mut usable = bitarr![u8, Msb0; 0; 300];
+        usable[3..200].fill(true);
+        usable.set(100, false);
+        let ba = BitAlloc4K::from_usable(&usable[..300]);
+        assert_eq!(ba.free_count(), 196);
+        assert_eq!(
+            (ba.next(0), ba.next(100), ba.next(200)),
+            (Some(3), Some(101), None)
+        );
+        let mut out = bitarr![u16, Lsb0; 1; 300];
+        ba.copy_to_bitslice(&mut out[..300]);
+        assert_eq!(out[..300], usable[..300]);
+        #[cfg(feature = "alloc")]
+        {
+            let bits = ba.to_bitvec();
+            assert_eq!(bits.len(), 4096);
+            assert_eq!(bits.count_ones(), 196);
+            assert_eq!(bits.as_raw_slice()[0] as usize, ba.raw_word(0));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn bulk_range_ops() {
+        // whole children are only marked, so this does not touch 32 MiB
+        let mut ba = BitAlloc256M::new_boxed();
+        assert_eq!(BitAlloc256M::CAP, 1 << 28);
+        ba.insert(0..BitAlloc256M::CAP);
+        assert_eq!(ba.free_count(), 1 << 28);
+        assert_eq!(ba.alloc_contiguous(1 << 20, 20), Some(0));
+        assert_eq!(ba.alloc_contiguous(1 << 19, 19), Some(1 << 20));
+        assert_eq!(ba.alloc_contiguous(1 << 21, 21), Some(1 << 21));
+        assert_eq!(ba.alloc_contiguous(1 << 19, 19), Some(3 << 19));
+        assert_eq!(ba.next_allocated(1 << 22), None);
+        assert_eq!(ba.alloc(), Some((1 << 28) - 1));
+        ba.remove(1 << 24..);
+        assert_eq!(ba.free_count(), (1 << 24) - (1 << 22));
+        assert_eq!(ba.prev(usize::MAX), Some((1 << 24) - 1));
+        assert_eq!(ba.raw_word(1 << 20), 0);
+    }
+
+    #[test]
+    fn bulk_range_reference() {
+        let mut ba = BitAlloc4K::default();
+        let mut flat = BitAllocArray::<4096, 64>::default();
+        let mut seed = 0x5ea1_u32;
+        for _ in 0..400 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            // ranges often cover whole children of both levels
+            let align = if seed & 16 != 0 { 256 } else { 16 };
+            let start = (seed >> 8) as usize % 4096 / align * align;
+            let end = (start + (seed >> 20) as usize % 1024).min(4096);
+            match seed & 7 {
+                0 | 1 => {
+                    ba.remove(start..end);
+                    flat.remove(start..end);
+                }
+                2 => assert_eq!(ba.alloc_low(), flat.alloc_low()),
+                3 => assert_eq!(ba.alloc(), flat.alloc()),
+                4 => assert_eq!(ba.alloc_aligned(5), flat.alloc_aligned(5)),
+                5 => {
+                    if !ba.test(start) && start < 4096 {
+                        ba.dealloc(start);
+                        flat.dealloc(start);
+                    }
+                }
+                _ => {
+                    ba.insert(start..end);
+                    flat.insert(start..end);
+                }
+            }
+            assert_eq!(ba.free_count(), flat.free_count());
+            assert_eq!(ba.count_free_in(start..end), flat.count_free_in(start..end));
+            assert_eq!(ba.next(start), flat.next(start));
+            assert_eq!(ba.prev(start), flat.prev(start));
+            assert_eq!(ba.next_allocated(start), flat.next_allocated(start));
+            assert_eq!(ba.prev_allocated(start), flat.prev_allocated(start));
+            assert_eq!(ba.peek_alloc(), flat.peek_alloc());
+            assert_eq!(
+                ba.peek_with::<LowestFirst>(),
+                flat.peek_with::<LowestFirst>()
+            );
+            assert_eq!(ba.max_contiguous(), flat.max_contiguous());
+            assert_eq!(ba.find_contiguous(300, 4), flat.find_contiguous(300, 4));
+            assert_eq!(
+                ba.raw_word(start / WORD_BITS % 64),
+                flat.raw_word(start / WORD_BITS % 64)
+            );
+            assert!(ba.iter_free().rev().eq(flat.iter_free().rev()));
+        }
+        // the same bits, with and without stale children
+        let mut words = [0; 4096 / WORD_BITS];
+        ba.as_raw_words(&mut |w, word| words[w] = word);
+        let copy = BitAlloc4K::from_raw_words(&words);
+        assert!(copy == ba);
+        assert_eq!(hash_of(&copy), hash_of(&ba));
+    }
+    #[test]
+    fn diff_snapshot() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(..);
+        let before = ba.snapshot();
+        assert_eq!(before.diff(&ba).next(), None);
+        ba.remove(256..1024);
+        ba.alloc();
+        ba.insert(300..301);
+        assert!(before.diff(&ba).eq([
+            Changed::Allocated(256..300),
+            Changed::Allocated(301..1024),
+            Changed::Allocated(4095..4096),
+        ]));
+        assert!(ba.diff(&before).eq([
+            Changed::Freed(256..300),
+            Changed::Freed(301..1024),
+            Changed::Freed(4095..4096),
+        ]));
+    }
+
+    #[test]
+    fn diff_reference() {
+        let mut old = BitAlloc4K::default();
+        let mut seed = 0xd1ff_u32;
+        for round in 0..200 {
+            let mut new = old.snapshot();
+            for _ in 0..round % 8 {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                // bulk changes leave stale children on one side only
+                let align = if seed & 16 != 0 { 256 } else { 16 };
+                let start = (seed >> 8) as usize % 4096 / align * align;
+                let end = (start + (seed >> 20) as usize % 1024).min(4096);
+                if seed & 1 != 0 {
+                    new.insert(start..end);
+                } else {
+                    new.remove(start..end);
+                }
+            }
+            // every differing bit is in exactly one maximal run
+            let mut key = 0;
+            for changed in old.diff(&new) {
+                let (range, freed) = match changed {
+                    Changed::Allocated(range) => (range, false),
+                    Changed::Freed(range) => (range, true),
+                };
+                assert!(key <= range.start && range.start < range.end);
+                assert!((key..range.start).all(|i| old.test(i) == new.test(i)));
+                assert!(range
+                    .clone()
+                    .all(|i| new.test(i) == freed && old.test(i) != freed));
+                assert!(
+                    range.end == 4096
+                        || new.test(range.end) != freed
+                        || old.test(range.end) == freed
+                );
+                key = range.end;
+            }
+            assert!((key..4096).all(|i| old.test(i) == new.test(i)));
+            old = new;
+        }
+    }
+
+    #[test]
+    fn set_ops_reference() {
+        let mut seed = 0x5e7_u32;
+        let mut random = |ba: &mut BitAlloc4K, flat: &mut BitAllocArray<4096, 64>| {
+            for _ in 0..8 {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                // whole children, so that some are stale
+                let align = if seed & 16 != 0 { 256 } else { 16 };
+                let start = (seed >> 8) as usize % 4096 / align * align;
+                let end = (start + (seed >> 20) as usize % 1024).min(4096);
+                if seed & 1 != 0 {
+                    ba.insert(start..end);
+                    flat.insert(start..end);
+                } else {
+                    ba.remove(start..end);
+                    flat.remove(start..end);
+                }
+            }
+        };
+        for round in 0..60 {
+            let (mut a, mut flat_a) = Default::default();
+            let (mut b, mut flat_b) = Default::default();
+            random(&mut a, &mut flat_a);
+            random(&mut b, &mut flat_b);
+            assert_eq!(a.is_subset_of(&b), flat_a.is_subset_of(&flat_b));
+            assert_eq!(a.is_disjoint_with(&b), flat_a.is_disjoint_with(&flat_b));
+            match round % 3 {
+                0 => {
+                    a.union_with(&b);
+                    flat_a.union_with(&flat_b);
+                    assert!(b.is_subset_of(&a));
+                }
+                1 => {
+                    a.intersect_with(&b);
+                    flat_a.intersect_with(&flat_b);
+                    assert!(a.is_subset_of(&b));
+                }
+                _ => {
+                    a.subtract(&b);
+                    flat_a.subtract(&flat_b);
+                    assert!(a.is_disjoint_with(&b));
+                }
+            }
+            assert!(a.free_ranges().eq(flat_a.free_ranges()));
+            assert_eq!(a.free_count(), flat_a.free_count());
+            assert_eq!(a.max_contiguous(), flat_a.max_contiguous());
+            assert_eq!(a.find_contiguous(300, 4), flat_a.find_contiguous(300, 4));
+        }
+    }
+
+    #[test]
+    fn subset_disjoint() {
+        let mut a = BitAlloc4K::default();
+        let mut b = BitAlloc4K::default();
+        assert!(a.is_subset_of(&b) && a.is_disjoint_with(&b));
+        a.insert(256..512);
+        b.insert(..);
+        assert!(a.is_subset_of(&b) && !b.is_subset_of(&a));
+        assert!(!a.is_disjoint_with(&b));
+        b.remove(300..301);
+        assert!(!a.is_subset_of(&b));
+        b.remove(..1024);
+        assert!(a.is_disjoint_with(&b) && b.is_disjoint_with(&a));
+    }
 }
@@ -0,0 +1,149 @@
+use crate::BitAlloc;
+use core::fmt;
+use core::ops::RangeBounds;
+use spin::{Mutex, MutexGuard};
+
+/// An allocator behind a spin lock, so that it can be used through shared
+/// references, e.g.
+/// `static FRAME_ALLOCATOR: LockedBitAlloc<BitAlloc16M> = LockedBitAlloc::new(BitAlloc16M::DEFAULT);`.
+/// Enabled by the `spin` feature.
+///
+/// Each method takes the lock for a single call. Use [`lock`](Self::lock)
+/// to hold it over several calls. The lock does not disable interrupts, so
+/// a kernel that also allocates from interrupt handlers must mask them
+/// around calls itself, or risk a deadlock.
+#[derive(Default)]
+pub struct LockedBitAlloc<T: BitAlloc> {
+    inner: Mutex<T>,
+}
+
+impl<T: BitAlloc> LockedBitAlloc<T> {
+    /// Wrap an allocator.
+    pub const fn new(inner: T) -> Self {
+        LockedBitAlloc {
+            inner: Mutex::new(inner),
+        }
+    }
+
+    /// Lock the allocator, spinning until it is available.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.inner.lock()
+    }
+
+    /// Lock the allocator if it is available right now.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.inner.try_lock()
+    }
+
+    /// The underlying allocator, without locking as the borrow is exclusive.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    /// Unwrap the underlying allocator.
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+
+    /// Allocate a free bit.
+    pub fn alloc(&self) -> Option<usize> {
+        self.lock().alloc()
+    }
+
+    /// Allocate a free block with a given size, and return the first bit position.
+    pub fn alloc_contiguous(&self, size: usize, align_log2: usize) -> Option<usize> {
+        self.lock().alloc_contiguous(size, align_log2)
+    }
+
+    /// Free an allocated bit.
+    pub fn dealloc(&self, key: usize) {
+        self.lock().dealloc(key)
+    }
+
+    /// Free a block allocated by `alloc_contiguous`.
+    pub fn dealloc_contiguous(&self, base: usize, size: usize) {
+        self.lock().dealloc_contiguous(base, size)
+    }
+
+    /// Mark bits in the range as unallocated (available).
+    pub fn insert(&self, range: impl RangeBounds<usize>) {
+        self.lock().insert(range)
+    }
+
+    /// Reverse of insert.
+    pub fn remove(&self, range: impl RangeBounds<usize>) {
+        self.lock().remove(range)
+    }
+
+    /// Whether a specific bit is free.
+    pub fn test(&self, key: usize) -> bool {
+        self.lock().test(key)
+    }
+
+    /// The number of free bits.
+    pub fn free_count(&self) -> usize {
+        self.lock().free_count()
+    }
+}
+
+impl<T: BitAlloc + fmt::Debug> fmt::Debug for LockedBitAlloc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // never wait for the lock here, it may be held by the caller
+        match self.try_lock() {
+            Some(inner) => f.debug_tuple("LockedBitAlloc").field(&*inner).finish(),
+            None => f.write_str("LockedBitAlloc(<locked>)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::format;
+    use crate::{BitAlloc16, BitAlloc64K};
+
+    #[test]
+    fn locked_static() {
+        extern crate std;
+        use std::vec::Vec;
+
+        static BA: LockedBitAlloc<BitAlloc64K> = LockedBitAlloc::new(BitAlloc64K::DEFAULT);
+        BA.insert(..);
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    let block = BA.alloc_contiguous(100, 4).unwrap();
+                    let keys: Vec<_> = (0..1000).map(|_| BA.alloc().unwrap()).collect();
+                    (block, keys)
+                })
+            })
+            .collect();
+        let mut count = 0;
+        for thread in threads {
+            let (block, keys) = thread.join().unwrap();
+            BA.dealloc_contiguous(block, 100);
+            for key in keys {
+                BA.dealloc(key);
+                count += 1;
+            }
+        }
+        assert_eq!(count, 4000);
+        assert_eq!(BA.free_count(), 65536);
+    }
+
+    #[test]
+    fn locked_debug() {
+        let ba = LockedBitAlloc::new(BitAlloc16::DEFAULT);
+        ba.insert(3..5);
+        assert!(ba.test(4));
+        let guard = ba.lock();
+        assert_eq!(
+            format(format_args!("{:?}", ba)).as_str(),
+            "LockedBitAlloc(<locked>)"
+        );
+        drop(guard);
+        assert!(format(format_args!("{:?}", ba))
+            .as_str()
+            .starts_with("LockedBitAlloc(BitAlloc16 {"));
+    }
+}
@@ -0,0 +1,267 @@
+use crate::{to_range, AllocPolicy, BitAlloc, BudgetExhausted, Inconsistency};
+use core::ops::{Range, RangeBounds};
+use log::trace;
+
+/// A wrapper emitting a trace-level `log` record for every allocation,
+/// free and range operation, with the indices involved. Enabled by the
+/// `log` feature.
+///
+/// Only the outermost calls are logged, not those a cascade makes into its
+/// children. Queries go straight to the inner allocator without logging.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LoggedBitAlloc<A: BitAlloc> {
+    inner: A,
+}
+
+impl<A: BitAlloc> LoggedBitAlloc<A> {
+    /// Wrap an allocator.
+    pub fn new(inner: A) -> Self {
+        LoggedBitAlloc { inner }
+    }
+
+    /// The underlying allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Unwrap the underlying allocator.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+}
+
+impl<A: BitAlloc> Default for LoggedBitAlloc<A> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<A: BitAlloc> BitAlloc for LoggedBitAlloc<A> {
+    const CAP: usize = A::CAP;
+
+    const DEFAULT: Self = LoggedBitAlloc { inner: A::DEFAULT };
+
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    fn alloc(&mut self) -> Option<usize> {
+        let res = self.inner.alloc();
+        trace!("alloc() = {:?}", res);
+        res
+    }
+    fn alloc_with<P: AllocPolicy>(&mut self) -> Option<usize> {
+        let res = self.inner.alloc_with::<P>();
+        trace!("alloc_with() = {:?}", res);
+        res
+    }
+    fn alloc_many(&mut self, out: &mut [usize]) -> usize {
+        let n = self.inner.alloc_many(out);
+        trace!("alloc_many({}) = {:?}", out.len(), &out[..n]);
+        n
+    }
+    fn alloc_many_with<P: AllocPolicy>(&mut self, out: &mut [usize]) -> usize {
+        let n = self.inner.alloc_many_with::<P>(out);
+        trace!("alloc_many_with({}) = {:?}", out.len(), &out[..n]);
+        n
+    }
+    fn peek_alloc(&self) -> Option<usize> {
+        self.inner.peek_alloc()
+    }
+    fn peek_with<P: AllocPolicy>(&self) -> Option<usize> {
+        self.inner.peek_with::<P>()
+    }
+    fn peek_full<P: AllocPolicy>() -> usize {
+        A::peek_full::<P>()
+    }
+    fn subtree_cap(depth: usize) -> usize {
+        A::subtree_cap(depth)
+    }
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        let res = self.inner.alloc_contiguous(size, align_log2);
+        trace!("alloc_contiguous({}, {}) = {:?}", size, align_log2, res);
+        res
+    }
+    fn find_contiguous(&self, size: usize, align_log2: usize) -> Option<usize> {
+        self.inner.find_contiguous(size, align_log2)
+    }
+    fn alloc_aligned(&mut self, align_log2: usize) -> Option<usize> {
+        let res = self.inner.alloc_aligned(align_log2);
+        trace!("alloc_aligned({}) = {:?}", align_log2, res);
+        res
+    }
+    fn next(&self, key: usize) -> Option<usize> {
+        self.inner.next(key)
+    }
+    fn prev(&self, key: usize) -> Option<usize> {
+        self.inner.prev(key)
+    }
+    fn dealloc(&mut self, key: usize) {
+        trace!("dealloc({})", key);
+        self.inner.dealloc(key)
+    }
+    fn dealloc_many(&mut self, keys: impl IntoIterator<Item = usize>) {
+        let keys = keys.into_iter().inspect(|key| trace!("dealloc({})", key));
+        self.inner.dealloc_many(keys)
+    }
+    fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        trace!("dealloc_contiguous({}, {})", base, size);
+        self.inner.dealloc_contiguous(base, size)
+    }
+    fn insert(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, Self::CAP);
+        trace!("insert({:?})", range);
+        self.inner.insert(range)
+    }
+    fn remove(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, Self::CAP);
+        trace!("remove({:?})", range);
+        self.inner.remove(range)
+    }
+    fn any(&self) -> bool {
+        self.inner.any()
+    }
+    fn test(&self, key: usize) -> bool {
+        self.inner.test(key)
+    }
+    fn free_count(&self) -> usize {
+        self.inner.free_count()
+    }
+    fn count_free_in(&self, range: impl RangeBounds<usize>) -> usize {
+        self.inner.count_free_in(range)
+    }
+    fn free_prefix(&self) -> usize {
+        self.inner.free_prefix()
+    }
+    fn free_suffix(&self) -> usize {
+        self.inner.free_suffix()
+    }
+    fn max_contiguous_len(&self) -> usize {
+        self.inner.max_contiguous_len()
+    }
+    fn max_contiguous(&self) -> Option<Range<usize>> {
+        self.inner.max_contiguous()
+    }
+    fn search_contiguous(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+    ) -> Option<usize> {
+        self.inner
+            .search_contiguous(offset, size, align_log2, run_start)
+    }
+    fn search_contiguous_bounded(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+        budget: &mut usize,
+    ) -> Result<Option<usize>, BudgetExhausted> {
+        self.inner
+            .search_contiguous_bounded(offset, size, align_log2, run_start, budget)
+    }
+    fn visit_free_runs(&self, run: &mut usize, f: &mut impl FnMut(usize)) {
+        self.inner.visit_free_runs(run, f)
+    }
+    fn next_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.next_allocated(key)
+    }
+    fn prev_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.prev_allocated(key)
+    }
+    fn raw_word(&self, w: usize) -> usize {
+        self.inner.raw_word(w)
+    }
+    fn next_difference(&self, other: &Self, key: usize) -> Option<usize> {
+        self.inner.next_difference(&other.inner, key)
+    }
+    fn is_subset_of(&self, other: &Self) -> bool {
+        self.inner.is_subset_of(&other.inner)
+    }
+    fn is_disjoint_with(&self, other: &Self) -> bool {
+        self.inner.is_disjoint_with(&other.inner)
+    }
+    fn union_with(&mut self, other: &Self) {
+        self.inner.union_with(&other.inner);
+        trace!("union_with(), {} free", self.inner.free_count());
+    }
+    fn intersect_with(&mut self, other: &Self) {
+        self.inner.intersect_with(&other.inner);
+        trace!("intersect_with(), {} free", self.inner.free_count());
+    }
+    fn subtract(&mut self, other: &Self) {
+        self.inner.subtract(&other.inner);
+        trace!("subtract(), {} free", self.inner.free_count());
+    }
+    fn write_raw_word(&mut self, w: usize, word: usize) {
+        self.inner.write_raw_word(w, word)
+    }
+    fn rebuild(&mut self) {
+        self.inner.rebuild()
+    }
+    fn validate(&self) -> Result<(), Inconsistency> {
+        self.inner.validate()
+    }
+    fn iter_free(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.inner.iter_free()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::Buf;
+    use crate::BitAlloc4K;
+    use core::fmt::Write;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+
+    extern crate std;
+    use std::sync::Mutex;
+
+    static RECORDS: AtomicUsize = AtomicUsize::new(0);
+    static LAST: Mutex<Option<Buf>> = Mutex::new(None);
+
+    struct Logger;
+
+    impl Log for Logger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= Level::Trace
+        }
+        fn log(&self, record: &Record) {
+            let mut buf = Buf::new();
+            write!(buf, "{}", record.args()).unwrap();
+            *LAST.lock().unwrap() = Some(buf);
+            RECORDS.fetch_add(1, Ordering::Relaxed);
+        }
+        fn flush(&self) {}
+    }
+
+    fn assert_last(expected: &str) {
+        assert_eq!(LAST.lock().unwrap().as_ref().unwrap().as_str(), expected);
+    }
+
+    #[test]
+    fn logged() {
+        log::set_logger(&Logger).unwrap();
+        log::set_max_level(LevelFilter::Trace);
+        let mut ba = LoggedBitAlloc::<BitAlloc4K>::default();
+        ba.insert(0..100);
+        assert_last("insert(0..100)");
+        let i = ba.alloc().unwrap();
+        assert_last("alloc() = Some(99)");
+        ba.dealloc(i);
+        assert_last("dealloc(99)");
+        ba.alloc_contiguous(8, 3);
+        assert_last("alloc_contiguous(8, 3) = Some(0)");
+        ba.remove(50..);
+        assert_last("remove(50..4096)");
+        // queries and the children of the cascade log nothing
+        let before = RECORDS.load(Ordering::Relaxed);
+        assert!(ba.test(10));
+        assert_eq!(ba.free_count(), 42);
+        assert_eq!(RECORDS.load(Ordering::Relaxed), before);
+    }
+}
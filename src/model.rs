@@ -0,0 +1,292 @@
+//! A reference model of [`BitAlloc`] and a replayer of operations against
+//! it, for differential tests, enabled by the `test-util` feature.
+//!
+//! [`ModelBitAlloc`] is a plain vector of `bool`s, too slow for anything
+//! but tests and simple enough to be obviously right. [`Replayer`] applies
+//! the same [`Op`]s to it and to a real allocator, and stops at the first
+//! one where they disagree, so a random sequence from [`Op::random`] puts
+//! the whole API through its paces.
+
+use crate::{align_up, to_range, BitAlloc, WORD_BITS};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::{Range, RangeBounds};
+
+/// An allocator of `bool`s, `true` meaning free, with the semantics of the
+/// cascades of [`BitAlloc`]: `alloc` takes the highest free bit, and
+/// `alloc_contiguous` the lowest block that fits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelBitAlloc {
+    bits: Vec<bool>,
+}
+
+impl ModelBitAlloc {
+    /// Create a model of `cap` bits, all allocated.
+    pub fn new(cap: usize) -> Self {
+        ModelBitAlloc {
+            bits: vec![false; cap],
+        }
+    }
+
+    /// The number of bits.
+    pub fn capacity(&self) -> usize {
+        self.bits.len()
+    }
+
+    /// Allocate the highest free bit.
+    pub fn alloc(&mut self) -> Option<usize> {
+        let key = self.bits.iter().rposition(|&free| free)?;
+        self.bits[key] = false;
+        Some(key)
+    }
+
+    /// Allocate the lowest block of `size` free bits whose first bit is
+    /// aligned to `1 << align_log2`.
+    pub fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        if size == 0 || align_log2 >= usize::BITS as usize {
+            return None;
+        }
+        let mut base: usize = 0;
+        while base.checked_add(size)? <= self.capacity() {
+            match self.bits[base..base + size].iter().rposition(|&free| !free) {
+                // restart past the last allocated bit in the way
+                Some(i) => base = align_up(base + i + 1, align_log2),
+                None => {
+                    self.remove(base..base + size);
+                    return Some(base);
+                }
+            }
+        }
+        None
+    }
+
+    /// Free an allocated bit.
+    pub fn dealloc(&mut self, key: usize) {
+        assert!(!self.bits[key], "freeing free bit {}", key);
+        self.bits[key] = true;
+    }
+
+    /// Mark bits in the range as free.
+    pub fn insert(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, self.capacity());
+        self.bits[range].fill(true);
+    }
+
+    /// Mark bits in the range as allocated.
+    pub fn remove(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, self.capacity());
+        self.bits[range].fill(false);
+    }
+
+    /// Whether a specific bit is free.
+    pub fn test(&self, key: usize) -> bool {
+        self.bits[key]
+    }
+
+    /// The number of free bits.
+    pub fn free_count(&self) -> usize {
+        self.bits.iter().filter(|&&free| free).count()
+    }
+
+    /// Raw word `w`, in the layout of `BitAlloc::raw_word`.
+    pub fn raw_word(&self, w: usize) -> usize {
+        let start = (w * WORD_BITS).min(self.capacity());
+        let end = (start + WORD_BITS).min(self.capacity());
+        (start..end)
+            .filter(|&i| self.bits[i])
+            .fold(0, |word, i| word | 1 << (i % WORD_BITS))
+    }
+}
+
+/// An operation of a differential test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    /// `alloc()`.
+    Alloc,
+    /// `alloc_contiguous(size, align_log2)`.
+    AllocContiguous { size: usize, align_log2: usize },
+    /// `dealloc(key)`, skipped if the bit is free, so that any sequence of
+    /// operations is valid.
+    Dealloc(usize),
+    /// `insert(range)`.
+    Insert(Range<usize>),
+    /// `remove(range)`.
+    Remove(Range<usize>),
+}
+
+impl Op {
+    /// A random operation on an allocator of `cap` bits, advancing `seed`.
+    ///
+    /// Ranges are short, and inserts a little less likely than allocations,
+    /// so that sequences keep a fragmented mix of free and allocated bits.
+    pub fn random(seed: &mut u32, cap: usize) -> Op {
+        let mut next = || {
+            *seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (*seed >> 8) as usize
+        };
+        let start = next() % cap;
+        let end = (start + next() % 200).min(cap);
+        match next() % 8 {
+            0 | 1 => Op::Alloc,
+            2 | 3 => Op::AllocContiguous {
+                size: 1 + next() % 64,
+                align_log2: next() % 8,
+            },
+            4 | 5 => Op::Dealloc(start),
+            6 => Op::Insert(start..end),
+            _ => Op::Remove(start..end),
+        }
+    }
+}
+
+/// The first operation where a [`Replayer`] saw the allocator and the
+/// model disagree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// The number of operations applied before it.
+    pub step: usize,
+    /// The operation.
+    pub op: Op,
+    /// The result of the allocator, if it allocated something.
+    pub actual: Option<usize>,
+    /// The result of the model.
+    pub expected: Option<usize>,
+    /// The first raw word where the bitmaps differ afterwards, if any.
+    pub word: Option<usize>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "step {}, {:?}: returned {:?} instead of {:?}",
+            self.step, self.op, self.actual, self.expected
+        )?;
+        if let Some(w) = self.word {
+            write!(f, ", bitmaps differ at word {}", w)?;
+        }
+        Ok(())
+    }
+}
+
+/// Applies operations to an allocator and a [`ModelBitAlloc`] alike, and
+/// checks after each one that they returned the same and that the whole
+/// bitmaps still match. Each step costs `O(CAP)`.
+pub struct Replayer<A: BitAlloc> {
+    ba: A,
+    model: ModelBitAlloc,
+    step: usize,
+}
+
+impl<A: BitAlloc> Default for Replayer<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: BitAlloc> Replayer<A> {
+    /// Start from an allocator with every bit allocated.
+    pub fn new() -> Self {
+        let ba = A::default();
+        let model = ModelBitAlloc::new(ba.capacity());
+        Replayer { ba, model, step: 0 }
+    }
+
+    /// The allocator under test.
+    pub fn alloc(&self) -> &A {
+        &self.ba
+    }
+
+    /// The model.
+    pub fn model(&self) -> &ModelBitAlloc {
+        &self.model
+    }
+
+    /// The number of operations applied so far.
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
+    /// Apply an operation to both, and check them.
+    pub fn apply(&mut self, op: Op) -> Result<(), Divergence> {
+        let (actual, expected) = match op {
+            Op::Alloc => (self.ba.alloc(), self.model.alloc()),
+            Op::AllocContiguous { size, align_log2 } => (
+                self.ba.alloc_contiguous(size, align_log2),
+                self.model.alloc_contiguous(size, align_log2),
+            ),
+            Op::Dealloc(key) => {
+                if !self.model.test(key) {
+                    self.ba.dealloc(key);
+                    self.model.dealloc(key);
+                }
+                (None, None)
+            }
+            Op::Insert(ref range) => {
+                self.ba.insert(range.clone());
+                self.model.insert(range.clone());
+                (None, None)
+            }
+            Op::Remove(ref range) => {
+                self.ba.remove(range.clone());
+                self.model.remove(range.clone());
+                (None, None)
+            }
+        };
+        let word = (0..self.ba.capacity().div_ceil(WORD_BITS))
+            .find(|&w| self.ba.raw_word(w) != self.model.raw_word(w));
+        self.step += 1;
+        if actual != expected || word.is_some() {
+            return Err(Divergence {
+                step: self.step - 1,
+                op,
+                actual,
+                expected,
+                word,
+            });
+        }
+        Ok(())
+    }
+
+    /// Apply a sequence of operations, up to the first divergence.
+    pub fn replay(&mut self, ops: impl IntoIterator<Item = Op>) -> Result<(), Divergence> {
+        ops.into_iter().try_for_each(|op| self.apply(op))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitAlloc256, BitAlloc4K, BitAlloc4K64, BitAllocCascade16, LowestFirst};
+    use core::iter;
+
+    fn random_ops(mut seed: u32, cap: usize) -> impl Iterator<Item = Op> {
+        iter::repeat_with(move || Op::random(&mut seed, cap)).take(2000)
+    }
+
+    #[test]
+    fn model_differential() {
+        let mut replayer = Replayer::<BitAlloc4K>::new();
+        replayer.apply(Op::Insert(0..4096)).unwrap();
+        replayer.replay(random_ops(1, 4096)).unwrap();
+        let mut replayer = Replayer::<BitAlloc4K64>::new();
+        replayer.replay(random_ops(2, 4096)).unwrap();
+        assert_eq!(replayer.step(), 2000);
+        assert_eq!(replayer.alloc().free_count(), replayer.model().free_count());
+    }
+
+    #[test]
+    fn model_divergence() {
+        // the model always takes the highest bit
+        let mut replayer = Replayer::<BitAllocCascade16<BitAlloc256, LowestFirst>>::new();
+        replayer.apply(Op::Insert(10..20)).unwrap();
+        let divergence = replayer.apply(Op::Alloc).unwrap_err();
+        assert_eq!(divergence.step, 1);
+        assert_eq!(
+            (divergence.actual, divergence.expected),
+            (Some(10), Some(19))
+        );
+        assert_eq!(divergence.word, Some(0));
+    }
+}
@@ -0,0 +1,178 @@
+use crate::BitAlloc;
+use core::fmt;
+use core::ops::RangeBounds;
+use lock_api::{Mutex, MutexGuard, RawMutex};
+
+/// An allocator behind any lock implementing `lock_api::RawMutex`, e.g. a
+/// kernel's own mutex or one that masks interrupts, so that it can be used
+/// through shared references. Enabled by the `lock_api` feature.
+///
+/// This is `LockedBitAlloc` with the lock chosen by the user. Each method takes the lock for a single call; the guard of
+/// [`lock`](Self::lock) gives the whole `BitAlloc` interface, and that of
+/// the inner allocator, e.g. the counters of a `StatsBitAlloc`.
+pub struct MutexBitAlloc<R: RawMutex, T: BitAlloc> {
+    inner: Mutex<R, T>,
+}
+
+impl<R: RawMutex, T: BitAlloc> MutexBitAlloc<R, T> {
+    /// Wrap an allocator.
+    pub const fn new(inner: T) -> Self {
+        MutexBitAlloc {
+            inner: Mutex::const_new(R::INIT, inner),
+        }
+    }
+
+    /// Lock the allocator, waiting as `R` does until it is available.
+    pub fn lock(&self) -> MutexGuard<'_, R, T> {
+        self.inner.lock()
+    }
+
+    /// Lock the allocator if it is available right now.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, R, T>> {
+        self.inner.try_lock()
+    }
+
+    /// The underlying allocator, without locking as the borrow is exclusive.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    /// Unwrap the underlying allocator.
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+
+    /// Allocate a free bit.
+    pub fn alloc(&self) -> Option<usize> {
+        self.lock().alloc()
+    }
+
+    /// Allocate a free block with a given size, and return the first bit position.
+    pub fn alloc_contiguous(&self, size: usize, align_log2: usize) -> Option<usize> {
+        self.lock().alloc_contiguous(size, align_log2)
+    }
+
+    /// Free an allocated bit.
+    pub fn dealloc(&self, key: usize) {
+        self.lock().dealloc(key)
+    }
+
+    /// Free a block allocated by `alloc_contiguous`.
+    pub fn dealloc_contiguous(&self, base: usize, size: usize) {
+        self.lock().dealloc_contiguous(base, size)
+    }
+
+    /// Mark bits in the range as unallocated (available).
+    pub fn insert(&self, range: impl RangeBounds<usize>) {
+        self.lock().insert(range)
+    }
+
+    /// Reverse of insert.
+    pub fn remove(&self, range: impl RangeBounds<usize>) {
+        self.lock().remove(range)
+    }
+
+    /// Whether a specific bit is free.
+    pub fn test(&self, key: usize) -> bool {
+        self.lock().test(key)
+    }
+
+    /// The number of free bits.
+    pub fn free_count(&self) -> usize {
+        self.lock().free_count()
+    }
+}
+
+impl<R: RawMutex, T: BitAlloc> Default for MutexBitAlloc<R, T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<R: RawMutex, T: BitAlloc + fmt::Debug> fmt::Debug for MutexBitAlloc<R, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // never wait for the lock here, it may be held by the caller
+        match self.try_lock() {
+            Some(inner) => f.debug_tuple("MutexBitAlloc").field(&*inner).finish(),
+            None => f.write_str("MutexBitAlloc(<locked>)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::format;
+    use crate::{BitAlloc16, BitAlloc64K, StatsBitAlloc};
+    use core::sync::atomic::{AtomicBool, Ordering};
+    use lock_api::GuardSend;
+
+    /// A lock of the user's, here a plain spin lock.
+    struct RawSpin(AtomicBool);
+
+    unsafe impl RawMutex for RawSpin {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const INIT: Self = RawSpin(AtomicBool::new(false));
+        type GuardMarker = GuardSend;
+
+        fn lock(&self) {
+            while !self.try_lock() {
+                core::hint::spin_loop();
+            }
+        }
+        fn try_lock(&self) -> bool {
+            self.0
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        }
+        unsafe fn unlock(&self) {
+            self.0.store(false, Ordering::Release);
+        }
+    }
+
+    #[test]
+    fn mutex_static() {
+        extern crate std;
+        use std::vec::Vec;
+
+        static BA: MutexBitAlloc<RawSpin, StatsBitAlloc<BitAlloc64K>> =
+            MutexBitAlloc::new(StatsBitAlloc::DEFAULT);
+        BA.insert(..);
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    let block = BA.alloc_contiguous(100, 4).unwrap();
+                    let keys: Vec<_> = (0..1000).map(|_| BA.alloc().unwrap()).collect();
+                    (block, keys)
+                })
+            })
+            .collect();
+        for thread in threads {
+            let (block, keys) = thread.join().unwrap();
+            BA.dealloc_contiguous(block, 100);
+            for key in keys {
+                BA.dealloc(key);
+            }
+        }
+        assert_eq!(BA.free_count(), 65536);
+        // the guard reaches the counters of the inner allocator
+        let outstanding = BA.lock().outstanding();
+        assert_eq!((outstanding.bits, outstanding.blocks), (0, 0));
+    }
+
+    #[test]
+    fn mutex_debug() {
+        let ba = MutexBitAlloc::<RawSpin, _>::new(BitAlloc16::DEFAULT);
+        ba.insert(3..5);
+        assert!(ba.test(4));
+        let guard = ba.lock();
+        assert_eq!(
+            format(format_args!("{:?}", ba)).as_str(),
+            "MutexBitAlloc(<locked>)"
+        );
+        drop(guard);
+        assert!(format(format_args!("{:?}", ba))
+            .as_str()
+            .starts_with("MutexBitAlloc(BitAlloc16 {"));
+    }
+}
@@ -0,0 +1,216 @@
+use crate::{align_size, align_up, AllocPolicy, BitAlloc};
+use core::ops::{Range, RangeBounds};
+
+/// A wrapper resuming every `alloc` and `alloc_contiguous` where the previous
+/// one stopped, wrapping around at the end.
+///
+/// This spreads allocations over the whole bitmap instead of rescanning a
+/// densely allocated region each time. Other methods, including
+/// `alloc_with`, go straight to the inner allocator.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NextFit<A: BitAlloc> {
+    inner: A,
+    cursor: usize,
+}
+
+impl<A: BitAlloc> NextFit<A> {
+    /// Wrap an allocator, starting the search at bit 0.
+    pub fn new(inner: A) -> Self {
+        NextFit { inner, cursor: 0 }
+    }
+
+    /// Where the next search starts.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// The underlying allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Unwrap the underlying allocator.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    /// First-fit search for a block starting at or after `key`.
+    fn find_from(&self, key: usize, size: usize, align_log2: usize) -> Option<usize> {
+        if align_size(align_log2).is_none_or(|align| align > A::CAP) || size == 0 {
+            return None;
+        }
+        let mut key = key;
+        while let Some(start) = self.inner.next(key) {
+            let end = self.inner.next_allocated(start).unwrap_or(A::CAP);
+            let base = align_up(start, align_log2);
+            if base.saturating_add(size) <= end {
+                return Some(base);
+            }
+            key = end;
+        }
+        None
+    }
+}
+
+impl<A: BitAlloc> Default for NextFit<A> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<A: BitAlloc> BitAlloc for NextFit<A> {
+    const CAP: usize = A::CAP;
+
+    const DEFAULT: Self = NextFit {
+        inner: A::DEFAULT,
+        cursor: 0,
+    };
+
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    fn alloc(&mut self) -> Option<usize> {
+        let i = self.peek_alloc()?;
+        self.inner.remove(i..i + 1);
+        self.cursor = (i + 1) % Self::CAP;
+        Some(i)
+    }
+    fn alloc_with<P: AllocPolicy>(&mut self) -> Option<usize> {
+        self.inner.alloc_with::<P>()
+    }
+    fn peek_alloc(&self) -> Option<usize> {
+        self.inner.next(self.cursor).or_else(|| self.inner.next(0))
+    }
+    fn peek_with<P: AllocPolicy>(&self) -> Option<usize> {
+        self.inner.peek_with::<P>()
+    }
+    fn peek_full<P: AllocPolicy>() -> usize {
+        A::peek_full::<P>()
+    }
+    fn subtree_cap(depth: usize) -> usize {
+        A::subtree_cap(depth)
+    }
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        let base = self.find_contiguous(size, align_log2)?;
+        self.inner.remove(base..base + size);
+        self.cursor = (base + size) % Self::CAP;
+        Some(base)
+    }
+    fn find_contiguous(&self, size: usize, align_log2: usize) -> Option<usize> {
+        self.find_from(self.cursor, size, align_log2)
+            .or_else(|| self.inner.find_contiguous(size, align_log2))
+    }
+    fn alloc_aligned(&mut self, align_log2: usize) -> Option<usize> {
+        self.inner.alloc_aligned(align_log2)
+    }
+    fn next(&self, key: usize) -> Option<usize> {
+        self.inner.next(key)
+    }
+    fn prev(&self, key: usize) -> Option<usize> {
+        self.inner.prev(key)
+    }
+    fn dealloc(&mut self, key: usize) {
+        self.inner.dealloc(key)
+    }
+    fn insert(&mut self, range: impl RangeBounds<usize>) {
+        self.inner.insert(range)
+    }
+    fn remove(&mut self, range: impl RangeBounds<usize>) {
+        self.inner.remove(range)
+    }
+    fn any(&self) -> bool {
+        self.inner.any()
+    }
+    fn test(&self, key: usize) -> bool {
+        self.inner.test(key)
+    }
+    fn free_count(&self) -> usize {
+        self.inner.free_count()
+    }
+    fn count_free_in(&self, range: impl RangeBounds<usize>) -> usize {
+        self.inner.count_free_in(range)
+    }
+    fn free_prefix(&self) -> usize {
+        self.inner.free_prefix()
+    }
+    fn free_suffix(&self) -> usize {
+        self.inner.free_suffix()
+    }
+    fn max_contiguous_len(&self) -> usize {
+        self.inner.max_contiguous_len()
+    }
+    fn max_contiguous(&self) -> Option<Range<usize>> {
+        self.inner.max_contiguous()
+    }
+    fn next_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.next_allocated(key)
+    }
+    fn prev_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.prev_allocated(key)
+    }
+    fn raw_word(&self, w: usize) -> usize {
+        self.inner.raw_word(w)
+    }
+    fn next_difference(&self, other: &Self, key: usize) -> Option<usize> {
+        self.inner.next_difference(&other.inner, key)
+    }
+    fn is_subset_of(&self, other: &Self) -> bool {
+        self.inner.is_subset_of(&other.inner)
+    }
+    fn is_disjoint_with(&self, other: &Self) -> bool {
+        self.inner.is_disjoint_with(&other.inner)
+    }
+    fn union_with(&mut self, other: &Self) {
+        self.inner.union_with(&other.inner)
+    }
+    fn intersect_with(&mut self, other: &Self) {
+        self.inner.intersect_with(&other.inner)
+    }
+    fn subtract(&mut self, other: &Self) {
+        self.inner.subtract(&other.inner)
+    }
+    fn write_raw_word(&mut self, w: usize, word: usize) {
+        self.inner.write_raw_word(w, word)
+    }
+    fn rebuild(&mut self) {
+        self.inner.rebuild()
+    }
+    fn iter_free(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.inner.iter_free()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BitAlloc4K;
+
+    #[test]
+    fn next_fit() {
+        let mut ba = NextFit::<BitAlloc4K>::default();
+        ba.insert(0..BitAlloc4K::CAP);
+        assert_eq!(ba.alloc(), Some(0));
+        assert_eq!(ba.alloc(), Some(1));
+        ba.dealloc(0);
+        // don't go back to the freed bit until wrapping around
+        assert_eq!(ba.alloc(), Some(2));
+        assert_eq!(ba.alloc_contiguous(10, 3), Some(8));
+        assert_eq!(ba.cursor(), 18);
+        assert_eq!(ba.alloc(), Some(18));
+        ba.remove(19..4090);
+        for i in 4090..4096 {
+            assert_eq!(ba.alloc(), Some(i));
+        }
+        assert_eq!(ba.alloc(), Some(0));
+        assert_eq!(ba.alloc(), Some(3));
+        // the only fit is before the cursor
+        assert_eq!(ba.find_contiguous(3, 0), Some(4));
+        assert_eq!(ba.alloc_contiguous(3, 0), Some(4));
+        ba.dealloc(4095);
+        assert_eq!(ba.find_contiguous(2, 0), None);
+        assert_eq!(ba.alloc_contiguous(2, 0), None);
+        assert_eq!(ba.cursor(), 7);
+        assert_eq!(ba.alloc(), Some(7));
+        assert_eq!(ba.alloc(), Some(4095));
+    }
+}
@@ -0,0 +1,243 @@
+use crate::{align_size, to_range, BitAlloc};
+use core::fmt;
+use core::ops::{Range, RangeBounds};
+
+/// The distance of a node to itself in the ACPI SLIT.
+const LOCAL_DISTANCE: u8 = 10;
+/// The distance the ACPI SLIT assumes between nodes when it has no table.
+const REMOTE_DISTANCE: u8 = 20;
+
+/// One allocator of type `A` per NUMA node, each owning `A::CAP` bits of a
+/// shared index space from a base set at boot, e.g. from the memory
+/// affinity entries of the ACPI SRAT.
+///
+/// `alloc` prefers the node of the caller and spills over to the others
+/// nearest first, by the distances of the ACPI SLIT if given. Nodes are 10
+/// from themselves and 20 from each other by default.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct NumaBitAlloc<A: BitAlloc, const NODES: usize> {
+    nodes: [A; NODES],
+    bases: [usize; NODES],
+    distances: [[u8; NODES]; NODES],
+}
+
+impl<A: BitAlloc, const NODES: usize> NumaBitAlloc<A, NODES> {
+    /// Create an allocator with all bits allocated, where node `n` owns
+    /// `bases[n]..bases[n] + A::CAP`. The ranges must not overlap.
+    pub fn new(bases: [usize; NODES]) -> Self {
+        for (n, &base) in bases.iter().enumerate() {
+            assert!(base.checked_add(A::CAP).is_some());
+            for &other in &bases[..n] {
+                assert!(
+                    base + A::CAP <= other || other + A::CAP <= base,
+                    "the bits of nodes overlap"
+                );
+            }
+        }
+        let mut distances = [[REMOTE_DISTANCE; NODES]; NODES];
+        for (n, row) in distances.iter_mut().enumerate() {
+            row[n] = LOCAL_DISTANCE;
+        }
+        NumaBitAlloc {
+            nodes: [A::DEFAULT; NODES],
+            bases,
+            distances,
+        }
+    }
+
+    /// Set the distance from node `from` to node `to`.
+    pub fn set_distance(&mut self, from: usize, to: usize, distance: u8) {
+        self.distances[from][to] = distance;
+    }
+
+    /// Set all distances at once, as the ACPI SLIT lists them.
+    pub fn set_distances(&mut self, distances: [[u8; NODES]; NODES]) {
+        self.distances = distances;
+    }
+
+    /// The allocator of node `n`, in its own indices.
+    pub fn node(&self, n: usize) -> &A {
+        &self.nodes[n]
+    }
+
+    /// The allocator of node `n`, mutably.
+    pub fn node_mut(&mut self, n: usize) -> &mut A {
+        &mut self.nodes[n]
+    }
+
+    /// The bits owned by node `n`.
+    pub fn node_range(&self, n: usize) -> Range<usize> {
+        self.bases[n]..self.bases[n] + A::CAP
+    }
+
+    /// The node owning `key`.
+    pub fn node_of(&self, key: usize) -> Option<usize> {
+        (0..NODES).find(|&n| self.node_range(n).contains(&key))
+    }
+
+    /// The nodes, nearest to `node` first, ties going to the lower node.
+    fn by_distance(&self, node: usize) -> [usize; NODES] {
+        let mut order = [0; NODES];
+        for (n, m) in order.iter_mut().enumerate() {
+            *m = n;
+        }
+        order.sort_unstable_by_key(|&m| (self.distances[node][m], m));
+        order
+    }
+
+    /// The node owning `key`, and the index of `key` in it.
+    fn locate(&self, key: usize) -> (usize, usize) {
+        let n = self
+            .node_of(key)
+            .unwrap_or_else(|| panic!("bit {} belongs to no node", key));
+        (n, key - self.bases[n])
+    }
+
+    /// Allocate a free bit, from `node` if it has one, else from the nearest
+    /// node that does.
+    pub fn alloc(&mut self, node: usize) -> Option<usize> {
+        self.by_distance(node)
+            .iter()
+            .find_map(|&n| self.nodes[n].alloc().map(|key| key + self.bases[n]))
+    }
+
+    /// Allocate a free block with a given size, and return the first bit
+    /// position, from `node` if it has room, else from the nearest node
+    /// that does. The block never spans two nodes, and is aligned in the
+    /// shared index space.
+    pub fn alloc_contiguous(
+        &mut self,
+        node: usize,
+        size: usize,
+        align_log2: usize,
+    ) -> Option<usize> {
+        if size == 0 || align_size(align_log2).is_none() {
+            return None;
+        }
+        self.by_distance(node).iter().find_map(|&n| {
+            // search from the base, so that the alignment is a shared one
+            let base = self.bases[n];
+            let key = self.nodes[n].search_contiguous(base, size, align_log2, &mut None)?;
+            self.nodes[n].remove(key - base..key - base + size);
+            Some(key)
+        })
+    }
+
+    /// Free an allocated bit.
+    pub fn dealloc(&mut self, key: usize) {
+        let (n, key) = self.locate(key);
+        self.nodes[n].dealloc(key)
+    }
+
+    /// Free a block allocated by `alloc_contiguous`.
+    pub fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        let (n, key) = self.locate(base);
+        self.nodes[n].dealloc_contiguous(key, size)
+    }
+
+    /// Mark bits in the range as unallocated (available), skipping those
+    /// of no node, so the range may span the holes between nodes.
+    pub fn insert(&mut self, range: impl RangeBounds<usize>) {
+        self.for_range(range, |node, range| node.insert(range))
+    }
+
+    /// Reverse of insert.
+    pub fn remove(&mut self, range: impl RangeBounds<usize>) {
+        self.for_range(range, |node, range| node.remove(range))
+    }
+
+    fn for_range(
+        &mut self,
+        range: impl RangeBounds<usize>,
+        mut f: impl FnMut(&mut A, Range<usize>),
+    ) {
+        let range = to_range(range, usize::MAX);
+        for n in 0..NODES {
+            let base = self.bases[n];
+            let start = range.start.max(base);
+            let end = range.end.min(base + A::CAP);
+            if start < end {
+                f(&mut self.nodes[n], start - base..end - base);
+            }
+        }
+    }
+
+    /// Whether a specific bit is free.
+    pub fn test(&self, key: usize) -> bool {
+        self.node_of(key)
+            .is_some_and(|n| self.nodes[n].test(key - self.bases[n]))
+    }
+
+    /// The number of free bits on all nodes.
+    pub fn free_count(&self) -> usize {
+        self.nodes.iter().map(A::free_count).sum()
+    }
+
+    /// The number of free bits on node `n`.
+    pub fn free_count_on(&self, n: usize) -> usize {
+        self.nodes[n].free_count()
+    }
+}
+
+impl<A: BitAlloc, const NODES: usize> fmt::Debug for NumaBitAlloc<A, NODES> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut free = [0; NODES];
+        for (n, free) in free.iter_mut().enumerate() {
+            *free = self.free_count_on(n);
+        }
+        f.debug_struct("NumaBitAlloc").field("free", &free).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::format;
+    use crate::BitAlloc4K;
+
+    #[test]
+    fn numa_spill() {
+        // node 1 sits below node 0, past a hole
+        let mut ba = NumaBitAlloc::<BitAlloc4K, 3>::new([0x10000, 0x1000, 0x20000]);
+        ba.set_distances([[10, 30, 20], [30, 10, 20], [20, 20, 10]]);
+        ba.insert(0x1000..0x11000);
+        ba.insert(0x20000 + 100..0x20000 + 102);
+        assert_eq!(ba.free_count_on(0), 4096);
+        assert_eq!(ba.free_count_on(1), 4096);
+        assert_eq!(ba.node_of(0x5000), None);
+        assert_eq!(ba.alloc(1), Some(0x1fff));
+        ba.remove(0x1000..0x2000);
+        // node 2 is nearer than node 0
+        assert_eq!(ba.alloc(1), Some(0x20000 + 101));
+        assert_eq!(ba.alloc(1), Some(0x20000 + 100));
+        assert_eq!(ba.alloc(1), Some(0x10fff));
+        ba.dealloc(0x1fff);
+        assert!(ba.test(0x1fff));
+        assert_eq!(ba.alloc(0), Some(0x10ffe));
+        assert_eq!(ba.alloc(2), Some(0x10ffd));
+        assert_eq!(
+            format(format_args!("{:?}", ba)).as_str(),
+            "NumaBitAlloc { free: [4093, 1, 0] }"
+        );
+    }
+
+    #[test]
+    fn numa_contiguous() {
+        let mut ba = NumaBitAlloc::<BitAlloc4K, 2>::new([0x100, 0x2000]);
+        ba.insert(..);
+        // aligned in the shared space, not in the node
+        assert_eq!(ba.alloc_contiguous(0, 0x200, 9), Some(0x200));
+        assert_eq!(ba.free_count_on(0), 4096 - 0x200);
+        ba.dealloc_contiguous(0x200, 0x200);
+        // node 0 holds no aligned block this large, so it spills to node 1
+        assert_eq!(ba.alloc_contiguous(0, 0x1000, 12), Some(0x2000));
+        assert_eq!(ba.alloc_contiguous(0, 0x1000, 12), None);
+        assert_eq!(ba.alloc_contiguous(1, 0x800, 11), Some(0x800));
+    }
+
+    #[test]
+    #[should_panic(expected = "overlap")]
+    fn numa_overlap() {
+        NumaBitAlloc::<BitAlloc4K, 2>::new([0, 4095]);
+    }
+}
@@ -0,0 +1,332 @@
+use crate::{to_range, AllocPolicy, BitAlloc, BudgetExhausted, Inconsistency};
+use core::ops::{Range, RangeBounds};
+
+/// Notified by [`ObservedBitAlloc`] of every change, with the bits
+/// involved.
+///
+/// All methods do nothing by default. Allocations that the provided methods
+/// of [`BitAlloc`] build on `remove`, like `alloc_from`, are reported as
+/// removals, and frees built on `insert` as insertions.
+pub trait AllocObserver: Default {
+    /// The default value. Workaround for `const fn new() -> Self`.
+    const DEFAULT: Self;
+
+    /// Bits were allocated.
+    fn on_alloc(&mut self, _range: Range<usize>) {}
+
+    /// Allocated bits were freed.
+    fn on_dealloc(&mut self, _range: Range<usize>) {}
+
+    /// Bits in the range were marked free, some of them maybe already free.
+    fn on_insert(&mut self, _range: Range<usize>) {}
+
+    /// Bits in the range were marked allocated, some of them maybe already
+    /// allocated.
+    fn on_remove(&mut self, _range: Range<usize>) {}
+}
+
+/// A wrapper notifying an [`AllocObserver`] of every allocation, free and
+/// range operation, e.g. to mirror the bitmap into a shadow copy or emit
+/// tracepoints.
+///
+/// As with [`LoggedBitAlloc`](crate::LoggedBitAlloc), only the outermost
+/// calls are reported, and queries are not.
+#[derive(Debug, Clone)]
+pub struct ObservedBitAlloc<A: BitAlloc, O: AllocObserver> {
+    inner: A,
+    observer: O,
+}
+
+impl<A: BitAlloc, O: AllocObserver> ObservedBitAlloc<A, O> {
+    /// Wrap an allocator.
+    pub fn new(inner: A, observer: O) -> Self {
+        ObservedBitAlloc { inner, observer }
+    }
+
+    /// The underlying allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// The observer.
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    /// The observer, mutably.
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+
+    /// Unwrap the underlying allocator and the observer.
+    pub fn into_parts(self) -> (A, O) {
+        (self.inner, self.observer)
+    }
+
+    fn allocated(&mut self, key: Option<usize>) -> Option<usize> {
+        if let Some(key) = key {
+            self.observer.on_alloc(key..key + 1);
+        }
+        key
+    }
+}
+
+impl<A: BitAlloc, O: AllocObserver> Default for ObservedBitAlloc<A, O> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<A: BitAlloc, O: AllocObserver> BitAlloc for ObservedBitAlloc<A, O> {
+    const CAP: usize = A::CAP;
+
+    const DEFAULT: Self = ObservedBitAlloc {
+        inner: A::DEFAULT,
+        observer: O::DEFAULT,
+    };
+
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    fn alloc(&mut self) -> Option<usize> {
+        let res = self.inner.alloc();
+        self.allocated(res)
+    }
+    fn alloc_with<P: AllocPolicy>(&mut self) -> Option<usize> {
+        let res = self.inner.alloc_with::<P>();
+        self.allocated(res)
+    }
+    fn alloc_many(&mut self, out: &mut [usize]) -> usize {
+        let n = self.inner.alloc_many(out);
+        for &key in &out[..n] {
+            self.observer.on_alloc(key..key + 1);
+        }
+        n
+    }
+    fn alloc_many_with<P: AllocPolicy>(&mut self, out: &mut [usize]) -> usize {
+        let n = self.inner.alloc_many_with::<P>(out);
+        for &key in &out[..n] {
+            self.observer.on_alloc(key..key + 1);
+        }
+        n
+    }
+    fn peek_alloc(&self) -> Option<usize> {
+        self.inner.peek_alloc()
+    }
+    fn peek_with<P: AllocPolicy>(&self) -> Option<usize> {
+        self.inner.peek_with::<P>()
+    }
+    fn peek_full<P: AllocPolicy>() -> usize {
+        A::peek_full::<P>()
+    }
+    fn subtree_cap(depth: usize) -> usize {
+        A::subtree_cap(depth)
+    }
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        let res = self.inner.alloc_contiguous(size, align_log2);
+        if let Some(base) = res {
+            self.observer.on_alloc(base..base + size);
+        }
+        res
+    }
+    fn find_contiguous(&self, size: usize, align_log2: usize) -> Option<usize> {
+        self.inner.find_contiguous(size, align_log2)
+    }
+    fn alloc_aligned(&mut self, align_log2: usize) -> Option<usize> {
+        let res = self.inner.alloc_aligned(align_log2);
+        self.allocated(res)
+    }
+    fn next(&self, key: usize) -> Option<usize> {
+        self.inner.next(key)
+    }
+    fn prev(&self, key: usize) -> Option<usize> {
+        self.inner.prev(key)
+    }
+    fn dealloc(&mut self, key: usize) {
+        self.inner.dealloc(key);
+        self.observer.on_dealloc(key..key + 1);
+    }
+    fn dealloc_many(&mut self, keys: impl IntoIterator<Item = usize>) {
+        let observer = &mut self.observer;
+        let keys = keys
+            .into_iter()
+            .inspect(|&key| observer.on_dealloc(key..key + 1));
+        self.inner.dealloc_many(keys)
+    }
+    fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        self.inner.dealloc_contiguous(base, size);
+        self.observer.on_dealloc(base..base + size);
+    }
+    fn insert(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, Self::CAP);
+        self.inner.insert(range.clone());
+        self.observer.on_insert(range);
+    }
+    fn remove(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, Self::CAP);
+        self.inner.remove(range.clone());
+        self.observer.on_remove(range);
+    }
+    fn any(&self) -> bool {
+        self.inner.any()
+    }
+    fn test(&self, key: usize) -> bool {
+        self.inner.test(key)
+    }
+    fn free_count(&self) -> usize {
+        self.inner.free_count()
+    }
+    fn count_free_in(&self, range: impl RangeBounds<usize>) -> usize {
+        self.inner.count_free_in(range)
+    }
+    fn free_prefix(&self) -> usize {
+        self.inner.free_prefix()
+    }
+    fn free_suffix(&self) -> usize {
+        self.inner.free_suffix()
+    }
+    fn max_contiguous_len(&self) -> usize {
+        self.inner.max_contiguous_len()
+    }
+    fn max_contiguous(&self) -> Option<Range<usize>> {
+        self.inner.max_contiguous()
+    }
+    fn search_contiguous(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+    ) -> Option<usize> {
+        self.inner
+            .search_contiguous(offset, size, align_log2, run_start)
+    }
+    fn search_contiguous_bounded(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+        budget: &mut usize,
+    ) -> Result<Option<usize>, BudgetExhausted> {
+        self.inner
+            .search_contiguous_bounded(offset, size, align_log2, run_start, budget)
+    }
+    fn visit_free_runs(&self, run: &mut usize, f: &mut impl FnMut(usize)) {
+        self.inner.visit_free_runs(run, f)
+    }
+    fn next_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.next_allocated(key)
+    }
+    fn prev_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.prev_allocated(key)
+    }
+    fn raw_word(&self, w: usize) -> usize {
+        self.inner.raw_word(w)
+    }
+    fn next_difference(&self, other: &Self, key: usize) -> Option<usize> {
+        self.inner.next_difference(&other.inner, key)
+    }
+    fn is_subset_of(&self, other: &Self) -> bool {
+        self.inner.is_subset_of(&other.inner)
+    }
+    fn is_disjoint_with(&self, other: &Self) -> bool {
+        self.inner.is_disjoint_with(&other.inner)
+    }
+    /// Reports the runs of bits the word frees or allocates as insertions
+    /// and removals.
+    fn write_raw_word(&mut self, w: usize, word: usize) {
+        let old = self.inner.raw_word(w);
+        self.inner.write_raw_word(w, word);
+        // bits past the end are ignored, and may differ
+        let word = self.inner.raw_word(w);
+        let base = w * usize::BITS as usize;
+        let mut changed = old ^ word;
+        while changed != 0 {
+            let start = changed.trailing_zeros() as usize;
+            // the run of changes in the same direction
+            let free = word >> start & 1 != 0;
+            let same = if free { word & !old } else { old & !word };
+            let len = (!(same >> start)).trailing_zeros() as usize;
+            let range = base + start..base + start + len;
+            if free {
+                self.observer.on_insert(range);
+            } else {
+                self.observer.on_remove(range);
+            }
+            changed &= !(usize::MAX >> (usize::BITS as usize - len) << start);
+        }
+    }
+    fn rebuild(&mut self) {
+        self.inner.rebuild()
+    }
+    fn validate(&self) -> Result<(), Inconsistency> {
+        self.inner.validate()
+    }
+    fn iter_free(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.inner.iter_free()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BitAlloc4K;
+
+    /// Mirrors the allocator into a copy of its own.
+    #[derive(Default)]
+    struct Shadow {
+        ba: BitAlloc4K,
+        events: usize,
+    }
+
+    impl AllocObserver for Shadow {
+        const DEFAULT: Self = Shadow {
+            ba: BitAlloc4K::DEFAULT,
+            events: 0,
+        };
+
+        fn on_alloc(&mut self, range: Range<usize>) {
+            assert!(self.ba.all_free(range.clone()));
+            self.ba.remove(range);
+            self.events += 1;
+        }
+        fn on_dealloc(&mut self, range: Range<usize>) {
+            assert!(!self.ba.any_free(range.clone()));
+            self.ba.insert(range);
+            self.events += 1;
+        }
+        fn on_insert(&mut self, range: Range<usize>) {
+            self.ba.insert(range);
+            self.events += 1;
+        }
+        fn on_remove(&mut self, range: Range<usize>) {
+            self.ba.remove(range);
+            self.events += 1;
+        }
+    }
+
+    #[test]
+    fn observed_shadow() {
+        let mut ba = ObservedBitAlloc::<BitAlloc4K, Shadow>::default();
+        ba.insert(..);
+        let key = ba.alloc().unwrap();
+        let base = ba.alloc_contiguous(100, 4).unwrap();
+        let mut out = [0; 3];
+        ba.alloc_many(&mut out);
+        ba.dealloc(key);
+        ba.dealloc_contiguous(base, 100);
+        ba.dealloc_many(out);
+        ba.alloc_from(1000);
+        ba.remove(2000..3000);
+        assert_eq!(ba.observer().events, 13);
+        // whole words written through the raw interface
+        let mut other = BitAlloc4K::default();
+        other.insert(1500..1600);
+        ba.intersect_with(&ObservedBitAlloc::new(other, Shadow::default()));
+        let (inner, shadow) = ba.into_parts();
+        assert!(inner == shadow.ba);
+        assert_eq!(inner.max_contiguous(), Some(1500..1600));
+        assert_eq!(inner.free_count(), 100);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::BitAlloc;
+use core::fmt;
+use core::ops::Range;
+
+/// A wrapper recording which subsystem owns each allocated bit, as a `u16`
+/// tag passed on allocation, so that leaks can be attributed, e.g. to tell
+/// apart the frames held by the page cache from those a driver forgot.
+/// Enabled by the `owner` feature.
+///
+/// `N` is the number of tags kept and must be `A::CAP`. Bits allocated
+/// when wrapped, or by `remove`, are owned by tag 0.
+#[derive(Clone)]
+pub struct OwnerBitAlloc<A: BitAlloc, const N: usize> {
+    inner: A,
+    owners: [u16; N],
+}
+
+impl<A: BitAlloc, const N: usize> OwnerBitAlloc<A, N> {
+    /// Wrap an allocator, its allocated bits owned by tag 0.
+    pub fn new(inner: A) -> Self {
+        assert_eq!(N, A::CAP, "one tag per bit is needed");
+        OwnerBitAlloc {
+            inner,
+            owners: [0; N],
+        }
+    }
+
+    /// The underlying allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Unwrap the underlying allocator, dropping the tags.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    /// Allocate a free bit for `tag`.
+    pub fn alloc(&mut self, tag: u16) -> Option<usize> {
+        let key = self.inner.alloc()?;
+        self.owners[key] = tag;
+        Some(key)
+    }
+
+    /// Allocate a free block like `alloc_contiguous`, for `tag`.
+    pub fn alloc_contiguous(&mut self, size: usize, align_log2: usize, tag: u16) -> Option<usize> {
+        let base = self.inner.alloc_contiguous(size, align_log2)?;
+        self.owners[base..base + size].fill(tag);
+        Some(base)
+    }
+
+    /// Free an allocated bit.
+    pub fn dealloc(&mut self, key: usize) {
+        self.inner.dealloc(key)
+    }
+
+    /// Free a block of `size` allocated bits starting at `base`.
+    pub fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        self.inner.dealloc_contiguous(base, size)
+    }
+
+    /// Mark bits in the range as unallocated.
+    pub fn insert(&mut self, range: Range<usize>) {
+        self.inner.insert(range)
+    }
+
+    /// Mark bits in the range as allocated, owned by tag 0. Bits already
+    /// allocated keep their owner.
+    pub fn remove(&mut self, range: Range<usize>) {
+        for key in range.clone() {
+            if self.inner.test(key) {
+                self.owners[key] = 0;
+            }
+        }
+        self.inner.remove(range)
+    }
+
+    /// Whether a specific bit is free.
+    pub fn test(&self, key: usize) -> bool {
+        self.inner.test(key)
+    }
+
+    /// The tag owning bit `key`, or `None` if it is free.
+    pub fn owner(&self, key: usize) -> Option<u16> {
+        if self.inner.test(key) {
+            None
+        } else {
+            Some(self.owners[key])
+        }
+    }
+
+    /// The number of allocated bits owned by `tag`.
+    pub fn owned_by(&self, tag: u16) -> usize {
+        self.inner
+            .iter_allocated()
+            .filter(|&key| self.owners[key] == tag)
+            .count()
+    }
+
+    /// The allocated bits of every tag owning some, as `(tag, bits)` in the
+    /// order of the tags.
+    ///
+    /// Each item takes a pass over the allocated bits; this is a report for
+    /// leak hunts, not for the allocation path.
+    pub fn outstanding(&self) -> impl Iterator<Item = (u16, usize)> + '_ {
+        let mut from = 0;
+        core::iter::from_fn(move || {
+            let mut found: Option<(u16, usize)> = None;
+            for key in self.inner.iter_allocated() {
+                let tag = self.owners[key];
+                if u32::from(tag) < from {
+                    continue;
+                }
+                match &mut found {
+                    Some((min, bits)) if *min == tag => *bits += 1,
+                    Some((min, _)) if *min < tag => {}
+                    _ => found = Some((tag, 1)),
+                }
+            }
+            let (tag, bits) = found?;
+            from = u32::from(tag) + 1;
+            Some((tag, bits))
+        })
+    }
+}
+
+impl<A: BitAlloc + fmt::Debug, const N: usize> fmt::Debug for OwnerBitAlloc<A, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OwnerBitAlloc")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BitAlloc256;
+
+    #[test]
+    fn owner_outstanding() {
+        let mut ba = BitAlloc256::default();
+        ba.insert(16..);
+        let mut ba = OwnerBitAlloc::<_, 256>::new(ba);
+        let a = ba.alloc(7).unwrap();
+        let block = ba.alloc_contiguous(32, 4, 3).unwrap();
+        ba.alloc(7).unwrap();
+        ba.alloc(9).unwrap();
+        assert_eq!(ba.owner(a), Some(7));
+        assert_eq!(ba.owner(block + 31), Some(3));
+        assert_eq!(ba.owner(100), None);
+        assert!(ba.outstanding().eq([(0, 16), (3, 32), (7, 2), (9, 1)]));
+        ba.dealloc_contiguous(block, 32);
+        ba.dealloc(a);
+        // a freed bit allocated again gets the new owner
+        ba.remove(block..block + 1);
+        assert_eq!(ba.owner(block), Some(0));
+        assert_eq!(ba.owned_by(3), 0);
+        assert!(ba.outstanding().eq([(0, 17), (7, 1), (9, 1)]));
+    }
+}
@@ -0,0 +1,397 @@
+use crate::{to_range, AllocPolicy, BitAlloc, BudgetExhausted, Inconsistency, WORD_BITS};
+use core::fmt;
+use core::ops::{Range, RangeBounds};
+
+/// A wrapper with up to `N` pinned ranges, e.g. the kernel image, ACPI
+/// tables or the framebuffer, which stay allocated whatever is inserted.
+///
+/// `insert` and the other ways of freeing bits in bulk leave pinned bits
+/// out; `insert_clipped` also tells what was freed. Freeing a single pinned
+/// bit with `dealloc` is a bug and panics. Adjacent and overlapping pins
+/// are merged, so `N` bounds the number of disjoint ranges.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct PinnedBitAlloc<A: BitAlloc, const N: usize> {
+    inner: A,
+    pins: [Range<usize>; N], // sorted and disjoint, the first `len` in use
+    len: usize,
+}
+
+impl<A: BitAlloc, const N: usize> PinnedBitAlloc<A, N> {
+    /// Wrap an allocator, with nothing pinned.
+    pub fn new(inner: A) -> Self {
+        PinnedBitAlloc {
+            inner,
+            pins: [const { 0..0 }; N],
+            len: 0,
+        }
+    }
+
+    /// The underlying allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Unwrap the underlying allocator, where pinned bits are allocated.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    /// The pinned ranges, in ascending order.
+    pub fn pinned(&self) -> &[Range<usize>] {
+        &self.pins[..self.len]
+    }
+
+    /// Whether a specific bit is pinned.
+    pub fn is_pinned(&self, key: usize) -> bool {
+        let i = self.pinned().partition_point(|pin| pin.end <= key);
+        self.pinned().get(i).is_some_and(|pin| pin.start <= key)
+    }
+
+    /// Pin the bits in the range, allocating those that are free.
+    pub fn pin(&mut self, range: impl RangeBounds<usize>) {
+        let Range { mut start, mut end } = to_range(range, A::CAP);
+        if start >= end {
+            return;
+        }
+        self.inner.remove(start..end);
+        // merge with the pins it touches
+        let first = self.pinned().partition_point(|pin| pin.end < start);
+        let last = self.pinned().partition_point(|pin| pin.start <= end);
+        if first < last {
+            start = start.min(self.pins[first].start);
+            end = end.max(self.pins[last - 1].end);
+        } else {
+            assert!(self.len < N, "too many pinned ranges");
+        }
+        self.splice(first..last, core::slice::from_ref(&(start..end)));
+    }
+
+    /// Unpin the bits in the range. They stay allocated, to be freed with
+    /// `insert`, e.g. once the boot code is no longer needed.
+    pub fn unpin(&mut self, range: impl RangeBounds<usize>) {
+        let Range { start, end } = to_range(range, A::CAP);
+        if start >= end {
+            return;
+        }
+        let first = self.pinned().partition_point(|pin| pin.end <= start);
+        let last = self.pinned().partition_point(|pin| pin.start < end);
+        if first == last {
+            return;
+        }
+        // what is left of the first and last pins it touches
+        let head = self.pins[first].start..start;
+        let tail = end..self.pins[last - 1].end;
+        let mut rest = [0..0, 0..0];
+        let mut n = 0;
+        for piece in [head, tail].iter().filter(|piece| piece.start < piece.end) {
+            rest[n] = piece.clone();
+            n += 1;
+        }
+        assert!(self.len - (last - first) + n <= N, "too many pinned ranges");
+        self.splice(first..last, &rest[..n]);
+    }
+
+    /// Replace the pins in `old` by `new`.
+    fn splice(&mut self, old: Range<usize>, new: &[Range<usize>]) {
+        let len = self.len - old.len() + new.len();
+        if new.len() > old.len() {
+            self.pins[old.start..len].rotate_right(new.len() - old.len());
+        } else {
+            self.pins[old.start..self.len].rotate_left(old.len() - new.len());
+        }
+        self.pins[old.start..old.start + new.len()].clone_from_slice(new);
+        self.len = len;
+    }
+
+    /// Mark the bits in the range that are not pinned as free, and return
+    /// those runs of bits.
+    pub fn insert_clipped(
+        &mut self,
+        range: impl RangeBounds<usize>,
+    ) -> impl Iterator<Item = Range<usize>> + '_ {
+        let range = to_range(range, A::CAP);
+        self.insert(range.clone());
+        unpinned(self.pinned(), range)
+    }
+}
+
+/// The runs of bits in `range` outside all of `pins`, which are sorted.
+fn unpinned(pins: &[Range<usize>], range: Range<usize>) -> impl Iterator<Item = Range<usize>> + '_ {
+    let first = pins.partition_point(|pin| pin.end <= range.start);
+    let mut pins = pins[first..].iter().peekable();
+    let mut key = range.start;
+    core::iter::from_fn(move || {
+        while let Some(pin) = pins.next_if(|pin| pin.start <= key) {
+            key = key.max(pin.end);
+        }
+        if key >= range.end {
+            return None;
+        }
+        let end = pins
+            .peek()
+            .map_or(range.end, |pin| pin.start.min(range.end));
+        let run = key..end;
+        key = end;
+        Some(run)
+    })
+}
+
+impl<A: BitAlloc, const N: usize> Default for PinnedBitAlloc<A, N> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<A: BitAlloc + fmt::Debug, const N: usize> fmt::Debug for PinnedBitAlloc<A, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PinnedBitAlloc")
+            .field("inner", &self.inner)
+            .field("pinned", &self.pinned())
+            .finish()
+    }
+}
+
+impl<A: BitAlloc, const N: usize> BitAlloc for PinnedBitAlloc<A, N> {
+    const CAP: usize = A::CAP;
+
+    const DEFAULT: Self = PinnedBitAlloc {
+        inner: A::DEFAULT,
+        pins: [const { 0..0 }; N],
+        len: 0,
+    };
+
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    fn alloc(&mut self) -> Option<usize> {
+        self.inner.alloc()
+    }
+    fn alloc_with<P: AllocPolicy>(&mut self) -> Option<usize> {
+        self.inner.alloc_with::<P>()
+    }
+    fn alloc_many(&mut self, out: &mut [usize]) -> usize {
+        self.inner.alloc_many(out)
+    }
+    fn alloc_many_with<P: AllocPolicy>(&mut self, out: &mut [usize]) -> usize {
+        self.inner.alloc_many_with::<P>(out)
+    }
+    fn peek_alloc(&self) -> Option<usize> {
+        self.inner.peek_alloc()
+    }
+    fn peek_with<P: AllocPolicy>(&self) -> Option<usize> {
+        self.inner.peek_with::<P>()
+    }
+    fn peek_full<P: AllocPolicy>() -> usize {
+        A::peek_full::<P>()
+    }
+    fn subtree_cap(depth: usize) -> usize {
+        A::subtree_cap(depth)
+    }
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        self.inner.alloc_contiguous(size, align_log2)
+    }
+    fn find_contiguous(&self, size: usize, align_log2: usize) -> Option<usize> {
+        self.inner.find_contiguous(size, align_log2)
+    }
+    fn alloc_aligned(&mut self, align_log2: usize) -> Option<usize> {
+        self.inner.alloc_aligned(align_log2)
+    }
+    fn next(&self, key: usize) -> Option<usize> {
+        self.inner.next(key)
+    }
+    fn prev(&self, key: usize) -> Option<usize> {
+        self.inner.prev(key)
+    }
+    fn dealloc(&mut self, key: usize) {
+        assert!(!self.is_pinned(key), "bit {} is pinned", key);
+        self.inner.dealloc(key)
+    }
+    fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        assert!(
+            self.pinned()
+                .iter()
+                .all(|pin| pin.end <= base || base + size <= pin.start),
+            "block at {} is pinned",
+            base
+        );
+        self.inner.dealloc_contiguous(base, size)
+    }
+    /// Frees the bits in the range that are not pinned.
+    fn insert(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, Self::CAP);
+        for run in unpinned(&self.pins[..self.len], range) {
+            self.inner.insert(run);
+        }
+    }
+    fn remove(&mut self, range: impl RangeBounds<usize>) {
+        self.inner.remove(range)
+    }
+    fn any(&self) -> bool {
+        self.inner.any()
+    }
+    fn test(&self, key: usize) -> bool {
+        self.inner.test(key)
+    }
+    fn free_count(&self) -> usize {
+        self.inner.free_count()
+    }
+    fn count_free_in(&self, range: impl RangeBounds<usize>) -> usize {
+        self.inner.count_free_in(range)
+    }
+    fn free_prefix(&self) -> usize {
+        self.inner.free_prefix()
+    }
+    fn free_suffix(&self) -> usize {
+        self.inner.free_suffix()
+    }
+    fn max_contiguous_len(&self) -> usize {
+        self.inner.max_contiguous_len()
+    }
+    fn max_contiguous(&self) -> Option<Range<usize>> {
+        self.inner.max_contiguous()
+    }
+    fn search_contiguous(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+    ) -> Option<usize> {
+        self.inner
+            .search_contiguous(offset, size, align_log2, run_start)
+    }
+    fn search_contiguous_bounded(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+        budget: &mut usize,
+    ) -> Result<Option<usize>, BudgetExhausted> {
+        self.inner
+            .search_contiguous_bounded(offset, size, align_log2, run_start, budget)
+    }
+    fn visit_free_runs(&self, run: &mut usize, f: &mut impl FnMut(usize)) {
+        self.inner.visit_free_runs(run, f)
+    }
+    fn next_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.next_allocated(key)
+    }
+    fn prev_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.prev_allocated(key)
+    }
+    fn raw_word(&self, w: usize) -> usize {
+        self.inner.raw_word(w)
+    }
+    fn next_difference(&self, other: &Self, key: usize) -> Option<usize> {
+        self.inner.next_difference(&other.inner, key)
+    }
+    fn is_subset_of(&self, other: &Self) -> bool {
+        self.inner.is_subset_of(&other.inner)
+    }
+    fn is_disjoint_with(&self, other: &Self) -> bool {
+        self.inner.is_disjoint_with(&other.inner)
+    }
+    /// Keeps pinned bits allocated.
+    fn write_raw_word(&mut self, w: usize, word: usize) {
+        let base = w * WORD_BITS;
+        let mask = unpinned(self.pinned(), base..base + WORD_BITS).fold(0, |mask, run| {
+            mask | usize::MAX >> (WORD_BITS - run.len()) << (run.start - base)
+        });
+        self.inner.write_raw_word(w, word & mask)
+    }
+    /// The returned allocator gets the pins from `at` on, a pin across `at`
+    /// being cut in two.
+    fn split_off(&mut self, at: usize) -> Self {
+        let mut hi = PinnedBitAlloc::new(self.inner.split_off(at));
+        let first = self.pinned().partition_point(|pin| pin.end <= at);
+        for pin in &self.pins[first..self.len] {
+            hi.pins[hi.len] = pin.start.max(at)..pin.end;
+            hi.len += 1;
+        }
+        self.len = self.pinned().partition_point(|pin| pin.start < at);
+        if let Some(pin) = self.pins[..self.len].last_mut() {
+            pin.end = pin.end.min(at);
+        }
+        hi
+    }
+    /// Takes back the pins of `other` too.
+    fn merge(&mut self, other: &Self) {
+        for pin in other.pinned() {
+            self.pin(pin.clone());
+        }
+        self.inner.merge(&other.inner)
+    }
+    fn rebuild(&mut self) {
+        self.inner.rebuild()
+    }
+    fn validate(&self) -> Result<(), Inconsistency> {
+        self.inner.validate()
+    }
+    fn iter_free(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.inner.iter_free()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BitAlloc4K;
+
+    #[test]
+    fn pinned_insert_clipped() {
+        let mut ba = PinnedBitAlloc::<BitAlloc4K, 4>::default();
+        ba.pin(100..200);
+        ba.pin(1000..1100);
+        ba.pin(150..300);
+        ba.pin(300..310);
+        assert_eq!(ba.pinned(), &[100..310, 1000..1100]);
+        assert!(ba
+            .insert_clipped(50..2000)
+            .eq([50..100, 310..1000, 1100..2000]));
+        // a late blanket insert leaves the pins alone
+        ba.insert(..);
+        assert_eq!(ba.free_count(), 4096 - 210 - 100);
+        assert!(ba.is_pinned(100) && !ba.test(100) && !ba.is_pinned(310));
+        // whole raw words, as set operations write them
+        let mut all = PinnedBitAlloc::<BitAlloc4K, 4>::default();
+        all.insert(..);
+        ba.union_with(&all);
+        assert_eq!(ba.free_count(), 4096 - 210 - 100);
+        ba.unpin(120..130);
+        assert_eq!(ba.pinned(), &[100..120, 130..310, 1000..1100]);
+        ba.unpin(..);
+        assert_eq!(ba.pinned(), &[]);
+        assert!(!ba.test(100));
+        ba.insert(..);
+        assert_eq!(ba.free_count(), 4096);
+    }
+
+    #[test]
+    fn pinned_split_off() {
+        let mut ba = PinnedBitAlloc::<BitAlloc4K, 3>::default();
+        ba.insert(..);
+        ba.pin(1000..1010);
+        ba.pin(2040..2060);
+        ba.pin(4000..4010);
+        let mut hi = ba.split_off(2048);
+        assert_eq!(ba.pinned(), &[1000..1010, 2040..2048]);
+        assert_eq!(hi.pinned(), &[2048..2060, 4000..4010]);
+        hi.insert(2048..);
+        ba.insert(..2048);
+        assert!(!hi.test(2050) && !ba.test(2045));
+        assert_eq!(hi.free_count(), 2048 - 22);
+        ba.merge(&hi);
+        assert_eq!(ba.pinned(), &[1000..1010, 2040..2060, 4000..4010]);
+        ba.insert(..);
+        assert_eq!(ba.free_count(), 4096 - 40);
+    }
+
+    #[test]
+    #[should_panic(expected = "is pinned")]
+    fn pinned_dealloc() {
+        let mut ba = PinnedBitAlloc::<BitAlloc4K, 1>::default();
+        ba.pin(10..20);
+        ba.dealloc(15);
+    }
+}
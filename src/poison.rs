@@ -0,0 +1,303 @@
+use crate::{to_range, AllocPolicy, BitAlloc, BudgetExhausted, Inconsistency};
+use core::fmt;
+use core::ops::{Range, RangeBounds};
+
+/// A wrapper with a third state for bits: poisoned, e.g. frames with ECC
+/// errors or bad NAND blocks.
+///
+/// A poisoned bit is allocated, and stays so: `insert`, `dealloc` and the
+/// other ways of freeing bits skip it, so a later blanket `insert` won't
+/// bring it back. A bit in use when it is poisoned is kept when freed.
+/// Only `unpoison` lifts this, leaving the bit allocated.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct PoisonBitAlloc<A: BitAlloc> {
+    inner: A,
+    poisoned: A, // free bits here are the poisoned ones
+}
+
+impl<A: BitAlloc> PoisonBitAlloc<A> {
+    /// Wrap an allocator, with no bit poisoned.
+    pub fn new(inner: A) -> Self {
+        PoisonBitAlloc {
+            inner,
+            poisoned: A::DEFAULT,
+        }
+    }
+
+    /// The underlying allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Unwrap the underlying allocator, where poisoned bits are allocated.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    /// Poison the bits in the range, allocating those that are free.
+    pub fn poison(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, A::CAP);
+        self.inner.remove(range.clone());
+        self.poisoned.insert(range);
+    }
+
+    /// Lift the poison from the bits in the range. They stay allocated, to be
+    /// freed with `insert` once known good.
+    pub fn unpoison(&mut self, range: impl RangeBounds<usize>) {
+        self.poisoned.remove(range);
+    }
+
+    /// Whether a specific bit is poisoned.
+    pub fn is_poisoned(&self, key: usize) -> bool {
+        self.poisoned.test(key)
+    }
+
+    /// The number of poisoned bits.
+    pub fn poisoned_count(&self) -> usize {
+        self.poisoned.free_count()
+    }
+
+    /// Iterate over the maximal runs of poisoned bits in ascending order.
+    pub fn poisoned_ranges(&self) -> impl DoubleEndedIterator<Item = Range<usize>> + '_ {
+        self.poisoned.free_ranges()
+    }
+}
+
+impl<A: BitAlloc> Default for PoisonBitAlloc<A> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<A: BitAlloc + fmt::Debug> fmt::Debug for PoisonBitAlloc<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PoisonBitAlloc")
+            .field("inner", &self.inner)
+            .field("poisoned", &self.poisoned_count())
+            .finish()
+    }
+}
+
+impl<A: BitAlloc> BitAlloc for PoisonBitAlloc<A> {
+    const CAP: usize = A::CAP;
+
+    const DEFAULT: Self = PoisonBitAlloc {
+        inner: A::DEFAULT,
+        poisoned: A::DEFAULT,
+    };
+
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    fn alloc(&mut self) -> Option<usize> {
+        self.inner.alloc()
+    }
+    fn alloc_with<P: AllocPolicy>(&mut self) -> Option<usize> {
+        self.inner.alloc_with::<P>()
+    }
+    fn alloc_many(&mut self, out: &mut [usize]) -> usize {
+        self.inner.alloc_many(out)
+    }
+    fn alloc_many_with<P: AllocPolicy>(&mut self, out: &mut [usize]) -> usize {
+        self.inner.alloc_many_with::<P>(out)
+    }
+    fn peek_alloc(&self) -> Option<usize> {
+        self.inner.peek_alloc()
+    }
+    fn peek_with<P: AllocPolicy>(&self) -> Option<usize> {
+        self.inner.peek_with::<P>()
+    }
+    fn peek_full<P: AllocPolicy>() -> usize {
+        A::peek_full::<P>()
+    }
+    fn subtree_cap(depth: usize) -> usize {
+        A::subtree_cap(depth)
+    }
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        self.inner.alloc_contiguous(size, align_log2)
+    }
+    fn find_contiguous(&self, size: usize, align_log2: usize) -> Option<usize> {
+        self.inner.find_contiguous(size, align_log2)
+    }
+    fn alloc_aligned(&mut self, align_log2: usize) -> Option<usize> {
+        self.inner.alloc_aligned(align_log2)
+    }
+    fn next(&self, key: usize) -> Option<usize> {
+        self.inner.next(key)
+    }
+    fn prev(&self, key: usize) -> Option<usize> {
+        self.inner.prev(key)
+    }
+    /// Keeps a poisoned bit allocated.
+    fn dealloc(&mut self, key: usize) {
+        if !self.poisoned.test(key) {
+            self.inner.dealloc(key)
+        }
+    }
+    /// Frees the bits of the block that are not poisoned.
+    fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        debug_assert!(
+            self.next(base).is_none_or(|i| i >= base + size),
+            "freeing a block that is not fully allocated"
+        );
+        self.insert(base..base + size)
+    }
+    /// Frees the bits in the range that are not poisoned.
+    fn insert(&mut self, range: impl RangeBounds<usize>) {
+        let Range { start, end } = to_range(range, Self::CAP);
+        let mut key = start;
+        // the runs of bits not poisoned
+        while let Some(run) = self.poisoned.next_allocated(key).filter(|&i| i < end) {
+            let run_end = self.poisoned.next(run).map_or(end, |i| i.min(end));
+            self.inner.insert(run..run_end);
+            key = run_end;
+        }
+    }
+    fn remove(&mut self, range: impl RangeBounds<usize>) {
+        self.inner.remove(range)
+    }
+    fn any(&self) -> bool {
+        self.inner.any()
+    }
+    fn test(&self, key: usize) -> bool {
+        self.inner.test(key)
+    }
+    fn free_count(&self) -> usize {
+        self.inner.free_count()
+    }
+    fn count_free_in(&self, range: impl RangeBounds<usize>) -> usize {
+        self.inner.count_free_in(range)
+    }
+    fn free_prefix(&self) -> usize {
+        self.inner.free_prefix()
+    }
+    fn free_suffix(&self) -> usize {
+        self.inner.free_suffix()
+    }
+    fn max_contiguous_len(&self) -> usize {
+        self.inner.max_contiguous_len()
+    }
+    fn max_contiguous(&self) -> Option<Range<usize>> {
+        self.inner.max_contiguous()
+    }
+    fn search_contiguous(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+    ) -> Option<usize> {
+        self.inner
+            .search_contiguous(offset, size, align_log2, run_start)
+    }
+    fn search_contiguous_bounded(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+        budget: &mut usize,
+    ) -> Result<Option<usize>, BudgetExhausted> {
+        self.inner
+            .search_contiguous_bounded(offset, size, align_log2, run_start, budget)
+    }
+    fn visit_free_runs(&self, run: &mut usize, f: &mut impl FnMut(usize)) {
+        self.inner.visit_free_runs(run, f)
+    }
+    fn next_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.next_allocated(key)
+    }
+    fn prev_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.prev_allocated(key)
+    }
+    fn raw_word(&self, w: usize) -> usize {
+        self.inner.raw_word(w)
+    }
+    fn next_difference(&self, other: &Self, key: usize) -> Option<usize> {
+        self.inner.next_difference(&other.inner, key)
+    }
+    fn is_subset_of(&self, other: &Self) -> bool {
+        self.inner.is_subset_of(&other.inner)
+    }
+    fn is_disjoint_with(&self, other: &Self) -> bool {
+        self.inner.is_disjoint_with(&other.inner)
+    }
+    /// Keeps poisoned bits allocated.
+    fn write_raw_word(&mut self, w: usize, word: usize) {
+        self.inner
+            .write_raw_word(w, word & !self.poisoned.raw_word(w))
+    }
+    /// The returned allocator keeps the poisoned bits from `at` on.
+    fn split_off(&mut self, at: usize) -> Self {
+        PoisonBitAlloc {
+            inner: self.inner.split_off(at),
+            poisoned: self.poisoned.split_off(at),
+        }
+    }
+    /// Takes back the poisoned bits of `other` too.
+    fn merge(&mut self, other: &Self) {
+        self.poisoned.union_with(&other.poisoned);
+        self.inner.merge(&other.inner)
+    }
+    fn rebuild(&mut self) {
+        self.inner.rebuild()
+    }
+    fn validate(&self) -> Result<(), Inconsistency> {
+        self.inner.validate()
+    }
+    fn iter_free(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.inner.iter_free()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BitAlloc4K;
+
+    #[test]
+    fn poison_survives_insert() {
+        let mut ba = PoisonBitAlloc::<BitAlloc4K>::default();
+        ba.insert(..);
+        let key = ba.alloc().unwrap();
+        ba.poison(100..104);
+        ba.poison(key..key + 1);
+        assert_eq!(ba.free_count(), 4096 - 5);
+        // freeing the poisoned bit in use keeps it allocated
+        ba.dealloc(key);
+        ba.insert(..);
+        assert_eq!(ba.free_count(), 4096 - 5);
+        assert!(ba.poisoned_ranges().eq([100..104, 4095..4096]));
+        let base = ba.alloc_contiguous(200, 0).unwrap();
+        ba.dealloc_contiguous(base, 200);
+        assert!(!ba.test(101) && ba.is_poisoned(101));
+        // nor do raw words or set operations free them
+        let mut all = PoisonBitAlloc::<BitAlloc4K>::default();
+        all.insert(..);
+        ba.union_with(&all);
+        assert_eq!(ba.free_count(), 4096 - 5);
+        ba.unpoison(100..102);
+        assert!(!ba.test(100));
+        ba.insert(..);
+        assert_eq!(ba.free_count(), 4096 - 3);
+        assert_eq!(ba.poisoned_count(), 3);
+    }
+
+    #[test]
+    fn poison_split_off() {
+        let mut ba = PoisonBitAlloc::<BitAlloc4K>::default();
+        ba.insert(..);
+        ba.poison(1000..1010);
+        ba.poison(3000..3010);
+        let mut hi = ba.split_off(2048);
+        hi.insert(2048..);
+        ba.insert(..2048);
+        assert!(!hi.test(3005) && hi.is_poisoned(3005));
+        assert!(!ba.test(1005) && !ba.is_poisoned(3005));
+        assert_eq!(hi.free_count(), 2048 - 10);
+        ba.merge(&hi);
+        assert!(ba.is_poisoned(3005));
+        ba.insert(..);
+        assert_eq!(ba.free_count(), 4096 - 20);
+    }
+}
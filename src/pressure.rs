@@ -0,0 +1,393 @@
+use crate::{AllocPolicy, BitAlloc, BudgetExhausted, Inconsistency};
+use core::ops::{Range, RangeBounds, RangeInclusive};
+
+/// Told by [`PressureBitAlloc`] when the free count crosses one of its
+/// thresholds.
+///
+/// Both methods do nothing by default, e.g. for `()`, which leaves the
+/// `is_low` flags to be polled.
+pub trait PressureHandler: Default {
+    /// The default value. Workaround for `const fn new() -> Self`.
+    const DEFAULT: Self;
+
+    /// The free count dropped below `threshold`, to `free`.
+    fn on_low(&mut self, _threshold: usize, _free: usize) {}
+
+    /// The free count rose back to `threshold` or more, to `free`.
+    fn on_recovered(&mut self, _threshold: usize, _free: usize) {}
+}
+
+impl PressureHandler for () {
+    const DEFAULT: Self = ();
+}
+
+/// A wrapper watching the free count against `N` thresholds, e.g. to wake a
+/// reclaim thread, instead of polling `any` in a loop.
+///
+/// After every change that moves the free count across a threshold, each
+/// way, the handler `H` is told, and the flag of that threshold changes.
+/// Thresholds are checked in the order given, and only the outermost calls
+/// check them. Between `write_raw_word` and `rebuild` the free count is
+/// stale, so only `rebuild` checks.
+#[derive(Debug, Clone)]
+pub struct PressureBitAlloc<A: BitAlloc, const N: usize, H: PressureHandler = ()> {
+    inner: A,
+    thresholds: [usize; N],
+    low: [bool; N],
+    handler: H,
+}
+
+impl<A: BitAlloc, const N: usize, H: PressureHandler> PressureBitAlloc<A, N, H> {
+    /// Wrap an allocator. Thresholds the free count is already below are
+    /// flagged without telling the handler.
+    pub fn new(inner: A, thresholds: [usize; N], handler: H) -> Self {
+        let free = inner.free_count();
+        PressureBitAlloc {
+            inner,
+            thresholds,
+            low: thresholds.map(|threshold| free < threshold),
+            handler,
+        }
+    }
+
+    /// The underlying allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// The handler.
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    /// The handler, mutably.
+    pub fn handler_mut(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    /// Unwrap the underlying allocator and the handler.
+    pub fn into_parts(self) -> (A, H) {
+        (self.inner, self.handler)
+    }
+
+    /// The thresholds, in the order given.
+    pub fn thresholds(&self) -> &[usize; N] {
+        &self.thresholds
+    }
+
+    /// Move threshold `i`, telling the handler if the free count is now on
+    /// the other side of it.
+    pub fn set_threshold(&mut self, i: usize, threshold: usize) {
+        self.thresholds[i] = threshold;
+        self.check();
+    }
+
+    /// Whether the free count is below threshold `i`.
+    pub fn is_low(&self, i: usize) -> bool {
+        self.low[i]
+    }
+
+    /// The number of thresholds the free count is below.
+    pub fn level(&self) -> usize {
+        self.low.iter().filter(|&&low| low).count()
+    }
+
+    /// Compare the free count with every threshold again, and tell the
+    /// handler of those it crossed.
+    fn check(&mut self) {
+        let free = self.inner.free_count();
+        for (&threshold, low) in self.thresholds.iter().zip(&mut self.low) {
+            if *low != (free < threshold) {
+                *low = !*low;
+                if *low {
+                    self.handler.on_low(threshold, free);
+                } else {
+                    self.handler.on_recovered(threshold, free);
+                }
+            }
+        }
+    }
+
+    fn checked<R>(&mut self, res: R) -> R {
+        self.check();
+        res
+    }
+}
+
+impl<A: BitAlloc, const N: usize, H: PressureHandler> Default for PressureBitAlloc<A, N, H> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<A: BitAlloc, const N: usize, H: PressureHandler> BitAlloc for PressureBitAlloc<A, N, H> {
+    const CAP: usize = A::CAP;
+
+    /// With all thresholds at 0, so none is ever crossed until moved.
+    const DEFAULT: Self = PressureBitAlloc {
+        inner: A::DEFAULT,
+        thresholds: [0; N],
+        low: [false; N],
+        handler: H::DEFAULT,
+    };
+
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    fn alloc(&mut self) -> Option<usize> {
+        let res = self.inner.alloc();
+        self.checked(res)
+    }
+    fn alloc_with<P: AllocPolicy>(&mut self) -> Option<usize> {
+        let res = self.inner.alloc_with::<P>();
+        self.checked(res)
+    }
+    fn alloc_many(&mut self, out: &mut [usize]) -> usize {
+        let n = self.inner.alloc_many(out);
+        self.checked(n)
+    }
+    fn alloc_many_with<P: AllocPolicy>(&mut self, out: &mut [usize]) -> usize {
+        let n = self.inner.alloc_many_with::<P>(out);
+        self.checked(n)
+    }
+    fn alloc_from(&mut self, hint: usize) -> Option<usize> {
+        let res = self.inner.alloc_from(hint);
+        self.checked(res)
+    }
+    fn alloc_near(&mut self, key: usize) -> Option<usize> {
+        let res = self.inner.alloc_near(key);
+        self.checked(res)
+    }
+    fn peek_alloc(&self) -> Option<usize> {
+        self.inner.peek_alloc()
+    }
+    fn peek_with<P: AllocPolicy>(&self) -> Option<usize> {
+        self.inner.peek_with::<P>()
+    }
+    fn peek_full<P: AllocPolicy>() -> usize {
+        A::peek_full::<P>()
+    }
+    fn subtree_cap(depth: usize) -> usize {
+        A::subtree_cap(depth)
+    }
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        let res = self.inner.alloc_contiguous(size, align_log2);
+        self.checked(res)
+    }
+    fn alloc_contiguous_at(&mut self, base: usize, size: usize) -> bool {
+        let res = self.inner.alloc_contiguous_at(base, size);
+        self.checked(res)
+    }
+    fn alloc_contiguous_best_fit(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        let res = self.inner.alloc_contiguous_best_fit(size, align_log2);
+        self.checked(res)
+    }
+    fn alloc_contiguous_top(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        let res = self.inner.alloc_contiguous_top(size, align_log2);
+        self.checked(res)
+    }
+    fn alloc_contiguous_guarded_gap(
+        &mut self,
+        size: usize,
+        align_log2: usize,
+        guard: usize,
+    ) -> Option<usize> {
+        let res = self
+            .inner
+            .alloc_contiguous_guarded_gap(size, align_log2, guard);
+        self.checked(res)
+    }
+    fn alloc_contiguous_flex(
+        &mut self,
+        size: RangeInclusive<usize>,
+        align_log2: usize,
+    ) -> Option<Range<usize>> {
+        let res = self.inner.alloc_contiguous_flex(size, align_log2);
+        self.checked(res)
+    }
+    fn alloc_scatter(
+        &mut self,
+        total_size: usize,
+        max_fragments: usize,
+        align_log2: usize,
+        out: &mut [Range<usize>],
+    ) -> Option<usize> {
+        let res = self
+            .inner
+            .alloc_scatter(total_size, max_fragments, align_log2, out);
+        self.checked(res)
+    }
+    fn find_contiguous(&self, size: usize, align_log2: usize) -> Option<usize> {
+        self.inner.find_contiguous(size, align_log2)
+    }
+    fn alloc_aligned(&mut self, align_log2: usize) -> Option<usize> {
+        let res = self.inner.alloc_aligned(align_log2);
+        self.checked(res)
+    }
+    fn next(&self, key: usize) -> Option<usize> {
+        self.inner.next(key)
+    }
+    fn prev(&self, key: usize) -> Option<usize> {
+        self.inner.prev(key)
+    }
+    fn dealloc(&mut self, key: usize) {
+        self.inner.dealloc(key);
+        self.check();
+    }
+    fn dealloc_many(&mut self, keys: impl IntoIterator<Item = usize>) {
+        self.inner.dealloc_many(keys);
+        self.check();
+    }
+    fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        self.inner.dealloc_contiguous(base, size);
+        self.check();
+    }
+    fn insert(&mut self, range: impl RangeBounds<usize>) {
+        self.inner.insert(range);
+        self.check();
+    }
+    fn remove(&mut self, range: impl RangeBounds<usize>) {
+        self.inner.remove(range);
+        self.check();
+    }
+    fn any(&self) -> bool {
+        self.inner.any()
+    }
+    fn test(&self, key: usize) -> bool {
+        self.inner.test(key)
+    }
+    fn free_count(&self) -> usize {
+        self.inner.free_count()
+    }
+    fn count_free_in(&self, range: impl RangeBounds<usize>) -> usize {
+        self.inner.count_free_in(range)
+    }
+    fn free_prefix(&self) -> usize {
+        self.inner.free_prefix()
+    }
+    fn free_suffix(&self) -> usize {
+        self.inner.free_suffix()
+    }
+    fn max_contiguous_len(&self) -> usize {
+        self.inner.max_contiguous_len()
+    }
+    fn max_contiguous(&self) -> Option<Range<usize>> {
+        self.inner.max_contiguous()
+    }
+    fn search_contiguous(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+    ) -> Option<usize> {
+        self.inner
+            .search_contiguous(offset, size, align_log2, run_start)
+    }
+    fn search_contiguous_bounded(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+        budget: &mut usize,
+    ) -> Result<Option<usize>, BudgetExhausted> {
+        self.inner
+            .search_contiguous_bounded(offset, size, align_log2, run_start, budget)
+    }
+    fn visit_free_runs(&self, run: &mut usize, f: &mut impl FnMut(usize)) {
+        self.inner.visit_free_runs(run, f)
+    }
+    fn next_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.next_allocated(key)
+    }
+    fn prev_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.prev_allocated(key)
+    }
+    fn raw_word(&self, w: usize) -> usize {
+        self.inner.raw_word(w)
+    }
+    fn next_difference(&self, other: &Self, key: usize) -> Option<usize> {
+        self.inner.next_difference(&other.inner, key)
+    }
+    fn is_subset_of(&self, other: &Self) -> bool {
+        self.inner.is_subset_of(&other.inner)
+    }
+    fn is_disjoint_with(&self, other: &Self) -> bool {
+        self.inner.is_disjoint_with(&other.inner)
+    }
+    fn write_raw_word(&mut self, w: usize, word: usize) {
+        self.inner.write_raw_word(w, word)
+    }
+    /// The returned allocator has the same thresholds and a default
+    /// handler.
+    fn split_off(&mut self, at: usize) -> Self {
+        let hi = PressureBitAlloc::new(self.inner.split_off(at), self.thresholds, H::DEFAULT);
+        self.check();
+        hi
+    }
+    fn rebuild(&mut self) {
+        self.inner.rebuild();
+        self.check();
+    }
+    fn validate(&self) -> Result<(), Inconsistency> {
+        self.inner.validate()
+    }
+    fn iter_free(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.inner.iter_free()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BitAlloc4K;
+
+    /// Records the last crossing, and how many there were.
+    #[derive(Default)]
+    struct Last {
+        crossing: Option<(bool, usize, usize)>,
+        count: usize,
+    }
+
+    impl PressureHandler for Last {
+        const DEFAULT: Self = Last {
+            crossing: None,
+            count: 0,
+        };
+
+        fn on_low(&mut self, threshold: usize, free: usize) {
+            self.crossing = Some((true, threshold, free));
+            self.count += 1;
+        }
+        fn on_recovered(&mut self, threshold: usize, free: usize) {
+            self.crossing = Some((false, threshold, free));
+            self.count += 1;
+        }
+    }
+
+    #[test]
+    fn pressure_crossings() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(..);
+        let mut ba = PressureBitAlloc::new(ba, [1000, 100], Last::default());
+        assert_eq!(ba.level(), 0);
+        ba.remove(..3000);
+        assert_eq!(ba.handler().crossing, None);
+        let base = ba.alloc_contiguous(97, 0).unwrap();
+        assert_eq!(ba.handler().crossing, Some((true, 1000, 999)));
+        assert!(ba.is_low(0) && !ba.is_low(1));
+        ba.remove(3100..4000);
+        assert_eq!(ba.handler().crossing, Some((true, 100, 99)));
+        assert_eq!(ba.level(), 2);
+        // recovering goes through both again, in the order given
+        ba.dealloc_contiguous(base, 97);
+        assert_eq!(ba.handler().crossing, Some((false, 100, 196)));
+        ba.insert(..);
+        assert_eq!(ba.handler().crossing, Some((false, 1000, 4096)));
+        assert_eq!((ba.level(), ba.handler().count), (0, 4));
+        ba.set_threshold(1, 5000);
+        assert!(ba.is_low(1));
+        assert_eq!(ba.handler().count, 5);
+    }
+}
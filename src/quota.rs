@@ -0,0 +1,170 @@
+use crate::BitAlloc;
+use core::fmt;
+
+/// Why a [`QuotaBitAlloc`] refused an allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaError {
+    /// The allocation would take the tag over its quota.
+    OverQuota,
+    /// There is no room for it in the allocator.
+    NoSpace,
+}
+
+impl fmt::Display for QuotaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            QuotaError::OverQuota => "quota exceeded",
+            QuotaError::NoSpace => "no free bit left",
+        };
+        f.write_str(msg)
+    }
+}
+
+/// A wrapper charging every allocation to one of `T` tags, e.g. a
+/// container, a VM or a subsystem, each with a quota of bits.
+///
+/// An allocation that would take its tag over the quota is refused, and a
+/// tag is only charged once the bits are actually allocated, so the usage
+/// never drifts from the bitmap on error paths. Frees are given the tag to
+/// credit. Tags are indices into `0..T`; others panic.
+#[derive(Debug, Clone)]
+pub struct QuotaBitAlloc<A: BitAlloc, const T: usize> {
+    inner: A,
+    limits: [usize; T],
+    usage: [usize; T],
+}
+
+impl<A: BitAlloc, const T: usize> QuotaBitAlloc<A, T> {
+    /// Wrap an allocator, with the quotas of all tags and nothing charged.
+    pub fn new(inner: A, limits: [usize; T]) -> Self {
+        QuotaBitAlloc {
+            inner,
+            limits,
+            usage: [0; T],
+        }
+    }
+
+    /// The underlying allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// The underlying allocator, to change it without charging any tag,
+    /// e.g. to `insert` free memory or `remove` reserved ranges.
+    pub fn inner_mut(&mut self) -> &mut A {
+        &mut self.inner
+    }
+
+    /// Unwrap the underlying allocator.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    /// The quota of `tag`.
+    pub fn limit(&self, tag: usize) -> usize {
+        self.limits[tag]
+    }
+
+    /// Change the quota of `tag`. Lowering it below the usage frees
+    /// nothing, but refuses its allocations until enough is freed.
+    pub fn set_limit(&mut self, tag: usize, limit: usize) {
+        self.limits[tag] = limit;
+    }
+
+    /// The number of bits charged to `tag`.
+    pub fn usage(&self, tag: usize) -> usize {
+        self.usage[tag]
+    }
+
+    /// The number of bits `tag` may still allocate, room permitting.
+    pub fn remaining(&self, tag: usize) -> usize {
+        self.limits[tag].saturating_sub(self.usage[tag])
+    }
+
+    /// Allocate a free bit charged to `tag`.
+    pub fn alloc(&mut self, tag: usize) -> Result<usize, QuotaError> {
+        self.charge(tag, 1, |ba| ba.alloc())
+    }
+
+    /// Allocate a free block like `alloc_contiguous`, charged to `tag`.
+    pub fn alloc_contiguous(
+        &mut self,
+        tag: usize,
+        size: usize,
+        align_log2: usize,
+    ) -> Result<usize, QuotaError> {
+        self.charge(tag, size, |ba| ba.alloc_contiguous(size, align_log2))
+    }
+
+    /// Free an allocated bit charged to `tag`.
+    pub fn dealloc(&mut self, tag: usize, key: usize) {
+        self.credit(tag, 1);
+        self.inner.dealloc(key)
+    }
+
+    /// Free a block of `size` bits charged to `tag`, the counterpart of
+    /// `alloc_contiguous`.
+    pub fn dealloc_contiguous(&mut self, tag: usize, base: usize, size: usize) {
+        self.credit(tag, size);
+        self.inner.dealloc_contiguous(base, size)
+    }
+
+    fn charge(
+        &mut self,
+        tag: usize,
+        n: usize,
+        alloc: impl FnOnce(&mut A) -> Option<usize>,
+    ) -> Result<usize, QuotaError> {
+        if n > self.remaining(tag) {
+            return Err(QuotaError::OverQuota);
+        }
+        let res = alloc(&mut self.inner).ok_or(QuotaError::NoSpace)?;
+        self.usage[tag] += n;
+        Ok(res)
+    }
+
+    fn credit(&mut self, tag: usize, n: usize) {
+        assert!(
+            n <= self.usage[tag],
+            "tag {} frees more than is charged to it",
+            tag
+        );
+        self.usage[tag] -= n;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BitAlloc256;
+
+    #[test]
+    fn quota_limits() {
+        let mut ba = QuotaBitAlloc::new(BitAlloc256::default(), [10, 300]);
+        assert_eq!(ba.alloc(1), Err(QuotaError::NoSpace));
+        assert_eq!(ba.usage(1), 0);
+        ba.inner_mut().insert(..);
+        let base = ba.alloc_contiguous(0, 8, 3).unwrap();
+        assert_eq!(ba.alloc_contiguous(0, 4, 0), Err(QuotaError::OverQuota));
+        assert!(ba.alloc(0).is_ok() && ba.alloc(0).is_ok());
+        assert_eq!(ba.alloc(0), Err(QuotaError::OverQuota));
+        assert_eq!((ba.usage(0), ba.remaining(0)), (10, 0));
+        // the other tag gets the rest, however large its quota
+        assert!(ba.alloc_contiguous(1, 246, 0).is_ok());
+        assert_eq!(ba.alloc(1), Err(QuotaError::NoSpace));
+        ba.dealloc_contiguous(0, base, 8);
+        assert_eq!(ba.remaining(0), 8);
+        ba.set_limit(0, 1);
+        assert_eq!(ba.alloc(0), Err(QuotaError::OverQuota));
+        assert_eq!(ba.inner().free_count(), 8);
+    }
+
+    #[test]
+    #[should_panic(expected = "frees more than is charged")]
+    fn quota_overfree() {
+        let mut ba = QuotaBitAlloc::new(BitAlloc256::default(), [10, 10]);
+        ba.inner_mut().insert(..);
+        let key = ba.alloc(0).unwrap();
+        ba.dealloc(1, key);
+    }
+}
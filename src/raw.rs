@@ -0,0 +1,156 @@
+use crate::{
+    AllocPolicy, BitAlloc, BitAlloc128, BitAlloc16, BitAlloc32, BitAlloc64, BitAllocArray,
+    BitAllocCascade, BitAllocConst,
+};
+use crate::{CheckHandler, CheckedBitAlloc, NextFit};
+#[cfg(feature = "alloc")]
+use alloc::alloc::{alloc_zeroed, handle_alloc_error, Layout};
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+use core::mem::{align_of, size_of};
+use core::ops::{Deref, DerefMut};
+
+/// Allocators whose empty state is all zero bytes, so that zeroed memory can
+/// be used as one without constructing it first.
+///
+/// # Safety
+///
+/// The all-zero bit pattern must be a valid value equal to `DEFAULT`.
+pub unsafe trait Zeroable: BitAlloc {
+    /// Create an empty allocator directly on the heap, as zeroed memory.
+    /// Unlike `Box::new(Self::default())`, nothing as large as `Self` ever
+    /// goes on the stack, so this is safe for `BitAlloc256M` on a kernel
+    /// stack. Enabled by the `alloc` feature.
+    #[cfg(feature = "alloc")]
+    fn new_boxed() -> Box<Self> {
+        let layout = Layout::new::<Self>();
+        assert_ne!(layout.size(), 0);
+        // SAFETY: the layout is not empty, and zeroed memory is a valid
+        // `Self` as this trait promises.
+        unsafe {
+            let ptr = alloc_zeroed(layout) as *mut Self;
+            if ptr.is_null() {
+                handle_alloc_error(layout);
+            }
+            Box::from_raw(ptr)
+        }
+    }
+}
+
+unsafe impl Zeroable for BitAlloc16 {}
+unsafe impl Zeroable for BitAlloc32 {}
+unsafe impl Zeroable for BitAlloc64 {}
+unsafe impl Zeroable for BitAlloc128 {}
+unsafe impl<T: Zeroable, const N: usize, P: AllocPolicy> Zeroable for BitAllocCascade<T, N, P> {}
+unsafe impl<const BITS: usize, const WORDS: usize> Zeroable for BitAllocArray<BITS, WORDS> {}
+unsafe impl<const BITS: usize, A: Zeroable> Zeroable for BitAllocConst<BITS, A> {}
+unsafe impl<A: Zeroable, H: CheckHandler> Zeroable for CheckedBitAlloc<A, H> {}
+unsafe impl<A: Zeroable> Zeroable for NextFit<A> {}
+
+/// An allocator living in memory supplied by the caller rather than inside
+/// this struct, e.g. a frame map placed in a physical region found at boot
+/// instead of in `.bss`.
+///
+/// It dereferences to the allocator `A` itself.
+pub struct BitAllocRaw<A: Zeroable + 'static> {
+    inner: &'static mut A,
+}
+
+impl<A: Zeroable + 'static> BitAllocRaw<A> {
+    /// The number of `usize` words of storage an `A` takes.
+    pub const fn words_needed() -> usize {
+        size_of::<A>().div_ceil(size_of::<usize>())
+    }
+
+    /// Build an empty allocator in `storage`, which must hold at least
+    /// `words_needed()` words. Only those words are cleared; nothing as
+    /// large as `A` is ever put on the stack.
+    pub fn new(storage: &'static mut [usize]) -> Self {
+        assert!(storage.len() >= Self::words_needed(), "storage too small");
+        assert_eq!(
+            storage.as_ptr() as usize % align_of::<A>(),
+            0,
+            "storage is not aligned for the allocator"
+        );
+        let storage = &mut storage[..Self::words_needed()];
+        storage.fill(0);
+        // SAFETY: the memory is large enough, aligned, zeroed, which is a
+        // valid empty `A` as `Zeroable` promises, and borrowed for 'static.
+        let inner = unsafe { &mut *(storage.as_mut_ptr() as *mut A) };
+        BitAllocRaw { inner }
+    }
+
+    /// Build an empty allocator in `len` words starting at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// The memory must be valid for reads and writes, not used by anything
+    /// else for the rest of the program, and aligned for `usize`.
+    pub unsafe fn from_raw_parts(ptr: *mut usize, len: usize) -> Self {
+        Self::new(core::slice::from_raw_parts_mut(ptr, len))
+    }
+}
+
+impl<A: Zeroable + 'static> Deref for BitAllocRaw<A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        self.inner
+    }
+}
+
+impl<A: Zeroable + 'static> DerefMut for BitAllocRaw<A> {
+    fn deref_mut(&mut self) -> &mut A {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitAlloc1M, BitAlloc4K};
+
+    extern crate std;
+    use std::vec;
+
+    #[test]
+    fn raw() {
+        let storage = vec![usize::MAX; BitAllocRaw::<BitAlloc4K>::words_needed() + 3].leak();
+        let mut ba = BitAllocRaw::<BitAlloc4K>::new(storage);
+        assert!(*ba == BitAlloc4K::default());
+        ba.insert(0..100);
+        assert_eq!(ba.alloc(), Some(99));
+        assert_eq!(ba.alloc_contiguous(10, 4), Some(0));
+        assert_eq!(ba.free_count(), 89);
+    }
+
+    #[test]
+    fn raw_large() {
+        // far larger than the test thread's stack could hold as a temporary
+        let storage = vec![0; BitAllocRaw::<BitAlloc1M>::words_needed()].leak();
+        let mut ba = unsafe {
+            BitAllocRaw::<BitAlloc1M>::from_raw_parts(storage.as_mut_ptr(), storage.len())
+        };
+        ba.insert(..);
+        assert_eq!(ba.free_count(), BitAlloc1M::CAP);
+        assert_eq!(ba.alloc_contiguous(1 << 19, 19), Some(0));
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn new_boxed() {
+        // 32 MiB, which would overflow the stack if built there first
+        let mut ba = crate::BitAlloc256M::new_boxed();
+        assert_eq!(ba.free_count(), 0);
+        ba.insert(1 << 27..(1 << 27) + 100);
+        assert_eq!(ba.alloc(), Some((1 << 27) + 99));
+        assert!(*BitAlloc4K::new_boxed() == BitAlloc4K::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "storage too small")]
+    fn raw_too_small() {
+        let storage = vec![0; 4].leak();
+        BitAllocRaw::<BitAlloc4K>::new(storage);
+    }
+}
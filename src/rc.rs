@@ -0,0 +1,129 @@
+use crate::{BitAlloc, BitAllocError};
+use core::fmt;
+
+/// A wrapper keeping a reference count per bit, for frames shared between
+/// owners, e.g. by copy-on-write: `acquire` takes another reference to an
+/// allocated bit, and `release` drops one, freeing the bit with the last.
+///
+/// `N` is the number of counts and must be `A::CAP`. A count that reaches
+/// `u8::MAX` saturates: it stays there, `release` no longer lowers it, and
+/// the bit is never freed, leaking it rather than freeing it under an owner
+/// whose reference could not be counted.
+#[derive(Clone)]
+pub struct RcBitAlloc<A: BitAlloc, const N: usize> {
+    inner: A,
+    counts: [u8; N],
+}
+
+impl<A: BitAlloc, const N: usize> RcBitAlloc<A, N> {
+    /// Wrap an allocator, with all counts 0.
+    pub fn new(inner: A) -> Self {
+        assert_eq!(N, A::CAP, "one count per bit is needed");
+        RcBitAlloc {
+            inner,
+            counts: [0; N],
+        }
+    }
+
+    /// The underlying allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// The underlying allocator, mutably, e.g. to `insert` free memory.
+    /// Bits allocated through it have a count of 0, and can't be acquired.
+    pub fn inner_mut(&mut self) -> &mut A {
+        &mut self.inner
+    }
+
+    /// The number of references to bit `key`, 0 if it is not allocated
+    /// through this wrapper.
+    pub fn count(&self, key: usize) -> u8 {
+        self.counts[key]
+    }
+
+    /// Whether the count of bit `key` has saturated, so that it will never
+    /// be freed.
+    pub fn is_saturated(&self, key: usize) -> bool {
+        self.counts[key] == u8::MAX
+    }
+
+    /// Allocate a free bit, with a single reference.
+    pub fn alloc(&mut self) -> Option<usize> {
+        let key = self.inner.alloc()?;
+        self.counts[key] = 1;
+        Some(key)
+    }
+
+    /// Allocate a free block like `alloc_contiguous`, with a single
+    /// reference to each of its bits.
+    pub fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        let base = self.inner.alloc_contiguous(size, align_log2)?;
+        self.counts[base..base + size].fill(1);
+        Some(base)
+    }
+
+    /// Take another reference to an allocated bit, and return the new count.
+    pub fn acquire(&mut self, key: usize) -> Result<u8, BitAllocError> {
+        let count = self.counts.get_mut(key).ok_or(BitAllocError::OutOfRange)?;
+        if *count == 0 {
+            return Err(BitAllocError::AlreadyFree);
+        }
+        *count = count.saturating_add(1);
+        Ok(*count)
+    }
+
+    /// Drop a reference to an allocated bit, and return the new count. The
+    /// bit is freed when it drops to 0.
+    pub fn release(&mut self, key: usize) -> Result<u8, BitAllocError> {
+        let count = self.counts.get_mut(key).ok_or(BitAllocError::OutOfRange)?;
+        match *count {
+            0 => return Err(BitAllocError::AlreadyFree),
+            u8::MAX => return Ok(u8::MAX),
+            1 => self.inner.dealloc(key),
+            _ => {}
+        }
+        *count -= 1;
+        Ok(*count)
+    }
+}
+
+impl<A: BitAlloc + fmt::Debug, const N: usize> fmt::Debug for RcBitAlloc<A, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RcBitAlloc")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BitAlloc256;
+
+    #[test]
+    fn rc_shared() {
+        let mut ba = RcBitAlloc::<BitAlloc256, 256>::new(BitAlloc256::default());
+        ba.inner_mut().insert(..);
+        let key = ba.alloc().unwrap();
+        assert_eq!(ba.acquire(key), Ok(2));
+        assert_eq!(ba.release(key), Ok(1));
+        assert!(!ba.inner().test(key));
+        assert_eq!(ba.release(key), Ok(0));
+        assert!(ba.inner().test(key));
+        assert_eq!(ba.release(key), Err(BitAllocError::AlreadyFree));
+        assert_eq!(ba.acquire(key), Err(BitAllocError::AlreadyFree));
+        assert_eq!(ba.acquire(256), Err(BitAllocError::OutOfRange));
+        let base = ba.alloc_contiguous(4, 2).unwrap();
+        assert_eq!((ba.count(base), ba.count(base + 3)), (1, 1));
+        // a saturated count never frees its bit
+        for _ in 0..300 {
+            ba.acquire(base).unwrap();
+        }
+        assert!(ba.is_saturated(base));
+        for _ in 0..300 {
+            assert_eq!(ba.release(base), Ok(u8::MAX));
+        }
+        assert!(!ba.inner().test(base));
+    }
+}
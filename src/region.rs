@@ -0,0 +1,197 @@
+use crate::{align_size, align_up, BitAllocVec};
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
+
+/// A region of a [`RegionPool`], with one bit per frame.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Region {
+    first: usize, // the frame number of bit 0, counted from address 0
+    ba: BitAllocVec,
+}
+
+impl Region {
+    fn frames(&self) -> Range<usize> {
+        self.first..self.first + self.ba.capacity()
+    }
+}
+
+/// Frames of several disjoint address ranges, each with a bitmap of just
+/// its size, behind a single interface by physical address. Enabled by the
+/// `alloc` feature.
+///
+/// Unlike one [`FrameAlloc`](crate::FrameAlloc) over all of memory, the
+/// holes between regions cost no bits, and no contiguous block ever spans
+/// a hole. Regions can be added at any time, e.g. for hot-plugged memory.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct RegionPool {
+    frame_size: usize,
+    regions: Vec<Region>, // sorted by address
+}
+
+impl RegionPool {
+    /// Create a pool without regions. `frame_size` must be a power of two.
+    pub fn new(frame_size: usize) -> Self {
+        assert!(frame_size.is_power_of_two());
+        RegionPool {
+            frame_size,
+            regions: Vec::new(),
+        }
+    }
+
+    /// The size of a frame in bytes.
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    /// Add the whole frames within `len` bytes at `addr` as a new region of
+    /// free frames. Partial frames at the edges are left out.
+    ///
+    /// Returns the address range of an existing region it overlaps, and
+    /// adds nothing then.
+    pub fn add_region(&mut self, addr: usize, len: usize) -> Result<(), Range<usize>> {
+        let first = addr.div_ceil(self.frame_size);
+        let end = addr.saturating_add(len) / self.frame_size;
+        if first >= end {
+            return Ok(());
+        }
+        let i = self.regions.partition_point(|r| r.first < first);
+        let neighbours = self.regions[i.saturating_sub(1)..].iter().take(2);
+        for r in neighbours {
+            let frames = r.frames();
+            if frames.start < end && first < frames.end {
+                return Err(frames.start * self.frame_size..frames.end * self.frame_size);
+            }
+        }
+        let mut ba = BitAllocVec::with_capacity(end - first);
+        ba.insert(..);
+        self.regions.insert(i, Region { first, ba });
+        Ok(())
+    }
+
+    /// The address ranges of the regions, in ascending order.
+    pub fn regions(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        self.regions.iter().map(move |r| {
+            let frames = r.frames();
+            frames.start * self.frame_size..frames.end * self.frame_size
+        })
+    }
+
+    /// The region holding the frame at `addr`, which must be frame aligned.
+    fn locate(&mut self, addr: usize) -> (&mut BitAllocVec, usize) {
+        assert_eq!(addr % self.frame_size, 0, "unaligned frame address");
+        let frame = addr / self.frame_size;
+        let i = self.regions.partition_point(|r| r.first <= frame);
+        match self.regions[..i].last_mut() {
+            Some(r) if frame < r.first + r.ba.capacity() => (&mut r.ba, frame - r.first),
+            _ => panic!("address {:#x} is in no region", addr),
+        }
+    }
+
+    /// Allocate a free frame and return its address, from the highest
+    /// region that has one.
+    pub fn alloc_frame(&mut self) -> Option<usize> {
+        let frame_size = self.frame_size;
+        self.regions
+            .iter_mut()
+            .rev()
+            .find_map(|r| r.ba.alloc().map(|i| (r.first + i) * frame_size))
+    }
+
+    /// Allocate `count` contiguous frames whose first address is aligned to
+    /// `align_bytes`, from the lowest region with room, and return that
+    /// address. `align_bytes` must be a power of two.
+    pub fn alloc_contiguous_frames(&mut self, count: usize, align_bytes: usize) -> Option<usize> {
+        assert!(align_bytes.is_power_of_two());
+        let align_log2 = (align_bytes.max(self.frame_size) / self.frame_size).trailing_zeros();
+        let align_log2 = align_log2 as usize;
+        if count == 0 || align_size(align_log2).is_none() {
+            return None;
+        }
+        let frame_size = self.frame_size;
+        self.regions.iter_mut().find_map(|r| {
+            // first fit over the free runs, aligned by frame number
+            let mut key = 0;
+            while let Some(start) = r.ba.next(key) {
+                let end = r.ba.next_allocated(start).unwrap_or(r.ba.capacity());
+                let base = align_up(r.first + start, align_log2) - r.first;
+                if base.saturating_add(count) <= end {
+                    r.ba.remove(base..base + count);
+                    return Some((r.first + base) * frame_size);
+                }
+                key = end;
+            }
+            None
+        })
+    }
+
+    /// Free an allocated frame by its address.
+    pub fn dealloc_frame(&mut self, addr: usize) {
+        let (ba, i) = self.locate(addr);
+        ba.dealloc(i);
+    }
+
+    /// Free `count` contiguous frames starting at `addr`, as allocated by
+    /// `alloc_contiguous_frames`.
+    pub fn dealloc_contiguous_frames(&mut self, addr: usize, count: usize) {
+        let (ba, i) = self.locate(addr);
+        assert!(i + count <= ba.capacity(), "the frames span a hole");
+        assert_eq!(ba.next(i).filter(|&k| k < i + count), None);
+        ba.insert(i..i + count);
+    }
+
+    /// The number of free frames in all regions.
+    pub fn free_count(&self) -> usize {
+        self.regions.iter().map(|r| r.ba.free_count()).sum()
+    }
+}
+
+impl fmt::Debug for RegionPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RegionPool")
+            .field("frame_size", &self.frame_size)
+            .field("regions", &self.regions.len())
+            .field("free", &self.free_count())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::format;
+
+    #[test]
+    fn region_pool() {
+        let mut pool = RegionPool::new(4096);
+        pool.add_region(0x100000, 0x10000).unwrap();
+        pool.add_region(0x1000 + 1, 0x9e000).unwrap();
+        assert_eq!(pool.add_region(0x10f000, 0x2000), Err(0x100000..0x110000));
+        // no whole frame, so nothing to add
+        pool.add_region(0x10, 0x1000).unwrap();
+        assert_eq!(pool.add_region(0, 0x3000), Err(0x2000..0x9f000));
+        assert!(pool.regions().eq([0x2000..0x9f000, 0x100000..0x110000]));
+        assert_eq!(pool.free_count(), 0x9d + 0x10);
+        assert_eq!(pool.alloc_frame(), Some(0x10f000));
+        // aligned by address, so not at the start of the first region
+        assert_eq!(pool.alloc_contiguous_frames(4, 0x10000), Some(0x10000));
+        // no block spans the hole
+        assert_eq!(pool.alloc_contiguous_frames(0x9e, 0x1000), None);
+        assert_eq!(pool.alloc_contiguous_frames(0x8b, 0x1000), Some(0x14000));
+        pool.dealloc_frame(0x10f000);
+        pool.dealloc_contiguous_frames(0x10000, 4);
+        assert_eq!(pool.free_count(), 0x9d + 0x10 - 0x8b);
+        assert_eq!(
+            format(format_args!("{:?}", pool)).as_str(),
+            "RegionPool { frame_size: 4096, regions: 2, free: 34 }"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "in no region")]
+    fn region_pool_hole() {
+        let mut pool = RegionPool::new(4096);
+        pool.add_region(0x100000, 0x10000).unwrap();
+        pool.dealloc_frame(0x200000);
+    }
+}
@@ -0,0 +1,393 @@
+use crate::{AllocPolicy, BitAlloc, BudgetExhausted, Inconsistency};
+use core::ops::{Range, RangeBounds, RangeInclusive};
+
+/// A wrapper keeping a reserve of free bits for privileged callers, like
+/// the min-free pages of a kernel, so that the page-out path can still get
+/// frames under memory pressure.
+///
+/// `alloc` and the other allocations fail once they would take the free
+/// count below the reserve; `alloc_reserved` and `alloc_contiguous_reserved`
+/// may dip into it. The reserve is a number of bits, not a range: any free
+/// bit serves. Queries like `free_count` and `any` still tell the state of
+/// the bitmap, reserve included; `available` tells what normal allocations
+/// can get.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ReserveBitAlloc<A: BitAlloc> {
+    inner: A,
+    reserve: usize,
+}
+
+impl<A: BitAlloc> ReserveBitAlloc<A> {
+    /// Wrap an allocator, keeping `reserve` free bits back.
+    pub fn new(inner: A, reserve: usize) -> Self {
+        ReserveBitAlloc { inner, reserve }
+    }
+
+    /// The underlying allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Unwrap the underlying allocator.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    /// The number of free bits kept back.
+    pub fn reserve(&self) -> usize {
+        self.reserve
+    }
+
+    /// Change the number of free bits kept back. Bits already allocated
+    /// stay so, even if the free count is now below the reserve.
+    pub fn set_reserve(&mut self, reserve: usize) {
+        self.reserve = reserve;
+    }
+
+    /// The number of free bits normal allocations can get.
+    pub fn available(&self) -> usize {
+        self.inner.free_count().saturating_sub(self.reserve)
+    }
+
+    /// Allocate a free bit, dipping into the reserve if need be.
+    pub fn alloc_reserved(&mut self) -> Option<usize> {
+        self.inner.alloc()
+    }
+
+    /// Allocate a free block like `alloc_contiguous`, dipping into the
+    /// reserve if need be.
+    pub fn alloc_contiguous_reserved(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        self.inner.alloc_contiguous(size, align_log2)
+    }
+
+    /// Whether `n` bits can be allocated outside the reserve.
+    fn fits(&self, n: usize) -> bool {
+        n <= self.available()
+    }
+}
+
+impl<A: BitAlloc> Default for ReserveBitAlloc<A> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<A: BitAlloc> BitAlloc for ReserveBitAlloc<A> {
+    const CAP: usize = A::CAP;
+
+    const DEFAULT: Self = ReserveBitAlloc {
+        inner: A::DEFAULT,
+        reserve: 0,
+    };
+
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    fn alloc(&mut self) -> Option<usize> {
+        if !self.fits(1) {
+            return None;
+        }
+        self.inner.alloc()
+    }
+    fn alloc_with<P: AllocPolicy>(&mut self) -> Option<usize> {
+        if !self.fits(1) {
+            return None;
+        }
+        self.inner.alloc_with::<P>()
+    }
+    /// Allocates only as many bits as are available outside the reserve.
+    fn alloc_many(&mut self, out: &mut [usize]) -> usize {
+        let n = out.len().min(self.available());
+        self.inner.alloc_many(&mut out[..n])
+    }
+    fn alloc_many_with<P: AllocPolicy>(&mut self, out: &mut [usize]) -> usize {
+        let n = out.len().min(self.available());
+        self.inner.alloc_many_with::<P>(&mut out[..n])
+    }
+    fn alloc_from(&mut self, hint: usize) -> Option<usize> {
+        if !self.fits(1) {
+            return None;
+        }
+        self.inner.alloc_from(hint)
+    }
+    fn alloc_near(&mut self, key: usize) -> Option<usize> {
+        if !self.fits(1) {
+            return None;
+        }
+        self.inner.alloc_near(key)
+    }
+    fn alloc_random(&mut self, rng: &mut impl FnMut() -> u64) -> Option<usize> {
+        if !self.fits(1) {
+            return None;
+        }
+        self.inner.alloc_random(rng)
+    }
+    fn peek_alloc(&self) -> Option<usize> {
+        if !self.fits(1) {
+            return None;
+        }
+        self.inner.peek_alloc()
+    }
+    fn peek_with<P: AllocPolicy>(&self) -> Option<usize> {
+        if !self.fits(1) {
+            return None;
+        }
+        self.inner.peek_with::<P>()
+    }
+    fn peek_full<P: AllocPolicy>() -> usize {
+        A::peek_full::<P>()
+    }
+    fn subtree_cap(depth: usize) -> usize {
+        A::subtree_cap(depth)
+    }
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        if !self.fits(size) {
+            return None;
+        }
+        self.inner.alloc_contiguous(size, align_log2)
+    }
+    fn alloc_contiguous_random(
+        &mut self,
+        size: usize,
+        align_log2: usize,
+        rng: &mut impl FnMut() -> u64,
+    ) -> Option<usize> {
+        if !self.fits(size) {
+            return None;
+        }
+        self.inner.alloc_contiguous_random(size, align_log2, rng)
+    }
+    fn alloc_contiguous_offset(
+        &mut self,
+        size: usize,
+        align_log2: usize,
+        phase: usize,
+    ) -> Option<usize> {
+        if !self.fits(size) {
+            return None;
+        }
+        self.inner.alloc_contiguous_offset(size, align_log2, phase)
+    }
+    fn alloc_contiguous_bounded(
+        &mut self,
+        size: usize,
+        align_log2: usize,
+        max_steps: usize,
+    ) -> Result<Option<usize>, BudgetExhausted> {
+        if !self.fits(size) {
+            return Ok(None);
+        }
+        self.inner
+            .alloc_contiguous_bounded(size, align_log2, max_steps)
+    }
+    fn alloc_contiguous_at(&mut self, base: usize, size: usize) -> bool {
+        self.fits(size) && self.inner.alloc_contiguous_at(base, size)
+    }
+    fn alloc_contiguous_best_fit(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        if !self.fits(size) {
+            return None;
+        }
+        self.inner.alloc_contiguous_best_fit(size, align_log2)
+    }
+    fn alloc_contiguous_top(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        if !self.fits(size) {
+            return None;
+        }
+        self.inner.alloc_contiguous_top(size, align_log2)
+    }
+    fn alloc_contiguous_guarded_gap(
+        &mut self,
+        size: usize,
+        align_log2: usize,
+        guard: usize,
+    ) -> Option<usize> {
+        if !self.fits(size.saturating_add(guard.saturating_mul(2))) {
+            return None;
+        }
+        self.inner
+            .alloc_contiguous_guarded_gap(size, align_log2, guard)
+    }
+    fn alloc_contiguous_flex(
+        &mut self,
+        size: RangeInclusive<usize>,
+        align_log2: usize,
+    ) -> Option<Range<usize>> {
+        let max = (*size.end()).min(self.available());
+        self.inner
+            .alloc_contiguous_flex(*size.start()..=max, align_log2)
+    }
+    fn alloc_scatter(
+        &mut self,
+        total_size: usize,
+        max_fragments: usize,
+        align_log2: usize,
+        out: &mut [Range<usize>],
+    ) -> Option<usize> {
+        if !self.fits(total_size) {
+            return None;
+        }
+        self.inner
+            .alloc_scatter(total_size, max_fragments, align_log2, out)
+    }
+    fn find_contiguous(&self, size: usize, align_log2: usize) -> Option<usize> {
+        self.inner.find_contiguous(size, align_log2)
+    }
+    fn alloc_aligned(&mut self, align_log2: usize) -> Option<usize> {
+        if !self.fits(1) {
+            return None;
+        }
+        self.inner.alloc_aligned(align_log2)
+    }
+    fn next(&self, key: usize) -> Option<usize> {
+        self.inner.next(key)
+    }
+    fn prev(&self, key: usize) -> Option<usize> {
+        self.inner.prev(key)
+    }
+    fn dealloc(&mut self, key: usize) {
+        self.inner.dealloc(key)
+    }
+    fn dealloc_many(&mut self, keys: impl IntoIterator<Item = usize>) {
+        self.inner.dealloc_many(keys)
+    }
+    fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        self.inner.dealloc_contiguous(base, size)
+    }
+    fn insert(&mut self, range: impl RangeBounds<usize>) {
+        self.inner.insert(range)
+    }
+    fn remove(&mut self, range: impl RangeBounds<usize>) {
+        self.inner.remove(range)
+    }
+    fn any(&self) -> bool {
+        self.inner.any()
+    }
+    fn test(&self, key: usize) -> bool {
+        self.inner.test(key)
+    }
+    fn free_count(&self) -> usize {
+        self.inner.free_count()
+    }
+    fn count_free_in(&self, range: impl RangeBounds<usize>) -> usize {
+        self.inner.count_free_in(range)
+    }
+    fn free_prefix(&self) -> usize {
+        self.inner.free_prefix()
+    }
+    fn free_suffix(&self) -> usize {
+        self.inner.free_suffix()
+    }
+    fn max_contiguous_len(&self) -> usize {
+        self.inner.max_contiguous_len()
+    }
+    fn max_contiguous(&self) -> Option<Range<usize>> {
+        self.inner.max_contiguous()
+    }
+    fn search_contiguous(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+    ) -> Option<usize> {
+        self.inner
+            .search_contiguous(offset, size, align_log2, run_start)
+    }
+    fn search_contiguous_bounded(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+        budget: &mut usize,
+    ) -> Result<Option<usize>, BudgetExhausted> {
+        self.inner
+            .search_contiguous_bounded(offset, size, align_log2, run_start, budget)
+    }
+    fn visit_free_runs(&self, run: &mut usize, f: &mut impl FnMut(usize)) {
+        self.inner.visit_free_runs(run, f)
+    }
+    fn next_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.next_allocated(key)
+    }
+    fn prev_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.prev_allocated(key)
+    }
+    fn raw_word(&self, w: usize) -> usize {
+        self.inner.raw_word(w)
+    }
+    fn next_difference(&self, other: &Self, key: usize) -> Option<usize> {
+        self.inner.next_difference(&other.inner, key)
+    }
+    fn is_subset_of(&self, other: &Self) -> bool {
+        self.inner.is_subset_of(&other.inner)
+    }
+    fn is_disjoint_with(&self, other: &Self) -> bool {
+        self.inner.is_disjoint_with(&other.inner)
+    }
+    fn write_raw_word(&mut self, w: usize, word: usize) {
+        self.inner.write_raw_word(w, word)
+    }
+    fn rebuild(&mut self) {
+        self.inner.rebuild()
+    }
+    fn validate(&self) -> Result<(), Inconsistency> {
+        self.inner.validate()
+    }
+    fn iter_free(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.inner.iter_free()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BitAlloc256;
+
+    #[test]
+    fn reserve_kept_back() {
+        let mut ba = BitAlloc256::default();
+        ba.insert(..);
+        let mut ba = ReserveBitAlloc::new(ba, 16);
+        let mut out = [0; 256];
+        assert_eq!(ba.alloc_many(&mut out), 240);
+        assert_eq!((ba.available(), ba.free_count()), (0, 16));
+        assert_eq!((ba.peek_alloc(), ba.alloc()), (None, None));
+        assert_eq!(ba.alloc_contiguous(1, 0), None);
+        assert_eq!(ba.alloc_contiguous_offset(1, 0, 3), None);
+        assert_eq!(ba.alloc_contiguous_bounded(1, 0, 100), Ok(None));
+        assert!(!ba.alloc_contiguous_at(ba.next(0).unwrap(), 1));
+        // the privileged path dips into it
+        assert!(ba.alloc_reserved().is_some());
+        assert!(ba.alloc_contiguous_reserved(4, 2).is_some());
+        assert_eq!(ba.free_count(), 11);
+        ba.dealloc_many(out[..5].iter().cloned());
+        assert_eq!(ba.alloc(), None);
+        ba.dealloc_many(out[5..7].iter().cloned());
+        assert_eq!(ba.available(), 2);
+        let key = ba.peek_alloc();
+        assert!(key.is_some() && ba.alloc() == key);
+        ba.set_reserve(0);
+        assert_eq!(ba.alloc_many(&mut out), 17);
+        assert!(!ba.any());
+    }
+
+    #[test]
+    fn reserve_random() {
+        let mut ba = BitAlloc256::default();
+        ba.insert(..);
+        let mut ba = ReserveBitAlloc::new(ba, 256);
+        let mut seed = 1u64;
+        let mut rng = || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            seed
+        };
+        assert_eq!(ba.alloc_random(&mut rng), None);
+        assert_eq!(ba.alloc_contiguous_random(4, 2, &mut rng), None);
+        ba.set_reserve(251);
+        assert!(ba.alloc_contiguous_random(4, 2, &mut rng).is_some());
+        assert!(ba.alloc_random(&mut rng).is_some());
+        assert_eq!(ba.alloc_random(&mut rng), None);
+        assert_eq!(ba.free_count(), 251);
+    }
+}
@@ -0,0 +1,293 @@
+use crate::{to_range, AllocPolicy, BitAlloc, BudgetExhausted, Inconsistency};
+use core::fmt;
+use core::ops::{Range, RangeBounds};
+
+/// A wrapper holding freed bits back until they are scrubbed, e.g. frames
+/// that must be zeroed before they are handed out again.
+///
+/// `dealloc` and `dealloc_contiguous` make bits dirty rather than free:
+/// still allocated, and queued. A background task takes them from the queue
+/// with `take_scrub_batch`, scrubs them and hands them back with
+/// `finish_scrub`, which frees them. `insert` frees bits as clean right
+/// away, e.g. memory known to be zero at boot.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct ScrubBitAlloc<A: BitAlloc> {
+    inner: A,
+    dirty: A, // free bits here are the ones waiting for a scrub
+}
+
+impl<A: BitAlloc> ScrubBitAlloc<A> {
+    /// Wrap an allocator, its free bits clean.
+    pub fn new(inner: A) -> Self {
+        ScrubBitAlloc {
+            inner,
+            dirty: A::DEFAULT,
+        }
+    }
+
+    /// The underlying allocator, where dirty bits are allocated.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Unwrap the underlying allocator, where dirty bits are allocated.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    /// Whether a specific bit is waiting for a scrub.
+    pub fn is_dirty(&self, key: usize) -> bool {
+        self.dirty.test(key)
+    }
+
+    /// The number of bits waiting for a scrub.
+    pub fn dirty_count(&self) -> usize {
+        self.dirty.free_count()
+    }
+
+    /// Take up to `max` dirty bits off the queue, the lowest run of them,
+    /// to be scrubbed. They stay allocated until `finish_scrub`.
+    pub fn take_scrub_batch(&mut self, max: usize) -> Option<Range<usize>> {
+        let start = self.dirty.next(0).filter(|_| max > 0)?;
+        let end = self.dirty.next_allocated(start).unwrap_or(A::CAP);
+        let batch = start..end.min(start + max);
+        self.dirty.remove(batch.clone());
+        Some(batch)
+    }
+
+    /// Free the bits of a batch from `take_scrub_batch` once scrubbed.
+    pub fn finish_scrub(&mut self, batch: Range<usize>) {
+        debug_assert!(
+            self.inner.next(batch.start).is_none_or(|i| i >= batch.end),
+            "finishing a batch that is already free"
+        );
+        self.inner.insert(batch)
+    }
+}
+
+impl<A: BitAlloc> Default for ScrubBitAlloc<A> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<A: BitAlloc + fmt::Debug> fmt::Debug for ScrubBitAlloc<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ScrubBitAlloc")
+            .field("inner", &self.inner)
+            .field("dirty", &self.dirty_count())
+            .finish()
+    }
+}
+
+impl<A: BitAlloc> BitAlloc for ScrubBitAlloc<A> {
+    const CAP: usize = A::CAP;
+
+    const DEFAULT: Self = ScrubBitAlloc {
+        inner: A::DEFAULT,
+        dirty: A::DEFAULT,
+    };
+
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    fn alloc(&mut self) -> Option<usize> {
+        self.inner.alloc()
+    }
+    fn alloc_with<P: AllocPolicy>(&mut self) -> Option<usize> {
+        self.inner.alloc_with::<P>()
+    }
+    fn alloc_many(&mut self, out: &mut [usize]) -> usize {
+        self.inner.alloc_many(out)
+    }
+    fn alloc_many_with<P: AllocPolicy>(&mut self, out: &mut [usize]) -> usize {
+        self.inner.alloc_many_with::<P>(out)
+    }
+    fn peek_alloc(&self) -> Option<usize> {
+        self.inner.peek_alloc()
+    }
+    fn peek_with<P: AllocPolicy>(&self) -> Option<usize> {
+        self.inner.peek_with::<P>()
+    }
+    fn peek_full<P: AllocPolicy>() -> usize {
+        A::peek_full::<P>()
+    }
+    fn subtree_cap(depth: usize) -> usize {
+        A::subtree_cap(depth)
+    }
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        self.inner.alloc_contiguous(size, align_log2)
+    }
+    fn find_contiguous(&self, size: usize, align_log2: usize) -> Option<usize> {
+        self.inner.find_contiguous(size, align_log2)
+    }
+    fn alloc_aligned(&mut self, align_log2: usize) -> Option<usize> {
+        self.inner.alloc_aligned(align_log2)
+    }
+    fn next(&self, key: usize) -> Option<usize> {
+        self.inner.next(key)
+    }
+    fn prev(&self, key: usize) -> Option<usize> {
+        self.inner.prev(key)
+    }
+    /// Queues the bit for scrubbing.
+    fn dealloc(&mut self, key: usize) {
+        assert!(!self.inner.test(key), "bit {} is not allocated", key);
+        self.dirty.insert(key..key + 1)
+    }
+    /// Queues the block for scrubbing.
+    fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        debug_assert!(
+            self.next(base).is_none_or(|i| i >= base + size),
+            "freeing a block that is not fully allocated"
+        );
+        self.dirty.insert(base..base + size)
+    }
+    /// Frees the bits as clean, e.g. memory known to be zero at boot.
+    fn insert(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, Self::CAP);
+        self.dirty.remove(range.clone());
+        self.inner.insert(range)
+    }
+    fn remove(&mut self, range: impl RangeBounds<usize>) {
+        self.inner.remove(range)
+    }
+    fn any(&self) -> bool {
+        self.inner.any()
+    }
+    fn test(&self, key: usize) -> bool {
+        self.inner.test(key)
+    }
+    fn free_count(&self) -> usize {
+        self.inner.free_count()
+    }
+    fn count_free_in(&self, range: impl RangeBounds<usize>) -> usize {
+        self.inner.count_free_in(range)
+    }
+    fn free_prefix(&self) -> usize {
+        self.inner.free_prefix()
+    }
+    fn free_suffix(&self) -> usize {
+        self.inner.free_suffix()
+    }
+    fn max_contiguous_len(&self) -> usize {
+        self.inner.max_contiguous_len()
+    }
+    fn max_contiguous(&self) -> Option<Range<usize>> {
+        self.inner.max_contiguous()
+    }
+    fn search_contiguous(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+    ) -> Option<usize> {
+        self.inner
+            .search_contiguous(offset, size, align_log2, run_start)
+    }
+    fn search_contiguous_bounded(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+        budget: &mut usize,
+    ) -> Result<Option<usize>, BudgetExhausted> {
+        self.inner
+            .search_contiguous_bounded(offset, size, align_log2, run_start, budget)
+    }
+    fn visit_free_runs(&self, run: &mut usize, f: &mut impl FnMut(usize)) {
+        self.inner.visit_free_runs(run, f)
+    }
+    fn next_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.next_allocated(key)
+    }
+    fn prev_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.prev_allocated(key)
+    }
+    fn raw_word(&self, w: usize) -> usize {
+        self.inner.raw_word(w)
+    }
+    fn next_difference(&self, other: &Self, key: usize) -> Option<usize> {
+        self.inner.next_difference(&other.inner, key)
+    }
+    fn is_subset_of(&self, other: &Self) -> bool {
+        self.inner.is_subset_of(&other.inner)
+    }
+    fn is_disjoint_with(&self, other: &Self) -> bool {
+        self.inner.is_disjoint_with(&other.inner)
+    }
+    /// Keeps dirty bits allocated.
+    fn write_raw_word(&mut self, w: usize, word: usize) {
+        self.inner.write_raw_word(w, word & !self.dirty.raw_word(w))
+    }
+    /// The returned allocator keeps the dirty bits from `at` on.
+    fn split_off(&mut self, at: usize) -> Self {
+        ScrubBitAlloc {
+            inner: self.inner.split_off(at),
+            dirty: self.dirty.split_off(at),
+        }
+    }
+    /// Takes back the dirty bits of `other` too.
+    fn merge(&mut self, other: &Self) {
+        self.dirty.union_with(&other.dirty);
+        self.inner.merge(&other.inner)
+    }
+    fn rebuild(&mut self) {
+        self.inner.rebuild()
+    }
+    fn validate(&self) -> Result<(), Inconsistency> {
+        self.inner.validate()
+    }
+    fn iter_free(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.inner.iter_free()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BitAlloc4K;
+
+    #[test]
+    fn scrub_before_reuse() {
+        let mut ba = ScrubBitAlloc::<BitAlloc4K>::default();
+        ba.insert(0..100);
+        let key = ba.alloc().unwrap();
+        let base = ba.alloc_contiguous(10, 0).unwrap();
+        ba.dealloc(key);
+        ba.dealloc_contiguous(base, 10);
+        assert_eq!((ba.free_count(), ba.dirty_count()), (89, 11));
+        // dirty bits are not handed out
+        assert!(ba.is_dirty(key) && !ba.test(key));
+        assert_eq!(ba.alloc_contiguous(90, 0), None);
+        assert_eq!(ba.take_scrub_batch(4), Some(0..4));
+        assert_eq!(ba.take_scrub_batch(100), Some(4..10));
+        assert_eq!(ba.take_scrub_batch(100), Some(99..100));
+        assert_eq!(ba.take_scrub_batch(100), None);
+        assert_eq!((ba.free_count(), ba.dirty_count()), (89, 0));
+        ba.finish_scrub(0..4);
+        ba.finish_scrub(4..10);
+        ba.finish_scrub(99..100);
+        assert_eq!(ba.free_count(), 100);
+        assert_eq!(ba.alloc_contiguous(100, 0), Some(0));
+    }
+
+    #[test]
+    fn scrub_split_off() {
+        let mut ba = ScrubBitAlloc::<BitAlloc4K>::default();
+        ba.remove(..);
+        ba.dealloc_contiguous(1000, 10);
+        ba.dealloc_contiguous(3000, 10);
+        let mut hi = ba.split_off(2048);
+        assert_eq!((ba.dirty_count(), hi.dirty_count()), (10, 10));
+        assert_eq!(hi.take_scrub_batch(100), Some(3000..3010));
+        hi.finish_scrub(3000..3010);
+        hi.remove(3005..3006);
+        hi.dealloc(3005);
+        ba.merge(&hi);
+        assert_eq!((ba.free_count(), ba.dirty_count()), (9, 11));
+        assert!(ba.is_dirty(3005) && !ba.test(3005));
+    }
+}
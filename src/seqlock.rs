@@ -0,0 +1,231 @@
+use crate::{
+    AllocPolicy, BitAlloc, BitAlloc128, BitAlloc16, BitAlloc32, BitAlloc64, BitAllocArray,
+    BitAllocCascade, BitAllocConst,
+};
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::hint::spin_loop;
+use core::mem::MaybeUninit;
+use core::ptr;
+#[cfg(not(feature = "portable-atomic"))]
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+#[cfg(feature = "portable-atomic")]
+use portable_atomic::{fence, AtomicUsize, Ordering};
+
+/// An allocator that is plain data, so that a copy of its bytes is an
+/// allocator of its own, for the copies `SeqLockBitAlloc` readers take.
+/// Cascades are not `Copy`, so as not to be duplicated by accident, but
+/// they are plain data all the same.
+///
+/// # Safety
+///
+/// A bitwise copy of a value must be a valid value sharing nothing with the
+/// original, and the type must not implement `Drop`.
+pub unsafe trait PlainBitAlloc: BitAlloc {}
+
+// SAFETY: a `u16`.
+unsafe impl PlainBitAlloc for BitAlloc16 {}
+// SAFETY: a word.
+unsafe impl PlainBitAlloc for BitAlloc32 {}
+// SAFETY: a word.
+unsafe impl PlainBitAlloc for BitAlloc64 {}
+// SAFETY: a word.
+unsafe impl PlainBitAlloc for BitAlloc128 {}
+// SAFETY: words and counts.
+unsafe impl<const BITS: usize, const WORDS: usize> PlainBitAlloc for BitAllocArray<BITS, WORDS> {}
+// SAFETY: summaries and plain children.
+unsafe impl<T: PlainBitAlloc, const N: usize, P: AllocPolicy> PlainBitAlloc
+    for BitAllocCascade<T, N, P>
+{
+}
+// SAFETY: a plain allocator.
+unsafe impl<const BITS: usize, A: PlainBitAlloc> PlainBitAlloc for BitAllocConst<BITS, A> {}
+
+/// An allocator behind a sequence lock: a single writer at a time changes
+/// it, and readers take consistent snapshots without locking, retrying if
+/// a write got in the way, e.g. for statistics and debuggers that should
+/// not contend with the allocation path.
+///
+/// The sequence number is odd while a write is in progress. Writers take
+/// turns on it, spinning like a spin lock. A reader copies the allocator
+/// and keeps the copy only if the number was even and unchanged around it,
+/// which is why `A` must be a [`PlainBitAlloc`], with nothing to drop or
+/// point to. Reads cost a copy of `A` on the reader's stack, so they suit
+/// small and medium maps; `free_count` is kept on the side and is cheap at
+/// any size.
+pub struct SeqLockBitAlloc<A: PlainBitAlloc> {
+    seq: AtomicUsize,
+    free: AtomicUsize,
+    inner: UnsafeCell<A>,
+}
+
+// SAFETY: writers exclude each other through `seq`, and readers only keep
+// copies taken while no writer ran.
+unsafe impl<A: PlainBitAlloc + Send> Sync for SeqLockBitAlloc<A> {}
+
+impl<A: PlainBitAlloc> SeqLockBitAlloc<A> {
+    /// An allocator with every bit allocated, e.g. for a `static`.
+    pub const fn empty() -> Self {
+        SeqLockBitAlloc {
+            seq: AtomicUsize::new(0),
+            free: AtomicUsize::new(0),
+            inner: UnsafeCell::new(A::DEFAULT),
+        }
+    }
+
+    /// Wrap an allocator.
+    pub fn new(inner: A) -> Self {
+        SeqLockBitAlloc {
+            seq: AtomicUsize::new(0),
+            free: AtomicUsize::new(inner.free_count()),
+            inner: UnsafeCell::new(inner),
+        }
+    }
+
+    /// Change the allocator, waiting for any other writer to finish.
+    /// Readers retry until the write is over.
+    pub fn write<R>(&self, f: impl FnOnce(&mut A) -> R) -> R {
+        let mut seq = self.seq.load(Ordering::Relaxed);
+        loop {
+            if seq & 1 == 0 {
+                match self.seq.compare_exchange_weak(
+                    seq,
+                    seq + 1,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(now) => seq = now,
+                }
+            } else {
+                spin_loop();
+                seq = self.seq.load(Ordering::Relaxed);
+            }
+        }
+        // readers must see the odd number before any change
+        fence(Ordering::Release);
+        let guard = WriteGuard { ba: self, seq };
+        // SAFETY: the odd sequence number keeps other writers out, and
+        // readers do not keep what they read meanwhile.
+        f(unsafe { &mut *guard.ba.inner.get() })
+    }
+
+    /// A consistent copy of the allocator, retrying while writes race with it.
+    pub fn snapshot(&self) -> A {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 == 0 {
+                // SAFETY: the copy may be torn by a racing write, as
+                // `MaybeUninit` it is never used then.
+                let copy = unsafe { ptr::read_volatile(self.inner.get() as *const MaybeUninit<A>) };
+                fence(Ordering::Acquire);
+                if self.seq.load(Ordering::Relaxed) == seq {
+                    // SAFETY: no write ran during the copy, and `A` is
+                    // plain data so the copy owns nothing of the original.
+                    return unsafe { copy.assume_init() };
+                }
+            }
+            spin_loop();
+        }
+    }
+
+    /// Run a query on a consistent copy of the allocator.
+    pub fn read<R>(&self, f: impl FnOnce(&A) -> R) -> R {
+        f(&self.snapshot())
+    }
+
+    /// The number of free bits as of the last write, without copying.
+    pub fn free_count(&self) -> usize {
+        self.free.load(Ordering::Relaxed)
+    }
+
+    /// Whether a specific bit is free.
+    pub fn test(&self, key: usize) -> bool {
+        self.read(|ba| ba.test(key))
+    }
+
+    /// Find a index not less than a given key, where the bit is free.
+    pub fn next(&self, key: usize) -> Option<usize> {
+        self.read(|ba| ba.next(key))
+    }
+
+    /// The underlying allocator, without the lock as the borrow is exclusive.
+    pub fn get_mut(&mut self) -> &mut A {
+        self.inner.get_mut()
+    }
+
+    /// Unwrap the underlying allocator.
+    pub fn into_inner(self) -> A {
+        self.inner.into_inner()
+    }
+}
+
+/// Ends a write, also when it panics, so that readers do not spin forever.
+struct WriteGuard<'a, A: PlainBitAlloc> {
+    ba: &'a SeqLockBitAlloc<A>,
+    seq: usize,
+}
+
+impl<A: PlainBitAlloc> Drop for WriteGuard<'_, A> {
+    fn drop(&mut self) {
+        // SAFETY: the write still holds the odd sequence number.
+        let free = unsafe { (*self.ba.inner.get()).free_count() };
+        self.ba.free.store(free, Ordering::Relaxed);
+        self.ba.seq.store(self.seq + 2, Ordering::Release);
+    }
+}
+
+impl<A: PlainBitAlloc> Default for SeqLockBitAlloc<A> {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl<A: PlainBitAlloc> fmt::Debug for SeqLockBitAlloc<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SeqLockBitAlloc")
+            .field("seq", &self.seq.load(Ordering::Relaxed))
+            .field("free", &self.free_count())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BitAlloc4K;
+
+    extern crate std;
+    use std::thread;
+
+    #[test]
+    fn seqlock_readers() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(..);
+        let ba = SeqLockBitAlloc::new(ba);
+        thread::scope(|s| {
+            s.spawn(|| {
+                // bits are only ever taken and freed in pairs
+                for _ in 0..2048 {
+                    let base = ba.write(|ba| ba.alloc_contiguous(2, 1)).unwrap();
+                    ba.write(|ba| ba.dealloc_contiguous(base, 2));
+                    ba.write(|ba| ba.alloc_contiguous(2, 1));
+                }
+            });
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..200 {
+                        let snapshot = ba.snapshot();
+                        let free = snapshot.free_count();
+                        assert_eq!(free % 2, 0);
+                        assert_eq!(snapshot.iter_free().count(), free);
+                        assert!(ba.free_count() % 2 == 0);
+                    }
+                });
+            }
+        });
+        assert_eq!(ba.free_count(), 0);
+        assert_eq!(ba.next(0), None);
+        assert!(!ba.test(4095));
+    }
+}
@@ -0,0 +1,185 @@
+//! `Serialize` and `Deserialize`, behind the `serde` feature.
+//!
+//! An allocator is stored as the sequence of its `CAP / 16` leaf words, bit
+//! `i` of word `w` being 1 if bit `16 * w + i` is free. The summaries of a
+//! cascade are not stored; they are rebuilt when deserializing.
+
+use crate::{AllocPolicy, BitAlloc, BitAlloc128, BitAlloc16, BitAlloc32, BitAlloc64};
+use crate::{BitAllocCascade, WORD_BITS};
+use core::fmt;
+use core::marker::PhantomData;
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeSeq, Serializer};
+
+/// Write the leaf words of `ba`, cut out of its raw words.
+fn serialize_words<A: BitAlloc, S: Serializer>(ba: &A, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut seq = serializer.serialize_seq(Some(A::CAP / 16))?;
+    for k in 0..A::CAP / 16 {
+        let bit = k * 16;
+        let word = (ba.raw_word(bit / WORD_BITS) >> (bit % WORD_BITS)) as u16;
+        seq.serialize_element(&word)?;
+    }
+    seq.end()
+}
+
+struct WordsVisitor<A>(PhantomData<A>);
+
+impl<'de, A: BitAlloc> Visitor<'de> for WordsVisitor<A> {
+    type Value = A;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a sequence of {} leaf words", A::CAP / 16)
+    }
+
+    fn visit_seq<S: SeqAccess<'de>>(self, mut seq: S) -> Result<A, S::Error> {
+        let mut ba = A::default();
+        // leaf words are gathered into raw words, written as they fill up
+        let mut raw = 0;
+        for k in 0..A::CAP / 16 {
+            let word: u16 = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(k, &self))?;
+            let bit = k * 16;
+            raw |= (word as usize) << (bit % WORD_BITS);
+            if (bit + 16) % WORD_BITS == 0 || k + 1 == A::CAP / 16 {
+                ba.write_raw_word(bit / WORD_BITS, raw);
+                raw = 0;
+            }
+        }
+        ba.rebuild();
+        if seq.next_element::<de::IgnoredAny>()?.is_some() {
+            return Err(de::Error::invalid_length(A::CAP / 16 + 1, &self));
+        }
+        Ok(ba)
+    }
+}
+
+impl Serialize for BitAlloc16 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_words(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for BitAlloc16 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(WordsVisitor(PhantomData))
+    }
+}
+
+impl Serialize for BitAlloc32 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_words(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for BitAlloc32 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(WordsVisitor(PhantomData))
+    }
+}
+
+impl Serialize for BitAlloc64 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_words(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for BitAlloc64 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(WordsVisitor(PhantomData))
+    }
+}
+
+impl Serialize for BitAlloc128 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_words(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for BitAlloc128 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(WordsVisitor(PhantomData))
+    }
+}
+
+impl<T: BitAlloc, const N: usize, P: AllocPolicy> Serialize for BitAllocCascade<T, N, P> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_words(self, serializer)
+    }
+}
+
+impl<'de, T: BitAlloc, const N: usize, P: AllocPolicy> Deserialize<'de>
+    for BitAllocCascade<T, N, P>
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(WordsVisitor(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BitAlloc, BitAlloc16, BitAlloc256, BitAlloc4K};
+    use serde_test::{assert_de_tokens_error, assert_tokens, Token};
+
+    #[test]
+    fn serde_leaf() {
+        let mut ba = BitAlloc16::default();
+        ba.insert(0..4);
+        ba.insert(15..16);
+        assert_tokens(
+            &ba,
+            &[
+                Token::Seq { len: Some(1) },
+                Token::U16(0x800f),
+                Token::SeqEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn serde_cascade() {
+        let mut ba = BitAlloc256::default();
+        ba.insert(12..40);
+        ba.insert(255..256);
+        let mut tokens = [Token::U16(0); 18];
+        tokens[0] = Token::Seq { len: Some(16) };
+        tokens[1] = Token::U16(0xf000);
+        tokens[2] = Token::U16(0xffff);
+        tokens[3] = Token::U16(0x00ff);
+        tokens[16] = Token::U16(0x8000);
+        tokens[17] = Token::SeqEnd;
+        assert_tokens(&ba, &tokens);
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let mut ba = BitAlloc4K::default();
+        let mut seed = 0x5eed_u32;
+        for i in 0..BitAlloc4K::CAP {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            if (seed >> 16) & 1 == 0 {
+                ba.insert(i..i + 1);
+            }
+        }
+        ba.insert(1000..3000);
+        let mut tokens = [Token::U16(0); 258];
+        tokens[0] = Token::Seq { len: Some(256) };
+        for (w, token) in tokens[1..257].iter_mut().enumerate() {
+            let bits = (0..16)
+                .filter(|&i| ba.test(w * 16 + i))
+                .fold(0u16, |bits, i| bits | 1 << i);
+            *token = Token::U16(bits);
+        }
+        tokens[257] = Token::SeqEnd;
+        // what comes back compares equal to the original
+        assert_tokens(&ba, &tokens);
+    }
+
+    #[test]
+    fn serde_wrong_length() {
+        assert_de_tokens_error::<BitAlloc256>(
+            &[Token::Seq { len: Some(1) }, Token::U16(0), Token::SeqEnd],
+            "invalid length 1, expected a sequence of 16 leaf words",
+        );
+    }
+}
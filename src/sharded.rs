@@ -0,0 +1,183 @@
+use crate::{to_range, BitAlloc, LockedBitAlloc};
+use core::fmt;
+use core::ops::RangeBounds;
+
+/// `N` allocators of type `T`, each behind its own lock, splitting an index
+/// space of `T::CAP * N` bits, usually one per CPU. Enabled by the `spin`
+/// feature.
+///
+/// Shard `i` owns the bits `i * T::CAP..(i + 1) * T::CAP`. A CPU allocates
+/// from its own shard, so CPUs do not contend for one lock. When its shard
+/// runs dry, it steals from the other shards, nearest first: `cpu + 1`,
+/// `cpu - 1`, `cpu + 2`, and so on around the ring. A bit is always freed
+/// back to the shard owning it, whichever CPU frees it.
+pub struct ShardedBitAlloc<T: BitAlloc, const N: usize> {
+    shards: [LockedBitAlloc<T>; N],
+}
+
+impl<T: BitAlloc, const N: usize> ShardedBitAlloc<T, N> {
+    /// The total number of bits.
+    pub const CAP: usize = T::CAP * N;
+
+    /// Create an allocator with all bits allocated.
+    pub const fn new() -> Self {
+        assert!(N > 0, "there must be at least one shard");
+        ShardedBitAlloc {
+            shards: [const { LockedBitAlloc::new(T::DEFAULT) }; N],
+        }
+    }
+
+    /// The shard owning bits `i * T::CAP..(i + 1) * T::CAP`.
+    pub fn shard(&self, i: usize) -> &LockedBitAlloc<T> {
+        &self.shards[i]
+    }
+
+    /// The shards, starting with the one of `cpu`, nearest first.
+    fn nearest(cpu: usize) -> impl Iterator<Item = usize> {
+        let home = cpu % N;
+        (0..N).map(move |d| {
+            // 0, +1, -1, +2, -2, ...
+            let offset = if d % 2 == 1 { d / 2 + 1 } else { N - d / 2 };
+            (home + offset) % N
+        })
+    }
+
+    /// Allocate a free bit, from the shard of `cpu` if it has one.
+    pub fn alloc(&self, cpu: usize) -> Option<usize> {
+        Self::nearest(cpu).find_map(|i| self.shards[i].alloc().map(|key| key + i * T::CAP))
+    }
+
+    /// Allocate a free block with a given size, and return the first bit
+    /// position, from the shard of `cpu` if it has room.
+    ///
+    /// A block never spans two shards.
+    pub fn alloc_contiguous(&self, cpu: usize, size: usize, align_log2: usize) -> Option<usize> {
+        Self::nearest(cpu)
+            // the block is aligned within the shard, so the shard must be
+            // aligned as well
+            .filter(|&i| (i * T::CAP).trailing_zeros() as usize >= align_log2)
+            .find_map(|i| {
+                self.shards[i]
+                    .alloc_contiguous(size, align_log2)
+                    .map(|key| key + i * T::CAP)
+            })
+    }
+
+    /// Free an allocated bit.
+    pub fn dealloc(&self, key: usize) {
+        self.shards[key / T::CAP].dealloc(key % T::CAP)
+    }
+
+    /// Free a block allocated by `alloc_contiguous`.
+    pub fn dealloc_contiguous(&self, base: usize, size: usize) {
+        self.shards[base / T::CAP].dealloc_contiguous(base % T::CAP, size)
+    }
+
+    /// Mark bits in the range as unallocated (available).
+    pub fn insert(&self, range: impl RangeBounds<usize>) {
+        self.for_range(range, |shard, begin, end| shard.insert(begin..end))
+    }
+
+    /// Reverse of insert.
+    pub fn remove(&self, range: impl RangeBounds<usize>) {
+        self.for_range(range, |shard, begin, end| shard.remove(begin..end))
+    }
+
+    fn for_range(
+        &self,
+        range: impl RangeBounds<usize>,
+        f: impl Fn(&LockedBitAlloc<T>, usize, usize),
+    ) {
+        let range = to_range(range, Self::CAP);
+        if range.start == range.end {
+            return;
+        }
+        assert!(range.start < range.end);
+        assert!(range.end <= Self::CAP);
+        for i in range.start / T::CAP..=(range.end - 1) / T::CAP {
+            let begin = range.start.max(i * T::CAP) - i * T::CAP;
+            let end = range.end.min((i + 1) * T::CAP) - i * T::CAP;
+            f(&self.shards[i], begin, end);
+        }
+    }
+
+    /// Whether a specific bit is free.
+    pub fn test(&self, key: usize) -> bool {
+        key < Self::CAP && self.shards[key / T::CAP].test(key % T::CAP)
+    }
+
+    /// The number of free bits in all shards.
+    pub fn free_count(&self) -> usize {
+        self.shards.iter().map(LockedBitAlloc::free_count).sum()
+    }
+}
+
+impl<T: BitAlloc, const N: usize> Default for ShardedBitAlloc<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: BitAlloc, const N: usize> fmt::Debug for ShardedBitAlloc<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShardedBitAlloc")
+            .field("cap", &Self::CAP)
+            .field("shards", &N)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitAlloc256, BitAlloc4K};
+
+    #[test]
+    fn sharded_steal() {
+        let ba = ShardedBitAlloc::<BitAlloc256, 4>::new();
+        assert!(ShardedBitAlloc::<BitAlloc256, 4>::nearest(1).eq([1, 2, 0, 3]));
+        ba.insert(300..310);
+        ba.insert(900..1000);
+        // cpu 1 owns bits 256..512
+        assert_eq!(ba.alloc(1), Some(309));
+        ba.remove(256..512);
+        // then its neighbours, the nearest first
+        assert_eq!(ba.alloc(1), Some(999));
+        assert_eq!(ba.alloc(6), Some(998));
+        assert_eq!(ba.alloc_contiguous(0, 32, 5), Some(928));
+        assert_eq!(ba.alloc_contiguous(0, 32, 9), None);
+        ba.dealloc(300);
+        assert_eq!(ba.shard(1).free_count(), 1);
+        assert_eq!(ba.free_count(), 1 + 100 - 2 - 32);
+        assert!(ba.test(300) && !ba.test(999));
+    }
+
+    #[test]
+    fn sharded_threads() {
+        extern crate std;
+        use std::vec::Vec;
+
+        static BA: ShardedBitAlloc<BitAlloc4K, 4> = ShardedBitAlloc::new();
+        // only shard 0 starts with free bits, the others have to steal
+        BA.insert(..2000);
+        let threads: Vec<_> = (0..4)
+            .map(|cpu| {
+                std::thread::spawn(move || {
+                    let keys: Vec<_> = (0..400).map(|_| BA.alloc(cpu).unwrap()).collect();
+                    for &key in &keys[200..] {
+                        BA.dealloc(key);
+                    }
+                    keys[..200].to_vec()
+                })
+            })
+            .collect();
+        let mut seen = BitAlloc4K::default();
+        for thread in threads {
+            for key in thread.join().unwrap() {
+                seen.dealloc(key);
+            }
+        }
+        assert_eq!(seen.free_count(), 800);
+        assert_eq!(BA.free_count(), 1200);
+    }
+}
@@ -0,0 +1,88 @@
+//! Skipping runs of words equal to a given one two words at a time, with
+//! SSE2 on x86_64 and NEON on AArch64, enabled by the `simd` feature. This
+//! is only used for the `next`, `prev`, `next_allocated` and
+//! `prev_allocated` of `BitAllocArray`, which its other scans step through;
+//! the cascades and leaves have no vectorized path. Other targets, and
+//! builds without the feature, compare one word at a time.
+
+/// The index of the first word in `words` other than `empty`.
+pub(crate) fn find_word(words: &[u64], empty: u64) -> Option<usize> {
+    let pairs = words.chunks_exact(2);
+    let skipped = 2 * pairs.take_while(|pair| all_equal(pair, empty)).count();
+    words[skipped..]
+        .iter()
+        .position(|&word| word != empty)
+        .map(|i| skipped + i)
+}
+
+/// The index of the last word in `words` other than `empty`.
+pub(crate) fn rfind_word(words: &[u64], empty: u64) -> Option<usize> {
+    let pairs = words.rchunks_exact(2);
+    let kept = words.len() - 2 * pairs.take_while(|pair| all_equal(pair, empty)).count();
+    words[..kept].iter().rposition(|&word| word != empty)
+}
+
+/// Whether both words of `pair` are `empty`.
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+fn all_equal(pair: &[u64], empty: u64) -> bool {
+    use core::arch::x86_64::{
+        __m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8, _mm_set1_epi64x,
+    };
+    debug_assert_eq!(pair.len(), 2);
+    // SAFETY: SSE2 is part of x86_64, and `pair` holds 16 bytes
+    unsafe {
+        let words = _mm_loadu_si128(pair.as_ptr() as *const __m128i);
+        let equal = _mm_cmpeq_epi8(words, _mm_set1_epi64x(empty as i64));
+        _mm_movemask_epi8(equal) == 0xffff
+    }
+}
+
+/// Whether both words of `pair` are `empty`.
+#[cfg(all(feature = "simd", target_arch = "aarch64"))]
+fn all_equal(pair: &[u64], empty: u64) -> bool {
+    use core::arch::aarch64::{
+        vceqq_u64, vdupq_n_u64, vld1q_u64, vminvq_u32, vreinterpretq_u32_u64,
+    };
+    debug_assert_eq!(pair.len(), 2);
+    // SAFETY: NEON is part of AArch64, and `pair` holds 16 bytes
+    unsafe {
+        let equal = vceqq_u64(vld1q_u64(pair.as_ptr()), vdupq_n_u64(empty));
+        vminvq_u32(vreinterpretq_u32_u64(equal)) == u32::MAX
+    }
+}
+
+/// Whether both words of `pair` are `empty`.
+#[cfg(not(all(feature = "simd", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+fn all_equal(pair: &[u64], empty: u64) -> bool {
+    pair.iter().all(|&word| word == empty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_words() {
+        let mut words = [0u64; 9];
+        for len in 0..=words.len() {
+            assert_eq!(find_word(&words[..len], 0), None);
+            assert_eq!(rfind_word(&words[..len], 0), None);
+        }
+        let mut seed = 1_u32;
+        for _ in 0..200 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let (i, j) = ((seed >> 8) as usize % 9, (seed >> 16) as usize % 9);
+            let empty = if seed & 1 == 0 { 0 } else { u64::MAX };
+            words = [empty; 9];
+            words[i] = empty ^ (1 << ((seed >> 24) % 64));
+            words[j] ^= 1 << 63;
+            for len in 0..=words.len() {
+                let words = &words[..len];
+                let expected = words.iter().position(|&word| word != empty);
+                assert_eq!(find_word(words, empty), expected);
+                let expected = words.iter().rposition(|&word| word != empty);
+                assert_eq!(rfind_word(words, empty), expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! A portable binary snapshot of an allocator, see
+//! [`BitAlloc::serialize_into`].
+//!
+//! The layout, all integers little-endian:
+//!
+//! | bytes    | content                                            |
+//! |----------|----------------------------------------------------|
+//! | 0..4     | magic, `b"BALC"`                                   |
+//! | 4..6     | format version, currently 1                        |
+//! | 6..8     | reserved, 0                                        |
+//! | 8..16    | capacity in bits, as a `u64`                       |
+//! | 16..     | the bitmap, `CAP.div_ceil(8)` bytes, bit `i` of    |
+//! |          | byte `k` being 1 if bit `8 * k + i` is free        |
+//!
+//! It does not depend on the width of `usize` nor on the shape of the tree,
+//! so any allocator of the same capacity can restore it.
+
+use crate::{BitAlloc, WORD_BITS};
+use core::fmt;
+
+const MAGIC: [u8; 4] = *b"BALC";
+const VERSION: u16 = 1;
+const HEADER_LEN: usize = 16;
+
+/// Why a snapshot could not be restored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The bytes do not start with the snapshot magic.
+    BadMagic,
+    /// The snapshot was written in a format version this crate can't read.
+    UnsupportedVersion(u16),
+    /// The snapshot is of an allocator with another capacity.
+    CapacityMismatch(u64),
+    /// The bytes end before the bitmap does.
+    Truncated,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::BadMagic => f.write_str("not an allocator snapshot"),
+            SnapshotError::UnsupportedVersion(v) => {
+                write!(f, "unsupported snapshot version {}", v)
+            }
+            SnapshotError::CapacityMismatch(cap) => {
+                write!(f, "snapshot of a {}-bit allocator", cap)
+            }
+            SnapshotError::Truncated => f.write_str("snapshot is truncated"),
+        }
+    }
+}
+
+pub(crate) fn len<A: BitAlloc>() -> usize {
+    HEADER_LEN + A::CAP.div_ceil(8)
+}
+
+pub(crate) fn write<A: BitAlloc>(ba: &A, buf: &mut [u8]) -> usize {
+    let len = len::<A>();
+    assert!(buf.len() >= len, "snapshot buffer too small");
+    buf[0..4].copy_from_slice(&MAGIC);
+    buf[4..6].copy_from_slice(&VERSION.to_le_bytes());
+    buf[6..8].copy_from_slice(&[0; 2]);
+    buf[8..16].copy_from_slice(&(A::CAP as u64).to_le_bytes());
+    for (k, byte) in buf[HEADER_LEN..len].iter_mut().enumerate() {
+        let bit = k * 8;
+        *byte = (ba.raw_word(bit / WORD_BITS) >> (bit % WORD_BITS)) as u8;
+    }
+    len
+}
+
+pub(crate) fn read<A: BitAlloc>(ba: &mut A, bytes: &[u8]) -> Result<(), SnapshotError> {
+    if bytes.len() < HEADER_LEN {
+        return Err(SnapshotError::Truncated);
+    }
+    if bytes[0..4] != MAGIC {
+        return Err(SnapshotError::BadMagic);
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+    let mut cap = [0; 8];
+    cap.copy_from_slice(&bytes[8..16]);
+    let cap = u64::from_le_bytes(cap);
+    if cap != A::CAP as u64 {
+        return Err(SnapshotError::CapacityMismatch(cap));
+    }
+    let bitmap = bytes
+        .get(HEADER_LEN..len::<A>())
+        .ok_or(SnapshotError::Truncated)?;
+    // bytes are gathered into raw words, written as they fill up
+    let mut raw = 0;
+    for (k, &byte) in bitmap.iter().enumerate() {
+        let bit = k * 8;
+        raw |= (byte as usize) << (bit % WORD_BITS);
+        if (bit + 8) % WORD_BITS == 0 || k + 1 == bitmap.len() {
+            ba.write_raw_word(bit / WORD_BITS, raw);
+            raw = 0;
+        }
+    }
+    ba.rebuild();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitAlloc16, BitAlloc4K, BitAllocArray};
+
+    #[test]
+    fn snapshot() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(0..3);
+        ba.insert(100..2000);
+        ba.remove(1000..1001);
+        let mut buf = [0xaa; 600];
+        let n = ba.serialize_into(&mut buf);
+        assert_eq!(n, BitAlloc4K::snapshot_len());
+        assert_eq!(n, 16 + 512);
+        assert_eq!(
+            &buf[..16],
+            b"BALC\x01\x00\x00\x00\x00\x10\x00\x00\x00\x00\x00\x00"
+        );
+        assert_eq!(buf[16], 0b111);
+        let copy = BitAlloc4K::deserialize_from(&buf[..n]).unwrap();
+        assert!(copy == ba);
+        assert_eq!(copy.max_contiguous(), Some(1001..2000));
+
+        // a flat bitmap of the same capacity reads it just as well
+        let flat = BitAllocArray::<4096, 64>::deserialize_from(&buf).unwrap();
+        assert!(flat.iter_free().eq(ba.iter_free()));
+    }
+
+    #[test]
+    fn snapshot_errors() {
+        let ba = BitAlloc16::default();
+        let mut buf = [0; 18];
+        ba.serialize_into(&mut buf);
+        assert_eq!(
+            BitAlloc4K::deserialize_from(&buf).unwrap_err(),
+            SnapshotError::CapacityMismatch(16)
+        );
+        assert_eq!(
+            BitAlloc16::deserialize_from(&buf[..17]).unwrap_err(),
+            SnapshotError::Truncated
+        );
+        buf[4] = 2;
+        assert_eq!(
+            BitAlloc16::deserialize_from(&buf).unwrap_err(),
+            SnapshotError::UnsupportedVersion(2)
+        );
+        buf[0] = 0;
+        assert_eq!(
+            BitAlloc16::deserialize_from(&buf).unwrap_err(),
+            SnapshotError::BadMagic
+        );
+    }
+}
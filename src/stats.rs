@@ -0,0 +1,592 @@
+use crate::{AllocPolicy, BitAlloc, BudgetExhausted, Inconsistency};
+use core::ops::{Range, RangeBounds, RangeInclusive};
+
+/// A snapshot of the statistics of a [`StatsBitAlloc`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BitAllocStats {
+    /// Successful allocations of single bits.
+    pub allocs: u64,
+    /// Successful allocations of contiguous blocks.
+    pub contiguous_allocs: u64,
+    /// Allocations of bits or blocks that found no room, including
+    /// `alloc_many` calls that got fewer bits than asked for.
+    pub failed_allocs: u64,
+    /// Frees of single bits or blocks.
+    pub deallocs: u64,
+    /// The number of bits in use now.
+    pub in_use: usize,
+    /// The most bits in use at once since the last `reset_watermarks`.
+    pub peak_in_use: usize,
+    /// The number of free bits now.
+    pub free: usize,
+    /// The lowest free count since the last `reset_watermarks`.
+    pub min_free: usize,
+}
+
+/// The allocations of a [`StatsBitAlloc`] not freed yet.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Outstanding {
+    /// Single bits, from `alloc` and the like.
+    pub bits: usize,
+    /// Contiguous blocks, from `alloc_contiguous` and the like.
+    pub blocks: usize,
+}
+
+/// The outstanding allocations of a [`StatsBitAlloc`] at some point, to
+/// tell how many a scope leaked, e.g. around a call into a subsystem.
+///
+/// Only the balance is kept, so a scope that frees what was allocated
+/// before it hides as many leaks of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ScopeMarker {
+    at: Outstanding,
+}
+
+impl ScopeMarker {
+    /// The allocations outstanding now that were not at the marker.
+    pub fn leaks<A: BitAlloc>(&self, ba: &StatsBitAlloc<A>) -> Outstanding {
+        let now = ba.outstanding();
+        Outstanding {
+            bits: now.bits.saturating_sub(self.at.bits),
+            blocks: now.blocks.saturating_sub(self.at.blocks),
+        }
+    }
+
+    /// Whether nothing was leaked since the marker.
+    pub fn is_balanced<A: BitAlloc>(&self, ba: &StatsBitAlloc<A>) -> bool {
+        self.leaks(ba) == Outstanding::default()
+    }
+}
+
+/// A wrapper counting allocations, frees and failed allocations, and
+/// keeping the high and low watermarks: the peak number of bits in use and
+/// the lowest free count, since the last `reset_watermarks`. It also
+/// counts the allocations not freed yet, for leak checks with `scope`.
+///
+/// The counters are plain integers, updated only by the outermost calls.
+/// Bits are in use from their allocation by `alloc`, `alloc_contiguous`
+/// and the like to their `dealloc`. Those taken by `remove`, e.g. reserved
+/// at boot, are not counted, but do lower the free count.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StatsBitAlloc<A: BitAlloc> {
+    inner: A,
+    allocs: u64,
+    contiguous_allocs: u64,
+    failed_allocs: u64,
+    deallocs: u64,
+    in_use: usize,
+    peak_in_use: usize,
+    min_free: usize,
+    outstanding: Outstanding,
+}
+
+impl<A: BitAlloc> StatsBitAlloc<A> {
+    /// Wrap an allocator, with nothing in use yet.
+    pub fn new(inner: A) -> Self {
+        let min_free = inner.free_count();
+        StatsBitAlloc {
+            inner,
+            allocs: 0,
+            contiguous_allocs: 0,
+            failed_allocs: 0,
+            deallocs: 0,
+            in_use: 0,
+            peak_in_use: 0,
+            min_free,
+            outstanding: Outstanding::default(),
+        }
+    }
+
+    /// The underlying allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Unwrap the underlying allocator.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    /// The number of bits in use now.
+    pub fn in_use(&self) -> usize {
+        self.in_use
+    }
+
+    /// The most bits in use at once since the last reset.
+    pub fn peak_in_use(&self) -> usize {
+        self.peak_in_use
+    }
+
+    /// The lowest free count since the last reset.
+    pub fn min_free(&self) -> usize {
+        self.min_free
+    }
+
+    /// All statistics at once.
+    pub fn stats(&self) -> BitAllocStats {
+        BitAllocStats {
+            allocs: self.allocs,
+            contiguous_allocs: self.contiguous_allocs,
+            failed_allocs: self.failed_allocs,
+            deallocs: self.deallocs,
+            in_use: self.in_use,
+            peak_in_use: self.peak_in_use,
+            free: self.inner.free_count(),
+            min_free: self.min_free,
+        }
+    }
+
+    /// The allocations not freed yet.
+    ///
+    /// A bit taken by `remove` and then freed with `dealloc` was never
+    /// counted in, so the counts saturate at 0 rather than going negative.
+    pub fn outstanding(&self) -> Outstanding {
+        self.outstanding
+    }
+
+    /// Mark the outstanding allocations now, to check for leaks later.
+    pub fn scope(&self) -> ScopeMarker {
+        ScopeMarker {
+            at: self.outstanding,
+        }
+    }
+
+    /// Start both watermarks again from the current state.
+    pub fn reset_watermarks(&mut self) {
+        self.peak_in_use = self.in_use;
+        self.min_free = self.inner.free_count();
+    }
+
+    /// Count `n` more bits in use, and update the watermarks.
+    fn allocated(&mut self, n: usize) {
+        self.in_use += n;
+        self.peak_in_use = self.peak_in_use.max(self.in_use);
+        self.min_free = self.min_free.min(self.inner.free_count());
+    }
+
+    /// Count `n` bits less in use, freed by `calls` calls. Freeing bits
+    /// taken by `remove` may count more than were counted in.
+    fn freed(&mut self, n: usize, calls: u64) {
+        self.in_use = self.in_use.saturating_sub(n);
+        self.deallocs += calls;
+    }
+
+    fn allocated_one(&mut self, key: Option<usize>) -> Option<usize> {
+        if key.is_some() {
+            self.allocs += 1;
+            self.outstanding.bits += 1;
+            self.allocated(1);
+        } else {
+            self.failed_allocs += 1;
+        }
+        key
+    }
+
+    fn allocated_many(&mut self, n: usize, wanted: usize) -> usize {
+        self.allocs += n as u64;
+        self.outstanding.bits += n;
+        self.failed_allocs += (n < wanted) as u64;
+        self.allocated(n);
+        n
+    }
+
+    fn allocated_block(&mut self, ok: bool, size: usize) {
+        if ok {
+            self.contiguous_allocs += 1;
+            self.outstanding.blocks += 1;
+            self.allocated(size);
+        } else {
+            self.failed_allocs += 1;
+        }
+    }
+}
+
+impl<A: BitAlloc> Default for StatsBitAlloc<A> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<A: BitAlloc> BitAlloc for StatsBitAlloc<A> {
+    const CAP: usize = A::CAP;
+
+    const DEFAULT: Self = StatsBitAlloc {
+        inner: A::DEFAULT,
+        allocs: 0,
+        contiguous_allocs: 0,
+        failed_allocs: 0,
+        deallocs: 0,
+        in_use: 0,
+        peak_in_use: 0,
+        min_free: 0,
+        outstanding: Outstanding { bits: 0, blocks: 0 },
+    };
+
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    fn alloc(&mut self) -> Option<usize> {
+        let res = self.inner.alloc();
+        self.allocated_one(res)
+    }
+    fn alloc_with<P: AllocPolicy>(&mut self) -> Option<usize> {
+        let res = self.inner.alloc_with::<P>();
+        self.allocated_one(res)
+    }
+    fn alloc_many(&mut self, out: &mut [usize]) -> usize {
+        let n = self.inner.alloc_many(out);
+        self.allocated_many(n, out.len())
+    }
+    fn alloc_many_with<P: AllocPolicy>(&mut self, out: &mut [usize]) -> usize {
+        let n = self.inner.alloc_many_with::<P>(out);
+        self.allocated_many(n, out.len())
+    }
+    fn alloc_from(&mut self, hint: usize) -> Option<usize> {
+        let res = self.inner.alloc_from(hint);
+        self.allocated_one(res)
+    }
+    fn alloc_near(&mut self, key: usize) -> Option<usize> {
+        let res = self.inner.alloc_near(key);
+        self.allocated_one(res)
+    }
+    fn alloc_random(&mut self, rng: &mut impl FnMut() -> u64) -> Option<usize> {
+        let res = self.inner.alloc_random(rng);
+        self.allocated_one(res)
+    }
+    fn peek_alloc(&self) -> Option<usize> {
+        self.inner.peek_alloc()
+    }
+    fn peek_with<P: AllocPolicy>(&self) -> Option<usize> {
+        self.inner.peek_with::<P>()
+    }
+    fn peek_full<P: AllocPolicy>() -> usize {
+        A::peek_full::<P>()
+    }
+    fn subtree_cap(depth: usize) -> usize {
+        A::subtree_cap(depth)
+    }
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        let res = self.inner.alloc_contiguous(size, align_log2);
+        self.allocated_block(res.is_some(), size);
+        res
+    }
+    fn alloc_contiguous_random(
+        &mut self,
+        size: usize,
+        align_log2: usize,
+        rng: &mut impl FnMut() -> u64,
+    ) -> Option<usize> {
+        let res = self.inner.alloc_contiguous_random(size, align_log2, rng);
+        self.allocated_block(res.is_some(), size);
+        res
+    }
+    fn alloc_contiguous_offset(
+        &mut self,
+        size: usize,
+        align_log2: usize,
+        phase: usize,
+    ) -> Option<usize> {
+        let res = self.inner.alloc_contiguous_offset(size, align_log2, phase);
+        self.allocated_block(res.is_some(), size);
+        res
+    }
+    /// Giving up counts as neither a success nor a failure.
+    fn alloc_contiguous_bounded(
+        &mut self,
+        size: usize,
+        align_log2: usize,
+        max_steps: usize,
+    ) -> Result<Option<usize>, BudgetExhausted> {
+        let res = self
+            .inner
+            .alloc_contiguous_bounded(size, align_log2, max_steps)?;
+        self.allocated_block(res.is_some(), size);
+        Ok(res)
+    }
+    fn alloc_contiguous_at(&mut self, base: usize, size: usize) -> bool {
+        let res = self.inner.alloc_contiguous_at(base, size);
+        self.allocated_block(res, size);
+        res
+    }
+    fn alloc_contiguous_best_fit(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        let res = self.inner.alloc_contiguous_best_fit(size, align_log2);
+        self.allocated_block(res.is_some(), size);
+        res
+    }
+    fn alloc_contiguous_top(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        let res = self.inner.alloc_contiguous_top(size, align_log2);
+        self.allocated_block(res.is_some(), size);
+        res
+    }
+    fn alloc_contiguous_guarded_gap(
+        &mut self,
+        size: usize,
+        align_log2: usize,
+        guard: usize,
+    ) -> Option<usize> {
+        let res = self
+            .inner
+            .alloc_contiguous_guarded_gap(size, align_log2, guard);
+        self.allocated_block(res.is_some(), size + 2 * guard);
+        res
+    }
+    fn alloc_contiguous_flex(
+        &mut self,
+        size: RangeInclusive<usize>,
+        align_log2: usize,
+    ) -> Option<Range<usize>> {
+        let res = self.inner.alloc_contiguous_flex(size, align_log2);
+        self.allocated_block(res.is_some(), res.as_ref().map_or(0, |r| r.len()));
+        res
+    }
+    fn alloc_scatter(
+        &mut self,
+        total_size: usize,
+        max_fragments: usize,
+        align_log2: usize,
+        out: &mut [Range<usize>],
+    ) -> Option<usize> {
+        let res = self
+            .inner
+            .alloc_scatter(total_size, max_fragments, align_log2, out);
+        match res {
+            Some(n) => out[..n]
+                .iter()
+                .for_each(|block| self.allocated_block(true, block.len())),
+            None => self.allocated_block(false, 0),
+        }
+        res
+    }
+    fn find_contiguous(&self, size: usize, align_log2: usize) -> Option<usize> {
+        self.inner.find_contiguous(size, align_log2)
+    }
+    fn alloc_aligned(&mut self, align_log2: usize) -> Option<usize> {
+        let res = self.inner.alloc_aligned(align_log2);
+        self.allocated_one(res)
+    }
+    fn next(&self, key: usize) -> Option<usize> {
+        self.inner.next(key)
+    }
+    fn prev(&self, key: usize) -> Option<usize> {
+        self.inner.prev(key)
+    }
+    fn dealloc(&mut self, key: usize) {
+        self.inner.dealloc(key);
+        self.outstanding.bits = self.outstanding.bits.saturating_sub(1);
+        self.freed(1, 1);
+    }
+    fn dealloc_many(&mut self, keys: impl IntoIterator<Item = usize>) {
+        let mut n = 0;
+        self.inner
+            .dealloc_many(keys.into_iter().inspect(|_| n += 1));
+        self.outstanding.bits = self.outstanding.bits.saturating_sub(n);
+        self.freed(n, n as u64);
+    }
+    fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        self.inner.dealloc_contiguous(base, size);
+        self.outstanding.blocks = self.outstanding.blocks.saturating_sub(1);
+        self.freed(size, 1);
+    }
+    fn insert(&mut self, range: impl RangeBounds<usize>) {
+        self.inner.insert(range)
+    }
+    fn remove(&mut self, range: impl RangeBounds<usize>) {
+        self.inner.remove(range);
+        self.min_free = self.min_free.min(self.inner.free_count());
+    }
+    fn any(&self) -> bool {
+        self.inner.any()
+    }
+    fn test(&self, key: usize) -> bool {
+        self.inner.test(key)
+    }
+    fn free_count(&self) -> usize {
+        self.inner.free_count()
+    }
+    fn count_free_in(&self, range: impl RangeBounds<usize>) -> usize {
+        self.inner.count_free_in(range)
+    }
+    fn free_prefix(&self) -> usize {
+        self.inner.free_prefix()
+    }
+    fn free_suffix(&self) -> usize {
+        self.inner.free_suffix()
+    }
+    fn max_contiguous_len(&self) -> usize {
+        self.inner.max_contiguous_len()
+    }
+    fn max_contiguous(&self) -> Option<Range<usize>> {
+        self.inner.max_contiguous()
+    }
+    fn search_contiguous(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+    ) -> Option<usize> {
+        self.inner
+            .search_contiguous(offset, size, align_log2, run_start)
+    }
+    fn search_contiguous_bounded(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+        budget: &mut usize,
+    ) -> Result<Option<usize>, BudgetExhausted> {
+        self.inner
+            .search_contiguous_bounded(offset, size, align_log2, run_start, budget)
+    }
+    fn visit_free_runs(&self, run: &mut usize, f: &mut impl FnMut(usize)) {
+        self.inner.visit_free_runs(run, f)
+    }
+    fn next_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.next_allocated(key)
+    }
+    fn prev_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.prev_allocated(key)
+    }
+    fn raw_word(&self, w: usize) -> usize {
+        self.inner.raw_word(w)
+    }
+    fn next_difference(&self, other: &Self, key: usize) -> Option<usize> {
+        self.inner.next_difference(&other.inner, key)
+    }
+    fn is_subset_of(&self, other: &Self) -> bool {
+        self.inner.is_subset_of(&other.inner)
+    }
+    fn is_disjoint_with(&self, other: &Self) -> bool {
+        self.inner.is_disjoint_with(&other.inner)
+    }
+    fn write_raw_word(&mut self, w: usize, word: usize) {
+        self.inner.write_raw_word(w, word)
+    }
+    /// The returned allocator starts its counters afresh.
+    fn split_off(&mut self, at: usize) -> Self {
+        let hi = StatsBitAlloc::new(self.inner.split_off(at));
+        self.min_free = self.min_free.min(self.inner.free_count());
+        hi
+    }
+    fn rebuild(&mut self) {
+        self.inner.rebuild();
+        self.min_free = self.min_free.min(self.inner.free_count());
+    }
+    fn validate(&self) -> Result<(), Inconsistency> {
+        self.inner.validate()
+    }
+    fn iter_free(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.inner.iter_free()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitAlloc4K, ReserveBitAlloc};
+
+    #[test]
+    fn stats_watermarks() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(..);
+        let mut ba = StatsBitAlloc::new(ba);
+        ba.remove(..96);
+        let a = ba.alloc().unwrap();
+        let base = ba.alloc_contiguous(64, 6).unwrap();
+        ba.dealloc(a);
+        assert_eq!((ba.in_use(), ba.peak_in_use()), (64, 65));
+        assert_eq!(ba.min_free(), 4096 - 96 - 65);
+        ba.reset_watermarks();
+        assert_eq!((ba.peak_in_use(), ba.min_free()), (64, 4096 - 96 - 64));
+        ba.dealloc_contiguous(base, 64);
+        assert!(ba.alloc_from(1000).is_some());
+        assert_eq!(ba.in_use(), 1);
+        assert_eq!((ba.peak_in_use(), ba.min_free()), (64, 4096 - 96 - 64));
+    }
+
+    #[test]
+    fn stats_counters() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(..100);
+        let mut ba = StatsBitAlloc::new(ba);
+        let mut out = [0; 60];
+        ba.alloc_many(&mut out);
+        ba.alloc_many(&mut out);
+        assert_eq!(ba.alloc_contiguous(64, 0), None);
+        ba.dealloc_many(out[..10].iter().cloned());
+        ba.dealloc(out[10]);
+        let base = ba.alloc_contiguous(11, 0).unwrap();
+        ba.dealloc_contiguous(base, 11);
+        assert_eq!(
+            ba.stats(),
+            BitAllocStats {
+                allocs: 100,
+                contiguous_allocs: 1,
+                failed_allocs: 2,
+                deallocs: 12,
+                in_use: 89,
+                peak_in_use: 100,
+                free: 11,
+                min_free: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn stats_random() {
+        let mut seed = 1u64;
+        let mut rng = || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            seed
+        };
+        let mut ba = BitAlloc4K::default();
+        ba.insert(..);
+        // nothing is available past a full reserve, and the failures count
+        let mut ba = StatsBitAlloc::new(ReserveBitAlloc::new(ba, 4096));
+        assert_eq!(ba.alloc_random(&mut rng), None);
+        assert_eq!(ba.alloc_contiguous_random(8, 3, &mut rng), None);
+        assert_eq!(ba.alloc_contiguous_offset(8, 3, 5), None);
+        assert_eq!(ba.alloc_contiguous_bounded(8, 3, 100), Ok(None));
+        assert_eq!((ba.stats().failed_allocs, ba.in_use()), (4, 0));
+        let mut ba = StatsBitAlloc::new(ba.into_inner().into_inner());
+        let key = ba.alloc_random(&mut rng).unwrap();
+        let base = ba.alloc_contiguous_random(8, 3, &mut rng).unwrap();
+        let offset = ba.alloc_contiguous_offset(8, 3, 5).unwrap();
+        assert_eq!((offset + 5) % 8, 0);
+        let bounded = ba.alloc_contiguous_bounded(8, 3, 100).unwrap().unwrap();
+        assert_eq!(ba.outstanding().blocks, 3);
+        assert_eq!((ba.stats().allocs, ba.in_use()), (1, 25));
+        ba.dealloc_contiguous(bounded, 8);
+        ba.dealloc(key);
+        ba.dealloc_contiguous(base, 8);
+        ba.dealloc_contiguous(offset, 8);
+        assert_eq!(ba.in_use(), 0);
+    }
+
+    #[test]
+    fn stats_scope_leaks() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(..);
+        let mut ba = StatsBitAlloc::new(ba);
+        let kept = ba.alloc().unwrap();
+        let scope = ba.scope();
+        let a = ba.alloc().unwrap();
+        let base = ba.alloc_contiguous(16, 4).unwrap();
+        ba.alloc_contiguous(8, 0).unwrap();
+        let mut out = [0; 3];
+        ba.alloc_many(&mut out);
+        ba.dealloc(a);
+        ba.dealloc_contiguous(base, 16);
+        assert_eq!(ba.outstanding(), Outstanding { bits: 4, blocks: 1 });
+        assert_eq!(scope.leaks(&ba), Outstanding { bits: 3, blocks: 1 });
+        ba.dealloc_many(out.iter().cloned());
+        assert!(!scope.is_balanced(&ba));
+        // freeing what was allocated before the scope masks the leak
+        ba.dealloc(kept);
+        assert_eq!(scope.leaks(&ba), Outstanding { bits: 0, blocks: 1 });
+        ba.remove(..1);
+        ba.dealloc(0);
+        assert_eq!(ba.outstanding(), Outstanding { bits: 0, blocks: 1 });
+    }
+}
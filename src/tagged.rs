@@ -0,0 +1,167 @@
+use crate::BitAlloc;
+use core::fmt;
+use core::ops::RangeBounds;
+
+/// A wrapper keeping `K` bit-planes of tags beside the bitmap, e.g. dirty,
+/// accessed or pinned, one bit per tag for each allocated bit.
+///
+/// Each plane is an allocator of the same type, its free bits being the
+/// tagged ones, so `next_tagged` skips untagged stretches as fast as `next`
+/// skips allocated ones. Tags only go on allocated bits, and freeing a bit
+/// clears all its tags.
+#[derive(Clone)]
+pub struct TaggedBitAlloc<A: BitAlloc, const K: usize> {
+    inner: A,
+    planes: [A; K],
+}
+
+impl<A: BitAlloc, const K: usize> TaggedBitAlloc<A, K> {
+    /// Wrap an allocator, with no bit tagged.
+    pub fn new(inner: A) -> Self {
+        TaggedBitAlloc {
+            inner,
+            planes: [A::DEFAULT; K],
+        }
+    }
+
+    /// The underlying allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Unwrap the underlying allocator, dropping the tags.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    /// The plane of `tag`, in which tagged bits are free.
+    pub fn plane(&self, tag: usize) -> &A {
+        &self.planes[tag]
+    }
+
+    /// Allocate a free bit, untagged.
+    pub fn alloc(&mut self) -> Option<usize> {
+        self.inner.alloc()
+    }
+
+    /// Allocate a free block like `alloc_contiguous`, untagged.
+    pub fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        self.inner.alloc_contiguous(size, align_log2)
+    }
+
+    /// Free an allocated bit, clearing its tags.
+    pub fn dealloc(&mut self, key: usize) {
+        self.inner.dealloc(key);
+        self.clear_tags(key..key + 1);
+    }
+
+    /// Free a block of `size` allocated bits starting at `base`, clearing
+    /// their tags.
+    pub fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        self.inner.dealloc_contiguous(base, size);
+        self.clear_tags(base..base + size);
+    }
+
+    /// Mark bits in the range as unallocated, clearing their tags.
+    pub fn insert(&mut self, range: impl RangeBounds<usize> + Clone) {
+        self.inner.insert(range.clone());
+        self.clear_tags(range);
+    }
+
+    /// Mark bits in the range as allocated, keeping the tags of those that
+    /// already were.
+    pub fn remove(&mut self, range: impl RangeBounds<usize>) {
+        self.inner.remove(range)
+    }
+
+    /// Whether a specific bit is free.
+    pub fn test(&self, key: usize) -> bool {
+        self.inner.test(key)
+    }
+
+    /// Tag an allocated bit.
+    pub fn set_tag(&mut self, key: usize, tag: usize) {
+        assert!(!self.inner.test(key), "tagging free bit {}", key);
+        self.planes[tag].insert(key..key + 1);
+    }
+
+    /// Remove a tag from a bit.
+    pub fn clear_tag(&mut self, key: usize, tag: usize) {
+        self.planes[tag].remove(key..key + 1);
+    }
+
+    /// Whether a bit has a tag.
+    pub fn test_tag(&self, key: usize, tag: usize) -> bool {
+        self.planes[tag].test(key)
+    }
+
+    /// Remove every tag from the bits in the range.
+    pub fn clear_tags(&mut self, range: impl RangeBounds<usize> + Clone) {
+        for plane in &mut self.planes {
+            plane.remove(range.clone());
+        }
+    }
+
+    /// Find a index not less than a given key, where the bit has a tag.
+    pub fn next_tagged(&self, tag: usize, key: usize) -> Option<usize> {
+        self.planes[tag].next(key)
+    }
+
+    /// The number of bits with a tag.
+    pub fn tagged_count(&self, tag: usize) -> usize {
+        self.planes[tag].free_count()
+    }
+
+    /// Iterate over the bits with a tag, in ascending order.
+    pub fn iter_tagged(&self, tag: usize) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.planes[tag].iter_free()
+    }
+}
+
+impl<A: BitAlloc + fmt::Debug, const K: usize> fmt::Debug for TaggedBitAlloc<A, K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TaggedBitAlloc")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BitAlloc4K;
+
+    const DIRTY: usize = 0;
+    const PINNED: usize = 1;
+
+    #[test]
+    fn tags_follow_bits() {
+        let mut ba = TaggedBitAlloc::<BitAlloc4K, 2>::new(BitAlloc4K::default());
+        ba.insert(..);
+        let base = ba.alloc_contiguous(100, 0).unwrap();
+        let key = ba.alloc().unwrap();
+        ba.set_tag(base + 10, DIRTY);
+        ba.set_tag(base + 90, DIRTY);
+        ba.set_tag(key, DIRTY);
+        ba.set_tag(key, PINNED);
+        assert_eq!(ba.next_tagged(DIRTY, base + 11), Some(base + 90));
+        assert_eq!(ba.tagged_count(DIRTY), 3);
+        assert!(ba.test_tag(key, PINNED) && !ba.test_tag(base + 10, PINNED));
+        ba.clear_tag(base + 90, DIRTY);
+        assert!(ba.iter_tagged(DIRTY).eq([base + 10, key]));
+        // freeing drops the tags, so the bits come back untagged
+        ba.dealloc(key);
+        ba.dealloc_contiguous(base, 100);
+        assert_eq!((ba.tagged_count(DIRTY), ba.tagged_count(PINNED)), (0, 0));
+        assert_eq!(ba.alloc(), Some(key));
+        assert!(!ba.test_tag(key, PINNED));
+    }
+
+    #[test]
+    #[should_panic(expected = "tagging free bit")]
+    fn tag_free_bit() {
+        let mut ba = TaggedBitAlloc::<BitAlloc4K, 1>::new(BitAlloc4K::default());
+        ba.insert(..);
+        ba.set_tag(5, DIRTY);
+    }
+}
@@ -0,0 +1,395 @@
+use crate::{to_range, AllocPolicy, BitAlloc, BudgetExhausted, Inconsistency};
+use core::ops::{Range, RangeBounds, RangeInclusive};
+
+/// An operation recorded by a [`TracedBitAlloc`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TraceOp {
+    /// A single bit was asked for, and this one allocated, if any. Each bit
+    /// of `alloc_many` is recorded on its own.
+    Alloc(Option<usize>),
+    /// A block was asked for, and this one allocated, if any.
+    AllocContiguous {
+        size: usize,
+        align_log2: usize,
+        base: Option<usize>,
+    },
+    /// A bit was freed.
+    Dealloc(usize),
+    /// A block was freed.
+    DeallocContiguous { base: usize, size: usize },
+    /// A range was marked free.
+    Insert(Range<usize>),
+    /// A range was marked allocated.
+    Remove(Range<usize>),
+    /// Raw word `w` was overwritten.
+    WriteRawWord(usize),
+    /// Another bitmap was merged in, by `union_with`, `intersect_with` or
+    /// `subtract`, leaving this many free bits.
+    Merge(usize),
+}
+
+/// A [`TraceOp`], numbered from 0 in the order of the operations.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TraceEvent {
+    pub seq: u64,
+    pub op: TraceOp,
+}
+
+/// A wrapper keeping the last `N` allocations, frees and range operations
+/// in a ring buffer, for post-mortem debugging, e.g. to dump from a panic
+/// handler how a corrupt-looking frame allocator got that way. Enabled by
+/// the `trace` feature.
+///
+/// Recording costs a copy of the event and allocates nothing. As with
+/// `LoggedBitAlloc`, only the outermost calls are recorded, and queries go
+/// straight to the inner allocator.
+#[derive(Debug, Clone)]
+pub struct TracedBitAlloc<A: BitAlloc, const N: usize = 64> {
+    inner: A,
+    events: [Option<TraceEvent>; N],
+    seq: u64,
+}
+
+impl<A: BitAlloc, const N: usize> TracedBitAlloc<A, N> {
+    /// Wrap an allocator, with an empty trace.
+    pub fn new(inner: A) -> Self {
+        TracedBitAlloc {
+            inner,
+            events: [const { None }; N],
+            seq: 0,
+        }
+    }
+
+    /// The underlying allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Unwrap the underlying allocator, dropping the trace.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    /// The number of operations recorded so far, including those the ring
+    /// buffer has dropped since.
+    pub fn recorded(&self) -> u64 {
+        self.seq
+    }
+
+    /// The last operations, up to `N` of them, from the oldest to the newest.
+    pub fn trace(&self) -> impl DoubleEndedIterator<Item = &TraceEvent> + '_ {
+        let start = (self.seq as usize) % N.max(1);
+        let (newer, older) = self.events.split_at(start);
+        older.iter().chain(newer).flatten()
+    }
+
+    /// Forget every recorded operation, keeping the numbering going.
+    pub fn clear_trace(&mut self) {
+        self.events = [const { None }; N];
+    }
+
+    fn record(&mut self, op: TraceOp) {
+        if N != 0 {
+            self.events[(self.seq as usize) % N] = Some(TraceEvent { seq: self.seq, op });
+        }
+        self.seq += 1;
+    }
+
+    fn allocated(&mut self, res: Option<usize>) -> Option<usize> {
+        self.record(TraceOp::Alloc(res));
+        res
+    }
+
+    fn allocated_block(
+        &mut self,
+        size: usize,
+        align_log2: usize,
+        base: Option<usize>,
+    ) -> Option<usize> {
+        self.record(TraceOp::AllocContiguous {
+            size,
+            align_log2,
+            base,
+        });
+        base
+    }
+
+    fn merged(&mut self) {
+        self.record(TraceOp::Merge(self.inner.free_count()));
+    }
+}
+
+impl<A: BitAlloc, const N: usize> Default for TracedBitAlloc<A, N> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<A: BitAlloc, const N: usize> BitAlloc for TracedBitAlloc<A, N> {
+    const CAP: usize = A::CAP;
+
+    const DEFAULT: Self = TracedBitAlloc {
+        inner: A::DEFAULT,
+        events: [const { None }; N],
+        seq: 0,
+    };
+
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    fn alloc(&mut self) -> Option<usize> {
+        let res = self.inner.alloc();
+        self.allocated(res)
+    }
+    fn alloc_with<P: AllocPolicy>(&mut self) -> Option<usize> {
+        let res = self.inner.alloc_with::<P>();
+        self.allocated(res)
+    }
+    fn alloc_many(&mut self, out: &mut [usize]) -> usize {
+        let n = self.inner.alloc_many(out);
+        for &key in &out[..n] {
+            self.record(TraceOp::Alloc(Some(key)));
+        }
+        n
+    }
+    fn alloc_many_with<P: AllocPolicy>(&mut self, out: &mut [usize]) -> usize {
+        let n = self.inner.alloc_many_with::<P>(out);
+        for &key in &out[..n] {
+            self.record(TraceOp::Alloc(Some(key)));
+        }
+        n
+    }
+    fn peek_alloc(&self) -> Option<usize> {
+        self.inner.peek_alloc()
+    }
+    fn peek_with<P: AllocPolicy>(&self) -> Option<usize> {
+        self.inner.peek_with::<P>()
+    }
+    fn peek_full<P: AllocPolicy>() -> usize {
+        A::peek_full::<P>()
+    }
+    fn subtree_cap(depth: usize) -> usize {
+        A::subtree_cap(depth)
+    }
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        let res = self.inner.alloc_contiguous(size, align_log2);
+        self.allocated_block(size, align_log2, res)
+    }
+    fn alloc_contiguous_best_fit(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        let res = self.inner.alloc_contiguous_best_fit(size, align_log2);
+        self.allocated_block(size, align_log2, res)
+    }
+    fn alloc_contiguous_top(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        let res = self.inner.alloc_contiguous_top(size, align_log2);
+        self.allocated_block(size, align_log2, res)
+    }
+    /// Recorded as the block with its guard bits.
+    fn alloc_contiguous_guarded_gap(
+        &mut self,
+        size: usize,
+        align_log2: usize,
+        guard: usize,
+    ) -> Option<usize> {
+        let res = self
+            .inner
+            .alloc_contiguous_guarded_gap(size, align_log2, guard);
+        self.allocated_block(
+            size.saturating_add(guard.saturating_mul(2)),
+            align_log2,
+            res.map(|base| base - guard),
+        );
+        res
+    }
+    fn alloc_contiguous_flex(
+        &mut self,
+        size: RangeInclusive<usize>,
+        align_log2: usize,
+    ) -> Option<Range<usize>> {
+        let res = self.inner.alloc_contiguous_flex(size.clone(), align_log2);
+        self.allocated_block(
+            res.as_ref().map_or(*size.start(), |r| r.len()),
+            align_log2,
+            res.as_ref().map(|r| r.start),
+        );
+        res
+    }
+    fn alloc_scatter(
+        &mut self,
+        total_size: usize,
+        max_fragments: usize,
+        align_log2: usize,
+        out: &mut [Range<usize>],
+    ) -> Option<usize> {
+        let res = self
+            .inner
+            .alloc_scatter(total_size, max_fragments, align_log2, out);
+        match res {
+            Some(n) => {
+                for block in &out[..n] {
+                    self.allocated_block(block.len(), align_log2, Some(block.start));
+                }
+            }
+            None => {
+                self.allocated_block(total_size, align_log2, None);
+            }
+        }
+        res
+    }
+    fn find_contiguous(&self, size: usize, align_log2: usize) -> Option<usize> {
+        self.inner.find_contiguous(size, align_log2)
+    }
+    fn alloc_aligned(&mut self, align_log2: usize) -> Option<usize> {
+        let res = self.inner.alloc_aligned(align_log2);
+        self.allocated_block(1, align_log2, res)
+    }
+    fn next(&self, key: usize) -> Option<usize> {
+        self.inner.next(key)
+    }
+    fn prev(&self, key: usize) -> Option<usize> {
+        self.inner.prev(key)
+    }
+    fn dealloc(&mut self, key: usize) {
+        self.record(TraceOp::Dealloc(key));
+        self.inner.dealloc(key)
+    }
+    fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        self.record(TraceOp::DeallocContiguous { base, size });
+        self.inner.dealloc_contiguous(base, size)
+    }
+    fn insert(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, Self::CAP);
+        self.record(TraceOp::Insert(range.clone()));
+        self.inner.insert(range)
+    }
+    fn remove(&mut self, range: impl RangeBounds<usize>) {
+        let range = to_range(range, Self::CAP);
+        self.record(TraceOp::Remove(range.clone()));
+        self.inner.remove(range)
+    }
+    fn any(&self) -> bool {
+        self.inner.any()
+    }
+    fn test(&self, key: usize) -> bool {
+        self.inner.test(key)
+    }
+    fn free_count(&self) -> usize {
+        self.inner.free_count()
+    }
+    fn count_free_in(&self, range: impl RangeBounds<usize>) -> usize {
+        self.inner.count_free_in(range)
+    }
+    fn free_prefix(&self) -> usize {
+        self.inner.free_prefix()
+    }
+    fn free_suffix(&self) -> usize {
+        self.inner.free_suffix()
+    }
+    fn max_contiguous_len(&self) -> usize {
+        self.inner.max_contiguous_len()
+    }
+    fn max_contiguous(&self) -> Option<Range<usize>> {
+        self.inner.max_contiguous()
+    }
+    fn search_contiguous(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+    ) -> Option<usize> {
+        self.inner
+            .search_contiguous(offset, size, align_log2, run_start)
+    }
+    fn search_contiguous_bounded(
+        &self,
+        offset: usize,
+        size: usize,
+        align_log2: usize,
+        run_start: &mut Option<usize>,
+        budget: &mut usize,
+    ) -> Result<Option<usize>, BudgetExhausted> {
+        self.inner
+            .search_contiguous_bounded(offset, size, align_log2, run_start, budget)
+    }
+    fn visit_free_runs(&self, run: &mut usize, f: &mut impl FnMut(usize)) {
+        self.inner.visit_free_runs(run, f)
+    }
+    fn next_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.next_allocated(key)
+    }
+    fn prev_allocated(&self, key: usize) -> Option<usize> {
+        self.inner.prev_allocated(key)
+    }
+    fn raw_word(&self, w: usize) -> usize {
+        self.inner.raw_word(w)
+    }
+    fn next_difference(&self, other: &Self, key: usize) -> Option<usize> {
+        self.inner.next_difference(&other.inner, key)
+    }
+    fn is_subset_of(&self, other: &Self) -> bool {
+        self.inner.is_subset_of(&other.inner)
+    }
+    fn is_disjoint_with(&self, other: &Self) -> bool {
+        self.inner.is_disjoint_with(&other.inner)
+    }
+    fn union_with(&mut self, other: &Self) {
+        self.inner.union_with(&other.inner);
+        self.merged();
+    }
+    fn intersect_with(&mut self, other: &Self) {
+        self.inner.intersect_with(&other.inner);
+        self.merged();
+    }
+    fn subtract(&mut self, other: &Self) {
+        self.inner.subtract(&other.inner);
+        self.merged();
+    }
+    fn write_raw_word(&mut self, w: usize, word: usize) {
+        self.record(TraceOp::WriteRawWord(w));
+        self.inner.write_raw_word(w, word)
+    }
+    fn rebuild(&mut self) {
+        self.inner.rebuild()
+    }
+    fn validate(&self) -> Result<(), Inconsistency> {
+        self.inner.validate()
+    }
+    fn iter_free(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.inner.iter_free()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BitAlloc4K;
+
+    #[test]
+    fn trace_ring() {
+        let mut ba = TracedBitAlloc::<BitAlloc4K, 4>::default();
+        assert_eq!(ba.trace().count(), 0);
+        ba.insert(0..100);
+        let key = ba.alloc().unwrap();
+        ba.dealloc(key);
+        assert!(ba.trace().map(|event| event.seq).eq([0, 1, 2]));
+        assert_eq!(ba.trace().nth(1).unwrap().op, TraceOp::Alloc(Some(99)));
+        ba.alloc_contiguous(8, 3);
+        let mut out = [0; 2];
+        ba.alloc_many(&mut out);
+        ba.remove(50..);
+        // the oldest ones were dropped
+        assert_eq!(ba.recorded(), 7);
+        let ops = [
+            TraceOp::Alloc(Some(99)),
+            TraceOp::Alloc(Some(98)),
+            TraceOp::Remove(50..4096),
+        ];
+        assert!(ba.trace().skip(1).map(|event| &event.op).eq(&ops));
+        assert_eq!(ba.trace().next().unwrap().seq, 3);
+        // queries record nothing
+        assert!(ba.test(10));
+        assert_eq!(ba.recorded(), 7);
+        ba.clear_trace();
+        assert_eq!(ba.trace().count(), 0);
+    }
+}
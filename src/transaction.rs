@@ -0,0 +1,179 @@
+use crate::{to_range, BitAlloc, BitAllocError};
+use core::fmt;
+use core::ops::{Range, RangeBounds};
+
+/// A bit range changed by a [`Transaction`], and how.
+#[derive(Debug, Clone)]
+struct Change {
+    range: Range<usize>,
+    freed: bool, // else allocated
+}
+
+/// A series of changes to an allocator, undone together unless committed.
+///
+/// Every step either succeeds or changes nothing, and its undo is logged,
+/// so a multi-step operation can simply return on the first error: the
+/// steps so far are rolled back when the transaction is dropped.
+///
+/// The log holds up to `N` steps; one more panics. Returned by
+/// [`BitAlloc::begin`] with room for 16 steps.
+pub struct Transaction<'a, A: BitAlloc, const N: usize = 16> {
+    ba: &'a mut A,
+    log: [Option<Change>; N],
+    len: usize,
+}
+
+impl<'a, A: BitAlloc, const N: usize> Transaction<'a, A, N> {
+    /// Begin a transaction on `ba`.
+    pub fn new(ba: &'a mut A) -> Self {
+        Transaction {
+            ba,
+            log: [const { None }; N],
+            len: 0,
+        }
+    }
+
+    /// The allocator, as changed so far.
+    pub fn allocator(&self) -> &A {
+        self.ba
+    }
+
+    /// Run the step `f`, logging the bits it changes on success.
+    fn step<T>(
+        &mut self,
+        freed: bool,
+        f: impl FnOnce(&mut A) -> Result<(T, Range<usize>), BitAllocError>,
+    ) -> Result<T, BitAllocError> {
+        assert!(self.len < N, "transaction log is full");
+        let (value, range) = f(self.ba)?;
+        self.log[self.len] = Some(Change { range, freed });
+        self.len += 1;
+        Ok(value)
+    }
+
+    /// Allocate a free bit.
+    pub fn alloc(&mut self) -> Result<usize, BitAllocError> {
+        self.step(false, |ba| {
+            let key = ba.try_alloc()?;
+            Ok((key, key..key + 1))
+        })
+    }
+
+    /// Allocate a free block with a given size, and return the first bit
+    /// position.
+    pub fn alloc_contiguous(
+        &mut self,
+        size: usize,
+        align_log2: usize,
+    ) -> Result<usize, BitAllocError> {
+        self.step(false, |ba| {
+            let base = ba
+                .alloc_contiguous(size, align_log2)
+                .ok_or(BitAllocError::NoSpace)?;
+            Ok((base, base..base + size))
+        })
+    }
+
+    /// Free an allocated bit.
+    pub fn dealloc(&mut self, key: usize) -> Result<(), BitAllocError> {
+        self.step(true, |ba| {
+            ba.try_dealloc(key)?;
+            Ok(((), key..key + 1))
+        })
+    }
+
+    /// Mark bits in the range as available, requiring that all of them were
+    /// unavailable, as `try_insert`.
+    pub fn insert(&mut self, range: impl RangeBounds<usize>) -> Result<(), BitAllocError> {
+        let range = to_range(range, A::CAP);
+        self.step(true, |ba| {
+            ba.try_insert(range.clone())?;
+            Ok(((), range))
+        })
+    }
+
+    /// Mark bits in the range as unavailable, requiring that all of them were
+    /// available, as `try_remove`. E.g. to reserve an MMIO hole.
+    pub fn remove(&mut self, range: impl RangeBounds<usize>) -> Result<(), BitAllocError> {
+        let range = to_range(range, A::CAP);
+        self.step(false, |ba| {
+            ba.try_remove(range.clone())?;
+            Ok(((), range))
+        })
+    }
+
+    /// Keep all changes.
+    pub fn commit(mut self) {
+        self.len = 0;
+    }
+
+    /// Undo all changes, latest first. Dropping the transaction does the
+    /// same.
+    pub fn rollback(self) {}
+}
+
+impl<A: BitAlloc, const N: usize> Drop for Transaction<'_, A, N> {
+    fn drop(&mut self) {
+        for change in self.log[..self.len].iter_mut().rev() {
+            let Change { range, freed } = change.take().unwrap();
+            if freed {
+                self.ba.remove(range);
+            } else {
+                self.ba.insert(range);
+            }
+        }
+    }
+}
+
+impl<A: BitAlloc, const N: usize> fmt::Debug for Transaction<'_, A, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Transaction")
+            .field("steps", &self.len)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BitAlloc, BitAlloc4K, BitAllocError};
+
+    /// One frame, a 16-frame block and an MMIO hole, or nothing.
+    fn setup(ba: &mut BitAlloc4K, hole: core::ops::Range<usize>) -> Result<usize, BitAllocError> {
+        let mut tx = ba.begin();
+        let frame = tx.alloc()?;
+        tx.alloc_contiguous(16, 4)?;
+        tx.remove(hole)?;
+        tx.commit();
+        Ok(frame)
+    }
+
+    #[test]
+    fn transaction_rollback() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(..);
+        let before = ba.clone();
+        // the hole overlaps the block, so the whole setup is undone
+        assert_eq!(setup(&mut ba, 8..24), Err(BitAllocError::AlreadyAllocated));
+        assert!(ba == before);
+        assert_eq!(setup(&mut ba, 100..200), Ok(4095));
+        assert_eq!(ba.free_count(), 4096 - 1 - 16 - 100);
+        let mut tx = ba.begin();
+        tx.dealloc(4095).unwrap();
+        tx.insert(100..200).unwrap();
+        assert_eq!(tx.dealloc(4095), Err(BitAllocError::AlreadyFree));
+        assert_eq!(tx.allocator().free_count(), 4096 - 16);
+        tx.rollback();
+        assert_eq!(ba.free_count(), 4096 - 1 - 16 - 100);
+    }
+
+    #[test]
+    #[should_panic(expected = "log is full")]
+    fn transaction_full() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(..);
+        let mut tx = super::Transaction::<_, 2>::new(&mut ba);
+        for _ in 0..3 {
+            tx.alloc().unwrap();
+        }
+    }
+}
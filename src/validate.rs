@@ -0,0 +1,77 @@
+use core::fmt;
+
+/// A place where the summaries of an allocator disagree with what they
+/// summarize, found by [`BitAlloc::validate`](crate::BitAlloc::validate).
+///
+/// `base` is the first bit of the node or child at fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// The summary bit of a child says whether it has free bits, wrongly.
+    Summary { base: usize },
+    /// The free count of a node is not the sum of those of its children.
+    FreeCount { base: usize },
+    /// The free-run lengths of a node don't match its children.
+    Runs { base: usize },
+}
+
+impl Inconsistency {
+    /// The first bit of the node or child at fault.
+    pub fn base(&self) -> usize {
+        match *self {
+            Inconsistency::Summary { base }
+            | Inconsistency::FreeCount { base }
+            | Inconsistency::Runs { base } => base,
+        }
+    }
+
+    /// The same inconsistency, in a child starting at bit `offset`.
+    pub(crate) fn offset(self, offset: usize) -> Self {
+        match self {
+            Inconsistency::Summary { base } => Inconsistency::Summary {
+                base: base + offset,
+            },
+            Inconsistency::FreeCount { base } => Inconsistency::FreeCount {
+                base: base + offset,
+            },
+            Inconsistency::Runs { base } => Inconsistency::Runs {
+                base: base + offset,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let what = match self {
+            Inconsistency::Summary { .. } => "summary bit",
+            Inconsistency::FreeCount { .. } => "free count",
+            Inconsistency::Runs { .. } => "free-run lengths",
+        };
+        write!(f, "wrong {} at bit {}", what, self.base())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Inconsistency;
+    use crate::{BitAlloc, BitAlloc64K};
+
+    #[test]
+    fn validate_raw_writes() {
+        let mut ba = BitAlloc64K::default();
+        ba.insert(100..5000);
+        ba.dealloc_contiguous(0, 3);
+        assert_eq!(ba.validate(), Ok(()));
+        // a free bit under a summary bit saying there is none
+        ba.write_raw_word(100, 1);
+        assert_eq!(ba.validate(), Err(Inconsistency::Summary { base: 6400 }));
+        ba.rebuild();
+        assert_eq!(ba.validate(), Ok(()));
+        // a leaf losing a free bit its parents still count
+        ba.write_raw_word(0, 0b11);
+        assert_eq!(ba.validate(), Err(Inconsistency::FreeCount { base: 0 }));
+        ba.rebuild();
+        assert_eq!(ba.validate(), Ok(()));
+        assert_eq!(ba.free_count(), 4900 + 2 + 1);
+    }
+}
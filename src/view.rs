@@ -0,0 +1,279 @@
+use crate::{align_up, to_range, BitAlloc, BitAllocError, DynBitAlloc};
+use core::ops::{Range, RangeBounds};
+
+/// A view of the bits in `base..base + len` of an allocator, as bits
+/// `0..len` of one of its own, e.g. to give a driver its slice of the
+/// index space and nothing else.
+///
+/// It has the core methods of [`BitAlloc`], with indices rebased to 0 and
+/// checked against `len`: those outside the view panic, as out-of-range
+/// indices do in `BitAlloc`. It also implements [`DynBitAlloc`], so it
+/// can be handed out as a `&mut dyn DynBitAlloc`.
+///
+/// Returned by [`BitAlloc::view`].
+pub struct BitAllocView<'a, A: BitAlloc> {
+    parent: &'a mut A,
+    base: usize,
+    len: usize,
+}
+
+impl<'a, A: BitAlloc> BitAllocView<'a, A> {
+    pub(crate) fn new(parent: &'a mut A, range: Range<usize>) -> Self {
+        assert!(range.start <= range.end && range.end <= parent.capacity());
+        BitAllocView {
+            parent,
+            base: range.start,
+            len: range.len(),
+        }
+    }
+
+    /// The bits of the parent the view covers.
+    pub fn range(&self) -> Range<usize> {
+        self.base..self.base + self.len
+    }
+
+    /// The number of bits in the view.
+    pub fn capacity(&self) -> usize {
+        self.len
+    }
+
+    /// Rebase a range of the view onto the parent, checking it fits.
+    fn rebase(&self, range: impl RangeBounds<usize>) -> Range<usize> {
+        let range = to_range(range, self.len);
+        assert!(
+            range.start >= range.end || range.end <= self.len,
+            "range {:?} is outside the view",
+            range
+        );
+        self.base + range.start..self.base + range.end
+    }
+
+    fn rebase_key(&self, key: usize) -> usize {
+        assert!(key < self.len, "bit {} is outside the view", key);
+        self.base + key
+    }
+
+    /// Allocate the highest free bit, as `BitAlloc::alloc` does.
+    pub fn alloc(&mut self) -> Option<usize> {
+        let key = self.prev(self.len.checked_sub(1)?)?;
+        self.parent.remove(self.base + key..self.base + key + 1);
+        Some(key)
+    }
+
+    /// Allocate the lowest free bit.
+    pub fn alloc_low(&mut self) -> Option<usize> {
+        let key = self.next(0)?;
+        self.parent.remove(self.base + key..self.base + key + 1);
+        Some(key)
+    }
+
+    /// Allocate a free block of `size` bits whose first bit, counted in the
+    /// view, is aligned to `1 << align_log2`, the lowest such block.
+    pub fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        if size == 0 || size > self.len || align_log2 >= usize::BITS as usize {
+            return None;
+        }
+        let mut key = 0;
+        while let Some(start) = self.next(key) {
+            let end = self.next_allocated(start).unwrap_or(self.len);
+            let base = align_up(start, align_log2);
+            if base.saturating_add(size) <= end {
+                self.parent
+                    .remove(self.base + base..self.base + base + size);
+                return Some(base);
+            }
+            key = end;
+        }
+        None
+    }
+
+    /// Free an allocated bit.
+    pub fn dealloc(&mut self, key: usize) {
+        let key = self.rebase_key(key);
+        self.parent.dealloc(key)
+    }
+
+    /// Free an allocated bit, or report why it can't be freed.
+    pub fn try_dealloc(&mut self, key: usize) -> Result<(), BitAllocError> {
+        if key >= self.len {
+            return Err(BitAllocError::OutOfRange);
+        }
+        self.parent.try_dealloc(self.base + key)
+    }
+
+    /// Free a block from `alloc_contiguous`.
+    pub fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        let range = self.rebase(base..base + size);
+        self.parent.dealloc_contiguous(range.start, size)
+    }
+
+    /// Mark bits in the range as unallocated (available).
+    pub fn insert(&mut self, range: impl RangeBounds<usize>) {
+        let range = self.rebase(range);
+        self.parent.insert(range)
+    }
+
+    /// Reverse of insert.
+    pub fn remove(&mut self, range: impl RangeBounds<usize>) {
+        let range = self.rebase(range);
+        self.parent.remove(range)
+    }
+
+    /// Whether there are free bits remaining
+    pub fn any(&self) -> bool {
+        self.next(0).is_some()
+    }
+
+    /// Whether a specific bit is free
+    pub fn test(&self, key: usize) -> bool {
+        self.parent.test(self.rebase_key(key))
+    }
+
+    /// The number of free bits.
+    pub fn free_count(&self) -> usize {
+        self.parent.count_free_in(self.range())
+    }
+
+    /// The number of free bits in the range.
+    pub fn count_free_in(&self, range: impl RangeBounds<usize>) -> usize {
+        self.parent.count_free_in(self.rebase(range))
+    }
+
+    /// Find a index not less than a given key, where the bit is free.
+    pub fn next(&self, key: usize) -> Option<usize> {
+        if key >= self.len {
+            return None;
+        }
+        let i = self.parent.next(self.base + key)?;
+        Some(i - self.base).filter(|&i| i < self.len)
+    }
+
+    /// Find the greatest index not greater than a given key, where the bit is free.
+    /// Keys beyond the end are treated as the last bit.
+    pub fn prev(&self, key: usize) -> Option<usize> {
+        let key = key.min(self.len.checked_sub(1)?);
+        let i = self.parent.prev(self.base + key)?;
+        i.checked_sub(self.base)
+    }
+
+    /// Find a index not less than a given key, where the bit is allocated.
+    pub fn next_allocated(&self, key: usize) -> Option<usize> {
+        if key >= self.len {
+            return None;
+        }
+        let i = self.parent.next_allocated(self.base + key)?;
+        Some(i - self.base).filter(|&i| i < self.len)
+    }
+
+    /// Find the greatest index not greater than a given key, where the bit
+    /// is allocated. Keys beyond the end are treated as the last bit.
+    pub fn prev_allocated(&self, key: usize) -> Option<usize> {
+        let key = key.min(self.len.checked_sub(1)?);
+        let i = self.parent.prev_allocated(self.base + key)?;
+        i.checked_sub(self.base)
+    }
+
+    /// The length of the largest run of free bits.
+    pub fn max_contiguous_len(&self) -> usize {
+        let mut best = 0;
+        let mut key = 0;
+        while let Some(start) = self.next(key) {
+            let end = self.next_allocated(start).unwrap_or(self.len);
+            best = best.max(end - start);
+            key = end;
+        }
+        best
+    }
+}
+
+impl<A: BitAlloc> DynBitAlloc for BitAllocView<'_, A> {
+    fn dyn_capacity(&self) -> usize {
+        self.capacity()
+    }
+    fn dyn_alloc(&mut self) -> Option<usize> {
+        self.alloc()
+    }
+    fn dyn_alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        self.alloc_contiguous(size, align_log2)
+    }
+    fn dyn_dealloc(&mut self, key: usize) {
+        self.dealloc(key)
+    }
+    fn dyn_dealloc_contiguous(&mut self, base: usize, size: usize) {
+        self.dealloc_contiguous(base, size)
+    }
+    fn dyn_try_dealloc(&mut self, key: usize) -> Result<(), BitAllocError> {
+        self.try_dealloc(key)
+    }
+    fn dyn_insert(&mut self, range: Range<usize>) {
+        self.insert(range)
+    }
+    fn dyn_remove(&mut self, range: Range<usize>) {
+        self.remove(range)
+    }
+    fn dyn_any(&self) -> bool {
+        self.any()
+    }
+    fn dyn_test(&self, key: usize) -> bool {
+        self.test(key)
+    }
+    fn dyn_free_count(&self) -> usize {
+        self.free_count()
+    }
+    fn dyn_count_free_in(&self, range: Range<usize>) -> usize {
+        self.count_free_in(range)
+    }
+    fn dyn_max_contiguous_len(&self) -> usize {
+        self.max_contiguous_len()
+    }
+    fn dyn_next(&self, key: usize) -> Option<usize> {
+        self.next(key)
+    }
+    fn dyn_prev(&self, key: usize) -> Option<usize> {
+        self.prev(key)
+    }
+    fn dyn_next_allocated(&self, key: usize) -> Option<usize> {
+        self.next_allocated(key)
+    }
+    fn dyn_prev_allocated(&self, key: usize) -> Option<usize> {
+        self.prev_allocated(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BitAlloc, BitAlloc4K, DynBitAlloc};
+
+    #[test]
+    fn view_rebased() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(..);
+        let mut view = ba.view(1000..1100);
+        assert_eq!((view.capacity(), view.free_count()), (100, 100));
+        assert_eq!(view.alloc(), Some(99));
+        assert_eq!(view.alloc_low(), Some(0));
+        // aligned within the view, not the parent
+        assert_eq!(view.alloc_contiguous(10, 4), Some(16));
+        assert_eq!(view.alloc_contiguous(60, 0), Some(26));
+        assert_eq!(view.alloc_contiguous(20, 0), None);
+        view.remove(..);
+        assert!(!view.any());
+        assert_eq!((view.next(0), view.prev(1000)), (None, None));
+        view.insert(50..60);
+        assert_eq!((view.next(0), view.prev(1000)), (Some(50), Some(59)));
+        assert_eq!(view.next_allocated(50), Some(60));
+        let dynamic: &mut dyn DynBitAlloc = &mut view;
+        assert_eq!(dynamic.capacity(), 100);
+        assert!(dynamic.try_dealloc(100).is_err());
+        // nothing outside was touched
+        assert_eq!(ba.free_count(), 4096 - 100 + 10);
+        assert_eq!(ba.next(1000), Some(1050));
+    }
+
+    #[test]
+    #[should_panic(expected = "outside the view")]
+    fn view_bounds() {
+        let mut ba = BitAlloc4K::default();
+        ba.view(10..20).insert(5..11);
+    }
+}
@@ -0,0 +1,148 @@
+use crate::{BitAlloc, NextFit};
+use core::ops::RangeBounds;
+
+/// A wrapper leveling wear over `R` equal regions, as the free-block map
+/// of a flash translation layer: it counts the bits handed out in every
+/// region, and `alloc` takes its bit from the least worn region with room.
+///
+/// Within a region, and for `alloc_contiguous`, allocation goes round
+/// robin as with [`NextFit`], so a bit just freed is not reused before the
+/// others around it, and equally worn regions take turns. The counts saturate at `u32::MAX`, and can be saved
+/// and restored with `wear` and `set_wear`. `R` must divide `A::CAP`.
+#[derive(Debug, Clone)]
+pub struct WearLevelBitAlloc<A: BitAlloc, const R: usize> {
+    inner: NextFit<A>,
+    wear: [u32; R],
+    cursors: [usize; R], // where the next search starts in each region
+    region: usize,       // the region to try first among equally worn ones
+}
+
+impl<A: BitAlloc, const R: usize> WearLevelBitAlloc<A, R> {
+    /// Wrap an allocator, with no wear anywhere.
+    pub fn new(inner: A) -> Self {
+        assert!(
+            R > 0 && A::CAP.is_multiple_of(R),
+            "regions must split the allocator evenly"
+        );
+        WearLevelBitAlloc {
+            inner: NextFit::new(inner),
+            wear: [0; R],
+            cursors: [0; R],
+            region: 0,
+        }
+    }
+
+    /// The underlying allocator.
+    pub fn inner(&self) -> &A {
+        self.inner.inner()
+    }
+
+    /// Unwrap the underlying allocator.
+    pub fn into_inner(self) -> A {
+        self.inner.into_inner()
+    }
+
+    /// The number of bits in each region.
+    pub fn region_len(&self) -> usize {
+        A::CAP / R
+    }
+
+    /// The number of bits handed out in region `r` so far.
+    pub fn wear(&self, r: usize) -> u32 {
+        self.wear[r]
+    }
+
+    /// Set the wear of region `r`, e.g. to the erase count kept on flash.
+    pub fn set_wear(&mut self, r: usize, wear: u32) {
+        self.wear[r] = wear;
+    }
+
+    /// Allocate a free bit from the least worn region with one.
+    pub fn alloc(&mut self) -> Option<usize> {
+        let len = self.region_len();
+        let r = (self.region..self.region + R)
+            .map(|r| r % R)
+            .filter(|&r| self.inner.any_free(r * len..(r + 1) * len))
+            .min_by_key(|&r| self.wear[r])?;
+        let key = self
+            .inner
+            .next(r * len + self.cursors[r])
+            .filter(|&key| key < (r + 1) * len)
+            .or_else(|| self.inner.next(r * len))?;
+        self.inner.remove(key..key + 1);
+        self.cursors[r] = (key + 1) % len;
+        self.region = (r + 1) % R;
+        self.charge(key, 1);
+        Some(key)
+    }
+
+    /// Allocate a free block like `alloc_contiguous`, resuming where the
+    /// previous one stopped.
+    pub fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        let base = self.inner.alloc_contiguous(size, align_log2)?;
+        self.charge(base, size);
+        Some(base)
+    }
+
+    /// Free an allocated bit.
+    pub fn dealloc(&mut self, key: usize) {
+        self.inner.dealloc(key)
+    }
+
+    /// Free a block of `size` allocated bits starting at `base`.
+    pub fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        self.inner.dealloc_contiguous(base, size)
+    }
+
+    /// Mark bits in the range as unallocated (available).
+    pub fn insert(&mut self, range: impl RangeBounds<usize>) {
+        self.inner.insert(range)
+    }
+
+    /// Reverse of insert.
+    pub fn remove(&mut self, range: impl RangeBounds<usize>) {
+        self.inner.remove(range)
+    }
+
+    /// Add the `size` bits from `base` on to the wear of their regions.
+    fn charge(&mut self, base: usize, size: usize) {
+        let len = self.region_len();
+        let mut key = base;
+        while key < base + size {
+            let end = ((key / len + 1) * len).min(base + size);
+            let wear = &mut self.wear[key / len];
+            *wear = wear.saturating_add((end - key) as u32);
+            key = end;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BitAlloc256;
+
+    #[test]
+    fn wear_leveled() {
+        let mut ba = WearLevelBitAlloc::<BitAlloc256, 4>::new(BitAlloc256::default());
+        assert_eq!(ba.alloc(), None);
+        ba.insert(..);
+        // allocating and freeing the same block moves on through the regions
+        let keys: [usize; 8] = core::array::from_fn(|_| {
+            let key = ba.alloc().unwrap();
+            ba.dealloc(key);
+            key
+        });
+        assert_eq!(keys, [0, 64, 128, 192, 1, 65, 129, 193]);
+        assert_eq!(ba.wear(0), 2);
+        assert_eq!(ba.alloc_contiguous(60, 0), Some(0));
+        assert_eq!((ba.wear(0), ba.wear(1)), (62, 2));
+        // the least worn regions are drained first
+        ba.set_wear(1, 1000);
+        for _ in 0..4 + 64 + 64 {
+            assert!(ba.alloc().unwrap() / 64 != 1);
+        }
+        assert_eq!(ba.alloc().map(|key| key / 64), Some(1));
+        assert_eq!(ba.region_len(), 64);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::{align_size, BitAlloc};
+use core::fmt;
+use core::ops::Range;
+
+/// `N` allocators of type `A` side by side, addressed by `u64` indices, so
+/// that a 32-bit kernel can manage more than `usize::MAX` bits, e.g. the
+/// frames of a PAE machine with more memory than a 32-bit index reaches.
+///
+/// Part `n` owns `n * A::CAP..(n + 1) * A::CAP`. Each part is an ordinary
+/// allocator with `usize` indices, and only the arithmetic across parts is
+/// done in `u64`. Blocks never span two parts.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct BitAllocWide<A: BitAlloc, const N: usize> {
+    parts: [A; N],
+}
+
+impl<A: BitAlloc, const N: usize> BitAllocWide<A, N> {
+    /// The number of bits, `N * A::CAP`.
+    pub const CAP: u64 = N as u64 * A::CAP as u64;
+
+    /// Create an allocator with all bits allocated.
+    pub fn new() -> Self {
+        BitAllocWide {
+            parts: [A::DEFAULT; N],
+        }
+    }
+
+    /// The allocator of part `n`, in its own indices.
+    pub fn part(&self, n: usize) -> &A {
+        &self.parts[n]
+    }
+
+    /// The allocator of part `n`, mutably.
+    pub fn part_mut(&mut self, n: usize) -> &mut A {
+        &mut self.parts[n]
+    }
+
+    /// The first bit of part `n`.
+    fn base(n: usize) -> u64 {
+        n as u64 * A::CAP as u64
+    }
+
+    /// The part owning `key`, and the index of `key` in it.
+    fn locate(key: u64) -> (usize, usize) {
+        assert!(key < Self::CAP, "bit {} out of range", key);
+        let n = (key / A::CAP as u64) as usize;
+        (n, (key - Self::base(n)) as usize)
+    }
+
+    /// Allocate a free bit, from the highest part with one.
+    pub fn alloc(&mut self) -> Option<u64> {
+        (0..N)
+            .rev()
+            .find_map(|n| Some(Self::base(n) + self.parts[n].alloc()? as u64))
+    }
+
+    /// Allocate a free block with a given size, and return the first bit
+    /// position, from the lowest part with room. The block is aligned in
+    /// the `u64` index space.
+    pub fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<u64> {
+        let align = align_size(align_log2)? as u64;
+        if size == 0 {
+            return None;
+        }
+        (0..N).find_map(|n| {
+            // search from the base modulo the alignment, so that the
+            // alignment is a shared one
+            let offset = (Self::base(n) % align) as usize;
+            let key = self.parts[n].search_contiguous(offset, size, align_log2, &mut None)?;
+            self.parts[n].remove(key - offset..key - offset + size);
+            Some(Self::base(n) + (key - offset) as u64)
+        })
+    }
+
+    /// Free an allocated bit.
+    pub fn dealloc(&mut self, key: u64) {
+        let (n, key) = Self::locate(key);
+        self.parts[n].dealloc(key)
+    }
+
+    /// Free a block allocated by `alloc_contiguous`.
+    pub fn dealloc_contiguous(&mut self, base: u64, size: usize) {
+        let (n, key) = Self::locate(base);
+        self.parts[n].dealloc_contiguous(key, size)
+    }
+
+    /// Mark bits in the range as unallocated (available). The range may
+    /// span several parts.
+    pub fn insert(&mut self, range: Range<u64>) {
+        self.for_range(range, |part, range| part.insert(range))
+    }
+
+    /// Reverse of insert.
+    pub fn remove(&mut self, range: Range<u64>) {
+        self.for_range(range, |part, range| part.remove(range))
+    }
+
+    fn for_range(&mut self, range: Range<u64>, mut f: impl FnMut(&mut A, Range<usize>)) {
+        assert!(range.end <= Self::CAP, "range {:?} out of range", range);
+        for n in 0..N {
+            let base = Self::base(n);
+            let start = range.start.max(base);
+            let end = range.end.min(base + A::CAP as u64);
+            if start < end {
+                f(
+                    &mut self.parts[n],
+                    (start - base) as usize..(end - base) as usize,
+                );
+            }
+        }
+    }
+
+    /// Whether a specific bit is free.
+    pub fn test(&self, key: u64) -> bool {
+        let (n, key) = Self::locate(key);
+        self.parts[n].test(key)
+    }
+
+    /// Find a index not less than a given key, where the bit is free.
+    pub fn next(&self, key: u64) -> Option<u64> {
+        if key >= Self::CAP {
+            return None;
+        }
+        let (first, key) = Self::locate(key);
+        (first..N).find_map(|n| {
+            let from = if n == first { key } else { 0 };
+            Some(Self::base(n) + self.parts[n].next(from)? as u64)
+        })
+    }
+
+    /// The number of free bits in all parts.
+    pub fn free_count(&self) -> u64 {
+        self.parts.iter().map(|part| part.free_count() as u64).sum()
+    }
+}
+
+impl<A: BitAlloc, const N: usize> Default for BitAllocWide<A, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: BitAlloc, const N: usize> fmt::Debug for BitAllocWide<A, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BitAllocWide")
+            .field("cap", &Self::CAP)
+            .field("free", &self.free_count())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitAlloc256, BitAlloc256M, BitAllocConst};
+
+    #[test]
+    fn wide_parts() {
+        // 8G bits, past the reach of a 32-bit index
+        assert_eq!(BitAllocWide::<BitAlloc256M, 32>::CAP, 1 << 33);
+        // parts of 100 bits, so part bases are not aligned
+        let mut ba = BitAllocWide::<BitAllocConst<100, BitAlloc256>, 4>::new();
+        ba.insert(50..350);
+        assert_eq!(ba.free_count(), 300);
+        assert!(ba.test(199) && ba.test(200) && !ba.test(350));
+        assert_eq!(ba.alloc(), Some(349));
+        assert_eq!(ba.next(100), Some(100));
+        ba.remove(60..250);
+        assert_eq!(ba.next(55), Some(55));
+        assert_eq!(ba.next(60), Some(250));
+        // the first block aligned to 64 is in part 2, at 256
+        assert_eq!(ba.alloc_contiguous(32, 6), Some(256));
+        assert!(!ba.test(287) && ba.test(288));
+        ba.dealloc_contiguous(256, 32);
+        ba.dealloc(349);
+        assert_eq!(ba.free_count(), 110);
+        assert_eq!(ba.next(350), None);
+        assert_eq!(ba.next(1000), None);
+    }
+}
@@ -0,0 +1,57 @@
+use crate::BitAlloc;
+use core::fmt;
+use core::ops::Range;
+
+/// A window onto part of a bitmap, for printing it with `{:b}` or `{:x}`.
+///
+/// The window is cut into chunks of 16 bits in ascending order, separated
+/// by `_`. Each chunk is printed as a number whose bit `i` is 1 if bit
+/// `start + i` of the chunk is free, so a `BitAlloc16` prints just like its
+/// `u16`. The last chunk may be shorter. Nothing is buffered.
+///
+/// Returned by [`BitAlloc::fmt_range`].
+pub struct BitmapWindow<'a, A> {
+    ba: &'a A,
+    range: Range<usize>,
+}
+
+impl<'a, A: BitAlloc> BitmapWindow<'a, A> {
+    pub(crate) fn new(ba: &'a A, range: Range<usize>) -> Self {
+        assert!(range.start <= range.end);
+        assert!(range.end <= ba.capacity());
+        BitmapWindow { ba, range }
+    }
+
+    /// Print every chunk with `f`, given its bits and its length.
+    fn chunks(
+        &self,
+        f: &mut fmt::Formatter,
+        mut chunk: impl FnMut(&mut fmt::Formatter, u16, usize) -> fmt::Result,
+    ) -> fmt::Result {
+        for start in self.range.clone().step_by(16) {
+            let len = (self.range.end - start).min(16);
+            let bits = (0..len)
+                .filter(|&i| self.ba.test(start + i))
+                .fold(0u16, |bits, i| bits | 1 << i);
+            if start != self.range.start {
+                f.write_str("_")?;
+            }
+            chunk(f, bits, len)?;
+        }
+        Ok(())
+    }
+}
+
+impl<A: BitAlloc> fmt::Binary for BitmapWindow<'_, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.chunks(f, |f, bits, len| write!(f, "{:01$b}", bits, len))
+    }
+}
+
+impl<A: BitAlloc> fmt::LowerHex for BitmapWindow<'_, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.chunks(f, |f, bits, len| {
+            write!(f, "{:01$x}", bits, len.div_ceil(4))
+        })
+    }
+}
@@ -0,0 +1,71 @@
+//! Frame allocation for the `x86_64` crate's paging, enabled by the
+//! `x86_64` feature.
+
+use crate::{BitAlloc, FrameAlloc};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PageSize, PhysFrame};
+use x86_64::PhysAddr;
+
+/// The number of frames of `fa` making up one page of size `S`.
+fn frames_per_page<A: BitAlloc, S: PageSize>(fa: &FrameAlloc<A>) -> usize {
+    let size = S::SIZE as usize;
+    assert!(
+        size >= fa.frame_size(),
+        "pages must not be smaller than frames"
+    );
+    size / fa.frame_size()
+}
+
+/// Pages of any size `S`, made of contiguous frames aligned to `S::SIZE`
+/// in physical memory, e.g. single frames for `Size4KiB` when `frame_size`
+/// is 4096.
+unsafe impl<A: BitAlloc, S: PageSize> FrameAllocator<S> for FrameAlloc<A> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<S>> {
+        let count = frames_per_page::<A, S>(self);
+        let addr = if count == 1 {
+            assert_eq!(
+                self.base_addr() % self.frame_size(),
+                0,
+                "base address is not aligned to the frame size"
+            );
+            self.alloc_frame()?
+        } else {
+            self.alloc_contiguous_frames(count, S::SIZE as usize)?
+        };
+        Some(PhysFrame::containing_address(PhysAddr::new(addr as u64)))
+    }
+}
+
+impl<A: BitAlloc, S: PageSize> FrameDeallocator<S> for FrameAlloc<A> {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<S>) {
+        let count = frames_per_page::<A, S>(self);
+        let addr = frame.start_address().as_u64() as usize;
+        if count == 1 {
+            self.dealloc_frame(addr);
+        } else {
+            self.dealloc_contiguous_frames(addr, count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BitAlloc4K;
+    use x86_64::structures::paging::{Size2MiB, Size4KiB};
+
+    #[test]
+    fn x86_64_frames() {
+        let mut fa = FrameAlloc::new(0x4000_0000, 4096, BitAlloc4K::default());
+        fa.inner_mut().insert(..);
+        let frame: PhysFrame<Size4KiB> = fa.allocate_frame().unwrap();
+        assert_eq!(frame.start_address().as_u64(), 0x4000_0000 + 4095 * 4096);
+        let huge: PhysFrame<Size2MiB> = fa.allocate_frame().unwrap();
+        assert_eq!(huge.start_address().as_u64(), 0x4000_0000);
+        assert_eq!(fa.inner().free_count(), 4096 - 1 - 512);
+        unsafe {
+            fa.deallocate_frame(huge);
+            fa.deallocate_frame(frame);
+        }
+        assert_eq!(fa.inner().free_count(), 4096);
+    }
+}
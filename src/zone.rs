@@ -0,0 +1,296 @@
+use crate::{align_size, align_up, BitAlloc};
+use core::fmt;
+use core::ops::Range;
+
+/// A named part of the index space of a [`ZonedBitAlloc`], e.g. the frames
+/// of `DMA` below 16 MiB.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Zone {
+    /// The name, for lookups and debugging.
+    pub name: &'static str,
+    /// The bits of the zone.
+    pub range: Range<usize>,
+}
+
+impl Zone {
+    /// A zone of the bits in `range`.
+    pub const fn new(name: &'static str, range: Range<usize>) -> Self {
+        Zone { name, range }
+    }
+}
+
+/// One allocator split into `Z` zones, like `DMA`, `DMA32` and `Normal`,
+/// which are tried in a fallback order.
+///
+/// Requests may be limited to one zone, or to the bits below some limit,
+/// e.g. for a device that only reaches the low 4 GiB. Bits outside every
+/// zone are never allocated.
+///
+/// By default, zones are tried from the highest one down, so the scarce low
+/// zones are kept for the requests that need them.
+#[derive(Clone)]
+pub struct ZonedBitAlloc<A: BitAlloc, const Z: usize> {
+    inner: A,
+    zones: [Zone; Z],
+    fallback: [usize; Z], // indices of the zones, in the order they are tried
+}
+
+impl<A: BitAlloc, const Z: usize> ZonedBitAlloc<A, Z> {
+    /// Split `inner` into `zones`, which must not overlap and must lie within
+    /// `0..A::CAP`.
+    pub fn new(inner: A, zones: [Zone; Z]) -> Self {
+        for (i, zone) in zones.iter().enumerate() {
+            assert!(zone.range.start <= zone.range.end);
+            assert!(
+                zone.range.end <= A::CAP,
+                "zone {} is out of range",
+                zone.name
+            );
+            for other in &zones[..i] {
+                assert!(
+                    zone.range.end <= other.range.start || other.range.end <= zone.range.start,
+                    "zones {} and {} overlap",
+                    other.name,
+                    zone.name
+                );
+            }
+        }
+        let mut fallback = [0; Z];
+        for (i, z) in fallback.iter_mut().enumerate() {
+            *z = i;
+        }
+        fallback.sort_unstable_by_key(|&z| core::cmp::Reverse(zones[z].range.start));
+        ZonedBitAlloc {
+            inner,
+            zones,
+            fallback,
+        }
+    }
+
+    /// Set the order zones are tried in, as a permutation of their indices.
+    pub fn set_fallback(&mut self, order: [usize; Z]) {
+        let mut seen = [false; Z];
+        for &z in &order {
+            assert!(!seen[z], "zone {} appears twice in the fallback order", z);
+            seen[z] = true;
+        }
+        self.fallback = order;
+    }
+
+    /// The zones, in the order given to `new`.
+    pub fn zones(&self) -> &[Zone; Z] {
+        &self.zones
+    }
+
+    /// The index of the zone called `name`.
+    pub fn zone(&self, name: &str) -> Option<usize> {
+        self.zones.iter().position(|zone| zone.name == name)
+    }
+
+    /// The index of the zone holding `key`.
+    pub fn zone_of(&self, key: usize) -> Option<usize> {
+        self.zones.iter().position(|zone| zone.range.contains(&key))
+    }
+
+    /// The underlying allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// The underlying allocator, mutably.
+    pub fn inner_mut(&mut self) -> &mut A {
+        &mut self.inner
+    }
+
+    /// The bits of zone `z` below `limit`.
+    fn bounded(&self, z: usize, limit: usize) -> Range<usize> {
+        let range = &self.zones[z].range;
+        range.start..range.end.min(limit)
+    }
+
+    /// Allocate a free bit, from the first zone in the fallback order that
+    /// has one.
+    pub fn alloc(&mut self) -> Option<usize> {
+        self.alloc_below(usize::MAX)
+    }
+
+    /// Allocate a free bit below `limit`, trying zones in the fallback order.
+    pub fn alloc_below(&mut self, limit: usize) -> Option<usize> {
+        let fallback = self.fallback;
+        fallback
+            .iter()
+            .find_map(|&z| self.alloc_in_range(self.bounded(z, limit)))
+    }
+
+    /// Allocate a free bit from zone `z` only.
+    pub fn alloc_in(&mut self, z: usize) -> Option<usize> {
+        self.alloc_in_range(self.zones[z].range.clone())
+    }
+
+    /// The highest free bit in `range`, as `alloc` picks.
+    fn alloc_in_range(&mut self, range: Range<usize>) -> Option<usize> {
+        if range.start >= range.end {
+            return None;
+        }
+        let i = self
+            .inner
+            .prev(range.end - 1)
+            .filter(|&i| i >= range.start)?;
+        self.inner.remove(i..i + 1);
+        Some(i)
+    }
+
+    /// Allocate a free block with a given size, and return the first bit
+    /// position, from the first zone in the fallback order with room.
+    /// A block never spans two zones.
+    pub fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        self.alloc_contiguous_below(usize::MAX, size, align_log2)
+    }
+
+    /// Allocate a free block like `alloc_contiguous`, ending at or below
+    /// `limit`.
+    pub fn alloc_contiguous_below(
+        &mut self,
+        limit: usize,
+        size: usize,
+        align_log2: usize,
+    ) -> Option<usize> {
+        let fallback = self.fallback;
+        fallback.iter().find_map(|&z| {
+            let range = self.bounded(z, limit);
+            self.alloc_contiguous_in_range(range, size, align_log2)
+        })
+    }
+
+    /// Allocate a free block like `alloc_contiguous`, from zone `z` only.
+    pub fn alloc_contiguous_in(
+        &mut self,
+        z: usize,
+        size: usize,
+        align_log2: usize,
+    ) -> Option<usize> {
+        let range = self.zones[z].range.clone();
+        self.alloc_contiguous_in_range(range, size, align_log2)
+    }
+
+    /// The first fit in `range`, walking its free runs.
+    fn alloc_contiguous_in_range(
+        &mut self,
+        range: Range<usize>,
+        size: usize,
+        align_log2: usize,
+    ) -> Option<usize> {
+        if size == 0 || align_size(align_log2).is_none() {
+            return None;
+        }
+        let mut key = range.start;
+        while let Some(start) = self.inner.next(key).filter(|&i| i < range.end) {
+            let end = self
+                .inner
+                .next_allocated(start)
+                .unwrap_or(A::CAP)
+                .min(range.end);
+            let base = align_up(start, align_log2);
+            if base.saturating_add(size) <= end {
+                self.inner.remove(base..base + size);
+                return Some(base);
+            }
+            if end == range.end {
+                break;
+            }
+            key = end;
+        }
+        None
+    }
+
+    /// Free an allocated bit.
+    pub fn dealloc(&mut self, key: usize) {
+        self.inner.dealloc(key)
+    }
+
+    /// Free a block allocated by `alloc_contiguous`.
+    pub fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        self.inner.dealloc_contiguous(base, size)
+    }
+
+    /// The number of free bits in zone `z`.
+    pub fn free_count_in(&self, z: usize) -> usize {
+        self.inner.count_free_in(self.zones[z].range.clone())
+    }
+}
+
+impl<A: BitAlloc, const Z: usize> fmt::Debug for ZonedBitAlloc<A, Z> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // the free bits of each zone
+        let mut s = f.debug_struct("ZonedBitAlloc");
+        for (z, zone) in self.zones.iter().enumerate() {
+            s.field(zone.name, &self.free_count_in(z));
+        }
+        s.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::format;
+    use crate::{BitAlloc4K, WORD_BITS};
+
+    fn zoned() -> ZonedBitAlloc<BitAlloc4K, 3> {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(..);
+        ZonedBitAlloc::new(
+            ba,
+            [
+                Zone::new("DMA", 0..16),
+                Zone::new("DMA32", 16..1024),
+                Zone::new("Normal", 1024..4096),
+            ],
+        )
+    }
+
+    #[test]
+    fn zoned_fallback() {
+        let mut ba = zoned();
+        assert_eq!(ba.alloc(), Some(4095));
+        assert_eq!(ba.alloc_below(1024), Some(1023));
+        assert_eq!(ba.alloc_below(16), Some(15));
+        assert_eq!(ba.alloc_in(ba.zone("DMA").unwrap()), Some(14));
+        ba.inner_mut().remove(16..);
+        // Normal and DMA32 are full, so DMA is the last resort
+        assert_eq!(ba.alloc(), Some(13));
+        assert_eq!(ba.zone_of(13), Some(0));
+        // try DMA32 first, then DMA
+        ba.inner_mut().insert(1000..1010);
+        ba.set_fallback([1, 0, 2]);
+        assert_eq!(ba.alloc(), Some(1009));
+        assert_eq!(ba.free_count_in(1), 9);
+        assert_eq!(
+            format(format_args!("{:?}", ba)).as_str(),
+            "ZonedBitAlloc { DMA: 13, DMA32: 9, Normal: 0 }"
+        );
+    }
+
+    #[test]
+    fn zoned_contiguous() {
+        let mut ba = zoned();
+        assert_eq!(ba.alloc_contiguous(64, 6), Some(1024));
+        assert_eq!(ba.alloc_contiguous_below(1024, 64, 6), Some(64));
+        // a block never spans two zones
+        assert_eq!(ba.alloc_contiguous_below(32, 20, 0), None);
+        assert_eq!(ba.alloc_contiguous_in(1, 1000, 0), None);
+        assert_eq!(ba.alloc_contiguous_in(1, 48, 0), Some(16));
+        ba.dealloc_contiguous(64, 64);
+        assert_eq!(ba.alloc_contiguous_in(0, 16, 4), Some(0));
+        assert_eq!(ba.alloc_contiguous(1, WORD_BITS), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "overlap")]
+    fn zoned_overlap() {
+        ZonedBitAlloc::new(
+            BitAlloc4K::default(),
+            [Zone::new("low", 0..100), Zone::new("high", 99..200)],
+        );
+    }
+}